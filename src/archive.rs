@@ -0,0 +1,176 @@
+//! This file implements the archives used to move feature sets between repositories.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// The file within an archive which describes the exported feature set.
+pub const INFO_FILE: &str = "rustea-export.toml";
+
+/// The formats of an exported feature set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    TarGz,
+    Tar,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => Err(format!("Unknown archive format {}, use tar.gz or tar", s)),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    /// Returns the file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Tar => "tar",
+        }
+    }
+}
+
+/// The metadata of an exported feature set.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct ExportInfo {
+    pub feature_set: String,
+    /// The Gitea instance, repository and owner the feature set was exported from
+    pub url: String,
+    pub repository: String,
+    pub owner: String,
+    /// The latest commit of the feature set at the time of the export
+    pub commit: Option<String>,
+    /// The unix timestamp of the export
+    pub exported: u64,
+    /// The number of exported files
+    pub files: usize,
+}
+
+/// This function runs tar with the `args`.
+fn tar(args: &[&str]) -> Result<()> {
+    let out = Command::new("tar")
+        .args(args)
+        .output()
+        .map_err(|e| Error::Rustea(format!("Failed to run tar: {}", e)))?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+/// This function packs the content of `dir` into the `archive`.
+pub fn create(dir: &Path, archive: &Path, format: ArchiveFormat) -> Result<()> {
+    let flags = match format {
+        ArchiveFormat::TarGz => "-czf",
+        ArchiveFormat::Tar => "-cf",
+    };
+    let (archive, dir) = (archive.to_string_lossy(), dir.to_string_lossy());
+    tar(&[flags, &archive, "-C", &dir, "."])
+}
+
+/// This function unpacks the `archive` into `dir`. A gzip compressed archive is detected
+/// by its magic number.
+pub fn extract(archive: &Path, dir: &Path) -> Result<()> {
+    let mut magic = [0u8; 2];
+    let gzip = File::open(archive)?.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+    let flags = if gzip { "-xzf" } else { "-xf" };
+    let (archive, dir) = (archive.to_string_lossy(), dir.to_string_lossy());
+    tar(&[flags, &archive, "-C", &dir])
+}
+
+/// This function returns the paths of all files below `dir`, relative to it and sorted.
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, files)?;
+            } else if let Ok(rel) = path.strip_prefix(root) {
+                files.push(rel.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+    let mut files = vec![];
+    walk(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{create, extract, files, ArchiveFormat};
+    use crate::temp::TempDir;
+
+    #[test]
+    fn test_create_and_extract() {
+        let src = TempDir::new("rustea-archive-src").unwrap();
+        src.write("nginx/etc/nginx/nginx.conf", b"worker_processes 1;\n")
+            .unwrap();
+        src.write("nginx/SHA256SUMS", b"").unwrap();
+        src.write("rustea-export.toml", b"feature_set = 'nginx'\n")
+            .unwrap();
+        let out = TempDir::new("rustea-archive-out").unwrap();
+
+        for format in [ArchiveFormat::TarGz, ArchiveFormat::Tar] {
+            let archive = out.path().join(format!("nginx.{}", format.extension()));
+            create(src.path(), &archive, format).unwrap();
+            let dst = TempDir::new("rustea-archive-dst").unwrap();
+            extract(&archive, dst.path()).unwrap();
+            assert_eq!(
+                files(dst.path()).unwrap(),
+                vec![
+                    PathBuf::from("nginx/SHA256SUMS"),
+                    PathBuf::from("nginx/etc/nginx/nginx.conf"),
+                    PathBuf::from("rustea-export.toml"),
+                ]
+            );
+            assert_eq!(
+                std::fs::read(dst.path().join("nginx/etc/nginx/nginx.conf")).unwrap(),
+                b"worker_processes 1;\n"
+            );
+        }
+        assert!(extract(&out.path().join("missing.tar"), out.path()).is_err());
+    }
+
+    #[test]
+    fn test_archive_format() {
+        assert_eq!("tar.gz".parse(), Ok(ArchiveFormat::TarGz));
+        assert_eq!("tgz".parse(), Ok(ArchiveFormat::TarGz));
+        assert_eq!("tar".parse(), Ok(ArchiveFormat::Tar));
+        assert!("zip".parse::<ArchiveFormat>().is_err());
+    }
+}
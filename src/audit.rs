@@ -0,0 +1,290 @@
+//! This file implements the local audit log of the write operations of rustea.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env,
+    fmt::{self, Display},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use tabwriter::TabWriter;
+
+use crate::{
+    error::{ConfigError, Error, Result},
+    state,
+};
+
+/// The default audit log file name used by rustea.
+const DEFAULT_AUDIT_NAME: &str = ".rustea.audit.jsonl";
+
+/// The default audit log lives next to the state file in the users home directory.
+pub fn get_default_audit_path() -> Result<PathBuf> {
+    match env::var_os("HOME") {
+        Some(val) => Ok(PathBuf::from(val).join(DEFAULT_AUDIT_NAME)),
+        None => Err(Error::Configuration(ConfigError::LocationError)),
+    }
+}
+
+/// The write operations recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Push,
+    Pull,
+    Delete,
+    Rename,
+    Restore,
+    Copy,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Push => "push",
+            Operation::Pull => "pull",
+            Operation::Delete => "delete",
+            Operation::Rename => "rename",
+            Operation::Restore => "restore",
+            Operation::Copy => "copy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "push" => Ok(Operation::Push),
+            "pull" => Ok(Operation::Pull),
+            "delete" => Ok(Operation::Delete),
+            "rename" => Ok(Operation::Rename),
+            "restore" => Ok(Operation::Restore),
+            "copy" => Ok(Operation::Copy),
+            _ => Err(format!(
+                "Unknown operation {}, use push, pull, delete, rename, restore or copy",
+                s
+            )),
+        }
+    }
+}
+
+/// A single write operation of the audit log.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    /// The seconds since the unix epoch
+    pub timestamp: u64,
+    /// The local user who ran rustea
+    pub user: String,
+    /// The author of the commits in the remote repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub operation: Operation,
+    pub feature_set: String,
+    /// The written local files or the changed remote files
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// The shas of the commits returned by the remote repository
+    #[serde(default)]
+    pub commits: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Entry {
+    /// Creates an entry of the `operation` on the `feature_set` by the current user.
+    pub fn new(operation: Operation, feature_set: &str) -> Self {
+        Entry {
+            timestamp: state::now(),
+            user: user(),
+            author: None,
+            operation,
+            feature_set: feature_set.to_owned(),
+            files: vec![],
+            commits: vec![],
+            message: None,
+        }
+    }
+}
+
+/// Returns the name of the local user, the uid if it is unknown.
+fn user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("LOGNAME"))
+        .unwrap_or_else(|_| unsafe { libc::getuid() }.to_string())
+}
+
+/// This function appends the `entry` as a single JSON line to the audit log at `path`.
+/// The log is only readable by its owner.
+pub fn append(path: &Path, entry: &Entry) -> Result<()> {
+    let line = serde_json::to_string(entry)
+        .map_err(|e| Error::Rustea(format!("Failed to serialize audit entry: {}", e)))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())
+        .map_err(Error::Io)
+}
+
+/// This function reads every entry of the audit log at `path`, oldest first.
+/// A missing log is empty and broken lines are skipped.
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::Io(e)),
+    };
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .filter_map(|(i, l)| match serde_json::from_str(l) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!(
+                    "Skipped line {} of the audit log {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/// The `Query` selects entries of the audit log.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub feature_set: Option<String>,
+    pub operation: Option<Operation>,
+    /// Only entries at or after these seconds since the unix epoch
+    pub since: Option<u64>,
+    /// The maximum number of the latest entries
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    /// This function returns the matching `entries`, oldest first.
+    pub fn filter<'a>(&self, entries: &'a [Entry]) -> Vec<&'a Entry> {
+        let mut found: Vec<&Entry> = entries
+            .iter()
+            .filter(|e| {
+                self.feature_set
+                    .as_ref()
+                    .is_none_or(|f| *f == e.feature_set)
+            })
+            .filter(|e| self.operation.is_none_or(|o| o == e.operation))
+            .filter(|e| self.since.is_none_or(|s| e.timestamp >= s))
+            .collect();
+        if let Some(limit) = self.limit {
+            found.drain(..found.len().saturating_sub(limit));
+        }
+        found
+    }
+}
+
+/// This function formats the `entries` as table with the date, user, operation,
+/// feature set, the short commit shas and the number of files.
+pub fn format(entries: &[&Entry]) -> Result<String> {
+    let mut tw = TabWriter::new(vec![]);
+    writeln!(
+        &mut tw,
+        "Date\tUser\tOperation\tFeature set\tCommits\tFiles"
+    )?;
+    for e in entries {
+        let commits: Vec<&str> = e.commits.iter().map(|c| c.get(..10).unwrap_or(c)).collect();
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            state::format_timestamp(e.timestamp),
+            e.user,
+            e.operation,
+            e.feature_set,
+            commits.join(","),
+            e.files.len()
+        )?;
+    }
+    let table = tw
+        .into_inner()
+        .map_err(|e| Error::Rustea(format!("Failed to align the history: {}", e)))?;
+    Ok(String::from_utf8_lossy(&table).trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{append, format, read, Entry, Operation, Query};
+    use crate::temp::TempDir;
+
+    fn entry(operation: Operation, feature_set: &str, timestamp: u64) -> Entry {
+        Entry {
+            timestamp,
+            user: "ops".into(),
+            author: Some("Ops Team".into()),
+            operation,
+            feature_set: feature_set.into(),
+            files: vec!["/etc/nginx/nginx.conf".into()],
+            commits: vec!["7d2a1f3c9be0a4d5e6f7".into()],
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let tmp = TempDir::new("rustea-audit").unwrap();
+        let path = tmp.path().join("audit.jsonl");
+        assert!(read(&path).unwrap().is_empty());
+        append(&path, &entry(Operation::Push, "web", 100)).unwrap();
+        append(&path, &entry(Operation::Delete, "db", 200)).unwrap();
+        std::fs::write(&path, std::fs::read_to_string(&path).unwrap() + "{broken\n").unwrap();
+        append(&path, &entry(Operation::Pull, "web", 300)).unwrap();
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1], entry(Operation::Delete, "db", 200));
+
+        let query = Query {
+            feature_set: Some("web".into()),
+            ..Default::default()
+        };
+        assert_eq!(query.filter(&entries).len(), 2);
+        let query = Query {
+            operation: Some("delete".parse().unwrap()),
+            since: Some(150),
+            ..Default::default()
+        };
+        assert_eq!(query.filter(&entries)[0].feature_set, "db");
+        let query = Query {
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(query.filter(&entries)[0].timestamp, 300);
+        assert!("merge".parse::<Operation>().is_err());
+
+        let table = format(&query.filter(&entries)).unwrap();
+        assert!(
+            table.ends_with("1970-01-01T00:05:00Z  ops   pull       web          7d2a1f3c9b  1")
+        );
+    }
+}
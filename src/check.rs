@@ -0,0 +1,109 @@
+//! This file implements the check commands which validate pulled files before they are installed.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{path::Path, process::Command};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    glob::Pattern,
+};
+
+/// A check of the metadata sidecar, e.g. `nginx -t -c {}` for `/etc/nginx/nginx.conf`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Check {
+    /// The shell command run with `sh -c`, `{}` is replaced by the path of the staged copy
+    pub command: String,
+    /// Glob patterns of the local files which are checked, see `glob::Pattern`
+    pub files: Vec<String>,
+}
+
+/// Returns the `path` quoted for the shell.
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+impl Check {
+    /// Returns the command which checks the staged `copy`.
+    fn command_for(&self, copy: &Path) -> String {
+        self.command.replace("{}", &quote(copy))
+    }
+
+    /// This function runs the check for the staged `copy` of the `target`, which are also
+    /// passed as `RUSTEA_FILE` and `RUSTEA_TARGET`. The output of a failed check is returned
+    /// as error.
+    pub fn run(&self, target: &Path, copy: &Path) -> Result<()> {
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg(self.command_for(copy))
+            .env("RUSTEA_FILE", copy)
+            .env("RUSTEA_TARGET", target)
+            .output()
+            .map_err(|e| Error::Rustea(format!("Failed to run {}: {}", self.command, e)))?;
+        match out.status.success() {
+            true => Ok(()),
+            false => Err(Error::Rustea(format!(
+                "Check {} of {} failed: {}",
+                self.command,
+                target.display(),
+                String::from_utf8_lossy(&[out.stdout, out.stderr].concat()).trim()
+            ))),
+        }
+    }
+}
+
+/// This function runs every check for the `staged` files, given as target and copy,
+/// which match one of its patterns. The first failed check is returned as error.
+pub fn run_all(checks: &[Check], staged: &[(&Path, &Path)]) -> Result<()> {
+    for check in checks {
+        let patterns = Pattern::all(&check.files)?;
+        for (target, copy) in staged {
+            if patterns.iter().any(|p| p.matches(target)) {
+                check.run(target, copy)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{run_all, Check};
+
+    #[test]
+    fn test_run_checks() {
+        let check = Check {
+            command: "grep -q listen {} && test \"$RUSTEA_TARGET\" = /etc/nginx/nginx.conf".into(),
+            files: vec!["/etc/nginx/*.conf".into()],
+        };
+        assert_eq!(
+            check.command_for(Path::new("/etc/it's.conf")),
+            "grep -q listen '/etc/it'\\''s.conf' && test \"$RUSTEA_TARGET\" = /etc/nginx/nginx.conf"
+        );
+        let tmp = crate::temp::TempDir::new("rustea-check").unwrap();
+        let good = tmp.write("good", b"listen 80;").unwrap();
+        let bad = tmp.write("bad", b"server {").unwrap();
+        let target = Path::new("/etc/nginx/nginx.conf");
+        let other = Path::new("/etc/hosts");
+        let checks = [check];
+        assert!(run_all(&checks, &[(target, &good), (other, &bad)]).is_ok());
+        let err = run_all(&checks, &[(target, &bad)]).unwrap_err();
+        assert!(err.to_string().contains("/etc/nginx/nginx.conf"));
+    }
+}
@@ -0,0 +1,155 @@
+//! This file implements the checksums used to compare local and remote content.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{collections::BTreeMap, fmt::Display};
+
+use ring::digest::{digest, Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+
+use crate::{
+    error::{Error, Result},
+    gitea::in_folder,
+};
+
+/// The checksum manifest is stored at the root of the feature set.
+pub const MANIFEST_FILE: &str = "SHA256SUMS";
+
+/// Convert a digest to its lowercase hex representation.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// This function computes the git blob sha of `content`, which is the
+/// SHA-1 of `blob <len>\0<content>`. Gitea reports it as `sha` of a file.
+pub fn git_blob_sha(content: &[u8]) -> String {
+    let mut ctx = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    ctx.update(format!("blob {}\0", content.len()).as_bytes());
+    ctx.update(content);
+    to_hex(ctx.finish().as_ref())
+}
+
+/// This function computes the hex encoded SHA-256 of `content`.
+pub fn sha256(content: &[u8]) -> String {
+    to_hex(digest(&SHA256, content).as_ref())
+}
+
+/// Returns true if the remote `path` is the checksum manifest of the feature set.
+pub fn is_manifest(path: &str, feature_set: &str) -> bool {
+    path == format!("{}/{}", feature_set, MANIFEST_FILE)
+}
+
+/// The `Manifest` lists the SHA-256 of every file of a feature set in the format
+/// of `sha256sum`. Files are addressed by their path within the feature set.
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    /// This function parses the lines `<sha256>  <path>` of a manifest.
+    pub fn parse(content: &str) -> Result<Manifest> {
+        let mut files = BTreeMap::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            match line.split_once(' ') {
+                Some((sum, path)) if sum.len() == 64 => {
+                    // sha256sum marks binary files with a leading asterisk
+                    let path = path.trim_start_matches(' ').trim_start_matches('*');
+                    files.insert(path.to_owned(), sum.to_lowercase());
+                }
+                _ => {
+                    return Err(Error::Rustea(format!(
+                        "Invalid line in {}: {}",
+                        MANIFEST_FILE, line
+                    )))
+                }
+            }
+        }
+        Ok(Manifest { files })
+    }
+
+    /// Record the checksum of `content` for the file at `path`.
+    pub fn insert(&mut self, path: &str, content: &[u8]) {
+        self.files.insert(path.to_owned(), sha256(content));
+    }
+
+    /// This function removes the checksums of the file or folder `path`.
+    pub fn remove(&mut self, path: &str) {
+        let path = path.trim_matches('/');
+        self.files.retain(|p, _| !in_folder(p, path));
+    }
+
+    /// This function returns an error if `path` is listed in the manifest
+    /// and its checksum doesn't match the one of `content`.
+    pub fn verify(&self, path: &str, content: &[u8]) -> Result<()> {
+        match self.files.get(path) {
+            Some(sum) if *sum != sha256(content) => Err(Error::Rustea(format!(
+                "Checksum of {} doesn't match {}",
+                path, MANIFEST_FILE
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, sum) in &self.files {
+            writeln!(f, "{}  {}", sum, path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{git_blob_sha, is_manifest, sha256, Manifest};
+
+    #[test]
+    fn test_git_blob_sha() {
+        // `git hash-object` of an empty file and of "hello\n"
+        assert_eq!(
+            git_blob_sha(b""),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        assert_eq!(
+            git_blob_sha(b"hello\n"),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    fn test_manifest() {
+        assert_eq!(
+            sha256(b"hello\n"),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        let mut manifest = Manifest::default();
+        manifest.insert("etc/hosts", b"hello\n");
+        let content = manifest.to_string();
+        assert_eq!(
+            content,
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  etc/hosts\n"
+        );
+
+        let manifest = Manifest::parse(&content).unwrap();
+        assert!(manifest.verify("etc/hosts", b"hello\n").is_ok());
+        assert!(manifest.verify("etc/hosts", b"hello").is_err());
+        assert!(manifest.verify("etc/other", b"hello").is_ok());
+        assert!(Manifest::parse("invalid line").is_err());
+        assert!(is_manifest("web/SHA256SUMS", "web"));
+        assert!(!is_manifest("web/etc/SHA256SUMS", "web"));
+    }
+}
@@ -0,0 +1,141 @@
+//! This file implements the colored output on terminals.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env,
+    ffi::OsString,
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Colors are disabled until `init` enables them, e.g. for the tests.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The colors used for added, deleted and modified entries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    /// Added or written files
+    Green,
+    /// Deleted or missing files
+    Red,
+    /// Modified or overwritten files
+    Yellow,
+    /// Folders and hunk headers
+    Cyan,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Cyan => "36",
+        }
+    }
+}
+
+/// Returns true if colors are used. The `--no-color` switch and a non-empty `NO_COLOR`
+/// variable disable them, otherwise they are used if stdout is a terminal.
+pub fn use_color(no_color: bool, no_color_var: Option<OsString>, tty: bool) -> bool {
+    let disabled = no_color_var.map(|v| !v.is_empty()).unwrap_or(false);
+    !no_color && !disabled && tty
+}
+
+/// This function enables the colors if the output is a terminal, see `use_color`.
+pub fn init(no_color: bool) {
+    let tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    ENABLED.store(
+        use_color(no_color, env::var_os("NO_COLOR"), tty),
+        Ordering::Relaxed,
+    );
+}
+
+/// Returns true if colors are enabled.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// This function wraps the `text` in the escape codes of the `color`.
+pub fn colorize(color: Color, text: impl Display) -> String {
+    format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+}
+
+/// This function colors the `text` if colors are enabled.
+pub fn paint(color: Color, text: impl Display) -> String {
+    match enabled() {
+        true => colorize(color, text),
+        false => text.to_string(),
+    }
+}
+
+/// This function colors the lines of a unified diff, added lines green,
+/// deleted lines red and hunk headers cyan.
+pub fn diff(diff: &str, enabled: bool) -> String {
+    if !enabled {
+        return diff.to_owned();
+    }
+    let mut out = String::with_capacity(diff.len());
+    for line in diff.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let color = match text {
+            t if t.starts_with("+++") || t.starts_with("---") => None,
+            t if t.starts_with('+') => Some(Color::Green),
+            t if t.starts_with('-') => Some(Color::Red),
+            t if t.starts_with("@@") => Some(Color::Cyan),
+            _ => None,
+        };
+        match color {
+            Some(color) => out.push_str(&colorize(color, text)),
+            None => out.push_str(text),
+        }
+        out.push_str(newline);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{colorize, diff, use_color, Color};
+
+    #[test]
+    fn test_use_color() {
+        assert!(use_color(false, None, true));
+        assert!(!use_color(false, None, false));
+        assert!(!use_color(true, None, true));
+        assert!(!use_color(false, Some("1".into()), true));
+        assert!(use_color(false, Some("".into()), true));
+    }
+
+    #[test]
+    fn test_diff() {
+        let d = "--- a\n+++ b\n@@ -1,2 +1,2 @@\n same\n-old\n+new\n";
+        assert_eq!(diff(d, false), d);
+        assert_eq!(
+            diff(d, true),
+            format!(
+                "--- a\n+++ b\n{}\n same\n{}\n{}\n",
+                colorize(Color::Cyan, "@@ -1,2 +1,2 @@"),
+                colorize(Color::Red, "-old"),
+                colorize(Color::Green, "+new")
+            )
+        );
+        assert_eq!(colorize(Color::Yellow, "x"), "\x1b[33mx\x1b[0m");
+    }
+}
@@ -0,0 +1,155 @@
+//! This file implements the helpers of the watch mode which runs rustea as pull agent.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    error::{Error, Result},
+    state,
+};
+
+/// The default seconds between two polls of the remote repository.
+pub const DEFAULT_INTERVAL: u64 = 300;
+
+/// This function returns the default location of the pid file, which is below `/run`
+/// for root and the runtime or temporary directory of the user otherwise.
+pub fn default_pid_path() -> PathBuf {
+    let dir = match unsafe { libc::geteuid() } {
+        0 => PathBuf::from("/run"),
+        _ => env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir),
+    };
+    dir.join("rustea.pid")
+}
+
+/// A `PidFile` holds the process id of the running watch mode and prevents a second
+/// instance. The file is removed if the value is dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// This function creates the pid file at `path`. A pid file left by a process
+    /// which isn't running anymore is replaced.
+    pub fn acquire(path: &Path) -> Result<PidFile> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut f) => {
+                    writeln!(f, "{}", process::id())?;
+                    return Ok(PidFile {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let pid = fs::read_to_string(path)?.trim().parse::<libc::pid_t>();
+                    match pid {
+                        Ok(pid) if unsafe { libc::kill(pid, 0) } == 0 => {
+                            return Err(Error::Rustea(format!(
+                                "rustea is already running with pid {} ({})",
+                                pid,
+                                path.display()
+                            )))
+                        }
+                        _ => fs::remove_file(path)?,
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Quote a logfmt value if it contains spaces, quotes or an equal sign.
+fn quote(value: &str) -> String {
+    match value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') || value.is_empty() {
+        true => format!("{:?}", value),
+        false => value.to_owned(),
+    }
+}
+
+/// This function formats a log line in the logfmt format, e.g.
+/// `time=2021-10-01T12:00:00Z level=info msg="Pulled feature set" feature_set=nginx`.
+pub fn format_log(time: u64, level: &str, msg: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = format!(
+        "time={} level={} msg={}",
+        state::format_timestamp(time),
+        level,
+        quote(msg)
+    );
+    for (key, value) in fields {
+        line.push_str(&format!(" {}={}", key, quote(value)));
+    }
+    line
+}
+
+/// This function writes a log line to stdout, or stderr for the level `error`.
+pub fn log(level: &str, msg: &str, fields: &[(&str, &str)]) {
+    let line = format_log(state::now(), level, msg, fields);
+    match level {
+        "error" => eprintln!("{}", line),
+        _ => println!("{}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_log, PidFile};
+    use crate::temp::TempDir;
+
+    #[test]
+    fn test_format_log() {
+        assert_eq!(
+            format_log(0, "info", "Pulled feature set", &[("feature_set", "nginx")]),
+            "time=1970-01-01T00:00:00Z level=info msg=\"Pulled feature set\" feature_set=nginx"
+        );
+        assert_eq!(
+            format_log(0, "error", "failed", &[("error", "a=b"), ("files", "")]),
+            "time=1970-01-01T00:00:00Z level=error msg=failed error=\"a=b\" files=\"\""
+        );
+    }
+
+    #[test]
+    fn test_pid_file() {
+        let tmp = TempDir::new("rustea-pid").unwrap();
+        let path = tmp.path().join("rustea.pid");
+        let pid = PidFile::acquire(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+        assert!(PidFile::acquire(&path).is_err());
+        drop(pid);
+        assert!(!path.exists());
+
+        // A stale pid file is replaced
+        tmp.write("rustea.pid", b"not a pid").unwrap();
+        assert!(PidFile::acquire(&path).is_ok());
+    }
+}
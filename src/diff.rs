@@ -0,0 +1,323 @@
+//! This file implements the comparison of local and remote files.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{path::Path, process::Command};
+
+use crate::error::{Error, Result};
+
+/// The default arguments of known merge tools, the placeholders are
+/// replaced by the temporary files.
+const MERGETOOL_ARGS: [(&str, &str); 3] = [
+    ("meld", "$LOCAL $BASE $REMOTE --output $MERGED"),
+    ("kdiff3", "$BASE $LOCAL $REMOTE -o $MERGED"),
+    ("vimdiff", "-d $MERGED $LOCAL $BASE $REMOTE"),
+];
+
+/// The number of unchanged lines shown around a change.
+const CONTEXT: usize = 3;
+
+/// A single step to transform the old lines into the new lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// This function computes the shortest edit script between the lines `a` and `b`
+/// with the algorithm of Eugene W. Myers, "An O(ND) Difference Algorithm and Its Variations".
+fn edit_script(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max as usize + 1;
+    let mut v = vec![0isize; 2 * offset + 1];
+    let mut trace = vec![];
+
+    'outer: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset as isize) as usize;
+            let mut x = match k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                true => v[i + 1],
+                false => v[i - 1] + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+        }
+    }
+
+    // Walk back through the recorded states to collect the edits
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = |k: isize| (k + offset as isize) as usize;
+        let prev_k = match k == -d || (k != d && v[i(k - 1)] < v[i(k + 1)]) {
+            true => k + 1,
+            false => k - 1,
+        };
+        let prev_x = v[i(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(match x == prev_x {
+                true => Edit::Insert,
+                false => Edit::Delete,
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// This function creates a unified diff between the `old` and `new` content
+/// like `diff -u`. The files are labeled with `old_name` and `new_name`.
+/// An empty string is returned if both are equal.
+pub fn unified_diff(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let edits = edit_script(&a, &b);
+
+    // Every edit with the position in the old and the new lines before it
+    let mut steps = Vec::with_capacity(edits.len());
+    let (mut x, mut y) = (0, 0);
+    for e in &edits {
+        steps.push((*e, x, y));
+        match e {
+            Edit::Equal => {
+                x += 1;
+                y += 1;
+            }
+            Edit::Delete => x += 1,
+            Edit::Insert => y += 1,
+        }
+    }
+
+    let changes: Vec<usize> = (0..steps.len())
+        .filter(|i| steps[*i].0 != Edit::Equal)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    // Group changes whose context overlaps into hunks
+    let mut hunks: Vec<(usize, usize)> = vec![];
+    for c in changes {
+        let start = c.saturating_sub(CONTEXT);
+        let end = (c + CONTEXT + 1).min(steps.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let hunk = &steps[start..end];
+        let old_len = hunk.iter().filter(|s| s.0 != Edit::Insert).count();
+        let new_len = hunk.iter().filter(|s| s.0 != Edit::Delete).count();
+        let (_, x, y) = hunk[0];
+        let pos = |p: usize, len: usize| if len == 0 { p } else { p + 1 };
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            pos(x, old_len),
+            old_len,
+            pos(y, new_len),
+            new_len
+        ));
+        for (e, x, y) in hunk {
+            match e {
+                Edit::Equal => out.push_str(&format!(" {}\n", a[*x])),
+                Edit::Delete => out.push_str(&format!("-{}\n", a[*x])),
+                Edit::Insert => out.push_str(&format!("+{}\n", b[*y])),
+            }
+        }
+    }
+    out
+}
+
+/// This function launches an external program like `vimdiff` or `meld` with `files`
+/// as additional arguments. The `tool` may contain arguments itself, e.g. `code --diff`.
+/// The exit code of the tool is ignored since diff programs report differences that way.
+pub fn run_tool(tool: &str, files: &[&Path]) -> Result<()> {
+    let mut parts = tool.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| Error::Rustea("No external tool configured".into()))?;
+    Command::new(program)
+        .args(parts)
+        .args(files)
+        .status()
+        .map(|_| ())
+        .map_err(|e| Error::Rustea(format!("Failed to launch {}: {}", tool, e)))
+}
+
+/// A conflict exists if the local and the remote file changed in a different way
+/// since the `base` was pulled or pushed. All arguments are blob shas.
+pub fn is_conflict(base: Option<&str>, local: &str, remote: &str) -> bool {
+    match base {
+        Some(base) => local != base && remote != base && local != remote,
+        None => false,
+    }
+}
+
+/// The temporary files passed to a three-way merge tool.
+pub struct MergeFiles<'a> {
+    pub base: &'a Path,
+    pub local: &'a Path,
+    pub remote: &'a Path,
+    pub merged: &'a Path,
+}
+
+/// This function builds the arguments for an external three-way merge `tool`.
+///
+/// The tool may use the placeholders `$BASE`, `$LOCAL`, `$REMOTE` and `$MERGED` like
+/// `git mergetool`. Without placeholders the default arguments for `meld`, `kdiff3` and
+/// `vimdiff` are used or `$LOCAL $BASE $REMOTE $MERGED` is appended for unknown tools.
+fn merge_args(tool: &str, files: &MergeFiles) -> Vec<String> {
+    let mut cmd = tool.to_owned();
+    if !["$BASE", "$LOCAL", "$REMOTE", "$MERGED"]
+        .iter()
+        .any(|p| tool.contains(p))
+    {
+        let default = MERGETOOL_ARGS
+            .iter()
+            .find(|(name, _)| tool.split_whitespace().next() == Some(name))
+            .map(|(_, args)| *args)
+            .unwrap_or("$LOCAL $BASE $REMOTE $MERGED");
+        cmd = format!("{} {}", cmd, default);
+    }
+    cmd.split_whitespace()
+        .map(|arg| {
+            arg.replace("$BASE", &files.base.display().to_string())
+                .replace("$LOCAL", &files.local.display().to_string())
+                .replace("$REMOTE", &files.remote.display().to_string())
+                .replace("$MERGED", &files.merged.display().to_string())
+        })
+        .collect()
+}
+
+/// This function launches an external three-way merge `tool` like `meld` or `kdiff3`.
+/// The result is expected in `files.merged`. An error is returned if the tool can not
+/// be launched or exits with a failure, which means the merge was aborted.
+pub fn run_mergetool(tool: &str, files: &MergeFiles) -> Result<()> {
+    let args = merge_args(tool, files);
+    if args.is_empty() {
+        return Err(Error::Rustea("No mergetool configured".into()));
+    }
+    let status = Command::new(&args[0])
+        .args(&args[1..])
+        .status()
+        .map_err(|e| Error::Rustea(format!("Failed to launch {}: {}", tool, e)))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "Merge of {} aborted by {}",
+            files.merged.display(),
+            tool
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{is_conflict, merge_args, unified_diff, MergeFiles};
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a", "b", "x\ny\n", "x\ny\n"), "");
+        assert_eq!(
+            unified_diff(
+                "a",
+                "b",
+                "1\n2\n3\n4\n5\n6\n7\n8\n9\n",
+                "1\n2\n3\n4\nfive\n6\n7\n8\n9\n"
+            ),
+            "--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+        assert_eq!(
+            unified_diff("a", "b", "", "new\n"),
+            "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+new\n"
+        );
+        assert_eq!(
+            unified_diff("a", "b", "1\n2\n", "2\n3\n"),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n-1\n 2\n+3\n"
+        );
+        // Distant changes end up in separate hunks
+        let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (1..=20)
+            .map(|i| match i {
+                2 | 19 => format!("{}!\n", i),
+                _ => format!("{}\n", i),
+            })
+            .collect();
+        assert_eq!(
+            unified_diff("a", "b", &old, &new).matches("@@ -").count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_is_conflict() {
+        assert!(is_conflict(Some("a"), "b", "c"));
+        assert!(!is_conflict(Some("a"), "a", "c"));
+        assert!(!is_conflict(Some("a"), "b", "a"));
+        assert!(!is_conflict(Some("a"), "b", "b"));
+        assert!(!is_conflict(None, "b", "c"));
+    }
+
+    #[test]
+    fn test_merge_args() {
+        let files = MergeFiles {
+            base: Path::new("b"),
+            local: Path::new("l"),
+            remote: Path::new("r"),
+            merged: Path::new("m"),
+        };
+        assert_eq!(
+            merge_args("meld", &files),
+            vec!["meld", "l", "b", "r", "--output", "m"]
+        );
+        assert_eq!(
+            merge_args("kdiff3", &files),
+            vec!["kdiff3", "b", "l", "r", "-o", "m"]
+        );
+        assert_eq!(
+            merge_args("mymerge --out=$MERGED $REMOTE", &files),
+            vec!["mymerge", "--out=m", "r"]
+        );
+        assert_eq!(
+            merge_args("other", &files),
+            vec!["other", "l", "b", "r", "m"]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! This file implements the diagnostics of the environment by `rustea doctor`.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{
+    env,
+    ffi::CString,
+    fmt::Display,
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    color::{self, Color},
+    error::{Error, Result},
+    gitea::{gitea_api::ApiError, GiteaClient},
+    RusteaConfiguration,
+};
+
+/// The time to wait for a connection to the Gitea instance.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The result of one diagnostic.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, res: Result<String>) -> Check {
+        let (passed, detail) = match res {
+            Ok(detail) => (true, detail),
+            // The detail of a failed check is the explanation without the generic prefix
+            Err(Error::Rustea(e)) => (false, e),
+            Err(e) => (false, e.to_string()),
+        };
+        Check {
+            name,
+            passed,
+            detail,
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.passed {
+            true => color::paint(Color::Green, "pass"),
+            false => color::paint(Color::Red, "fail"),
+        };
+        write!(f, "{} {}: {}", status, self.name, self.detail)
+    }
+}
+
+/// This function checks the configuration file `config_path` with the `profile` and
+/// everything rustea needs to use it: the Gitea instance, its certificate, the api token,
+/// the repository and the folders written by a pull and by the updater.
+/// Checks which depend on a failed one are left out.
+pub fn run(config_path: Option<&str>, profile: Option<&str>) -> Vec<Check> {
+    let mut checks = vec![];
+    let config = RusteaConfiguration::read_config_file(config_path).and_then(|mut c| {
+        if let Some(profile) = profile {
+            c.use_profile(profile)?;
+        }
+        Ok(c)
+    });
+    let config = match config {
+        Ok(c) => {
+            checks.push(Check::new("config", Ok("parsed".into())));
+            c
+        }
+        Err(e) => {
+            checks.push(Check::new("config", Err(e)));
+            return checks;
+        }
+    };
+    checks.append(&mut check_remote(&config));
+    checks.push(Check::new(
+        "script_folder",
+        writable(&config.script_folder)
+            .map(|_| format!("{} is writable", config.script_folder.display())),
+    ));
+    for (feature_set, folder) in &config.script_folders {
+        checks.push(Check::new(
+            "script_folder",
+            writable(folder)
+                .map(|_| format!("{} of {} is writable", folder.display(), feature_set)),
+        ));
+    }
+    checks.push(Check::new("self-update", check_binary()));
+    checks
+}
+
+/// Returns the diagnostics of the Gitea instance and the repository of the `config`.
+fn check_remote(config: &RusteaConfiguration) -> Vec<Check> {
+    let url = &config.repo.url;
+    let reachable =
+        host_port(url).and_then(|addr| connect(&addr).map(|_| format!("{} is reachable", addr)));
+    let reached = reachable.is_ok();
+    let mut checks = vec![Check::new("url", reachable)];
+    if !reached {
+        return checks;
+    }
+    let client = config.repo.token().and_then(|token| {
+        let session = config.repo.oauth_session()?;
+        GiteaClient::new(
+            url,
+            Some(&token),
+            None,
+            &config.repo.repository,
+            &config.repo.owner,
+        )
+        .and_then(|c| c.with_http(&config.http))
+        .map(|c| c.with_username(config.repo.username.clone()))
+        .map(|c| c.with_oauth(session))
+        .map(|c| c.with_rate_limit_wait(false))
+        .map_err(Error::Api)
+    });
+    let client = match client {
+        Ok(c) => c,
+        Err(e) => {
+            checks.push(Check::new("token", Err(e)));
+            return checks;
+        }
+    };
+
+    let version = client.get_gitea_version();
+    if url.starts_with("https://") {
+        let tls = match &version {
+            Err(ApiError::Ureq(e)) if matches!(**e, ureq::Error::Transport(_)) => {
+                Err(Error::Rustea(format!("No valid TLS connection: {}", e)))
+            }
+            _ => Ok("the certificate is valid".into()),
+        };
+        checks.push(Check::new("tls", tls));
+    }
+    let version = version
+        .map(|v| format!("{} {}", v.server().flavor, v.version))
+        .map_err(Error::Api);
+    let reached = version.is_ok();
+    checks.push(Check::new("api", version));
+    if !reached {
+        return checks;
+    }
+    let user = client
+        .get_user()
+        .map(|u| format!("valid for the user {}", u.login));
+    let valid = user.is_ok();
+    checks.push(Check::new("token", user.map_err(Error::Api)));
+    if !valid {
+        return checks;
+    }
+    match client.get_repository_information() {
+        Ok(repo) => {
+            checks.push(Check::new(
+                "repository",
+                Ok(format!("{} exists", repo.full_name)),
+            ));
+            let push = match repo.permissions.push {
+                true => Ok("the token may push".into()),
+                false => Err(Error::Rustea(
+                    "the token can only read the repository, pushes fail".into(),
+                )),
+            };
+            checks.push(Check::new("permissions", push));
+        }
+        Err(e) => checks.push(Check::new("repository", Err(Error::Api(e)))),
+    }
+    checks
+}
+
+/// Returns the `host:port` of the `url`, the port defaults to the one of the scheme.
+fn host_port(url: &str) -> Result<String> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| Error::Rustea(format!("{} is no valid url", url)))?;
+    let host = rest.split('/').next().unwrap_or(rest);
+    let host = host.rsplit('@').next().unwrap_or(host);
+    match (host.rsplit_once(':'), scheme) {
+        _ if host.is_empty() => Err(Error::Rustea(format!("{} has no host", url))),
+        (Some((_, port)), _) if port.chars().all(|c| c.is_ascii_digit()) && !port.is_empty() => {
+            Ok(host.to_owned())
+        }
+        (_, "https") => Ok(format!("{}:443", host)),
+        (_, "http") => Ok(format!("{}:80", host)),
+        _ => Err(Error::Rustea(format!("{} has an unknown scheme", url))),
+    }
+}
+
+/// This function opens a tcp connection to the `addr` to check that it is reachable.
+fn connect(addr: &str) -> Result<()> {
+    let mut last = Error::Rustea(format!("{} has no address", addr));
+    for a in addr
+        .to_socket_addrs()
+        .map_err(|e| Error::Rustea(format!("Failed to resolve {}: {}", addr, e)))?
+    {
+        match TcpStream::connect_timeout(&a, CONNECT_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(e) => last = Error::Rustea(format!("Failed to connect to {}: {}", a, e)),
+        }
+    }
+    Err(last)
+}
+
+/// Returns the check of the rustea binary which is replaced by `rustea update`.
+fn check_binary() -> Result<String> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent().unwrap_or_else(|| Path::new("/"));
+    writable(&exe)?;
+    writable(dir)?;
+    Ok(format!("{} is writable", exe.display()))
+}
+
+/// This function checks that the `path` can be written by the current user.
+/// A missing path is checked at its deepest existing parent folder, which would contain it.
+pub fn writable(path: &Path) -> Result<()> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("/"));
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| Error::Rustea(format!("Invalid path {}: {}", path.display(), e)))?;
+    match unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } {
+        0 => Ok(()),
+        _ => Err(Error::Rustea(format!(
+            "{} is not writable by the current user",
+            existing.display()
+        ))),
+    }
+}
+
+/// Returns the lines of the `checks` and true if all passed.
+pub fn format(checks: &[Check]) -> (String, bool) {
+    let lines: Vec<String> = checks.iter().map(Check::to_string).collect();
+    (lines.join("\n"), checks.iter().all(|c| c.passed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::{format, host_port, writable, Check};
+    use crate::{error::Error, temp::TempDir};
+
+    #[test]
+    fn test_host_port() {
+        assert_eq!(
+            host_port("https://gitea.example.com").unwrap(),
+            "gitea.example.com:443"
+        );
+        assert_eq!(
+            host_port("http://localhost:3000/git/").unwrap(),
+            "localhost:3000"
+        );
+        assert_eq!(host_port("http://gitea/").unwrap(), "gitea:80");
+        assert!(host_port("gitea.example.com").is_err());
+        assert!(host_port("ftp://gitea").is_err());
+        assert!(host_port("https:///api").is_err());
+    }
+
+    #[test]
+    fn test_writable() {
+        let tmp = TempDir::new("rustea-doctor").unwrap();
+        assert!(writable(tmp.path()).is_ok());
+        assert!(writable(&tmp.path().join("usr/local/bin")).is_ok());
+        let locked = tmp.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        // root may write anyway
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(writable(&locked.join("bin")).is_err());
+        }
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_format() {
+        let checks = vec![
+            Check::new("config", Ok("parsed".into())),
+            Check::new("token", Err(Error::Rustea("rejected".into()))),
+        ];
+        assert_eq!(
+            format(&checks),
+            ("pass config: parsed\nfail token: rejected".into(), false)
+        );
+        assert!(format(&checks[..1]).1);
+    }
+}
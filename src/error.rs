@@ -14,9 +14,36 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
 use core::fmt;
-use std::{fmt::Display, io, num::ParseIntError};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    num::ParseIntError,
+    path::PathBuf,
+};
 
-use crate::gitea::gitea_api;
+use tabwriter::TabWriter;
+
+use crate::{
+    color::{self, Color},
+    gitea::gitea_api,
+};
+
+/// The exit code of rustea for every error without a more specific code.
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit code if the configuration is missing or invalid.
+pub const EXIT_CONFIG: i32 = 2;
+/// The exit code if the server rejected the credentials or the api token lacks a permission.
+pub const EXIT_AUTH: i32 = 3;
+/// The exit code if the server is unreachable, overloaded or limits the requests.
+pub const EXIT_NETWORK: i32 = 4;
+/// The exit code if local files drifted from their feature set.
+pub const EXIT_DRIFT: i32 = 5;
+/// The exit code if an operation failed for some files but succeeded for others.
+pub const EXIT_PARTIAL: i32 = 6;
+/// The exit code of `rustea doctor` if one of its checks failed.
+pub const EXIT_UNHEALTHY: i32 = 7;
+/// The exit code of `rustea update --check` if a newer release is available.
+pub const EXIT_OUTDATED: i32 = 8;
 
 /// A `Result` alias where the `Err` case is `rustea::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -27,15 +54,52 @@ pub enum Error {
     Api(gitea_api::ApiError),
     Io(io::Error),
     Version(ParseIntError),
-    Update(ureq::Error),
+    Update(Box<ureq::Error>),
     Configuration(ConfigError),
     Rustea(String),
+    /// A push, pull or delete with `keep_going` failed for these files, the others succeeded
+    Batch(Vec<(PathBuf, Error)>),
 }
 
 impl Error {
     pub fn io(kind: io::ErrorKind, msg: String) -> Self {
         Error::Io(io::Error::new(kind, msg))
     }
+
+    /// Returns the exit code of rustea for the error, see the `EXIT_` constants.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Configuration(_) => EXIT_CONFIG,
+            Error::Api(e) => api_exit_code(e),
+            Error::Update(e) => ureq_exit_code(e),
+            Error::Batch(_) => EXIT_PARTIAL,
+            Error::Io(_) | Error::Version(_) | Error::Rustea(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+fn api_exit_code(err: &gitea_api::ApiError) -> i32 {
+    use gitea_api::ApiError;
+    match err {
+        ApiError::InvalidCredentials(_)
+        | ApiError::Unauthorized(_)
+        | ApiError::PermissionDenied(_)
+        | ApiError::ProtectedBranch(_) => EXIT_AUTH,
+        ApiError::RateLimited(_) => EXIT_NETWORK,
+        ApiError::Ureq(e) => ureq_exit_code(e),
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// A failed connection and the answers of an unavailable server are network errors.
+fn ureq_exit_code(err: &ureq::Error) -> i32 {
+    match err {
+        ureq::Error::Transport(_) => EXIT_NETWORK,
+        ureq::Error::Status(401, _) | ureq::Error::Status(403, _) => EXIT_AUTH,
+        ureq::Error::Status(429, _) => EXIT_NETWORK,
+        ureq::Error::Status(status, _) if *status >= 500 => EXIT_NETWORK,
+        ureq::Error::Status(_, _) => EXIT_FAILURE,
+    }
 }
 
 #[derive(Debug)]
@@ -64,6 +128,7 @@ impl std::error::Error for Error {
             Error::Configuration(_) => None,
             Error::Version(ref c) => Some(c),
             Error::Update(ref c) => Some(c),
+            Error::Batch(_) => None,
         }
     }
 
@@ -75,10 +140,29 @@ impl std::error::Error for Error {
             Error::Configuration(_) => None,
             Error::Version(ref c) => Some(c),
             Error::Update(ref c) => Some(c),
+            Error::Batch(_) => None,
         }
     }
 }
 
+/// Returns the table of the `failed` files and their errors.
+fn failure_table(failed: &[(PathBuf, Error)]) -> io::Result<String> {
+    let mut tw = TabWriter::new(vec![]);
+    writeln!(&mut tw, "File\tError")?;
+    for (path, e) in failed {
+        writeln!(
+            &mut tw,
+            "{}\t{}",
+            color::paint(Color::Red, path.display()),
+            e
+        )?;
+    }
+    let table = tw
+        .into_inner()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(String::from_utf8_lossy(&table).trim_end().to_owned())
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -92,6 +176,10 @@ impl fmt::Display for Error {
             },
             Error::Version(e) => write!(f, "Failed to parse version: {}", e),
             Error::Update(e) => write!(f, "Update failed: {}", e),
+            Error::Batch(failed) => match failure_table(failed) {
+                Ok(table) => write!(f, "{} files failed\n{}", failed.len(), table),
+                Err(_) => Err(fmt::Error),
+            },
         }
     }
 }
@@ -128,6 +216,55 @@ impl From<ParseIntError> for Error {
 
 impl From<ureq::Error> for Error {
     fn from(err: ureq::Error) -> Self {
-        Error::Update(err)
+        Error::Update(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{
+        ConfigError, Error, EXIT_AUTH, EXIT_CONFIG, EXIT_FAILURE, EXIT_NETWORK, EXIT_PARTIAL,
+    };
+    use crate::gitea::gitea_api::ApiError;
+
+    #[test]
+    fn test_exit_code() {
+        let config = Error::Configuration(ConfigError::LocationError);
+        assert_eq!(config.exit_code(), EXIT_CONFIG);
+        let auth = Error::Api(ApiError::Unauthorized("bad token".into()));
+        assert_eq!(auth.exit_code(), EXIT_AUTH);
+        let read_only = Error::Api(ApiError::PermissionDenied("no push permission".into()));
+        assert_eq!(read_only.exit_code(), EXIT_AUTH);
+        let protected = Error::Api(ApiError::ProtectedBranch("use --via-pr".into()));
+        assert_eq!(protected.exit_code(), EXIT_AUTH);
+        let limited = Error::Api(ApiError::RateLimited(None));
+        assert_eq!(limited.exit_code(), EXIT_NETWORK);
+        let down = ureq::Error::Status(503, ureq::Response::new(503, "", "").unwrap());
+        assert_eq!(Error::from(ApiError::from(down)).exit_code(), EXIT_NETWORK);
+        let missing = Error::Api(ApiError::NotFound("web".into()));
+        assert_eq!(missing.exit_code(), EXIT_FAILURE);
+        let io = Error::io(io::ErrorKind::NotFound, "missing".into());
+        assert_eq!(io.exit_code(), EXIT_FAILURE);
+        let batch = Error::Batch(vec![("/etc/app.conf".into(), io)]);
+        assert_eq!(batch.exit_code(), EXIT_PARTIAL);
+    }
+
+    #[test]
+    fn test_batch_error() {
+        let failed = vec![
+            ("/etc/nginx.conf".into(), Error::Rustea("timeout".into())),
+            (
+                "/etc/app.conf".into(),
+                Error::io(io::ErrorKind::NotFound, "missing".into()),
+            ),
+        ];
+        let e = Error::Batch(failed).to_string();
+        let mut lines = e.lines();
+        assert_eq!(lines.next(), Some("2 files failed"));
+        assert!(lines.next().unwrap().starts_with("File             Error"));
+        assert!(lines.next().unwrap().contains("/etc/nginx.conf  "));
+        assert!(lines.next().unwrap().ends_with("IO Error: missing"));
     }
 }
@@ -0,0 +1,109 @@
+//! This file implements the commit of pulled files into the local etckeeper or git repository.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    path::Path,
+    process::{Command, Output},
+};
+
+use crate::error::{Error, Result};
+
+/// The folder which is tracked by etckeeper.
+const ETC: &str = "/etc";
+
+/// This function builds the commit message which references the feature set
+/// and the remote `commit` the files were pulled from.
+pub fn commit_message(feature_set: &str, commit: Option<&str>) -> String {
+    match commit {
+        Some(sha) => format!("rustea: pull feature set {} at {}", feature_set, sha),
+        None => format!("rustea: pull feature set {}", feature_set),
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<Output> {
+    cmd.output()
+        .map_err(|e| Error::Rustea(format!("Failed to run {:?}: {}", cmd, e)))
+}
+
+fn check(cmd: &mut Command) -> Result<()> {
+    let out = run(cmd)?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "{:?} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+/// This function commits the pulled `files` below `/etc` with `message`.
+///
+/// If `/etc` is managed by etckeeper `etckeeper commit` is used, which commits
+/// every pending change in `/etc`. Otherwise only the pulled files are committed
+/// into the git repository at `/etc`. Nothing happens if no pulled file is below
+/// `/etc` or `/etc` is no repository. Returns true if a commit was created.
+pub fn commit(files: &[String], message: &str) -> Result<bool> {
+    let files: Vec<&String> = files
+        .iter()
+        .filter(|f| Path::new(f).starts_with(ETC))
+        .collect();
+    let etc = Path::new(ETC);
+    if files.is_empty() || !etc.join(".git").exists() {
+        return Ok(false);
+    }
+
+    if etc.join(".etckeeper").exists() {
+        // etckeeper exits with an error if there is nothing to commit
+        let out = run(Command::new("etckeeper").args(["commit", message]))?;
+        return Ok(out.status.success());
+    }
+
+    check(
+        Command::new("git")
+            .args(["-C", ETC, "add", "--"])
+            .args(&files),
+    )?;
+    let staged = run(Command::new("git")
+        .args(["-C", ETC, "diff", "--cached", "--quiet", "--"])
+        .args(&files))?;
+    if staged.status.success() {
+        return Ok(false);
+    }
+    check(
+        Command::new("git")
+            .args(["-C", ETC, "commit", "-q", "-m", message, "--"])
+            .args(&files),
+    )?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::commit_message;
+
+    #[test]
+    fn test_commit_message() {
+        assert_eq!(
+            commit_message("nginx", Some("abc")),
+            "rustea: pull feature set nginx at abc"
+        );
+        assert_eq!(
+            commit_message("nginx", None),
+            "rustea: pull feature set nginx"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! This file implements the optional `feature.toml` manifest at the root of a feature set.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    hooks::Hooks,
+};
+
+/// The name of the manifest file in the root of a feature set.
+pub const MANIFEST_FILE: &str = "feature.toml";
+
+/// Returns true if the remote `path` is the manifest of the `feature_set`.
+pub fn is_manifest(path: &str, feature_set: &str) -> bool {
+    path == format!("{}/{}", feature_set, MANIFEST_FILE)
+}
+
+/// The `FeatureManifest` describes a feature set, e.g.
+///
+/// ```toml
+/// description = "The nginx web server"
+/// maintainer = "ops@example.com"
+/// version = "1.2.0"
+/// depends-on = ["base"]
+/// targets = ["/etc/nginx"]
+///
+/// [hooks]
+/// post_pull = "nginx -t"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct FeatureManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintainer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The feature sets which are applied before this one
+    #[serde(
+        default,
+        rename = "depends-on",
+        alias = "depends_on",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub depends_on: Vec<String>,
+    /// The local folders or files the configuration files are restricted to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<PathBuf>,
+    /// The hooks of the feature set, which run after the hooks of the configuration
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+}
+
+impl FeatureManifest {
+    pub fn parse(content: &str) -> Result<FeatureManifest> {
+        toml::from_str(content)
+            .map_err(|e| Error::Rustea(format!("Invalid {}: {}", MANIFEST_FILE, e)))
+    }
+
+    /// Returns true if the local `path` is within the `targets`, every path
+    /// is allowed without targets.
+    pub fn allows(&self, path: &Path) -> bool {
+        self.targets.is_empty() || self.targets.iter().any(|t| path.starts_with(t))
+    }
+}
+
+impl Display for FeatureManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = [
+            ("Description", self.description.clone()),
+            ("Maintainer", self.maintainer.clone()),
+            ("Version", self.version.clone()),
+            (
+                "Depends on",
+                Some(self.depends_on.join(", ")).filter(|d| !d.is_empty()),
+            ),
+            (
+                "Targets",
+                Some(
+                    self.targets
+                        .iter()
+                        .map(|t| t.display().to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                )
+                .filter(|t| !t.is_empty()),
+            ),
+        ];
+        for (name, value) in fields.iter() {
+            if let Some(value) = value {
+                writeln!(f, "{}: {}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// This function sorts the feature sets `names` and their dependencies, which are
+/// looked up with `depends_on`, topologically. Every feature set follows the ones it
+/// depends on, otherwise the order of `names` is kept. A cycle is returned as error.
+pub fn resolve<F>(names: &[String], mut depends_on: F) -> Result<Vec<String>>
+where
+    F: FnMut(&str) -> Result<Vec<String>>,
+{
+    fn visit<F>(
+        name: &str,
+        depends_on: &mut F,
+        path: &mut Vec<String>,
+        seen: &mut BTreeSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<()>
+    where
+        F: FnMut(&str) -> Result<Vec<String>>,
+    {
+        if let Some(start) = path.iter().position(|n| n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name.to_owned());
+            return Err(Error::Rustea(format!(
+                "Dependency cycle between feature sets: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        if seen.contains(name) {
+            return Ok(());
+        }
+        path.push(name.to_owned());
+        for dependency in depends_on(name)? {
+            visit(&dependency, depends_on, path, seen, order)?;
+        }
+        path.pop();
+        seen.insert(name.to_owned());
+        order.push(name.to_owned());
+        Ok(())
+    }
+
+    let (mut path, mut seen, mut order) = (vec![], BTreeSet::new(), vec![]);
+    for name in names {
+        visit(name, &mut depends_on, &mut path, &mut seen, &mut order)?;
+    }
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{is_manifest, resolve, FeatureManifest};
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = FeatureManifest::parse(
+            r#"
+description = "The nginx web server"
+version = "1.2.0"
+depends-on = ["base"]
+targets = ["/etc/nginx"]
+
+[hooks]
+post_pull = "nginx -t"
+"#,
+        )
+        .unwrap();
+        assert_eq!(manifest.depends_on, vec!["base"]);
+        assert_eq!(manifest.hooks.post_pull.as_deref(), Some("nginx -t"));
+        assert!(manifest.allows(Path::new("/etc/nginx/nginx.conf")));
+        assert!(!manifest.allows(Path::new("/etc/nginx.bak")));
+        assert!(FeatureManifest::default().allows(Path::new("/etc/passwd")));
+        assert_eq!(
+            manifest.to_string(),
+            "Description: The nginx web server\nVersion: 1.2.0\nDepends on: base\nTargets: /etc/nginx\n"
+        );
+        assert!(FeatureManifest::parse("depends-on = \"base\"").is_err());
+        assert!(is_manifest("nginx/feature.toml", "nginx"));
+        assert!(!is_manifest("nginx/etc/feature.toml", "nginx"));
+    }
+
+    #[test]
+    fn test_resolve_dependencies() {
+        let deps = |name: &str| {
+            Ok(match name {
+                "nginx" => vec!["base".to_owned(), "tls".to_owned()],
+                "tls" => vec!["base".to_owned()],
+                _ => vec![],
+            })
+        };
+        let names = vec!["nginx".to_owned(), "base".to_owned(), "mail".to_owned()];
+        assert_eq!(
+            resolve(&names, deps).unwrap(),
+            vec!["base", "tls", "nginx", "mail"]
+        );
+
+        let cyclic = |name: &str| {
+            Ok(match name {
+                "nginx" => vec!["tls".to_owned()],
+                "tls" => vec!["base".to_owned()],
+                _ => vec!["nginx".to_owned()],
+            })
+        };
+        let err = resolve(&names, cyclic).unwrap_err().to_string();
+        assert!(err.ends_with("nginx -> tls -> base -> nginx"), "{}", err);
+        let itself = |_: &str| Ok(vec!["base".to_owned()]);
+        assert!(resolve(&["base".to_owned()], itself).is_err());
+    }
+}
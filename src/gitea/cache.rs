@@ -0,0 +1,214 @@
+//! This file implements the local cache of the remote tree and responses between runs of rustea.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+
+use super::gitea_api::TreeEntry;
+
+/// The git tree of a branch or revision at a commit.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct CachedTree {
+    /// The sha of the commit the tree belongs to
+    pub commit: String,
+    pub entries: Vec<TreeEntry>,
+}
+
+/// A response of a `GET` request which is reused if the server answers
+/// `304 Not Modified` to a request with its ETag.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct CachedResponse {
+    pub etag: String,
+    pub body: String,
+    /// The `Link` header of a paginated response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_branch: Option<String>,
+    /// The last seen tree of every branch or revision
+    #[serde(default)]
+    trees: BTreeMap<String, CachedTree>,
+    /// The last response with an ETag of every requested url
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    responses: BTreeMap<String, CachedResponse>,
+}
+
+/// The `Cache` keeps the default branch and the last seen trees of the remote
+/// repository. Without a path it only lives as long as the client.
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: Option<PathBuf>,
+    data: CacheData,
+}
+
+/// Returns the cache folder of rustea, `$XDG_CACHE_HOME/rustea` or `~/.cache/rustea`.
+pub fn default_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CACHE_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("rustea")),
+        None => env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache").join("rustea")),
+    }
+}
+
+/// Returns the name of the cache file of a repository, e.g. `gitea.example.com-ops-config.json`.
+pub fn file_name(url: &str, owner: &str, repository: &str) -> String {
+    let host = url.split("://").last().unwrap_or(url).trim_end_matches('/');
+    let name = format!("{}-{}-{}", host, owner, repository);
+    let name: String = name
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                true => c,
+                false => '_',
+            },
+        )
+        .collect();
+    format!("{}.json", name)
+}
+
+impl Cache {
+    /// This function reads the cache at `path`. A missing or broken cache file is
+    /// ignored and replaced on the next change.
+    pub fn load(path: &Path) -> Cache {
+        let data = fs::read_to_string(path)
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default();
+        Cache {
+            path: Some(path.to_path_buf()),
+            data,
+        }
+    }
+
+    /// Returns the cached default branch of the repository.
+    pub fn default_branch(&self) -> Option<&str> {
+        self.data.default_branch.as_deref()
+    }
+
+    pub fn set_default_branch(&mut self, branch: &str) {
+        self.data.default_branch = Some(branch.to_owned());
+        self.save();
+    }
+
+    /// Returns the cached tree of `tree_ref` if it was seen at the `commit`.
+    pub fn tree(&self, tree_ref: &str, commit: &str) -> Option<&[TreeEntry]> {
+        match self.data.trees.get(tree_ref) {
+            Some(tree) if tree.commit == commit => Some(&tree.entries),
+            _ => None,
+        }
+    }
+
+    pub fn set_tree(&mut self, tree_ref: &str, commit: &str, entries: Vec<TreeEntry>) {
+        let tree = CachedTree {
+            commit: commit.to_owned(),
+            entries,
+        };
+        self.data.trees.insert(tree_ref.to_owned(), tree);
+        self.save();
+    }
+
+    /// Returns the last response of the `url` which had an ETag.
+    pub fn response(&self, url: &str) -> Option<&CachedResponse> {
+        self.data.responses.get(url)
+    }
+
+    pub fn set_response(&mut self, url: &str, response: CachedResponse) {
+        self.data.responses.insert(url.to_owned(), response);
+        self.save();
+    }
+
+    /// This function writes the cache file, a failure only disables the cache for later runs.
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let res = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(path, serde_json::to_vec(&self.data)?));
+            if let Err(e) = res {
+                debug!("Failed to write the cache {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_name, Cache, CachedResponse};
+    use crate::{gitea::gitea_api::TreeEntry, temp::TempDir};
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            file_name("https://gitea.example.com/", "ops", "config"),
+            "gitea.example.com-ops-config.json"
+        );
+        assert_eq!(
+            file_name("http://localhost:3000/git", "ops", "a b"),
+            "localhost_3000_git-ops-a_b.json"
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let tmp = TempDir::new("rustea-cache").unwrap();
+        let path = tmp.path().join("cache").join("repo.json");
+        let mut cache = Cache::load(&path);
+        assert!(cache.default_branch().is_none());
+        cache.set_default_branch("main");
+        let entry = TreeEntry {
+            path: "web/etc/nginx.conf".into(),
+            mode: "100644".into(),
+            entry_type: "blob".into(),
+            sha: "b2".into(),
+            size: Some(42),
+        };
+        cache.set_tree("main", "7d2a1f", vec![entry]);
+
+        let cache = Cache::load(&path);
+        assert_eq!(cache.default_branch(), Some("main"));
+        assert_eq!(cache.tree("main", "7d2a1f").unwrap()[0].sha, "b2");
+        assert_eq!(cache.tree("main", "7d2a1f").unwrap()[0].size, Some(42));
+        assert!(cache.tree("main", "8e3b20").is_none());
+        assert!(cache.tree("dev", "7d2a1f").is_none());
+
+        let mut cache = cache;
+        let url = "https://gitea/api/v1/repos/o/r/commits?sha=main&limit=1";
+        assert!(cache.response(url).is_none());
+        let response = CachedResponse {
+            etag: "\"9c0e\"".into(),
+            body: "[]".into(),
+            link: None,
+        };
+        cache.set_response(url, response);
+        let cache = Cache::load(&path);
+        assert_eq!(cache.response(url).unwrap().etag, "\"9c0e\"");
+        assert_eq!(cache.response(url).unwrap().body, "[]");
+
+        tmp.write("broken.json", b"{").unwrap();
+        assert!(Cache::load(&tmp.path().join("broken.json"))
+            .default_branch()
+            .is_none());
+    }
+}
@@ -14,21 +14,42 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
 use core::fmt;
-use std::{fmt::Display, io, io::Write};
+use std::{fmt::Display, io, io::Write, time::Duration};
 
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 use serde_json::Value;
 use tabwriter::TabWriter;
 
+use crate::color::{self, Color};
+
 /// All possible errors which can happen by using the gitea api.
 #[derive(Debug)]
 pub enum ApiError {
     Io(io::Error),
     // Reqwest(reqwest::Error),
-    Ureq(ureq::Error),
+    Ureq(Box<ureq::Error>),
     Json(serde_json::Error),
     InvalidCredentials(String),
     InvalidContentResponse(String),
+    PermissionDenied(String),
+    /// The branch protection rejected a direct change of the branch
+    ProtectedBranch(String),
+    /// The requested repository, file or endpoint doesn't exist (404)
+    NotFound(String),
+    /// The api token was rejected (401)
+    Unauthorized(String),
+    /// The change is based on an outdated revision of a file (409 or a sha mismatch)
+    Conflict {
+        message: String,
+        /// The current sha of the file on the server if Gitea reports it
+        expected_sha: Option<String>,
+    },
+    /// The server rejected the request because of its rate limit (429)
+    RateLimited(Option<Duration>),
+    /// The server is too old for a feature, see `Feature`
+    Unsupported(String),
+    /// A command of the plain git backend failed
+    Git(String),
 }
 
 impl std::error::Error for ApiError {
@@ -38,7 +59,15 @@ impl std::error::Error for ApiError {
             ApiError::Json(ref c) => Some(c),
             ApiError::InvalidCredentials(_) => None,
             ApiError::InvalidContentResponse(_) => None,
-            ApiError::Io(_) => todo!(),
+            ApiError::PermissionDenied(_) => None,
+            ApiError::ProtectedBranch(_) => None,
+            ApiError::NotFound(_) => None,
+            ApiError::Unauthorized(_) => None,
+            ApiError::Conflict { .. } => None,
+            ApiError::RateLimited(_) => None,
+            ApiError::Unsupported(_) => None,
+            ApiError::Git(_) => None,
+            ApiError::Io(ref c) => Some(c),
         }
     }
 
@@ -48,7 +77,15 @@ impl std::error::Error for ApiError {
             ApiError::Json(ref c) => Some(c),
             ApiError::InvalidCredentials(_) => None,
             ApiError::InvalidContentResponse(_) => None,
-            ApiError::Io(_) => todo!(),
+            ApiError::PermissionDenied(_) => None,
+            ApiError::ProtectedBranch(_) => None,
+            ApiError::NotFound(_) => None,
+            ApiError::Unauthorized(_) => None,
+            ApiError::Conflict { .. } => None,
+            ApiError::RateLimited(_) => None,
+            ApiError::Unsupported(_) => None,
+            ApiError::Git(_) => None,
+            ApiError::Io(ref c) => Some(c),
         }
     }
 }
@@ -65,6 +102,28 @@ impl fmt::Display for ApiError {
                 write!(f, "Invalid content response from server. Cause: {}", e)
             }
             ApiError::Io(e) => write!(f, "IO Error: {}", e),
+            ApiError::PermissionDenied(e) => write!(f, "Permission denied. Cause: {}", e),
+            ApiError::ProtectedBranch(e) => write!(f, "The branch is protected. Cause: {}", e),
+            ApiError::NotFound(e) => write!(f, "Not found: {}", e),
+            ApiError::Unauthorized(e) => write!(f, "Unauthorized. Cause: {}", e),
+            ApiError::Conflict {
+                message,
+                expected_sha,
+            } => {
+                write!(f, "The remote file changed in the meantime")?;
+                if let Some(sha) = expected_sha {
+                    write!(f, ", it is at {} now", sha)?;
+                }
+                write!(f, ". Pull the feature set and retry. Cause: {}", message)
+            }
+            ApiError::RateLimited(Some(delay)) => write!(
+                f,
+                "The rate limit of the server is reached, retry in {}s",
+                delay.as_secs()
+            ),
+            ApiError::RateLimited(None) => write!(f, "The rate limit of the server is reached"),
+            ApiError::Unsupported(e) => write!(f, "Unsupported by the server: {}", e),
+            ApiError::Git(e) => write!(f, "Failed to run git. Cause: {}", e),
         }
     }
 }
@@ -77,7 +136,7 @@ impl From<io::Error> for ApiError {
 
 impl From<ureq::Error> for ApiError {
     fn from(err: ureq::Error) -> Self {
-        ApiError::Ureq(err)
+        ApiError::Ureq(Box::new(err))
     }
 }
 
@@ -87,6 +146,45 @@ impl From<serde_json::Error> for ApiError {
     }
 }
 
+/// The json body of a rejected request, e.g. `{"message": "...", "url": "..."}`.
+#[derive(Deserialize, Debug, Default)]
+pub struct ErrorResponse {
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+impl ErrorResponse {
+    /// Returns the error of the response `body`, `None` if it has no message.
+    pub fn parse(body: &str) -> Option<ErrorResponse> {
+        serde_json::from_str::<ErrorResponse>(body)
+            .ok()
+            .filter(|e| !e.message.is_empty())
+    }
+
+    /// Returns true if a changed file doesn't match the file on the server, because
+    /// its sha differs or a new file already exists.
+    pub fn is_conflict(&self) -> bool {
+        let message = self.message.to_lowercase();
+        message.contains("sha does not match") || message.contains("already exists")
+    }
+
+    /// Returns the current sha of the file from a sha mismatch,
+    /// e.g. `sha does not match [given: 1f, expected: 2e]`.
+    pub fn expected_sha(&self) -> Option<String> {
+        let (_, rest) = self.message.split_once("expected: ")?;
+        let sha: String = rest.chars().take_while(|c| c.is_ascii_hexdigit()).collect();
+        Some(sha).filter(|s| !s.is_empty())
+    }
+
+    /// Returns true if the change was rejected by the protection of the branch.
+    pub fn is_protected_branch(&self) -> bool {
+        let message = self.message.to_lowercase();
+        message.contains("protected") || message.contains("cannot commit")
+    }
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct ApiToken {
     pub id: i64,
@@ -111,9 +209,128 @@ pub struct Version {
     pub version: String,
 }
 
+impl Version {
+    /// Returns the flavor and the compatible Gitea release of the server.
+    pub fn server(&self) -> ServerVersion {
+        ServerVersion::parse(&self.version)
+    }
+}
+
 impl Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Gitea version: {}", self.version)
+        write!(f, "{} version: {}", self.server().flavor, self.version)
+    }
+}
+
+/// The software of the server, Forgejo is a fork of Gitea with its own versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Gitea,
+    Forgejo,
+}
+
+impl Display for Flavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Flavor::Gitea => write!(f, "Gitea"),
+            Flavor::Forgejo => write!(f, "Forgejo"),
+        }
+    }
+}
+
+/// The api features which depend on the version of the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Changing several files in one commit with `POST /contents`
+    BatchContents,
+    /// Creating branches with `POST /branches`
+    CreateBranch,
+}
+
+impl Feature {
+    /// Returns the first Gitea release which supports the feature.
+    pub fn since(self) -> (u64, u64, u64) {
+        match self {
+            Feature::BatchContents => (1, 20, 0),
+            Feature::CreateBranch => (1, 13, 0),
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::BatchContents => write!(f, "changing several files in one commit"),
+            Feature::CreateBranch => write!(f, "creating branches"),
+        }
+    }
+}
+
+/// The detected software and version of the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerVersion {
+    pub flavor: Flavor,
+    pub version: String,
+    /// The Gitea release the api corresponds to, `None` if the version is unknown
+    pub gitea: Option<(u64, u64, u64)>,
+}
+
+impl ServerVersion {
+    /// This function parses the `version` reported by `/version`. Gitea reports releases
+    /// like `1.21.4` or `1.22.0+dev-12-g1f0a`. Forgejo reports its own release followed
+    /// by the Gitea release, like `7.0.0+gitea-1.22.0`, older ones a Gitea release with
+    /// a numeric suffix like `1.21.11-1`.
+    pub fn parse(version: &str) -> ServerVersion {
+        let triple = |v: &str| {
+            let mut parts = v.split('.').map(|p| {
+                let digits: String = p.chars().take_while(char::is_ascii_digit).collect();
+                digits.parse::<u64>().ok()
+            });
+            Some((
+                parts.next()??,
+                parts.next()??,
+                parts.next().flatten().unwrap_or(0),
+            ))
+        };
+        let (flavor, gitea) = match version.split_once("+gitea-") {
+            Some((_, gitea)) => (Flavor::Forgejo, triple(gitea)),
+            None => {
+                let forgejo = version
+                    .split('+')
+                    .next()
+                    .and_then(|v| v.split_once('-'))
+                    .is_some_and(|(_, suffix)| suffix.chars().all(|c| c.is_ascii_digit()));
+                match forgejo {
+                    true => (Flavor::Forgejo, triple(version)),
+                    false => (Flavor::Gitea, triple(version)),
+                }
+            }
+        };
+        ServerVersion {
+            flavor,
+            version: version.to_owned(),
+            gitea,
+        }
+    }
+
+    /// Returns true if the server supports the `feature`, a server with an unknown
+    /// version is assumed to support everything.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.gitea.is_none_or(|v| v >= feature.since())
+    }
+
+    /// Returns an `ApiError::Unsupported` if the server doesn't support the `feature`.
+    pub fn require(&self, feature: Feature) -> ApiResult<()> {
+        match self.supports(feature) {
+            true => Ok(()),
+            false => {
+                let (major, minor, patch) = feature.since();
+                Err(ApiError::Unsupported(format!(
+                    "{} needs Gitea {}.{}.{} or a Forgejo release based on it, the server runs {} {}",
+                    feature, major, minor, patch, self.flavor, self.version
+                )))
+            }
+        }
     }
 }
 
@@ -225,23 +442,234 @@ impl Display for Repository {
     }
 }
 
+/// The organization which owns a repository.
+#[derive(Deserialize, Debug, Default)]
+pub struct Organization {
+    pub id: i64,
+    pub username: String,
+    #[serde(default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub website: String,
+    #[serde(default)]
+    pub location: String,
+    #[serde(default)]
+    pub visibility: String,
+}
+
+impl Display for Organization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tw = TabWriter::new(vec![]);
+
+        write!(
+            &mut tw,
+            "Organization {} {{
+\tName\t= {}
+\tFull name\t= {}
+\tDescription\t= {}
+\tWebsite\t= {}
+\tLocation\t= {}
+\tVisibility\t= {}
+}}",
+            self.id,
+            self.username,
+            self.full_name,
+            self.description,
+            self.website,
+            self.location,
+            self.visibility
+        )
+        .unwrap();
+        tw.flush().unwrap();
+        let written = String::from_utf8(tw.into_inner().unwrap()).unwrap();
+        write!(f, "{}", written)
+    }
+}
+
+/// The author or committer of a commit.
+#[derive(Deserialize, Debug, Default)]
+pub struct CommitUser {
+    pub name: String,
+    pub email: String,
+    pub date: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct CommitDetails {
+    pub message: String,
+    pub author: CommitUser,
+    pub committer: CommitUser,
+}
+
+/// A single commit as returned by the commits API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Commit {
+    pub sha: String,
+    pub commit: CommitDetails,
+}
+
+/// The commit a tag points to.
+#[derive(Deserialize, Debug, Default)]
+pub struct TagCommit {
+    pub sha: String,
+}
+
+/// A single tag as returned by the tags API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Tag {
+    pub name: String,
+    pub commit: TagCommit,
+}
+
+/// The latest commit of a branch.
+#[derive(Deserialize, Debug, Default)]
+pub struct BranchCommit {
+    pub id: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// A single branch as returned by the branches API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Branch {
+    pub name: String,
+    pub commit: BranchCommit,
+    #[serde(default)]
+    pub protected: bool,
+}
+
+/// A pull request as returned by the pulls API.
+#[derive(Deserialize, Debug, Default)]
+pub struct PullRequest {
+    pub number: i64,
+    #[serde(default)]
+    pub title: String,
+    pub html_url: String,
+}
+
+/// A file attached to a release.
+#[derive(Deserialize, Debug, Default)]
+pub struct ReleaseAsset {
+    pub id: i64,
+    pub name: String,
+    pub size: u64,
+    pub browser_download_url: String,
+}
+
+/// A single release as returned by the releases API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Release {
+    pub id: i64,
+    pub tag_name: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+/// A git blob as returned by the blobs API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Blob {
+    pub sha: String,
+    pub size: u64,
+    /// The base64 encoded content
+    pub content: String,
+}
+
+/// A single entry of a git tree.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct TreeEntry {
+    pub path: String,
+    pub mode: String,
+    /// Either `blob`, `tree` or `commit` for submodules
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    pub sha: String,
+    /// The size of a blob in bytes, if the provider tells it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl From<TreeEntry> for ContentEntry {
+    fn from(entry: TreeEntry) -> ContentEntry {
+        let content_type = match (entry.entry_type.as_str(), entry.mode.as_str()) {
+            ("tree", _) => ContentType::Dir,
+            ("commit", _) => ContentType::Submodule,
+            (_, "120000") => ContentType::Symlink,
+            _ => ContentType::File,
+        };
+        let size = entry.size.filter(|_| entry.entry_type == "blob");
+        ContentEntry {
+            name: entry.path.rsplit('/').next().unwrap_or_default().to_owned(),
+            path: entry.path,
+            content_type,
+            sha: Some(entry.sha),
+            size,
+            ..ContentEntry::default()
+        }
+    }
+}
+
+/// A page of a git tree as returned by the trees API.
+#[derive(Deserialize, Debug, Default)]
+pub struct Tree {
+    pub sha: String,
+    #[serde(default)]
+    pub tree: Vec<TreeEntry>,
+    /// True if more entries are available on the next page
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// The commit which is created by changing a file with the contents API.
+#[derive(Deserialize, Debug, Default)]
+pub struct FileCommit {
+    pub sha: String,
+}
+
+/// The response of the contents API if several files are changed in one commit.
+#[derive(Deserialize, Debug, Default)]
+pub struct FilesResponse {
+    #[serde(default)]
+    pub files: Vec<Option<ContentEntry>>,
+    pub commit: FileCommit,
+}
+
+/// The response of the contents API if a file is created, updated or deleted.
+#[derive(Deserialize, Debug, Default)]
+pub struct FileResponse {
+    /// The changed file, `None` if it was deleted
+    pub content: Option<ContentEntry>,
+    pub commit: FileCommit,
+}
+
 /// The content type describes which type of "file"
 /// is found by gitea for a specific path or listing.
 /// If the content type is unknown the implementation returns
 /// a file as default type.
-#[derive(Debug, PartialEq)]
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(from = "String")]
 pub enum ContentType {
+    #[default]
     File,
     Dir,
     Symlink,
     Submodule,
 }
 
-impl ContentType {
+impl From<String> for ContentType {
     /// Create a new content type from a string.
     /// Returns `ContentType::File` if no valid content type is found.
-    fn new(stype: &str) -> ContentType {
-        match stype {
+    fn from(stype: String) -> ContentType {
+        match stype.as_str() {
             "file" => ContentType::File,
             "dir" => ContentType::Dir,
             "symlink" => ContentType::Symlink,
@@ -251,12 +679,6 @@ impl ContentType {
     }
 }
 
-impl Default for ContentType {
-    fn default() -> Self {
-        ContentType::File
-    }
-}
-
 impl Display for ContentType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -268,13 +690,22 @@ impl Display for ContentType {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Deserialize, Debug, Default)]
 pub struct ContentEntry {
+    #[serde(default)]
     pub download_url: Option<String>,
     pub name: String,
     pub path: String,
+    #[serde(rename = "type")]
     pub content_type: ContentType,
+    #[serde(default)]
     pub sha: Option<String>,
+    /// The size of a file in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// The base64 encoded content, only provided if a single file is requested.
+    #[serde(default)]
+    pub content: Option<String>,
 }
 
 impl Display for ContentEntry {
@@ -294,36 +725,31 @@ impl Display for ContentEntry {
 }
 
 impl ContentEntry {
-    pub fn new(entry: Value) -> ApiResult<ContentEntry> {
-        if entry.is_object() {
-            // println!("{}", entry["content"]);
-            Ok(ContentEntry {
-                download_url: entry["download_url"].as_str().map(String::from),
-                sha: entry["sha"].as_str().map(String::from),
-                name: entry["name"]
-                    .as_str()
-                    .ok_or(ApiError::InvalidContentResponse(
-                        "File name missing.".into(),
-                    ))?
-                    .to_string(),
-                path: entry["path"]
-                    .as_str()
-                    .ok_or(ApiError::InvalidContentResponse(
-                        "File path missing.".into(),
-                    ))?
-                    .to_string(),
-                content_type: ContentType::new(entry["type"].as_str().ok_or(
-                    ApiError::InvalidContentResponse("Content type missing.".into()),
-                )?),
-            })
-        } else {
-            Err(ApiError::InvalidContentResponse(
-                "A valid content object is needed.".into(),
-            ))
+    /// This function decodes the base64 `content` of a file if it is provided.
+    pub fn decode_content(&self) -> ApiResult<Option<Vec<u8>>> {
+        match &self.content {
+            Some(c) => decode_base64(c).map(Some).map_err(|e| {
+                ApiError::InvalidContentResponse(format!("Invalid content of {}: {}", self.path, e))
+            }),
+            None => Ok(None),
         }
     }
 }
 
+/// Gitea wraps base64 content into several lines which are joined before decoding.
+pub fn decode_base64(content: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    let content: String = content.split_whitespace().collect();
+    base64::decode(content)
+}
+
+/// The contents API returns a single object for a file and an array for a folder.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Contents {
+    Folder(Vec<ContentEntry>),
+    File(ContentEntry),
+}
+
 /// A handy struct definition for the list of content entries.
 #[derive(Debug)]
 pub struct ContentsResponse {
@@ -333,49 +759,48 @@ pub struct ContentsResponse {
 impl Display for ContentsResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut tw = TabWriter::new(vec![]).padding(15);
-        write!(&mut tw, "Name\tPath\n").unwrap();
+        writeln!(&mut tw, "Name\tPath").unwrap();
 
         for entry in &self.content {
-            write!(&mut tw, "{}\t{}\n", entry.name, entry.path).unwrap();
+            writeln!(&mut tw, "{}\t{}", entry.name, entry.path).unwrap();
         }
 
         tw.flush().unwrap();
         let written = String::from_utf8(tw.into_inner().unwrap()).unwrap();
-        write!(f, "{}", written)
+        // Color the aligned lines, the first one is the header
+        for (i, line) in written.lines().enumerate() {
+            match i.checked_sub(1).and_then(|i| self.content.get(i)) {
+                Some(e) if e.content_type == ContentType::Dir => {
+                    writeln!(f, "{}", color::paint(Color::Cyan, line))?
+                }
+                _ => writeln!(f, "{}", line)?,
+            }
+        }
+        Ok(())
     }
 }
 
 impl ContentsResponse {
     /// This function creates a new list of content entries called `ContentsResponse` by
-    /// Gitea. It returns an error if the `Value` is not a `Value::Array`, `Value::Object` or
+    /// Gitea. It returns an error if the `Value` is neither an array nor an object or
     /// the json objects are not valid content entries.
     /// If only one content object (e.g. a file) is provided it is converted to an array with only
     /// one entry.
     pub fn new(content: Value, type_filter: Option<ContentType>) -> ApiResult<ContentsResponse> {
-        match content {
-            Value::Array(entries) => {
-                let mut c = vec![];
-                for e in entries {
-                    let entry = ContentEntry::new(e)?;
-                    if let Some(ref t) = type_filter {
-                        // add only one type of files
-                        if entry.content_type == *t {
-                            c.push(entry);
-                        }
-                    } else {
-                        // Add all file types
-                        c.push(entry);
-                    }
-                }
-                Ok(ContentsResponse { content: c })
+        let content = match serde_json::from_value(content) {
+            Ok(Contents::Folder(entries)) => entries
+                .into_iter()
+                .filter(|e| type_filter.as_ref().is_none_or(|t| e.content_type == *t))
+                .collect(),
+            Ok(Contents::File(entry)) => vec![entry],
+            Err(e) => {
+                return Err(ApiError::InvalidContentResponse(format!(
+                    "Only content objects or arrays of them are valid content responses: {}",
+                    e
+                )))
             }
-            Value::Object(_) => Ok(ContentsResponse {
-                content: vec![ContentEntry::new(content)?],
-            }),
-            _ => Err(ApiError::InvalidContentResponse(
-                "Only json arrays are valid content responses".into(),
-            )),
-        }
+        };
+        Ok(ContentsResponse { content })
     }
 }
 
@@ -383,7 +808,152 @@ impl ContentsResponse {
 mod test {
     use serde_json::Value;
 
-    use super::{ContentType, ContentsResponse};
+    use super::{
+        Branch, ContentEntry, ContentType, ContentsResponse, ErrorResponse, Feature, FileResponse,
+        FilesResponse, Flavor, Release, ServerVersion, Tag, Tree,
+    };
+
+    #[test]
+    fn test_server_version() {
+        let gitea = ServerVersion::parse("1.19.4");
+        assert_eq!(gitea.flavor, Flavor::Gitea);
+        assert_eq!(gitea.gitea, Some((1, 19, 4)));
+        assert!(!gitea.supports(Feature::BatchContents));
+        assert!(gitea.supports(Feature::CreateBranch));
+        let e = gitea
+            .require(Feature::BatchContents)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("needs Gitea 1.20.0"), "{}", e);
+        assert!(e.contains("the server runs Gitea 1.19.4"), "{}", e);
+
+        let dev = ServerVersion::parse("1.22.0+dev-12-g1f0a");
+        assert_eq!((dev.flavor, dev.gitea), (Flavor::Gitea, Some((1, 22, 0))));
+        let rc = ServerVersion::parse("1.20.0-rc1");
+        assert_eq!((rc.flavor, rc.gitea), (Flavor::Gitea, Some((1, 20, 0))));
+
+        let forgejo = ServerVersion::parse("7.0.0+gitea-1.22.0");
+        assert_eq!(forgejo.flavor, Flavor::Forgejo);
+        assert_eq!(forgejo.gitea, Some((1, 22, 0)));
+        assert!(forgejo.supports(Feature::BatchContents));
+        let old = ServerVersion::parse("1.19.3-0");
+        assert_eq!((old.flavor, old.gitea), (Flavor::Forgejo, Some((1, 19, 3))));
+        assert!(!old.supports(Feature::BatchContents));
+
+        let unknown = ServerVersion::parse("development");
+        assert!(unknown.gitea.is_none() && unknown.supports(Feature::BatchContents));
+    }
+
+    #[test]
+    fn test_error_response() {
+        let e = ErrorResponse::parse(
+            r#"{"message": "user cannot commit to repo [user: ops]", "url": "https://gitea/api/swagger"}"#,
+        )
+        .unwrap();
+        assert!(e.is_protected_branch());
+        let e = ErrorResponse::parse(r#"{"message": "branch main is protected"}"#).unwrap();
+        assert!(e.is_protected_branch());
+        let e = ErrorResponse::parse(r#"{"message": "repository does not exist"}"#).unwrap();
+        assert!(!e.is_protected_branch() && !e.is_conflict());
+        assert!(e.expected_sha().is_none());
+        let e = ErrorResponse::parse(
+            r#"{"message": "sha does not match [given: 1f0a, expected: 2e9b]"}"#,
+        )
+        .unwrap();
+        assert!(e.is_conflict() && !e.is_protected_branch());
+        assert_eq!(e.expected_sha().unwrap(), "2e9b");
+        assert!(ErrorResponse::parse(r#"{"url": "https://gitea"}"#).is_none());
+        assert!(ErrorResponse::parse("Not Found").is_none());
+    }
+
+    #[test]
+    fn test_deserialize_tag() {
+        let tag: Tag = serde_json::from_str(
+            r#"{"name": "v1.2.0", "commit": {"sha": "7d2a1f", "url": "https://gitea/commit"},
+                "zipball_url": "https://gitea/v1.2.0.zip"}"#,
+        )
+        .unwrap();
+        assert_eq!(tag.name, "v1.2.0");
+        assert_eq!(tag.commit.sha, "7d2a1f");
+    }
+
+    #[test]
+    fn test_deserialize_typed_responses() {
+        let res: FileResponse = serde_json::from_str(
+            r#"{"content": {"name": "a", "path": "web/a", "sha": "1f", "type": "file"},
+                "commit": {"sha": "9c0e", "message": "add a"}}"#,
+        )
+        .unwrap();
+        assert_eq!(res.content.unwrap().sha.unwrap(), "1f");
+        assert_eq!(res.commit.sha, "9c0e");
+        let res: FileResponse =
+            serde_json::from_str(r#"{"content": null, "commit": {"sha": "9c0f"}}"#).unwrap();
+        assert!(res.content.is_none());
+        let res: FilesResponse = serde_json::from_str(
+            r#"{"files": [{"name": "a", "path": "web/a", "type": "file"}, null],
+                "commit": {"sha": "9c10"}}"#,
+        )
+        .unwrap();
+        assert_eq!(res.files.len(), 2);
+        assert_eq!(res.commit.sha, "9c10");
+
+        let branch: Branch = serde_json::from_str(
+            r#"{"name": "main", "commit": {"id": "7d2a1f", "message": "init"}, "protected": true}"#,
+        )
+        .unwrap();
+        assert!(branch.protected);
+        assert_eq!(branch.commit.id, "7d2a1f");
+
+        let release: Release = serde_json::from_str(
+            r#"{"id": 3, "tag_name": "v1.0.0", "prerelease": true, "assets": [{"id": 1,
+                "name": "web.tar.gz", "size": 12, "browser_download_url": "https://gitea/a"}]}"#,
+        )
+        .unwrap();
+        assert!(release.prerelease && !release.draft);
+        assert_eq!(release.assets[0].name, "web.tar.gz");
+    }
+
+    #[test]
+    fn test_deserialize_tree() {
+        let tree: Tree = serde_json::from_str(
+            r#"{"sha": "7d2a1f", "truncated": false, "tree": [
+                {"path": "web", "mode": "040000", "type": "tree", "sha": "a1"},
+                {"path": "web/etc/nginx.conf", "mode": "100644", "type": "blob", "sha": "b2"},
+                {"path": "web/etc/link", "mode": "120000", "type": "blob", "sha": "c3"},
+                {"path": "web/vendor", "mode": "160000", "type": "commit", "sha": "d4"}]}"#,
+        )
+        .unwrap();
+        let entries: Vec<ContentEntry> = tree.tree.into_iter().map(ContentEntry::from).collect();
+        assert_eq!(entries[0].content_type, ContentType::Dir);
+        assert_eq!(entries[1].name, "nginx.conf");
+        assert_eq!(entries[1].path, "web/etc/nginx.conf");
+        assert_eq!(entries[1].sha.as_deref(), Some("b2"));
+        assert_eq!(entries[1].content_type, ContentType::File);
+        assert_eq!(entries[2].content_type, ContentType::Symlink);
+        assert_eq!(entries[3].content_type, ContentType::Submodule);
+    }
+
+    #[test]
+    fn test_decode_binary_content() {
+        let binary: Vec<u8> = vec![0xff, 0xfe, 0x00, 0x80, 0xc3, 0x28, b'\n'];
+        let encoded = base64::encode(&binary);
+        let (first, second) = encoded.split_at(4);
+        let v: Value = serde_json::json!({
+            "name": "cert.der",
+            "path": "web/etc/cert.der",
+            "type": "file",
+            "content": format!("{}\n{}", first, second),
+        });
+        let entry: ContentEntry = serde_json::from_value(v).unwrap();
+        assert_eq!(entry.decode_content().unwrap().unwrap(), binary);
+
+        let v: Value = serde_json::json!({"name": "a", "path": "a", "type": "file"});
+        assert!(serde_json::from_value::<ContentEntry>(v)
+            .unwrap()
+            .decode_content()
+            .unwrap()
+            .is_none());
+    }
 
     #[test]
     fn test_content_response_new() {
@@ -13,25 +13,93 @@
 ///
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
+pub mod cache;
 pub mod gitea_api;
+#[cfg(feature = "async")]
+pub mod pool;
 
 use base64::encode;
-use std::io::Write;
+use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
+use std::{
+    io::{Read, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use ureq::{Agent, AgentBuilder};
 
-use gitea_api::{ApiError, ApiResult, ApiToken, ContentsResponse, Repository, Version};
+use gitea_api::{
+    ApiError, ApiResult, ApiToken, Blob, Branch, Commit, ContentsResponse, ErrorResponse, Feature,
+    FileResponse, FilesResponse, Organization, PullRequest, Release, Repository, ServerVersion,
+    Tag, Tree, User, Version,
+};
 
-use self::gitea_api::{ContentEntry, ContentType};
+use self::{
+    cache::{Cache, CachedResponse},
+    gitea_api::{ContentEntry, ContentType, TreeEntry},
+};
+use crate::{http::HttpConfig, oauth::Session};
 
 const API_PART: &str = "/api/v1";
+/// The number of items requested per page from listing endpoints.
+const PAGE_SIZE: usize = 50;
+/// The number of times a request is repeated after the server's rate limit rejected it.
+const MAX_RETRIES: usize = 5;
+
+/// The options of a repository created by `create_repository`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RepositoryOptions {
+    /// Initialize the repository with a README, an empty repository refuses new files via API
+    pub readme: bool,
+    /// Only allow the owner of the token to push to the default branch
+    pub protect_branch: bool,
+}
+
+/// A single file change of the commit created by `change_files`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    /// The path of the file in the remote repository
+    pub path: String,
+    /// The new content or `None` to delete the file
+    pub content: Option<Vec<u8>>,
+    /// The blob sha of the remote file or `None` if the file is created
+    pub sha: Option<String>,
+}
+
+impl FileChange {
+    /// Returns the operation of the change for the contents API.
+    fn operation(&self) -> &'static str {
+        match (&self.content, &self.sha) {
+            (None, _) => "delete",
+            (Some(_), None) => "create",
+            (Some(_), Some(_)) => "update",
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct GiteaClient {
     pub url: String,
     pub api_token: String,
+    /// The user of a basic authentication, the `api_token` is the password of the user then.
+    pub username: Option<String>,
     pub repository: String,
     pub owner: String,
+    /// The branch used for all content calls, the default branch if `None`.
+    pub branch: Option<String>,
+    /// Wait until the rate limit of the server is reset instead of failing.
+    pub wait_on_rate_limit: bool,
+    /// The tag or commit which is read instead of the branch, e.g. to pull an old revision.
+    pub revision: Option<String>,
     client: Agent,
+    /// The default branch and the trees seen by this or earlier runs.
+    cache: Mutex<Cache>,
+    /// The version of the server, requested once by `get_server_version`.
+    server: OnceLock<ServerVersion>,
+    /// The OAuth2 tokens of `rustea login` which replace the `api_token`.
+    oauth: Mutex<Option<Session>>,
 }
 
 impl Default for GiteaClient {
@@ -39,26 +107,52 @@ impl Default for GiteaClient {
         GiteaClient {
             url: String::with_capacity(0),
             api_token: String::with_capacity(0),
+            username: None,
             repository: String::with_capacity(0),
             owner: String::with_capacity(0),
+            branch: None,
+            wait_on_rate_limit: true,
+            revision: None,
             client: ureq::agent(),
+            cache: Mutex::new(Cache::default()),
+            server: OnceLock::new(),
+            oauth: Mutex::new(None),
         }
     }
 }
 
 impl GiteaClient {
-    /// Construct a new http client.
+    /// Construct a new http client with the timeouts, proxy and TLS settings of `http`.
     /// Since this is a cli tool the client is blocking
     /// and calls to the API are made order.
-    fn create_api_client(_api_token: &str) -> Agent {
-        AgentBuilder::new().user_agent("rustea").build()
+    pub(crate) fn create_api_client(http: &HttpConfig) -> ApiResult<Agent> {
+        let mut builder = AgentBuilder::new().user_agent("rustea");
+        if let Some(timeout) = http.connect_timeout() {
+            builder = builder.timeout_connect(timeout);
+        }
+        if let Some(timeout) = http.read_timeout() {
+            builder = builder.timeout_read(timeout);
+        }
+        if let Some(proxy) = &http.proxy {
+            builder = builder.proxy(ureq::Proxy::new(proxy)?);
+        }
+        if let Some(tls) = http.tls_config()? {
+            builder = builder.tls_config(tls);
+        }
+        Ok(builder.build())
+    }
+
+    /// Use the timeouts, proxy and TLS settings of `http` for all requests.
+    pub fn with_http(mut self, http: &HttpConfig) -> ApiResult<Self> {
+        self.client = GiteaClient::create_api_client(http)?;
+        Ok(self)
     }
 
     /// This functions requests a new Gitea API token if no one is provided.
     /// It asks the user for a token name, its username and password which is
     /// used for plain authentication against the Gitea API.
     fn create_new_api_token(url: &str, token_name: Option<&str>) -> ApiResult<ApiToken> {
-        println!("Requesting a new api token.");
+        info!("Requesting a new api token.");
         let username = read_from_cli("Username");
         let password = rpassword::read_password_from_tty(Some("Password: ")).unwrap();
         let auth = base64::encode(format!("{}:{}", username, password).as_bytes());
@@ -89,57 +183,466 @@ impl GiteaClient {
             Some(token) => Ok(GiteaClient {
                 url: url.into(),
                 api_token: token.to_string(),
+                username: None,
                 repository: repository.into(),
                 owner: owner.into(),
-                client: GiteaClient::create_api_client(token),
+                branch: None,
+                wait_on_rate_limit: true,
+                revision: None,
+                client: GiteaClient::create_api_client(&HttpConfig::default())?,
+                cache: Mutex::new(Cache::default()),
+                server: OnceLock::new(),
+                oauth: Mutex::new(None),
             }),
             // Create a new api token and client configuration
             None => {
-                println!(
-                    "Requesting new topen with name {}",
+                info!(
+                    "Requesting new token with name {}",
                     token_name.unwrap_or("rustea-devops")
                 );
                 let token = GiteaClient::create_new_api_token(url, token_name)?;
-                println!("{}", token);
+                info!("{}", token);
 
                 let client = GiteaClient {
                     url: url.into(),
                     api_token: token.sha1.clone(),
+                    username: None,
                     repository: repository.into(),
                     owner: owner.into(),
-                    client: GiteaClient::create_api_client(&token.sha1),
+                    branch: None,
+                    wait_on_rate_limit: true,
+                    revision: None,
+                    client: GiteaClient::create_api_client(&HttpConfig::default())?,
+                    cache: Mutex::new(Cache::default()),
+                    server: OnceLock::new(),
+                    oauth: Mutex::new(None),
                 };
-                println!("Testing connection to gitea...");
-                let gitea_version = client.get_gitea_version()?;
-                let repository = client.get_repository_information()?;
-                println!("{}\n{}", gitea_version, repository);
+                info!("Testing connection to gitea...");
+                info!("{}", client.get_gitea_version()?);
                 Ok(client)
             }
         }
     }
 
+    /// Keep the default branch and the seen trees in the cache file at `path`
+    /// between runs, e.g. `~/.cache/rustea/<host>-<owner>-<repository>.json`.
+    pub fn with_cache(self, path: Option<&Path>) -> Self {
+        let cache = path.map(Cache::load).unwrap_or_default();
+        GiteaClient {
+            cache: Mutex::new(cache),
+            ..self
+        }
+    }
+
+    /// Use the `branch` instead of the default branch for all content calls.
+    pub fn with_branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    /// Read the `revision` instead of the branch for all content calls.
+    pub fn with_revision(mut self, revision: Option<String>) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    /// Authenticate with the `username` and the `api_token` as password instead of the token,
+    /// e.g. on instances where tokens can't be created.
+    pub fn with_username(mut self, username: Option<String>) -> Self {
+        self.username = username;
+        self
+    }
+
+    /// Authenticate with the OAuth2 tokens of the `session`, which are refreshed if expired.
+    pub fn with_oauth(self, session: Option<Session>) -> Self {
+        GiteaClient {
+            oauth: Mutex::new(session),
+            ..self
+        }
+    }
+
+    /// Returns the value of the `Authorization` header of all requests.
+    fn authorization(&self) -> String {
+        let mut oauth = self.oauth.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(session) = oauth.as_mut() {
+            return format!("Bearer {}", session.access_token(&self.client));
+        }
+        match &self.username {
+            Some(user) => format!(
+                "Basic {}",
+                encode(format!("{}:{}", user, self.api_token).as_bytes())
+            ),
+            None => format!("token {}", self.api_token),
+        }
+    }
+
+    /// Returns the revision or branch which is read, the default branch if `None`.
+    fn read_ref(&self) -> Option<&String> {
+        self.revision.as_ref().or(self.branch.as_ref())
+    }
+
+    /// Fail instead of waiting if the rate limit of the server is reached.
+    pub fn with_rate_limit_wait(mut self, wait: bool) -> Self {
+        self.wait_on_rate_limit = wait;
+        self
+    }
+
+    /// This function sends the `request` with the json `body` if provided.
+    ///
+    /// If the server rejects the request with 429 it waits until the rate limit is reset
+    /// and retries. A response with `X-RateLimit-Remaining: 0` delays the next request.
+    /// Without `wait_on_rate_limit` the rejection is returned as error.
+    fn send(
+        &self,
+        request: ureq::Request,
+        body: Option<serde_json::Value>,
+    ) -> ApiResult<ureq::Response> {
+        let mut retries = 0;
+        loop {
+            debug!("{} {}", request.method(), request.url());
+            let res = match &body {
+                Some(b) => request.clone().send_json(b.clone()),
+                None => request.clone().call(),
+            };
+            match &res {
+                Ok(r) | Err(ureq::Error::Status(_, r)) => debug!(
+                    "{} {} -> {} {}",
+                    request.method(),
+                    r.get_url(),
+                    r.status(),
+                    r.status_text()
+                ),
+                Err(e) => debug!("{} {} failed: {}", request.method(), request.url(), e),
+            }
+            let rejected = matches!(res, Err(ureq::Error::Status(429, _)));
+            let delay = match &res {
+                Ok(r) | Err(ureq::Error::Status(_, r)) => rate_limit_delay(
+                    rejected,
+                    r.header("X-RateLimit-Remaining"),
+                    r.header("X-RateLimit-Reset"),
+                    r.header("Retry-After"),
+                    now(),
+                ),
+                Err(_) => None,
+            };
+            match delay {
+                Some(delay) if self.wait_on_rate_limit && retries < MAX_RETRIES => {
+                    warn!(
+                        "Rate limit of the server reached, waiting {}s",
+                        delay.as_secs()
+                    );
+                    thread::sleep(delay);
+                    if !rejected {
+                        return Ok(res?);
+                    }
+                    retries += 1;
+                }
+                _ => {
+                    if let Err(ureq::Error::Status(status, r)) = res {
+                        let (method, url) = (request.method(), request.url());
+                        return Err(status_error(status, r, method, url, &self.owner, delay));
+                    }
+                    return Ok(res?);
+                }
+            }
+        }
+    }
+
+    /// Add the `ref` query parameter to a request if a revision or branch is set.
+    fn with_ref(&self, request: ureq::Request) -> ureq::Request {
+        match self.read_ref() {
+            Some(b) => request.query("ref", b),
+            None => request,
+        }
+    }
+
+    /// Add the target `branch` to the json `body` of a request if a branch is set.
+    fn add_branch(&self, body: &mut serde_json::Value) {
+        if let (Some(b), Some(obj)) = (&self.branch, body.as_object_mut()) {
+            obj.insert("branch".into(), b.as_str().into());
+        }
+    }
+
+    /// This function requests every page of a listing endpoint until `max` items are read
+    /// and returns them as one json array. The `request` is called for each page which
+    /// is selected with the `page` and `limit` parameters. A response which is no array
+    /// is returned as is.
+    /// With `conditional` the pages are requested with `get_conditional`.
+    fn get_pages<F>(
+        &self,
+        request: F,
+        max: usize,
+        conditional: bool,
+    ) -> ApiResult<serde_json::Value>
+    where
+        F: Fn() -> ureq::Request,
+    {
+        let mut items = vec![];
+        for page in 1.. {
+            let limit = PAGE_SIZE.min(max - items.len());
+            if limit == 0 {
+                break;
+            }
+            let request = request()
+                .query("page", &page.to_string())
+                .query("limit", &limit.to_string())
+                .set("Authorization", &self.authorization());
+            let (body, link) = match conditional {
+                true => {
+                    let res = self.get_conditional(request)?;
+                    (serde_json::from_str(&res.body)?, res.link)
+                }
+                false => {
+                    let res = self.send(request, None)?;
+                    let link = res.header("Link").map(str::to_owned);
+                    (res.into_json().map_err(ApiError::Io)?, link)
+                }
+            };
+            match body {
+                serde_json::Value::Array(mut page_items) => {
+                    let count = page_items.len();
+                    items.append(&mut page_items);
+                    if items.len() >= max || !has_next_page(link.as_deref(), count, limit) {
+                        break;
+                    }
+                }
+                other => return Ok(other),
+            }
+        }
+        Ok(serde_json::Value::Array(items))
+    }
+
+    /// Returns the api path of the remote repository followed by `path`,
+    /// e.g. `/repos/{owner}/{repository}/branches` for `/branches`.
+    pub fn repo_path(&self, path: &str) -> String {
+        format!("/repos/{}/{}{}", self.owner, self.repository, path)
+    }
+
+    /// This function requests the api endpoint `path` with the `method` and the
+    /// json `body` and deserializes the response into `T`.
+    pub fn send_json<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> ApiResult<T> {
+        self.send(
+            self.client
+                .request(method, &format!("{}{}{}", self.url, API_PART, path))
+                .set("Authorization", &self.authorization()),
+            body,
+        )?
+        .into_json()
+        .map_err(ApiError::Io)
+    }
+
+    /// This function requests the api endpoint `path` and deserializes the response into `T`.
+    /// A response seen before is only transferred again if it changed.
+    pub fn get_json<T: DeserializeOwned>(&self, path: &str) -> ApiResult<T> {
+        let res = self.get_conditional(
+            self.client
+                .get(&format!("{}{}{}", self.url, API_PART, path))
+                .set("Authorization", &self.authorization()),
+        )?;
+        Ok(serde_json::from_str(&res.body)?)
+    }
+
+    /// This function sends the `GET` request with the ETag of the cached response
+    /// as `If-None-Match` and returns the cached body if the server answers with
+    /// `304 Not Modified`. A new response with an ETag is cached.
+    fn get_conditional(&self, request: ureq::Request) -> ApiResult<CachedResponse> {
+        let url = request.url().to_owned();
+        let cached = self.cache().response(&url).cloned();
+        let request = match &cached {
+            Some(c) => request.set("If-None-Match", &c.etag),
+            None => request,
+        };
+        let res = self.send(request, None)?;
+        if let (304, Some(cached)) = (res.status(), cached) {
+            debug!("Using the cached response of {}", url);
+            return Ok(cached);
+        }
+        let etag = res.header("ETag").map(str::to_owned);
+        let link = res.header("Link").map(str::to_owned);
+        let response = CachedResponse {
+            etag: etag.clone().unwrap_or_default(),
+            body: res.into_string()?,
+            link,
+        };
+        if etag.is_some() {
+            self.cache().set_response(&url, response.clone());
+        }
+        Ok(response)
+    }
+
+    /// Like `get_json` but returns `None` if the endpoint `path` doesn't exist.
+    pub fn find_json<T: DeserializeOwned>(&self, path: &str) -> ApiResult<Option<T>> {
+        match self.get_json(path) {
+            Ok(res) => Ok(Some(res)),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// This function requests every item of the listing endpoint `path`.
+    pub fn get_list<T: DeserializeOwned>(&self, path: &str) -> ApiResult<Vec<T>> {
+        let url = format!("{}{}{}", self.url, API_PART, path);
+        let items = self.get_pages(|| self.client.get(&url), usize::MAX, true)?;
+        Ok(serde_json::from_value(items)?)
+    }
+
     /// Returns the Gitea version of the remote instance used by rustea.
     pub fn get_gitea_version(&self) -> ApiResult<Version> {
-        // todo!()
-        self.client
-            .get(&format!("{}{}/version", self.url, API_PART))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .call()?
-            .into_json()
-            .map_err(ApiError::Io)
+        self.get_json("/version")
+    }
+
+    /// Returns the flavor and version of the server, which are requested only once.
+    pub fn get_server_version(&self) -> ApiResult<ServerVersion> {
+        if let Some(server) = self.server.get() {
+            return Ok(server.clone());
+        }
+        let server = self.get_gitea_version()?.server();
+        debug!("The server runs {} {}", server.flavor, server.version);
+        Ok(self.server.get_or_init(|| server).clone())
+    }
+
+    /// Returns an `ApiError::Unsupported` if the server is too old for the `feature`.
+    pub fn require(&self, feature: Feature) -> ApiResult<()> {
+        self.get_server_version()?.require(feature)
     }
 
     /// Returns informations about the remote repository used by rustea.
     pub fn get_repository_information(&self) -> ApiResult<Repository> {
-        self.client
-            .get(&format!(
-                "{}{}/repos/{}/{}",
-                self.url, API_PART, self.owner, self.repository
-            ))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .call()?
-            .into_json()
-            .map_err(ApiError::Io)
+        self.get_json(&self.repo_path(""))
+    }
+
+    /// Returns the branches of the remote repository.
+    pub fn get_branches(&self) -> ApiResult<Vec<Branch>> {
+        self.get_list(&self.repo_path("/branches"))
+    }
+
+    /// This function creates the branch `name` at the tip of the branch `from`.
+    pub fn create_branch(&self, name: &str, from: &str) -> ApiResult<Branch> {
+        self.require(Feature::CreateBranch)?;
+        let body = ureq::json!({"new_branch_name": name, "old_branch_name": from});
+        self.send_json("POST", &self.repo_path("/branches"), Some(body))
+    }
+
+    /// This function deletes the branch `name`.
+    pub fn delete_branch(&self, name: &str) -> ApiResult<()> {
+        let path = self.repo_path(&format!("/branches/{}", name));
+        self.send(
+            self.client
+                .delete(&format!("{}{}{}", self.url, API_PART, path))
+                .set("Authorization", &self.authorization()),
+            None,
+        )
+        .map(|_| ())
+    }
+
+    /// This function opens a pull request which merges the branch `head` into `base`.
+    pub fn create_pull_request(
+        &self,
+        head: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+    ) -> ApiResult<PullRequest> {
+        let body = ureq::json!({"head": head, "base": base, "title": title, "body": body});
+        self.send_json("POST", &self.repo_path("/pulls"), Some(body))
+    }
+
+    /// Returns the releases of the remote repository, the newest first.
+    pub fn get_releases(&self) -> ApiResult<Vec<Release>> {
+        self.get_list(&self.repo_path("/releases"))
+    }
+
+    /// Returns true if the remote repository exists and is accessible with the token.
+    pub fn repository_exists(&self) -> ApiResult<bool> {
+        Ok(self.find_json::<Repository>(&self.repo_path(""))?.is_some())
+    }
+
+    /// Returns the organization which owns the remote repository
+    /// or `None` if the owner is a user.
+    pub fn get_organization(&self) -> ApiResult<Option<Organization>> {
+        self.find_json(&format!("/orgs/{}", self.owner))
+    }
+
+    /// Returns the user who owns the api token.
+    pub fn get_user(&self) -> ApiResult<User> {
+        self.get_json("/user")
+    }
+
+    /// This function creates the remote repository as private repository of the user
+    /// or of the organization if the owner is not the user of the token.
+    pub fn create_repository(&self, options: RepositoryOptions) -> ApiResult<Repository> {
+        let user = self.get_user()?;
+        let path = match user.login == self.owner {
+            true => "/user/repos".to_owned(),
+            false => format!("/orgs/{}/repos", self.owner),
+        };
+        let mut body = ureq::json!({
+            "name": self.repository,
+            "private": true,
+            "auto_init": options.readme,
+        });
+        if options.readme {
+            body["readme"] = ureq::json!("Default");
+        }
+        let repository: Repository = self.send_json("POST", &path, Some(body))?;
+
+        if options.protect_branch {
+            self.send_json::<serde_json::Value>(
+                "POST",
+                &self.repo_path("/branch_protections"),
+                Some(ureq::json!({
+                    "branch_name": repository.default_branch,
+                    "enable_push": true,
+                    "enable_push_whitelist": true,
+                    "push_whitelist_usernames": [user.login],
+                })),
+            )?;
+        }
+        Ok(repository)
+    }
+
+    /// Returns the latest commit which touched `path` or `None` if there is no such commit.
+    pub fn get_latest_commit(&self, path: &str) -> ApiResult<Option<Commit>> {
+        Ok(self.get_commits(path, 1)?.into_iter().next())
+    }
+
+    /// Returns up to `limit` commits which touched `path`, the newest first.
+    pub fn get_commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>> {
+        let url = format!(
+            "{}{}/repos/{}/{}/commits",
+            self.url, API_PART, self.owner, self.repository
+        );
+        let commits = self.get_pages(
+            || {
+                let request = self.client.get(&url).query("path", path);
+                match self.read_ref() {
+                    Some(b) => request.query("sha", b),
+                    None => request,
+                }
+            },
+            limit,
+            true,
+        )?;
+        Ok(serde_json::from_value(commits)?)
+    }
+
+    /// Returns the tag `name` or `None` if there is no such tag.
+    pub fn get_tag(&self, name: &str) -> ApiResult<Option<Tag>> {
+        self.find_json(&self.repo_path(&format!("/tags/{}", name)))
+    }
+
+    /// This function resolves a tag to the sha of its commit. Anything else, like
+    /// a commit sha, is returned unchanged.
+    pub fn resolve_revision(&self, revision: &str) -> ApiResult<String> {
+        match self.get_tag(revision)? {
+            Some(tag) => Ok(tag.commit.sha),
+            None => Ok(revision.to_owned()),
+        }
     }
 
     /// Returns a `Vec` of `ContentEntry` which represents either a folder or file.
@@ -148,14 +651,17 @@ impl GiteaClient {
         name: &str,
         filter_type: Option<ContentType>,
     ) -> ApiResult<ContentsResponse> {
+        let url = format!(
+            "{}{}/repos/{}/{}/contents/{}",
+            self.url, API_PART, self.owner, self.repository, name
+        );
+        // The contents API ignores `page` and `limit` and lists the whole folder at once.
+        // File contents are too large for the response cache
+        let request = self
+            .with_ref(self.client.get(&url))
+            .set("Authorization", &self.authorization());
         let res = self
-            .client
-            .get(&format!(
-                "{}{}/repos/{}/{}/contents/{}",
-                self.url, API_PART, self.owner, self.repository, name
-            ))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .call()?
+            .send(request, None)?
             .into_json()
             .map_err(ApiError::Io)?;
         ContentsResponse::new(res, filter_type)
@@ -175,23 +681,108 @@ impl GiteaClient {
         })
     }
 
+    /// Returns every file below the folder `name`, or the file `name` itself.
+    /// The whole tree is requested at once and filtered, because the contents API
+    /// lists only a single folder per request.
     pub fn get_folder(&self, name: &str) -> ApiResult<ContentsResponse> {
-        let feature_set = self.get_file_or_folder(name, None)?;
+        let name = name.trim_matches('/');
+        let mut found = false;
         let mut files = vec![];
+        for entry in self.get_tree()? {
+            if !in_folder(&entry.path, name) {
+                continue;
+            }
+            found = true;
+            if entry.entry_type != "tree" && !entry.path.ends_with("/.gitkeep") {
+                files.push(ContentEntry::from(entry));
+            }
+        }
+        match found || name.is_empty() {
+            true => Ok(ContentsResponse { content: files }),
+            false => Err(ApiError::InvalidContentResponse(format!(
+                "{} doesn't exist in the remote repository",
+                name
+            ))),
+        }
+    }
 
-        for entity in feature_set.content {
-            match entity.content_type {
-                ContentType::Dir => {
-                    files.append(&mut self.get_folder(&entity.path)?.content);
-                }
-                _ => {
-                    if entity.name != ".gitkeep" {
-                        files.push(entity)
-                    }
-                }
+    /// Returns the default branch of the remote repository.
+    pub fn get_default_branch(&self) -> ApiResult<String> {
+        if let Some(branch) = self.cache().default_branch() {
+            return Ok(branch.to_owned());
+        }
+        let branch = self.get_repository_information()?.default_branch;
+        self.cache().set_default_branch(&branch);
+        Ok(branch)
+    }
+
+    /// Returns the sha of the commit the branch, tag or commit `revision` points to.
+    pub fn get_commit_sha(&self, revision: &str) -> ApiResult<String> {
+        let url = format!("{}{}{}", self.url, API_PART, self.repo_path("/commits"));
+        let res = self.get_conditional(
+            self.client
+                .get(&url)
+                .query("sha", revision)
+                .query("limit", "1")
+                .query("stat", "false")
+                .query("verification", "false")
+                .query("files", "false")
+                .set("Authorization", &self.authorization()),
+        )?;
+        let commits: Vec<Commit> = serde_json::from_str(&res.body)?;
+        match commits.into_iter().next() {
+            Some(c) => Ok(c.sha),
+            None => Err(ApiError::InvalidContentResponse(format!(
+                "No commit found for {}",
+                revision
+            ))),
+        }
+    }
+
+    /// Returns every entry of the git tree of the branch or revision in use.
+    /// The tree is only requested if its commit differs from the cached one.
+    pub fn get_tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        let tree_ref = match self.read_ref() {
+            Some(r) => r.clone(),
+            None => self.get_default_branch()?,
+        };
+        let commit = self.get_commit_sha(&tree_ref)?;
+        if let Some(entries) = self.cache().tree(&tree_ref, &commit) {
+            debug!("Using the cached tree of {} at {}", tree_ref, commit);
+            return Ok(entries.to_vec());
+        }
+        let url = format!(
+            "{}{}{}",
+            self.url,
+            API_PART,
+            self.repo_path(&format!("/git/trees/{}", commit))
+        );
+        let mut entries = vec![];
+        for page in 1.. {
+            let tree: Tree = self
+                .send(
+                    self.client
+                        .get(&url)
+                        .query("recursive", "true")
+                        .query("page", &page.to_string())
+                        .set("Authorization", &self.authorization()),
+                    None,
+                )?
+                .into_json()
+                .map_err(ApiError::Io)?;
+            let count = tree.tree.len();
+            entries.extend(tree.tree);
+            if !tree.truncated || count == 0 {
+                break;
             }
         }
-        Ok(ContentsResponse { content: files })
+        self.cache().set_tree(&tree_ref, &commit, entries.clone());
+        Ok(entries)
+    }
+
+    /// Returns the locked cache, a poisoned lock is still usable.
+    fn cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
     }
 
     /// A file exists if the first element of the `ContentsResponse` has the same name
@@ -214,7 +805,7 @@ impl GiteaClient {
         author: &str,
         mail: &str,
         cmt_msg: Option<&str>,
-    ) -> ApiResult<String> {
+    ) -> ApiResult<FileResponse> {
         let mut msg = match cmt_msg {
             Some(s) => ureq::json!({ "message": s }),
             None => ureq::json!({}),
@@ -223,17 +814,20 @@ impl GiteaClient {
             ureq::json!({"author": { "email": mail, "name": author}, "content": encode(content) });
         body.as_object_mut()
             .unwrap()
-            .append(&mut msg.as_object_mut().unwrap());
-        self.client
-            .post(&format!(
-                "{}{}/repos/{}/{}/contents/{}{}",
-                self.url, API_PART, self.owner, self.repository, feature_name, filename
-            ))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .set("content-type", "application/json")
-            .send_json(body)?
-            .into_string()
-            .map_err(ApiError::Io)
+            .append(msg.as_object_mut().unwrap());
+        self.add_branch(&mut body);
+        self.send(
+            self.client
+                .post(&format!(
+                    "{}{}/repos/{}/{}/contents/{}{}",
+                    self.url, API_PART, self.owner, self.repository, feature_name, filename
+                ))
+                .set("Authorization", &self.authorization())
+                .set("content-type", "application/json"),
+            Some(body),
+        )?
+        .into_json()
+        .map_err(ApiError::Io)
     }
 
     /// This function checks wether a file exists under the feature set and either uploads
@@ -246,34 +840,46 @@ impl GiteaClient {
         author: &str,
         mail: &str,
         cmt_msg: Option<&str>,
-    ) -> ApiResult<String> {
+    ) -> ApiResult<FileResponse> {
         if self.check_file_exists(feature_name, filename) {
-            let files = self.get_file_or_folder(&format!("{}{}", feature_name, filename), None)?;
-            let file_sha = files.content[0].sha.as_ref().unwrap();
-
-            let mut msg = match cmt_msg {
-                Some(s) => ureq::json!({ "message": s }),
-                None => ureq::json!({}),
-            };
-            let mut body = ureq::json!({"author": { "email": mail, "name": author}, "content": encode(content), "sha": file_sha, "message": cmt_msg });
+            let name = format!("{}{}", feature_name, filename);
+            let files = self.get_file_or_folder(&name, None)?;
+            let file_sha = files.content[0].sha.clone().unwrap_or_default();
+            self.update_file(&name, &file_sha, content, author, mail, cmt_msg)
+        } else {
+            self.create_file(feature_name, filename, content, author, mail, cmt_msg)
+        }
+    }
 
-            body.as_object_mut()
-                .unwrap()
-                .append(&mut msg.as_object_mut().unwrap());
+    /// This function replaces the content of the file `name` if it is still at the blob
+    /// sha `file_sha`. Otherwise Gitea rejects the change with `ApiError::Conflict`.
+    pub fn update_file(
+        &self,
+        name: &str,
+        file_sha: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<FileResponse> {
+        let mut body = ureq::json!({"author": { "email": mail, "name": author}, "content": encode(content), "sha": file_sha});
+        if let Some(msg) = cmt_msg {
+            body["message"] = msg.into();
+        }
+        self.add_branch(&mut body);
 
+        self.send(
             self.client
                 .put(&format!(
-                    "{}{}/repos/{}/{}/contents/{}{}",
-                    self.url, API_PART, self.owner, self.repository, feature_name, filename
+                    "{}{}/repos/{}/{}/contents/{}",
+                    self.url, API_PART, self.owner, self.repository, name
                 ))
-                .set("Authorization", &format!("token {}", self.api_token))
-                .set("content-type", "application/json")
-                .send_json(body)?
-                .into_string()
-                .map_err(ApiError::Io)
-        } else {
-            self.create_file(feature_name, filename, content, author, mail, cmt_msg)
-        }
+                .set("Authorization", &self.authorization())
+                .set("content-type", "application/json"),
+            Some(body),
+        )?
+        .into_json()
+        .map_err(ApiError::Io)
     }
 
     /// This function deletes a file from the remote repository.
@@ -284,7 +890,7 @@ impl GiteaClient {
         author: &str,
         mail: &str,
         cmt_msg: Option<&str>,
-    ) -> ApiResult<String> {
+    ) -> ApiResult<FileResponse> {
         let mut msg = match cmt_msg {
             Some(s) => ureq::json!({ "message": s }),
             None => ureq::json!({}),
@@ -293,17 +899,79 @@ impl GiteaClient {
 
         body.as_object_mut()
             .unwrap()
-            .append(&mut msg.as_object_mut().unwrap());
+            .append(msg.as_object_mut().unwrap());
+        self.add_branch(&mut body);
 
-        self.client
-            .delete(&format!(
-                "{}{}/repos/{}/{}/contents/{}",
-                self.url, API_PART, self.owner, self.repository, name
-            ))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .send_json(ureq::json!({"author": { "email": mail, "name": author}, "sha": file_sha , "message": cmt_msg }))?
-            .into_string()
-            .map_err(ApiError::Io)
+        self.send(
+            self.client
+                .delete(&format!(
+                    "{}{}/repos/{}/{}/contents/{}",
+                    self.url, API_PART, self.owner, self.repository, name
+                ))
+                .set("Authorization", &self.authorization()),
+            Some(body),
+        )?
+        .into_json()
+        .map_err(ApiError::Io)
+    }
+
+    /// This function applies all `changes` in one commit and returns the sha of the commit,
+    /// or `None` if there are no changes. Gitea instances older than 1.20 don't support
+    /// this and every change is committed on its own.
+    pub fn change_files(
+        &self,
+        changes: &[FileChange],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        // An unknown version is tried, the rejection tells if it is unsupported
+        let batch = self
+            .get_server_version()
+            .map_or(true, |s| s.supports(Feature::BatchContents));
+        if !batch {
+            return self.change_files_one_by_one(changes, author, mail, cmt_msg);
+        }
+        let mut body = files_body(changes, author, mail, cmt_msg);
+        self.add_branch(&mut body);
+        let res = self.send_json::<FilesResponse>("POST", &self.repo_path("/contents"), Some(body));
+        match res {
+            Ok(res) => Ok(Some(res.commit.sha)),
+            Err(ref e) if is_unsupported(e) => {
+                self.change_files_one_by_one(changes, author, mail, cmt_msg)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// This function commits every change of `change_files` on its own.
+    fn change_files_one_by_one(
+        &self,
+        changes: &[FileChange],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        warn!("The Gitea instance can't change several files in one commit");
+        let mut commit = None;
+        for change in changes {
+            // The shas of the changes detect files which changed in the meantime
+            let res = match (&change.content, &change.sha) {
+                (Some(content), Some(sha)) => {
+                    self.update_file(&change.path, sha, content, author, mail, cmt_msg)?
+                }
+                (Some(content), None) => {
+                    self.create_file("", &change.path, content, author, mail, cmt_msg)?
+                }
+                (None, Some(sha)) => self.delete_file(&change.path, sha, author, mail, cmt_msg)?,
+                (None, None) => continue,
+            };
+            commit = Some(res.commit.sha);
+        }
+        Ok(commit)
     }
 
     /// This functions deletes either a file or the whole folder from
@@ -316,49 +984,220 @@ impl GiteaClient {
         author: &str,
         mail: &str,
         cmt_msg: Option<&str>,
-    ) -> ApiResult<()> {
+    ) -> ApiResult<Vec<String>> {
         let content = self.get_file_or_folder(name, None)?;
 
+        let mut commits = vec![];
         for file in content.content {
             match file.content_type {
-                ContentType::Dir => {
-                    if recursive {
-                        self.delete_file_or_folder(&file.path, true, author, mail, cmt_msg)?;
-                    } else {
-                        self.delete_file(
-                            &file.path,
-                            file.sha.as_ref().unwrap(),
-                            author,
-                            mail,
-                            cmt_msg,
-                        )?;
-                    }
+                ContentType::Dir if recursive => {
+                    commits.append(
+                        &mut self.delete_file_or_folder(&file.path, true, author, mail, cmt_msg)?,
+                    );
                 }
                 _ => {
-                    self.delete_file(
+                    let res = self.delete_file(
                         &file.path,
                         file.sha.as_ref().unwrap(),
                         author,
                         mail,
                         cmt_msg,
                     )?;
+                    commits.push(res.commit.sha);
                 }
             }
         }
-        Ok(())
+        Ok(commits)
     }
 
-    pub fn download_file(&self, name: &str) -> ApiResult<String> {
-        let content = self.get_file(name)?;
-        self.client
-            .get(&format!(
-                "{}{}/repos/{}/{}/raw/{}",
-                self.url, API_PART, self.owner, self.repository, content.path
-            ))
-            .set("Authorization", &format!("token {}", self.api_token))
-            .call()?
-            .into_string()
-            .map_err(ApiError::Io)
+    /// Returns the content of the git blob with the `sha`.
+    pub fn get_blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        // Blobs never change and are too large for the response cache
+        let path = self.repo_path(&format!("/git/blobs/{}", sha));
+        let blob: Blob = self.send_json("GET", &path, None)?;
+        gitea_api::decode_base64(&blob.content)
+            .map_err(|e| ApiError::InvalidContentResponse(format!("Invalid blob {}: {}", sha, e)))
+    }
+
+    /// This function downloads the content of the file `name`. The content is decoded
+    /// from the contents API if provided, otherwise the raw endpoint is used.
+    pub fn download_file(&self, name: &str) -> ApiResult<Vec<u8>> {
+        let entry = self.get_file(name)?;
+        if let Some(content) = entry.decode_content()? {
+            return Ok(content);
+        }
+        let mut content = vec![];
+        let request = self.with_ref(self.client.get(&format!(
+            "{}{}/repos/{}/{}/raw/{}",
+            self.url, API_PART, self.owner, self.repository, entry.path
+        )));
+        self.send(request.set("Authorization", &self.authorization()), None)?
+            .into_reader()
+            .read_to_end(&mut content)
+            .map_err(ApiError::Io)?;
+        Ok(content)
+    }
+
+    /// This function downloads the file `name` which must contain valid UTF-8.
+    pub fn download_text(&self, name: &str) -> ApiResult<String> {
+        String::from_utf8(self.download_file(name)?).map_err(|e| {
+            ApiError::InvalidContentResponse(format!("{} is no valid UTF-8: {}", name, e))
+        })
+    }
+}
+
+/// Returns the seconds since the unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// This function returns how long to wait for the rate limit of the server, if at all.
+///
+/// A `rejected` request or no `remaining` requests wait until the `reset` time in
+/// seconds since the unix epoch, or for `retry_after` seconds. Without any of both
+/// a minute is waited.
+fn rate_limit_delay(
+    rejected: bool,
+    remaining: Option<&str>,
+    reset: Option<&str>,
+    retry_after: Option<&str>,
+    now: u64,
+) -> Option<Duration> {
+    if !rejected && remaining.map(str::trim) != Some("0") {
+        return None;
+    }
+    let parse = |value: Option<&str>| value.and_then(|v| v.trim().parse::<u64>().ok());
+    let secs = match (parse(reset), parse(retry_after)) {
+        (Some(reset), _) => reset.saturating_sub(now),
+        (None, Some(after)) => after,
+        (None, None) => 60,
+    };
+    Some(Duration::from_secs(secs.max(1)))
+}
+
+/// Returns true if another page follows a page with `count` of the requested `limit` items.
+/// The `Link` header is used if the server sends one, otherwise a full page is
+/// expected to have a successor.
+fn has_next_page(link: Option<&str>, count: usize, limit: usize) -> bool {
+    match link {
+        Some(link) => link
+            .split(',')
+            .any(|l| l.split(';').skip(1).any(|p| p.trim() == "rel=\"next\"")),
+        None => count > 0 && count == limit,
+    }
+}
+
+/// Returns true if the `path` is the file `folder` or located below the folder `folder`.
+pub(crate) fn in_folder(path: &str, folder: &str) -> bool {
+    match path.strip_prefix(folder) {
+        Some(rest) => folder.is_empty() || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// This function creates the json body which changes several files in one commit.
+fn files_body(
+    changes: &[FileChange],
+    author: &str,
+    mail: &str,
+    cmt_msg: Option<&str>,
+) -> serde_json::Value {
+    let files: Vec<serde_json::Value> = changes
+        .iter()
+        .map(|c| {
+            let mut file = ureq::json!({"operation": c.operation(), "path": c.path});
+            if let Some(content) = &c.content {
+                file["content"] = encode(content).into();
+            }
+            if let Some(sha) = &c.sha {
+                file["sha"] = sha.as_str().into();
+            }
+            file
+        })
+        .collect();
+    let mut body = ureq::json!({"author": { "email": mail, "name": author}, "files": files});
+    if let Some(msg) = cmt_msg {
+        body["message"] = msg.into();
+    }
+    body
+}
+
+/// This function turns a rejected request into an error which explains the missing
+/// permission. Gitea answers with 403 if the token lacks the scope or the user isn't
+/// a member of a team of the organization `owner` with access to the repository.
+fn permission_error(status: u16, method: &str, url: &str, owner: &str) -> Option<ApiError> {
+    let path = api_path(url);
+    match status {
+        401 => Some(ApiError::Unauthorized(format!(
+            "The api token was rejected for {} {}, check the token of the profile",
+            method, path
+        ))),
+        403 => Some(ApiError::PermissionDenied(format!(
+            "The api token has no permission for {} {}. If {} is an organization, \
+             the user of the token must be a member of a team with access to the repository",
+            method, path, owner
+        ))),
+        _ => None,
+    }
+}
+
+/// Returns true if the error tells that the endpoint doesn't exist on older Gitea instances.
+fn is_unsupported(e: &ApiError) -> bool {
+    match e {
+        ApiError::NotFound(_) => true,
+        ApiError::Ureq(e) => matches!(**e, ureq::Error::Status(405, _)),
+        _ => false,
+    }
+}
+
+/// Returns the api path of the `url` without the query, e.g. `/repos/o/r/contents/a`.
+fn api_path(url: &str) -> &str {
+    let path = url.split_once(API_PART).map_or(url, |(_, p)| p);
+    path.split('?').next().unwrap_or(path)
+}
+
+/// This function turns the `response` of a rejected request into an error. The status
+/// and the json body of Gitea select the variant, e.g. a rejection by the branch protection
+/// or a change of an outdated file. A rejection by the rate limit carries the `retry_after`
+/// delay. Other rejections are returned as status error with the body for the caller.
+pub(crate) fn status_error(
+    status: u16,
+    response: ureq::Response,
+    method: &str,
+    url: &str,
+    owner: &str,
+    retry_after: Option<Duration>,
+) -> ApiError {
+    let text = response.status_text().to_owned();
+    let body = response.into_string().unwrap_or_default();
+    let gitea = ErrorResponse::parse(&body);
+    let message = || match &gitea {
+        Some(e) => format!("{} {}: {}", method, api_path(url), e.message),
+        None => format!("{} {}", method, api_path(url)),
+    };
+    let conflict = || ApiError::Conflict {
+        message: message(),
+        expected_sha: gitea.as_ref().and_then(ErrorResponse::expected_sha),
+    };
+    match (status, &gitea) {
+        (403 | 422, Some(e)) if e.is_protected_branch() => {
+            return ApiError::ProtectedBranch(e.message.clone())
+        }
+        (404, _) => return ApiError::NotFound(message()),
+        (409 | 412, _) => return conflict(),
+        (422, Some(e)) if e.is_conflict() => return conflict(),
+        (429, _) => return ApiError::RateLimited(retry_after),
+        _ => {}
+    }
+    if let Some(e) = permission_error(status, method, url, owner) {
+        return e;
+    }
+    match ureq::Response::new(status, &text, &body) {
+        Ok(r) => ureq::Error::Status(status, r).into(),
+        Err(e) => e.into(),
     }
 }
 
@@ -376,3 +1215,246 @@ fn read_from_cli(prefix: &str) -> String {
         .expect("Failed to read line");
     input.trim().to_owned()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use super::{
+        files_body, has_next_page, in_folder, is_unsupported, permission_error, rate_limit_delay,
+        status_error, ApiError, FileChange, GiteaClient, PAGE_SIZE,
+    };
+
+    #[test]
+    fn test_rate_limit_delay() {
+        assert_eq!(
+            rate_limit_delay(false, Some("12"), Some("1100"), None, 1000),
+            None
+        );
+        assert_eq!(rate_limit_delay(false, None, None, None, 1000), None);
+        assert_eq!(
+            rate_limit_delay(false, Some("0"), Some("1100"), None, 1000),
+            Some(Duration::from_secs(100))
+        );
+        assert_eq!(
+            rate_limit_delay(true, None, None, Some("30"), 1000),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            rate_limit_delay(true, None, Some("900"), None, 1000),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            rate_limit_delay(true, None, None, None, 1000),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_in_folder() {
+        assert!(in_folder("web/etc/nginx.conf", "web"));
+        assert!(in_folder("web/etc/nginx.conf", "web/etc"));
+        assert!(in_folder("web/etc/nginx.conf", "web/etc/nginx.conf"));
+        assert!(in_folder("web/etc/nginx.conf", ""));
+        assert!(!in_folder("webserver/etc/nginx.conf", "web"));
+        assert!(!in_folder("db/etc/my.cnf", "web"));
+    }
+
+    #[test]
+    fn test_files_body() {
+        let changes = vec![
+            FileChange {
+                path: "web/a".into(),
+                content: Some(b"a".to_vec()),
+                sha: None,
+            },
+            FileChange {
+                path: "web/b".into(),
+                content: Some(b"b".to_vec()),
+                sha: Some("1f".into()),
+            },
+            FileChange {
+                path: "web/c".into(),
+                content: None,
+                sha: Some("2e".into()),
+            },
+        ];
+        let body = files_body(&changes, "rustea", "rustea@localhost", Some("push web"));
+        assert_eq!(body["message"], "push web");
+        assert_eq!(body["author"]["name"], "rustea");
+        let files = body["files"].as_array().unwrap();
+        assert_eq!(files[0]["operation"], "create");
+        assert_eq!(files[0]["content"], base64::encode("a"));
+        assert!(files[0]["sha"].is_null());
+        assert_eq!(files[1]["operation"], "update");
+        assert_eq!(files[1]["sha"], "1f");
+        assert_eq!(files[2]["operation"], "delete");
+        assert!(files[2]["content"].is_null());
+        assert!(files_body(&changes, "a", "b", None)["message"].is_null());
+    }
+
+    #[test]
+    fn test_authorization() {
+        let client = GiteaClient {
+            api_token: "secret".into(),
+            ..GiteaClient::default()
+        };
+        assert_eq!(client.authorization(), "token secret");
+        let client = client.with_username(Some("ops".into()));
+        assert_eq!(client.authorization(), "Basic b3BzOnNlY3JldA==");
+    }
+
+    #[test]
+    fn test_permission_error() {
+        let url = "https://gitea/api/v1/repos/org/r/contents/a?ref=main";
+        match permission_error(403, "PUT", url, "org") {
+            Some(ApiError::PermissionDenied(e)) => {
+                assert!(e.starts_with(
+                    "The api token has no permission for PUT /repos/org/r/contents/a."
+                ));
+                assert!(e.contains("If org is an organization"));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(
+            permission_error(401, "GET", url, "org"),
+            Some(ApiError::Unauthorized(_))
+        ));
+        assert!(permission_error(404, "GET", url, "org").is_none());
+    }
+
+    #[test]
+    fn test_status_error() {
+        let url = "https://gitea/api/v1/repos/org/r/contents/a?ref=main";
+        let error = |status: u16, body: &str| {
+            let res = ureq::Response::new(status, "Rejected", body).unwrap();
+            status_error(
+                status,
+                res,
+                "PUT",
+                url,
+                "org",
+                Some(Duration::from_secs(30)),
+            )
+        };
+        let body =
+            r#"{"message": "user cannot commit to repo [user: ops]", "url": "https://gitea"}"#;
+        match error(403, body) {
+            ApiError::ProtectedBranch(e) => assert_eq!(e, "user cannot commit to repo [user: ops]"),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(error(403, "{}"), ApiError::PermissionDenied(_)));
+        assert!(matches!(error(401, ""), ApiError::Unauthorized(_)));
+        match error(404, r#"{"message": "GetContentsOrList"}"#) {
+            ApiError::NotFound(e) => {
+                assert_eq!(e, "PUT /repos/org/r/contents/a: GetContentsOrList")
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        match error(
+            422,
+            r#"{"message": "sha does not match [given: 1f, expected: 2e]"}"#,
+        ) {
+            ApiError::Conflict { expected_sha, .. } => assert_eq!(expected_sha.unwrap(), "2e"),
+            e => panic!("unexpected {:?}", e),
+        }
+        assert!(matches!(error(412, ""), ApiError::Conflict { .. }));
+        assert!(matches!(
+            error(
+                422,
+                r#"{"message": "repository file already exists [path: web/a]"}"#
+            ),
+            ApiError::Conflict { .. }
+        ));
+        assert!(matches!(
+            error(409, "Conflict"),
+            ApiError::Conflict {
+                expected_sha: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            error(429, ""),
+            ApiError::RateLimited(Some(d)) if d.as_secs() == 30
+        ));
+        assert!(is_unsupported(&error(404, "")));
+        assert!(is_unsupported(&error(405, "")));
+        assert!(!is_unsupported(&error(409, "")));
+        match error(422, r#"{"message": "invalid path"}"#) {
+            ApiError::Ureq(e) => match *e {
+                ureq::Error::Status(422, r) => {
+                    assert!(r.into_string().unwrap().contains("invalid path"))
+                }
+                e => panic!("unexpected {:?}", e),
+            },
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_folder_of_page_size() {
+        // The contents API sends full folders without `Link` header, a paginated
+        // request of a folder with PAGE_SIZE entries would never end
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let entries: Vec<String> = (0..PAGE_SIZE)
+            .map(|i| {
+                format!(
+                    r#"{{"name": "{0}.conf", "path": "web/{0}.conf", "type": "file"}}"#,
+                    i
+                )
+            })
+            .collect();
+        let body = format!("[{}]", entries.join(","));
+        let requests = thread::spawn(move || {
+            let mut requests = 0;
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request = vec![0; 4096];
+                let n = stream.read(&mut request).unwrap();
+                if n == 0 || request.starts_with(b"DONE") {
+                    break;
+                }
+                requests += 1;
+                let res = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(res.as_bytes()).unwrap();
+            }
+            requests
+        });
+        let client = GiteaClient {
+            url: url.clone(),
+            ..GiteaClient::default()
+        };
+        let folder = client.get_file_or_folder("web", None).unwrap();
+        assert_eq!(folder.content.len(), PAGE_SIZE);
+        let addr = url.trim_start_matches("http://");
+        TcpStream::connect(addr)
+            .unwrap()
+            .write_all(b"DONE")
+            .unwrap();
+        assert_eq!(requests.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_has_next_page() {
+        let link = "<https://gitea/api/v1/repos/o/r/commits?page=2&limit=50>; rel=\"next\",\
+                    <https://gitea/api/v1/repos/o/r/commits?page=4&limit=50>; rel=\"last\"";
+        assert!(has_next_page(Some(link), 50, 50));
+        let link = "<https://gitea/api/v1/repos/o/r/commits?page=1&limit=50>; rel=\"first\",\
+                    <https://gitea/api/v1/repos/o/r/commits?page=3&limit=50>; rel=\"prev\"";
+        assert!(!has_next_page(Some(link), 50, 50));
+        assert!(has_next_page(None, 50, 50));
+        assert!(!has_next_page(None, 12, 50));
+        assert!(!has_next_page(None, 0, 0));
+    }
+}
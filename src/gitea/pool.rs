@@ -0,0 +1,305 @@
+//! This file implements a thread pool which runs the requests of the `GiteaClient` as futures behind the `async` feature.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use super::{
+    gitea_api::{
+        ApiError, ApiResult, Commit, ContentEntry, ContentsResponse, FileResponse, Organization,
+        Repository, Version,
+    },
+    GiteaClient,
+};
+
+/// The number of worker threads of a `PooledGiteaClient` created with `from`.
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// A request which waits for a worker thread.
+type Job = Box<dyn FnOnce(&GiteaClient) + Send>;
+
+/// The shared state of a `Call`.
+struct State<T> {
+    result: Option<ApiResult<T>>,
+    waker: Option<Waker>,
+    /// Set if the `Call` was dropped, a waiting request is skipped then
+    cancelled: bool,
+}
+
+fn lock<T>(state: &Mutex<State<T>>) -> MutexGuard<'_, State<T>> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A `Call` is the future of a request made by the `PooledGiteaClient`.
+/// Dropping it skips the request if it didn't start yet, a running request
+/// can't be interrupted and its result is discarded.
+pub struct Call<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T> Future for Call<T> {
+    type Output = ApiResult<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.state);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Call<T> {
+    fn drop(&mut self) {
+        lock(&self.state).cancelled = true;
+    }
+}
+
+/// The `PooledGiteaClient` is no async http client. It runs the requests of the blocking
+/// `GiteaClient` on a fixed number of worker threads and returns a future for each,
+/// e.g. to embed rustea in async services which orchestrate rollouts. At most as many
+/// requests as there are workers run at once, the others wait in order.
+/// The futures work with any executor.
+#[derive(Debug, Clone)]
+pub struct PooledGiteaClient {
+    client: Arc<GiteaClient>,
+    jobs: Sender<Job>,
+}
+
+impl From<GiteaClient> for PooledGiteaClient {
+    fn from(client: GiteaClient) -> Self {
+        PooledGiteaClient::new(client, DEFAULT_WORKERS)
+    }
+}
+
+impl PooledGiteaClient {
+    /// This function starts `workers` threads, at least one, which run the requests
+    /// with the `client`. The threads stop with the last clone of the pool.
+    pub fn new(client: GiteaClient, workers: usize) -> PooledGiteaClient {
+        let client = Arc::new(client);
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..workers.max(1) {
+            let (client, queue) = (client.clone(), queue.clone());
+            thread::spawn(move || work(&client, &queue));
+        }
+        PooledGiteaClient { client, jobs }
+    }
+
+    /// Returns the blocking client used for the requests.
+    pub fn blocking(&self) -> &GiteaClient {
+        &self.client
+    }
+
+    /// This function queues the `request` for the next free worker
+    /// and returns the future of its result.
+    pub fn call<T, F>(&self, request: F) -> Call<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&GiteaClient) -> ApiResult<T> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(State {
+            result: None,
+            waker: None,
+            cancelled: false,
+        }));
+        let shared = state.clone();
+        let job: Job = Box::new(move |client| {
+            if lock(&shared).cancelled {
+                return;
+            }
+            let result = request(client);
+            let mut state = lock(&shared);
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        if self.jobs.send(job).is_err() {
+            lock(&state).result = Some(Err(ApiError::InvalidContentResponse(
+                "The worker threads of the client stopped".into(),
+            )));
+        }
+        Call { state }
+    }
+
+    /// See `GiteaClient::get_gitea_version`.
+    pub fn get_gitea_version(&self) -> Call<Version> {
+        self.call(|c| c.get_gitea_version())
+    }
+
+    /// See `GiteaClient::get_repository_information`.
+    pub fn get_repository_information(&self) -> Call<Repository> {
+        self.call(|c| c.get_repository_information())
+    }
+
+    /// See `GiteaClient::get_organization`.
+    pub fn get_organization(&self) -> Call<Option<Organization>> {
+        self.call(|c| c.get_organization())
+    }
+
+    /// See `GiteaClient::get_commits`.
+    pub fn get_commits(&self, path: &str, limit: usize) -> Call<Vec<Commit>> {
+        let path = path.to_owned();
+        self.call(move |c| c.get_commits(&path, limit))
+    }
+
+    /// See `GiteaClient::get_file`.
+    pub fn get_file(&self, name: &str) -> Call<ContentEntry> {
+        let name = name.to_owned();
+        self.call(move |c| c.get_file(&name))
+    }
+
+    /// See `GiteaClient::get_folder`.
+    pub fn get_folder(&self, name: &str) -> Call<ContentsResponse> {
+        let name = name.to_owned();
+        self.call(move |c| c.get_folder(&name))
+    }
+
+    /// See `GiteaClient::download_file`.
+    pub fn download_file(&self, name: &str) -> Call<Vec<u8>> {
+        let name = name.to_owned();
+        self.call(move |c| c.download_file(&name))
+    }
+
+    /// See `GiteaClient::create_or_update_file`.
+    pub fn create_or_update_file(
+        &self,
+        feature_name: &str,
+        filename: &str,
+        content: Vec<u8>,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> Call<FileResponse> {
+        let (feature_name, filename) = (feature_name.to_owned(), filename.to_owned());
+        let (author, mail) = (author.to_owned(), mail.to_owned());
+        let cmt_msg = cmt_msg.map(str::to_owned);
+        self.call(move |c| {
+            c.create_or_update_file(
+                &feature_name,
+                &filename,
+                &content,
+                &author,
+                &mail,
+                cmt_msg.as_deref(),
+            )
+        })
+    }
+
+    /// See `GiteaClient::delete_file_or_folder`.
+    pub fn delete_file_or_folder(
+        &self,
+        name: &str,
+        recursive: bool,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> Call<Vec<String>> {
+        let (name, author, mail) = (name.to_owned(), author.to_owned(), mail.to_owned());
+        let cmt_msg = cmt_msg.map(str::to_owned);
+        self.call(move |c| {
+            c.delete_file_or_folder(&name, recursive, &author, &mail, cmt_msg.as_deref())
+        })
+    }
+}
+
+/// This function runs the queued requests until every sender of the `queue` is dropped.
+fn work(client: &GiteaClient, queue: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        match job {
+            Ok(job) => job(client),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        task::{Context, Poll, Wake},
+        thread::{self, Thread},
+    };
+
+    use super::PooledGiteaClient;
+    use crate::gitea::{gitea_api::ApiError, GiteaClient};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_call() {
+        let client = PooledGiteaClient::from(GiteaClient::default());
+        let wait = client.call(|c| Ok(c.wait_on_rate_limit));
+        assert!(block_on(wait).unwrap());
+        let failed = client.call::<(), _>(|_| Err(ApiError::InvalidCredentials("no".into())));
+        assert!(matches!(
+            block_on(failed),
+            Err(ApiError::InvalidCredentials(_))
+        ));
+    }
+
+    #[test]
+    fn test_dropped_call() {
+        let client = PooledGiteaClient::new(GiteaClient::default(), 1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let busy = client.call(move |_| Ok(blocked.recv().is_ok()));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let dropped = client.call(move |_| Ok(counter.fetch_add(1, Ordering::SeqCst)));
+        drop(dropped);
+        release.send(()).unwrap();
+        assert!(block_on(busy).unwrap());
+        let counter = runs.clone();
+        let next = client.call(move |_| Ok(counter.fetch_add(1, Ordering::SeqCst)));
+        assert_eq!(block_on(next).unwrap(), 0);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,195 @@
+//! This file implements the glob patterns used to exclude files from a push.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::error::{Error, Result};
+
+/// The file which lists the patterns excluded within a folder and its subfolders.
+pub const IGNORE_FILE: &str = ".rusteaignore";
+
+/// A glob `Pattern` matches paths like `*.swp`, `secrets/*` or `/etc/**/cache`.
+///
+/// `*` matches anything except `/`, `**` matches across folders, `?` matches a
+/// single character and `[...]` a character class. A pattern which starts with
+/// `/` matches from the beginning of the path, otherwise it matches any trailing
+/// sequence of path components. A matching folder excludes everything below it.
+///
+/// A pattern with a `base` folder only matches paths below it and is matched
+/// against the path relative to the folder.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    base: Option<PathBuf>,
+}
+
+impl Pattern {
+    /// This function compiles the `glob` into a `Pattern`.
+    pub fn new(glob: &str) -> Result<Pattern> {
+        let (anchor, rest) = match glob.strip_prefix('/') {
+            Some(rest) => ("^/", rest),
+            None => ("(^|/)", glob),
+        };
+        let mut re = String::from(anchor);
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    match chars.peek() {
+                        Some('/') => {
+                            chars.next();
+                            re.push_str("(.*/)?");
+                        }
+                        _ => re.push_str(".*"),
+                    }
+                }
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                '[' => {
+                    re.push('[');
+                    if chars.peek() == Some(&'!') {
+                        chars.next();
+                        re.push('^');
+                    }
+                    for c in chars.by_ref() {
+                        match c {
+                            ']' => break,
+                            '\\' | '[' | '^' => {
+                                re.push('\\');
+                                re.push(c);
+                            }
+                            _ => re.push(c),
+                        }
+                    }
+                    re.push(']');
+                }
+                _ => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push_str("(/|$)");
+        let regex = Regex::new(&re)
+            .map_err(|e| Error::Rustea(format!("Invalid exclude pattern {}: {}", glob, e)))?;
+        Ok(Pattern { regex, base: None })
+    }
+
+    /// This function compiles the `glob` into a `Pattern` which only applies below `base`.
+    pub fn within(base: &Path, glob: &str) -> Result<Pattern> {
+        Ok(Pattern {
+            base: Some(base.to_path_buf()),
+            ..Pattern::new(glob)?
+        })
+    }
+
+    /// This function compiles every glob in `globs`.
+    pub fn all(globs: &[String]) -> Result<Vec<Pattern>> {
+        globs.iter().map(|g| Pattern::new(g)).collect()
+    }
+
+    /// Returns true if the `path` or one of its parent folders matches the pattern.
+    pub fn matches(&self, path: &Path) -> bool {
+        match &self.base {
+            Some(base) => match path.strip_prefix(base) {
+                Ok(rel) => self.regex.is_match(&format!("/{}", rel.to_string_lossy())),
+                Err(_) => false,
+            },
+            None => self.regex.is_match(&path.to_string_lossy()),
+        }
+    }
+}
+
+/// This function parses the `content` of an ignore file located in `dir`.
+///
+/// Every line is a glob pattern, empty lines and lines starting with `#` are skipped.
+/// Like in a `.gitignore` a pattern containing a `/` is relative to `dir`, otherwise
+/// it matches in any subfolder. A trailing `/` is ignored.
+pub fn parse_ignore(dir: &Path, content: &str) -> Result<Vec<Pattern>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let l = l.trim_end_matches('/');
+            match l.contains('/') && !l.starts_with('/') {
+                true => Pattern::within(dir, &format!("/{}", l)),
+                false => Pattern::within(dir, l),
+            }
+        })
+        .collect()
+}
+
+/// This function reads the ignore file of `dir` if one exists.
+pub fn read_ignore_file(dir: &Path) -> Result<Vec<Pattern>> {
+    let path = dir.join(IGNORE_FILE);
+    match path.is_file() {
+        true => parse_ignore(dir, &fs::read_to_string(path)?),
+        false => Ok(vec![]),
+    }
+}
+
+/// Returns true if the `path` matches one of the `patterns`.
+pub fn is_excluded(patterns: &[Pattern], path: &Path) -> bool {
+    patterns.iter().any(|p| p.matches(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{is_excluded, parse_ignore, Pattern};
+
+    fn matches(glob: &str, path: &str) -> bool {
+        Pattern::new(glob).unwrap().matches(Path::new(path))
+    }
+
+    #[test]
+    fn test_glob_pattern() {
+        assert!(matches(".git", "/etc/nginx/.git"));
+        assert!(matches(".git", "/etc/nginx/.git/config"));
+        assert!(!matches(".git", "/etc/nginx/.gitignore"));
+        assert!(matches("*.swp", "/etc/.nginx.conf.swp"));
+        assert!(!matches("*.swp", "/etc/nginx.conf"));
+        assert!(matches("secrets/*", "/etc/app/secrets/key"));
+        assert!(!matches("secrets/*", "/etc/app/secrets"));
+        assert!(matches("/etc/*.conf", "/etc/nginx.conf"));
+        assert!(!matches("/etc/*.conf", "/srv/etc/nginx.conf"));
+        assert!(matches("/etc/**/cache", "/etc/cache"));
+        assert!(matches("/etc/**/cache", "/etc/app/var/cache/file"));
+        assert!(matches("file.?", "/file.1"));
+        assert!(matches("[!a]b", "/cb"));
+        assert!(!matches("[!a]b", "/ab"));
+        assert!(matches("a+b", "/a+b"));
+        assert!(Pattern::new("[z-a]").is_err());
+    }
+
+    #[test]
+    fn test_parse_ignore() {
+        let dir = Path::new("/etc/app");
+        let patterns = parse_ignore(dir, "# comment\n\ncache/\n*.key\nlib/tmp\n").unwrap();
+        assert_eq!(patterns.len(), 3);
+        assert!(is_excluded(&patterns, Path::new("/etc/app/cache")));
+        assert!(is_excluded(&patterns, Path::new("/etc/app/sub/cache")));
+        assert!(is_excluded(&patterns, Path::new("/etc/app/sub/tls.key")));
+        assert!(is_excluded(&patterns, Path::new("/etc/app/lib/tmp/file")));
+        assert!(!is_excluded(&patterns, Path::new("/etc/app/sub/lib/tmp")));
+        assert!(!is_excluded(&patterns, Path::new("/etc/tls.key")));
+    }
+}
@@ -0,0 +1,137 @@
+//! This file implements the lifecycle hooks of rustea.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{fmt::Display, process::Command};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// The points in the lifecycle of rustea where hooks can run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PrePush,
+    PostPush,
+    PrePull,
+    PostPull,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::PrePush => write!(f, "pre_push"),
+            Hook::PostPush => write!(f, "post_push"),
+            Hook::PrePull => write!(f, "pre_pull"),
+            Hook::PostPull => write!(f, "post_pull"),
+        }
+    }
+}
+
+/// The context which is passed to a hook via environment variables.
+#[derive(Debug, Default)]
+pub struct HookContext<'a> {
+    /// The feature set in question, passed as `RUSTEA_FEATURE_SET`.
+    pub feature_set: &'a str,
+    /// The local files which are or were transferred, passed newline separated as `RUSTEA_FILES`.
+    pub files: Vec<String>,
+    /// The sha of the remote commit if known, passed as `RUSTEA_COMMIT`.
+    pub commit: Option<String>,
+    /// The commit message if provided, passed as `RUSTEA_MESSAGE`.
+    pub message: Option<&'a str>,
+}
+
+/// The `[hooks]` section of the configuration. Every hook is a shell command
+/// which is executed with `sh -c`. A failing pre hook aborts the operation.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Hooks {
+    pub pre_push: Option<String>,
+    pub post_push: Option<String>,
+    pub pre_pull: Option<String>,
+    pub post_pull: Option<String>,
+}
+
+impl Hooks {
+    /// Returns true if no hook is configured.
+    pub fn is_empty(&self) -> bool {
+        self.pre_push.is_none()
+            && self.post_push.is_none()
+            && self.pre_pull.is_none()
+            && self.post_pull.is_none()
+    }
+
+    fn get(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::PrePush => self.pre_push.as_deref(),
+            Hook::PostPush => self.post_push.as_deref(),
+            Hook::PrePull => self.pre_pull.as_deref(),
+            Hook::PostPull => self.post_pull.as_deref(),
+        }
+    }
+
+    /// Run the configured command for `hook` and return an error if it fails.
+    /// Nothing happens if the hook is not configured.
+    pub fn run(&self, hook: Hook, ctx: &HookContext) -> Result<()> {
+        let cmd = match self.get(hook) {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .env("RUSTEA_HOOK", hook.to_string())
+            .env("RUSTEA_FEATURE_SET", ctx.feature_set)
+            .env("RUSTEA_FILES", ctx.files.join("\n"))
+            .env("RUSTEA_COMMIT", ctx.commit.as_deref().unwrap_or(""))
+            .env("RUSTEA_MESSAGE", ctx.message.unwrap_or(""))
+            .status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(Error::Rustea(format!(
+                "Hook {} failed with {}",
+                hook, status
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hook, HookContext, Hooks};
+
+    #[test]
+    fn test_run_hooks() {
+        let hooks = Hooks {
+            pre_push: Some("test \"$RUSTEA_FEATURE_SET\" = nginx".into()),
+            post_push: Some("test \"$RUSTEA_FILES\" = \"/a\n/b\"".into()),
+            ..Hooks::default()
+        };
+        let ctx = HookContext {
+            feature_set: "nginx",
+            files: vec!["/a".into(), "/b".into()],
+            ..HookContext::default()
+        };
+        assert!(hooks.run(Hook::PrePush, &ctx).is_ok());
+        assert!(hooks.run(Hook::PostPush, &ctx).is_ok());
+        assert!(hooks.run(Hook::PrePull, &ctx).is_ok());
+
+        let ctx = HookContext {
+            feature_set: "postgres",
+            ..HookContext::default()
+        };
+        assert!(hooks.run(Hook::PrePush, &ctx).is_err());
+    }
+}
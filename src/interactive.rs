@@ -0,0 +1,223 @@
+//! This file implements the interactive selection of files on the command line.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    io::{self, BufRead, Write},
+};
+
+use crate::error::{Error, Result};
+
+/// This function parses a selection like `1,3-5` of the items numbered from 1 to `count`.
+/// `a` or `all` selects every item and an empty selection none.
+/// The indices of the selected items are returned in ascending order.
+pub fn parse_selection(input: &str, count: usize) -> Result<Vec<usize>> {
+    let input = input.trim();
+    if input == "a" || input == "all" {
+        return Ok((0..count).collect());
+    }
+    let invalid = |part: &str| Error::Rustea(format!("Invalid selection {}", part));
+    let number = |n: &str, part: &str| match n.trim().parse::<usize>() {
+        Ok(n) if n >= 1 && n <= count => Ok(n - 1),
+        _ => Err(invalid(part)),
+    };
+
+    let mut selected = BTreeSet::new();
+    for part in input.split([',', ' ']).filter(|p| !p.is_empty()) {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (number(from, part)?, number(to, part)?);
+                if from > to {
+                    return Err(invalid(part));
+                }
+                selected.extend(from..=to);
+            }
+            None => {
+                selected.insert(number(part, part)?);
+            }
+        }
+    }
+    Ok(selected.into_iter().collect())
+}
+
+/// This function lists the numbered `items` and asks on stdin which of them are selected.
+/// The question is repeated until the answer is a valid selection.
+pub fn select<T: Display>(items: &[T]) -> Result<Vec<usize>> {
+    for (i, item) in items.iter().enumerate() {
+        println!("{:>4}  {}", i + 1, item);
+    }
+    let stdin = io::stdin();
+    loop {
+        print!("Select files (e.g. 1,3-5, a for all, nothing for none): ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(vec![]);
+        }
+        match parse_selection(&line, items.len()) {
+            Ok(selected) => return Ok(selected),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+/// Returns the answer to a question, the `default` if the `input` is empty.
+pub fn parse_answer(input: &str, default: Option<&str>) -> String {
+    match (input.trim(), default) {
+        ("", Some(default)) => default.to_owned(),
+        (answer, _) => answer.to_owned(),
+    }
+}
+
+/// Returns the answer to a yes or no question, the `default` if the `input`
+/// is empty or `None` if it is neither yes nor no.
+pub fn parse_confirmation(input: &str, default: bool) -> Option<bool> {
+    match input.trim().to_lowercase().as_str() {
+        "" => Some(default),
+        "y" | "yes" => Some(true),
+        "n" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// This function reads a line from stdin after printing the `prompt`.
+/// Returns `None` if stdin is closed.
+fn read_answer(prompt: &str) -> Result<Option<String>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+/// This function asks the `question` on stdin and returns the answer.
+/// An empty answer or a closed stdin selects the `default`.
+pub fn ask(question: &str, default: Option<&str>) -> Result<String> {
+    let prompt = match default {
+        Some(d) if !d.is_empty() => format!("{} [{}]: ", question, d),
+        _ => format!("{}: ", question),
+    };
+    let answer = read_answer(&prompt)?.unwrap_or_default();
+    Ok(parse_answer(&answer, default))
+}
+
+/// This function asks the yes or no `question` on stdin until the answer is valid.
+/// An empty answer or a closed stdin selects the `default`.
+pub fn confirm(question: &str, default: bool) -> Result<bool> {
+    let choices = if default { "Y/n" } else { "y/N" };
+    loop {
+        match read_answer(&format!("{} [{}]: ", question, choices))? {
+            None => return Ok(default),
+            Some(answer) => match parse_confirmation(&answer, default) {
+                Some(confirmed) => return Ok(confirmed),
+                None => println!("Please answer yes or no"),
+            },
+        }
+    }
+}
+
+/// The number of items listed by `summarize`, the others are counted.
+const SUMMARY_ITEMS: usize = 20;
+
+/// Returns the summary of the `items` which an `action` destroys or overwrites.
+pub fn summarize(action: &str, items: &[String]) -> String {
+    let mut summary = format!("{} {} file(s):", action, items.len());
+    for item in items.iter().take(SUMMARY_ITEMS) {
+        summary.push_str(&format!("\n    {}", item));
+    }
+    if items.len() > SUMMARY_ITEMS {
+        summary.push_str(&format!(
+            "\n    ... and {} more",
+            items.len() - SUMMARY_ITEMS
+        ));
+    }
+    summary
+}
+
+/// This function prints the summary of the destroyed or overwritten `items` and
+/// asks whether the `action` continues. A refusal is returned as error.
+pub fn confirm_destruction(action: &str, items: &[String]) -> Result<()> {
+    println!("{}", summarize(action, items));
+    match confirm("Continue?", false)? {
+        true => Ok(()),
+        false => Err(Error::Rustea("Aborted, nothing was changed".into())),
+    }
+}
+
+/// This function lets the user choose some of the `items`, which are listed with their `label`.
+pub fn choose<T, F>(items: Vec<T>, label: F) -> Result<Vec<T>>
+where
+    F: Fn(&T) -> String,
+{
+    if items.is_empty() {
+        return Ok(items);
+    }
+    let labels: Vec<String> = items.iter().map(label).collect();
+    let selected = select(&labels)?;
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| selected.contains(i))
+        .map(|(_, item)| item)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_answer, parse_confirmation, parse_selection, summarize};
+
+    #[test]
+    fn test_parse_selection() {
+        assert_eq!(parse_selection("1,3-5", 5).unwrap(), vec![0, 2, 3, 4]);
+        assert_eq!(parse_selection(" 2 1 2\n", 3).unwrap(), vec![0, 1]);
+        assert_eq!(parse_selection("a", 3).unwrap(), vec![0, 1, 2]);
+        assert!(parse_selection("", 3).unwrap().is_empty());
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("3-1", 3).is_err());
+        assert!(parse_selection("x", 3).is_err());
+    }
+
+    #[test]
+    fn test_parse_answers() {
+        assert_eq!(parse_answer(" ops\n", Some("root")), "ops");
+        assert_eq!(parse_answer("\n", Some("root")), "root");
+        assert_eq!(parse_answer("", None), "");
+        assert_eq!(parse_confirmation("Yes\n", false), Some(true));
+        assert_eq!(parse_confirmation("n", true), Some(false));
+        assert_eq!(parse_confirmation("", true), Some(true));
+        assert_eq!(parse_confirmation("maybe", true), None);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let items = vec![
+            "web/etc/nginx.conf".to_owned(),
+            "web/scripts/run".to_owned(),
+        ];
+        assert_eq!(
+            summarize("Deleting web removes", &items),
+            "Deleting web removes 2 file(s):\n    web/etc/nginx.conf\n    web/scripts/run"
+        );
+        let items: Vec<String> = (0..25).map(|i| i.to_string()).collect();
+        let summary = summarize("Pulling overwrites", &items);
+        assert!(summary.starts_with("Pulling overwrites 25 file(s):\n    0\n"));
+        assert!(summary.ends_with("    19\n    ... and 5 more"));
+    }
+}
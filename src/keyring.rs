@@ -0,0 +1,131 @@
+//! This file implements the storage of API tokens in the secret service of the system.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// The prefix of a configured token which references an entry of the keyring.
+const REFERENCE_PREFIX: &str = "keyring:";
+
+/// The tool used to access the secret service, provided by libsecret.
+const SECRET_TOOL: &str = "secret-tool";
+
+/// Where the API token of a repository is stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenBackend {
+    /// The token is stored in plaintext within the configuration
+    #[default]
+    Plain,
+    /// The token is stored in the secret service and the configuration references it
+    Keyring,
+}
+
+impl TokenBackend {
+    /// Returns true for the default backend.
+    pub fn is_plain(&self) -> bool {
+        *self == TokenBackend::Plain
+    }
+}
+
+/// Returns the keyring account of the repository `owner/repository` at `url`.
+pub fn account(url: &str, owner: &str, repository: &str) -> String {
+    format!("{}/{}/{}", url, owner, repository)
+}
+
+/// Returns the reference to the keyring entry of `account` which replaces the token.
+pub fn reference(account: &str) -> String {
+    format!("{}{}", REFERENCE_PREFIX, account)
+}
+
+/// Returns the referenced account if the `token` is a reference.
+pub fn parse_reference(token: &str) -> Option<&str> {
+    token.strip_prefix(REFERENCE_PREFIX)
+}
+
+fn secret_tool(args: &[&str]) -> Command {
+    let mut cmd = Command::new(SECRET_TOOL);
+    cmd.args(args).args(["service", "rustea", "account"]);
+    cmd
+}
+
+fn spawn_error(e: std::io::Error) -> Error {
+    Error::Rustea(format!(
+        "Failed to run {}, install libsecret to use the keyring: {}",
+        SECRET_TOOL, e
+    ))
+}
+
+/// This function stores the `token` of `account` in the secret service.
+pub fn store(account: &str, token: &str) -> Result<()> {
+    let label = format!("--label=rustea API token for {}", account);
+    let mut child = secret_tool(&["store", &label])
+        .arg(account)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(token.as_bytes())?;
+    }
+    let out = child.wait_with_output()?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "Failed to store the token of {} in the keyring: {}",
+            account,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+/// This function reads the token of `account` from the secret service.
+pub fn lookup(account: &str) -> Result<String> {
+    let out = secret_tool(&["lookup"])
+        .arg(account)
+        .output()
+        .map_err(spawn_error)?;
+    let token = String::from_utf8_lossy(&out.stdout).trim().to_owned();
+    match out.status.success() && !token.is_empty() {
+        true => Ok(token),
+        false => Err(Error::Rustea(format!(
+            "No token for {} found in the keyring",
+            account
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account, parse_reference, reference, TokenBackend};
+
+    #[test]
+    fn test_token_reference() {
+        let account = account("https://git.example.com", "team", "devops");
+        assert_eq!(account, "https://git.example.com/team/devops");
+        let reference = reference(&account);
+        assert_eq!(reference, "keyring:https://git.example.com/team/devops");
+        assert_eq!(parse_reference(&reference), Some(account.as_str()));
+        assert_eq!(parse_reference("0123456789abcdef"), None);
+        assert!(TokenBackend::default().is_plain());
+    }
+}
@@ -2,9 +2,42 @@
 //!
 //! It implements the heavy lifting for the main binary.
 
+pub mod archive;
+pub mod audit;
+pub mod check;
+pub mod checksum;
+pub mod color;
+pub mod daemon;
+pub mod diff;
+pub mod doctor;
 pub mod error;
+pub mod etckeeper;
+pub mod feature;
 pub mod gitea;
+pub mod glob;
+pub mod hooks;
+pub mod http;
+pub mod interactive;
+pub mod keyring;
+pub mod listing;
+pub mod logger;
+pub mod metadata;
+pub mod metrics;
+pub mod notify;
+pub mod oauth;
+pub mod provider;
+pub mod report;
+pub mod scan;
+pub mod schedule;
+pub mod secret;
+pub mod service;
+pub mod settings;
+pub mod staging;
+pub mod state;
+pub mod temp;
+pub mod template;
 pub mod updater;
+pub mod watch;
 /// rustea is a small cli tool to interact with git repositories hosted
 /// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
 ///
@@ -20,63 +53,385 @@ pub mod updater;
 ///
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use archive::{ArchiveFormat, ExportInfo};
+use audit::Operation;
+use checksum::Manifest;
+use color::Color;
 use core::fmt;
 use error::{Error, Result};
+use feature::FeatureManifest;
 use gitea::{
-    gitea_api::{ContentEntry, ContentType, ContentsResponse},
-    GiteaClient,
+    cache,
+    gitea_api::{ApiError, Commit, ContentEntry, ContentType, ContentsResponse, User},
+    FileChange, GiteaClient, RepositoryOptions,
 };
+use glob::Pattern;
+use hooks::{Hook, HookContext, Hooks};
+use http::HttpConfig;
+use keyring::TokenBackend;
+use listing::{FileKind, ListOptions};
+use log::{error, info, warn};
+use metadata::Metadata;
+use metrics::Metrics;
+use notify::{Notify, Payload};
+use oauth::{OAuthConfig, Session};
+use provider::{Provider, RepoBackend};
 use regex::Regex;
+use report::{FeatureReport, FileStatus, Format, Report};
+use scan::Scanner;
+use secret::{SecretFiles, Secrets};
 use serde_derive::{Deserialize, Serialize};
+use staging::Staging;
+use state::{ManagedFile, State, StateLock};
 use std::{
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet},
     env,
+    ffi::{OsStr, OsString},
     fmt::Display,
     fs::{self, File},
     io::{self, Read, Write},
-    os::unix::prelude::PermissionsExt,
+    os::unix::{ffi::OsStrExt, prelude::PermissionsExt},
     path::{Path, PathBuf},
+    time::Duration,
 };
 use tabwriter::TabWriter;
+use temp::TempDir;
+use updater::{AllowedVersions, Updater, UpdaterConfig};
+use watch::Watcher;
 
 /// The version of rustea
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// The default configuration name used by rustea.
-const DEFAULT_CONF_NAME: &str = ".rustea.toml";
+/// The default configuration name used by rustea within its configuration folder.
+const DEFAULT_CONF_NAME: &str = "config.toml";
 
-/// The default path is in the users home directory.
-fn get_default_path() -> Result<String> {
-    match env::var_os("HOME") {
-        Some(val) => {
-            let home = String::from(val.to_str().unwrap());
-            Ok(home + "/" + DEFAULT_CONF_NAME)
+/// The former configuration name used by rustea in the users home directory.
+const LEGACY_CONF_NAME: &str = ".rustea.toml";
+
+/// Returns the default configuration path `$XDG_CONFIG_HOME/rustea/config.toml`,
+/// where `$XDG_CONFIG_HOME` defaults to `~/.config`, and the legacy path `~/.rustea.toml`.
+fn config_paths(
+    xdg_config_home: Option<OsString>,
+    home: Option<OsString>,
+) -> Result<(PathBuf, Option<PathBuf>)> {
+    let home = home.filter(|h| !h.is_empty()).map(PathBuf::from);
+    let config_home = match xdg_config_home.filter(|c| !c.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match &home {
+            Some(home) => home.join(".config"),
+            None => return Err(Error::Configuration(error::ConfigError::LocationError)),
+        },
+    };
+    Ok((
+        config_home.join("rustea").join(DEFAULT_CONF_NAME),
+        home.map(|h| h.join(LEGACY_CONF_NAME)),
+    ))
+}
+
+/// The default path is in the configuration folder of the user, see `config_paths`.
+///
+/// A configuration at the legacy path `~/.rustea.toml` is moved to the default path once.
+/// If this fails the legacy path is used.
+pub fn get_default_path() -> Result<String> {
+    let (path, legacy) = config_paths(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"))?;
+    if let Some(legacy) = legacy.filter(|l| !path.exists() && l.is_file()) {
+        match migrate_config(&legacy, &path) {
+            Ok(()) => info!(
+                "Moved the configuration {} to {}",
+                legacy.display(),
+                path.display()
+            ),
+            Err(e) => {
+                warn!(
+                    "Failed to move the configuration {}: {}",
+                    legacy.display(),
+                    e
+                );
+                return Ok(legacy.display().to_string());
+            }
+        }
+    }
+    Ok(path.display().to_string())
+}
+
+/// This function moves the configuration or state `from` the legacy path `to` the new one.
+fn migrate_config(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A rename fails across file systems, e.g. for a separately mounted home
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// The answers for `create_initial_configuration`, missing ones are asked on the command line.
+#[derive(Debug, Default)]
+pub struct InitOptions {
+    pub url: Option<String>,
+    pub api_token: Option<String>,
+    pub token_name: Option<String>,
+    pub repository: Option<String>,
+    pub owner: Option<String>,
+    /// Store the api token in the keyring
+    pub keyring: bool,
+    /// Create a missing repository with the options
+    pub create_repo: Option<RepositoryOptions>,
+    /// The author of commits, defaults to the name of the token owner
+    pub author: Option<String>,
+    /// The email of commits, defaults to the email of the token owner
+    pub email: Option<String>,
+    /// Authenticate as the user with its password instead of an api token
+    pub username: Option<String>,
+}
+
+/// Returns the `api_token` or for a basic authentication of the `username` without it
+/// the password, which is asked on the terminal.
+fn credential(username: Option<&str>, api_token: Option<String>) -> Result<Option<String>> {
+    match (username, api_token) {
+        (Some(user), None) => {
+            rpassword::read_password_from_tty(Some(&format!("Password of {}: ", user)))
+                .map(Some)
+                .map_err(Error::Io)
         }
-        None => Err(Error::Configuration(error::ConfigError::LocationError)),
+        (_, token) => Ok(token),
+    }
+}
+
+/// Returns the `value` or asks the `question` on the command line if it is missing.
+fn required(value: Option<String>, question: &str) -> Result<String> {
+    let value = match value {
+        Some(value) => value,
+        None => interactive::ask(question, None)?,
+    };
+    match value.trim().is_empty() {
+        true => Err(Error::Rustea(format!("{} is missing", question))),
+        false => Ok(value.trim().to_owned()),
+    }
+}
+
+/// Returns the full name of the `user` or the login if it has none.
+fn user_name(user: &User) -> &str {
+    match user.full_name.trim().is_empty() {
+        true => &user.login,
+        false => &user.full_name,
     }
 }
 
 /// The main configuration is serialized by the toml library.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct RusteaConfiguration {
-    script_folder: PathBuf,
-    exclude: String,
-    repo: RepositoryConfig,
+    pub script_folder: PathBuf,
+    /// The script folders of single feature sets, the others use `script_folder`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub script_folders: BTreeMap<String, PathBuf>,
+    /// The mode of pulled scripts as octal number, e.g. `0700`, 0751 if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_mode: Option<String>,
+    /// The script modes of single feature sets, the others use `script_mode`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub script_modes: BTreeMap<String, String>,
+    /// The owner as `user:group` of pulled files matching the glob patterns,
+    /// an owner in the metadata sidecar takes precedence
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ownership: BTreeMap<String, String>,
+    /// Glob patterns of files and folders which are not pushed
+    #[serde(deserialize_with = "one_or_many")]
+    pub exclude: Vec<String>,
+    /// The external program used by `rustea diff`
+    pub difftool: Option<String>,
+    /// The external three-way merge program used to resolve conflicts
+    pub mergetool: Option<String>,
+    /// The feature sets deployed by `rustea pull --assigned` and `rustea apply`
+    #[serde(default, alias = "features", skip_serializing_if = "Vec::is_empty")]
+    pub assigned: Vec<String>,
+    /// The feature sets whose configuration files are pulled into and pushed from
+    /// the home directory instead of `/`, e.g. for dotfiles
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub home_feature_sets: Vec<String>,
+    /// The folders into which pulled files may be written besides the script folder,
+    /// every folder except the pseudo file systems if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_paths: Vec<PathBuf>,
+    /// Commit pulled files below `/etc` into the local etckeeper or git repository
+    #[serde(default)]
+    pub etckeeper: bool,
+    /// Don't keep the remote tree in `~/.cache/rustea` between runs
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Move deleted files into the trash of the repository, see `TRASH_FOLDER`
+    #[serde(default)]
+    pub trash: bool,
+    /// The metrics file written by `watch` and `apply` for the node_exporter textfile collector
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics_file: Option<PathBuf>,
+    /// The audit log of all write operations, `~/.rustea.audit.jsonl` if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+    /// The path of the allowed versions manifest for the updater within the repository
+    pub update_manifest: Option<String>,
+    /// The variables substituted in template files on pull
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+    pub repo: RepositoryConfig,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub identities: BTreeMap<String, Identity>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Timeouts, proxy and certificates of the connection to the Gitea instance
+    #[serde(default, skip_serializing_if = "HttpConfig::is_empty")]
+    pub http: HttpConfig,
+    /// The files which are encrypted within the feature sets
+    #[serde(default, skip_serializing_if = "Secrets::is_empty")]
+    pub secrets: Secrets,
+    /// The source of the releases installed by `rustea update`
+    #[serde(default, skip_serializing_if = "UpdaterConfig::is_default")]
+    pub updater: UpdaterConfig,
+    /// The webhook which is notified about changes of the remote repository
+    #[serde(default, skip_serializing_if = "Notify::is_empty")]
+    pub notify: Notify,
+    /// Additional rules of the secret scan before a push as name and regex
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scan_rules: BTreeMap<String, String>,
+    /// Named repositories which replace `repo` if selected with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// The author of all commits given with `--author`
+    #[serde(skip)]
+    pub author_override: Option<String>,
+    /// The email of all commits given with `--email`
+    #[serde(skip)]
+    pub email_override: Option<String>,
+}
+
+/// Older configurations define a single exclude pattern instead of a list.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The exclude regex of the configurations created by older versions, which kept
+/// the `.git` folders out of a push.
+const LEGACY_EXCLUDE: &str = r"\.git$";
+
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match <OneOrMany as serde::Deserialize>::deserialize(deserializer)? {
+            OneOrMany::One(s) if s == LEGACY_EXCLUDE => vec![".git".to_owned()],
+            OneOrMany::One(s) => {
+                if s.contains(['\\', '^', '$', '(', '|', '+']) {
+                    warn!(
+                        "The exclude pattern {} is read as glob pattern, \
+                         replace the regular expression with a glob pattern",
+                        s
+                    );
+                }
+                vec![s]
+            }
+            OneOrMany::Many(v) => v,
+        },
+    )
 }
 
 impl Display for RusteaConfiguration {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "rustea version {}\nscript_folder = {}\nexclude= {}\nrepo = {{\n{}\n}}",
+            "rustea version {}\nscript_folder = {}\nexclude= [{}]\nrepo = {{\n{}\n}}",
             VERSION,
             self.script_folder.canonicalize().unwrap().display(),
-            self.exclude,
+            self.exclude.join(", "),
             self.repo
         )
     }
 }
 
 impl RusteaConfiguration {
+    /// Returns the path of the audit log, see `audit::get_default_audit_path`.
+    pub fn audit_path(&self) -> Result<PathBuf> {
+        match &self.audit_log {
+            Some(path) => Ok(path.clone()),
+            None => audit::get_default_audit_path(),
+        }
+    }
+
+    /// Returns the path of the cache file of the repository or `None` if the cache is disabled.
+    pub fn cache_path(&self) -> Option<PathBuf> {
+        match self.no_cache {
+            true => None,
+            false => cache::default_dir().map(|d| {
+                d.join(cache::file_name(
+                    &self.repo.url,
+                    &self.repo.owner,
+                    &self.repo.repository,
+                ))
+            }),
+        }
+    }
+
+    /// Returns the mode of the pulled scripts of `feature_set` without one in the sidecar.
+    pub fn script_mode(&self, feature_set: &str) -> Result<u32> {
+        match self
+            .script_modes
+            .get(feature_set)
+            .or(self.script_mode.as_ref())
+        {
+            Some(mode) => metadata::parse_mode(mode),
+            None => Ok(metadata::DEFAULT_SCRIPT_MODE),
+        }
+    }
+
+    /// This function compiles the patterns of the `ownership`, the longest pattern comes first.
+    pub fn ownership(&self) -> Result<Vec<(Pattern, &str)>> {
+        let mut ownership = self
+            .ownership
+            .iter()
+            .map(|(glob, owner)| Ok((glob, Pattern::new(glob)?, owner.as_str())))
+            .collect::<Result<Vec<_>>>()?;
+        ownership.sort_by_key(|(glob, _, _)| std::cmp::Reverse(glob.len()));
+        Ok(ownership.into_iter().map(|(_, p, o)| (p, o)).collect())
+    }
+
+    /// This function returns the author and email used for commits to a feature set.
+    /// Feature sets without an entry in `identities` use the repository identity.
+    /// The `author_override` and `email_override` replace both.
+    pub fn identity(&self, feature_set: &str) -> (&str, &str) {
+        let (author, email) = match self.identities.get(feature_set) {
+            Some(id) => (&id.author, &id.email),
+            None => (&self.repo.author, &self.repo.email),
+        };
+        (
+            self.author_override.as_deref().unwrap_or(author),
+            self.email_override.as_deref().unwrap_or(email),
+        )
+    }
+
+    /// This function returns the identity used for commits to a feature set like `identity`.
+    /// It fails if the author or email is empty, otherwise the commits would have none.
+    pub fn commit_identity(&self, feature_set: &str) -> Result<(&str, &str)> {
+        let (author, email) = self.identity(feature_set);
+        let missing = match (author.trim().is_empty(), email.trim().is_empty()) {
+            (false, false) => return Ok((author, email)),
+            (true, true) => "author and email are",
+            (true, false) => "author is",
+            (false, true) => "email is",
+        };
+        Err(Error::Rustea(format!(
+            "The commit {} not configured for feature set {}, set them with \
+             `rustea config set repo.author <name>` and `rustea config set repo.email <email>` \
+             or use --author and --email",
+            missing, feature_set
+        )))
+    }
+
     /// This function tries to read and convert the file provided as `PathBuf` into a new `Configuration`.
     pub fn read_config_file(path: Option<&str>) -> Result<RusteaConfiguration> {
         let path = PathBuf::from(path.unwrap_or(&get_default_path()?));
@@ -89,24 +444,314 @@ impl RusteaConfiguration {
     pub fn write_config_file(&self, file_path: &Path) -> Result<()> {
         // toml::to_string_pretty(self).and_then(|c| write_file(&c, file_path))
         let conf_string = toml::to_string_pretty(self)?;
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
         let mut file = File::create(file_path)?;
         file.write_all(conf_string.as_bytes()).map_err(Error::Io)
     }
 
     /// This function creates a new rustea configuration and stores it
-    /// in the users home directory. If no api token is provided, rustea
-    /// tries to create a new one by asking the users serveral questions.
-    pub fn create_initial_configuration(
+    /// in the configuration folder of the user.
+    ///
+    /// The url, owner and repository are asked on the command line if they are not
+    /// provided. If no api token is provided, rustea tries to create a new one by asking
+    /// the users serveral questions. Afterwards the connection and the access of the token
+    /// to the repository are verified. A missing repository is created with the
+    /// `create_repo` options if provided. For an empty repository the user is offered to
+    /// create a first feature set. The author and email used for commits are asked with
+    /// the name and email of the token owner as defaults.
+    /// With `keyring` the token is stored in the secret service of the system.
+    pub fn create_initial_configuration(options: InitOptions) -> Result<PathBuf> {
+        let url = required(options.url, "Url of the Gitea instance")?;
+        let url = url.trim_end_matches('/');
+        let owner = required(options.owner, "Owner of the repository")?;
+        let repository = required(options.repository, "Name of the repository")?;
+        let api_token = credential(options.username.as_deref(), options.api_token)?;
+        let client = GiteaClient::new(
+            url,
+            api_token.as_deref(),
+            options.token_name.as_deref(),
+            &repository,
+            &owner,
+        )?
+        .with_username(options.username);
+        let version = client
+            .get_gitea_version()
+            .map_err(|e| Error::Rustea(format!("Failed to connect to {}: {}", url, e)))?;
+        info!("{}", version);
+        let user = client
+            .get_user()
+            .map_err(|e| Error::Rustea(format!("The api token is not valid: {}", e)))?;
+        let repo = match (client.repository_exists()?, options.create_repo) {
+            (true, _) => client.get_repository_information()?,
+            (false, Some(create)) => {
+                info!("Creating the repository {}/{}", owner, repository);
+                client.create_repository(create)?
+            }
+            (false, None) => {
+                return Err(Error::Rustea(format!(
+                    "The repository {}/{} doesn't exist or the token has no access to it, \
+                     use --create-repo to create it",
+                    owner, repository
+                )))
+            }
+        };
+        info!("{}", repo);
+        if !repo.permissions.push {
+            warn!(
+                "The user {} has no write access to {}, pushes will fail",
+                user.login, repo.full_name
+            );
+        }
+
+        let author = match options.author {
+            Some(author) => author,
+            None => interactive::ask("Author of the commits", Some(user_name(&user)))?,
+        };
+        let email = match options.email {
+            Some(email) => email,
+            None => interactive::ask("Email of the commits", Some(&user.email))?,
+        };
+        if repo.empty
+            && interactive::confirm("The repository is empty, create a feature set?", false)?
+        {
+            let name = required(None, "Name of the feature set")?;
+            for file in &["/.gitkeep", "/scripts/.gitkeep"] {
+                client.create_or_update_file(&name, file, b"", &author, &email, None)?;
+            }
+            info!("Created new feature set {}", name);
+        }
+
+        let mut conf = RusteaConfiguration {
+            script_folder: PathBuf::from("/usr/local/bin"),
+            script_folders: BTreeMap::new(),
+            script_mode: None,
+            script_modes: BTreeMap::new(),
+            ownership: BTreeMap::new(),
+            exclude: vec![".git".to_owned()],
+            difftool: None,
+            mergetool: None,
+            assigned: vec![],
+            home_feature_sets: vec![],
+            allowed_paths: vec![],
+            trash: false,
+            metrics_file: None,
+            audit_log: None,
+            etckeeper: false,
+            no_cache: false,
+            update_manifest: None,
+            vars: BTreeMap::new(),
+            repo: RepositoryConfig {
+                url: client.url,
+                api_token: client.api_token,
+                repository: client.repository,
+                owner: client.owner,
+                email,
+                author,
+                branch: None,
+                token_backend: match options.keyring {
+                    true => TokenBackend::Keyring,
+                    false => TokenBackend::Plain,
+                },
+                token_command: None,
+                provider: Provider::Gitea,
+                username: client.username,
+                ssh_key: None,
+                oauth: None,
+            },
+            identities: BTreeMap::new(),
+            hooks: Hooks::default(),
+            http: HttpConfig::default(),
+            notify: Notify::default(),
+            secrets: Secrets::default(),
+            updater: UpdaterConfig::default(),
+            scan_rules: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            author_override: None,
+            email_override: None,
+        };
+
+        conf.repo.store_token()?;
+        let path = PathBuf::from(get_default_path()?);
+        conf.write_config_file(&path).and(Ok(path))
+    }
+
+    /// This function creates the configuration of a host from a `base` configuration shared
+    /// by many hosts. `{{ hostname }}`, `{{ short_hostname }}` and the `vars` are replaced
+    /// in the base first. If the base has a profile named like the `hostname` or its short
+    /// form, it becomes the repository of the host. The `api_token` is used for every
+    /// repository without a token or token command.
+    pub fn from_base(
+        base: &str,
+        hostname: &str,
+        vars: &BTreeMap<String, String>,
+        api_token: Option<&str>,
+    ) -> Result<RusteaConfiguration> {
+        let short = hostname.split('.').next().unwrap_or(hostname);
+        let mut all = vars.clone();
+        all.entry("hostname".into())
+            .or_insert_with(|| hostname.to_owned());
+        all.entry("short_hostname".into())
+            .or_insert_with(|| short.to_owned());
+        let mut conf: RusteaConfiguration = toml::from_str(&template::render(base, &all)?)?;
+        if let Some(profile) = [hostname, short]
+            .iter()
+            .copied()
+            .find(|name| conf.profiles.contains_key(*name))
+        {
+            conf.use_profile(profile)?;
+        }
+        if let Some(token) = api_token {
+            let repos = std::iter::once(&mut conf.repo)
+                .chain(conf.profiles.values_mut().map(|p| &mut p.repo));
+            for repo in repos.filter(|r| r.api_token.is_empty() && r.token_command.is_none()) {
+                repo.api_token = token.to_owned();
+            }
+        }
+        Ok(conf)
+    }
+
+    /// This function downloads the base configuration from `url` and stores the configuration
+    /// of this host created by `from_base` in the configuration folder of the user.
+    /// The token is the `api_token` or the one of `RUSTEA_API_TOKEN`, with `keyring` it is
+    /// stored in the secret service of the system.
+    pub fn create_configuration_from_url(
+        url: &str,
+        vars: &BTreeMap<String, String>,
+        api_token: Option<String>,
+        keyring: bool,
+    ) -> Result<PathBuf> {
+        let agent = GiteaClient::create_api_client(&HttpConfig::default())?;
+        let base = agent
+            .get(url)
+            .call()
+            .map_err(|e| {
+                Error::Rustea(format!(
+                    "Failed to download the base configuration {}: {}",
+                    url, e
+                ))
+            })?
+            .into_string()?;
+        let api_token = api_token.or_else(|| {
+            env::var(TOKEN_VAR)
+                .ok()
+                .map(|t| t.trim().to_owned())
+                .filter(|t| !t.is_empty())
+        });
+        let mut conf =
+            RusteaConfiguration::from_base(&base, &report::hostname(), vars, api_token.as_deref())
+                .map_err(|e| Error::Rustea(format!("Invalid base configuration {}: {}", url, e)))?;
+        if keyring {
+            conf.repo.token_backend = TokenBackend::Keyring;
+        }
+        conf.store_tokens()?;
+        let path = PathBuf::from(get_default_path()?);
+        conf.write_config_file(&path).and(Ok(path))
+    }
+
+    /// This function moves the plaintext tokens of the repository and all profiles to
+    /// the keyring if it is their backend. Returns true if the configuration changed.
+    pub fn store_tokens(&mut self) -> Result<bool> {
+        let mut changed = self.repo.store_token()?;
+        for profile in self.profiles.values_mut() {
+            changed |= profile.repo.store_token()?;
+        }
+        Ok(changed)
+    }
+
+    /// This function replaces the repository and script folder with the ones
+    /// of the profile `name`.
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or_else(|| Error::Rustea(format!("No profile named {}", name)))?;
+        self.repo = profile.repo;
+        if let Some(folder) = profile.script_folder {
+            self.script_folder = folder;
+        }
+        if let Some(secrets) = profile.secrets {
+            self.secrets = secrets;
+        }
+        Ok(())
+    }
+
+    /// This function adds the `profile` under `name`.
+    /// An existing profile with the same name is replaced.
+    pub fn add_profile(&mut self, name: &str, profile: Profile) {
+        self.profiles.insert(name.to_owned(), profile);
+    }
+
+    /// This function removes the profile `name`.
+    pub fn remove_profile(&mut self, name: &str) -> Result<()> {
+        self.profiles
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| Error::Rustea(format!("No profile named {}", name)))
+    }
+
+    /// Returns the repository of the profile `name` or the default one if `None`.
+    pub fn repo_mut(&mut self, name: Option<&str>) -> Result<&mut RepositoryConfig> {
+        match name {
+            Some(name) => self
+                .profiles
+                .get_mut(name)
+                .map(|p| &mut p.repo)
+                .ok_or_else(|| Error::Rustea(format!("No profile named {}", name))),
+            None => Ok(&mut self.repo),
+        }
+    }
+
+    /// This function lists the default repository and all profiles.
+    pub fn list_profiles(&self) -> String {
+        let mut tw = TabWriter::new(vec![]);
+        let repos = std::iter::once(("default", &self.repo))
+            .chain(self.profiles.iter().map(|(n, p)| (n.as_str(), &p.repo)));
+        for (name, repo) in repos {
+            let _ = writeln!(
+                &mut tw,
+                "{}\t{}\t{}/{}",
+                name, repo.url, repo.owner, repo.repository
+            );
+        }
+        match tw.into_inner() {
+            Ok(w) => String::from_utf8_lossy(&w).trim_end().to_owned(),
+            Err(e) => format!("Failed to align profiles: {}", e),
+        }
+    }
+}
+
+/// A `Profile` is a named repository with an optional own script folder
+/// and encryption of secret files.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_folder: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<Secrets>,
+    pub repo: RepositoryConfig,
+}
+
+impl Profile {
+    /// This function creates a new profile like `create_initial_configuration`.
+    /// If no api token is provided, rustea tries to create a new one.
+    /// With a `username` the password of the user is used instead of a token.
+    pub fn new(
         url: &str,
         api_token: Option<&str>,
         token_name: Option<&str>,
         repository: &str,
         owner: &str,
-    ) -> Result<PathBuf> {
-        let client = GiteaClient::new(url, api_token, token_name, repository, owner)?;
-        let conf = RusteaConfiguration {
-            script_folder: PathBuf::from("/usr/local/bin"),
-            exclude: r"\.git$".to_owned(),
+        script_folder: Option<PathBuf>,
+        username: Option<String>,
+    ) -> Result<Profile> {
+        let api_token = credential(username.as_deref(), api_token.map(str::to_owned))?;
+        let client = GiteaClient::new(url, api_token.as_deref(), token_name, repository, owner)?
+            .with_username(username);
+        Ok(Profile {
+            script_folder,
+            secrets: None,
             repo: RepositoryConfig {
                 url: client.url,
                 api_token: client.api_token,
@@ -114,24 +759,157 @@ impl RusteaConfiguration {
                 owner: client.owner.clone(),
                 email: String::new(),
                 author: client.owner,
+                branch: None,
+                token_backend: TokenBackend::Plain,
+                token_command: None,
+                provider: Provider::Gitea,
+                username: client.username,
+                ssh_key: None,
+                oauth: None,
             },
-        };
+        })
+    }
+}
 
-        let path = PathBuf::from(get_default_path()?);
-        conf.write_config_file(&path).and(Ok(path))
+/// Files of a feature set with the information whether it is a script file.
+type SelectedFiles = Vec<(ContentEntry, bool)>;
+
+/// The change of a prepared file of a push.
+enum PushChange {
+    /// The file is unchanged and recorded with the remote `sha`, the `content` is the
+    /// one of the remote file for its checksum
+    Unchanged { sha: String, content: Vec<u8> },
+    /// The file changed remotely since it was read
+    Conflict(String),
+    /// The file is pushed with the content
+    Changed(Vec<u8>),
+}
+
+/// A file of a push which is prepared without touching the metadata and checksums,
+/// so a file which fails leaves no trace in the pushed sidecar files.
+struct PreparedFile {
+    /// The remote path without the suffix of encrypted files, unique within a push
+    target: String,
+    remote_path: String,
+    full_path: String,
+    link: bool,
+    /// The mode of a script file
+    mode: Option<u32>,
+    change: PushChange,
+}
+
+/// The files which failed during a push, pull or delete with `keep_going` and their errors.
+type Failures = Vec<(PathBuf, Error)>;
+
+/// This function returns the `failed` files as `Error::Batch` if the `action` failed
+/// for some of the `total` files.
+fn failures(action: &str, total: usize, failed: Failures) -> Result<()> {
+    if failed.is_empty() {
+        return Ok(());
     }
+    warn!("{} failed for {} of {} files", action, failed.len(), total);
+    Err(Error::Batch(failed))
 }
 
 /// This struct defines the access to the remote repository
 /// which contains the features sets used by rustea.
-#[derive(Debug, Default, Deserialize, Serialize)]
-struct RepositoryConfig {
-    url: String,
-    api_token: String,
-    repository: String,
-    owner: String,
-    email: String,
-    author: String,
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct RepositoryConfig {
+    pub url: String,
+    /// May be empty if the token is provided by `token_command` or `RUSTEA_API_TOKEN`
+    #[serde(default)]
+    pub api_token: String,
+    pub repository: String,
+    pub owner: String,
+    pub email: String,
+    pub author: String,
+    /// The branch used for the feature sets, the default branch if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Where the `api_token` is stored, with `keyring` it only holds a reference
+    #[serde(default, skip_serializing_if = "TokenBackend::is_plain")]
+    pub token_backend: TokenBackend,
+    /// A shell command which prints the API token, used instead of `api_token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_command: Option<String>,
+    /// The service which hosts the repository, Gitea if not set
+    #[serde(default, skip_serializing_if = "Provider::is_gitea")]
+    pub provider: Provider,
+    /// The user of a basic authentication with Gitea, the api token is its password then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// The private SSH key used by the git provider, the keys of the ssh agent if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<PathBuf>,
+    /// The OAuth2 application of `rustea login`, used instead of the api token if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth: Option<OAuthConfig>,
+}
+
+/// The environment variable which overrides the configured API token.
+pub const TOKEN_VAR: &str = "RUSTEA_API_TOKEN";
+
+impl RepositoryConfig {
+    /// This function reads the OAuth2 tokens of `rustea login` if the repository uses them.
+    pub fn oauth_session(&self) -> Result<Option<Session>> {
+        match &self.oauth {
+            Some(oauth) => {
+                let path = oauth::token_path(&self.url, &self.owner, &self.repository)?;
+                Session::load(oauth.clone(), path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// This function returns the API token. The variable `RUSTEA_API_TOKEN` takes
+    /// precedence over the configured token, see `configured_token`.
+    pub fn token(&self) -> Result<String> {
+        match env::var(TOKEN_VAR) {
+            Ok(token) if !token.trim().is_empty() => Ok(token.trim().to_owned()),
+            _ => self.configured_token(),
+        }
+    }
+
+    /// This function returns the output of the `token_command` if configured.
+    /// Otherwise the `api_token` is returned, which is read from the keyring if
+    /// it is a reference.
+    pub fn configured_token(&self) -> Result<String> {
+        if let Some(cmd) = &self.token_command {
+            let out = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .output()
+                .map_err(|e| Error::Rustea(format!("Failed to run {}: {}", cmd, e)))?;
+            let token = String::from_utf8_lossy(&out.stdout).trim().to_owned();
+            return match out.status.success() && !token.is_empty() {
+                true => Ok(token),
+                false => Err(Error::Rustea(format!(
+                    "The token command {} failed: {}",
+                    cmd,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ))),
+            };
+        }
+        match keyring::parse_reference(&self.api_token) {
+            Some(account) => keyring::lookup(account),
+            None => Ok(self.api_token.clone()),
+        }
+    }
+
+    /// This function moves a plaintext token to the keyring if it is the configured
+    /// backend and replaces it with a reference. Returns true if the token was moved.
+    pub fn store_token(&mut self) -> Result<bool> {
+        if self.token_backend.is_plain()
+            || self.api_token.is_empty()
+            || keyring::parse_reference(&self.api_token).is_some()
+        {
+            return Ok(false);
+        }
+        let account = keyring::account(&self.url, &self.owner, &self.repository);
+        keyring::store(&account, &self.api_token)?;
+        self.api_token = keyring::reference(&account);
+        Ok(true)
+    }
 }
 
 impl Display for RepositoryConfig {
@@ -145,8 +923,17 @@ impl Display for RepositoryConfig {
              \trepository\t= {}
              \towner\t= {}
              \temail\t= {}
-             \tauthor\t= {}",
-            self.url, self.api_token, self.repository, self.owner, self.email, self.author
+             \tauthor\t= {}
+             \tbranch\t= {}
+             \tprovider\t= {}",
+            self.url,
+            self.api_token,
+            self.repository,
+            self.owner,
+            self.email,
+            self.author,
+            self.branch.as_deref().unwrap_or("default"),
+            self.provider
         )
         .unwrap();
 
@@ -157,12 +944,38 @@ impl Display for RepositoryConfig {
     }
 }
 
+/// An `Identity` overrides the commit author for a single feature set.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Identity {
+    pub author: String,
+    pub email: String,
+}
+
 /// The `RemoteRepository` deals with the actual backend repository
 /// and handles all the actions that can take place.
 pub struct RemoteRepository {
     config: RusteaConfiguration,
-    api: GiteaClient,
+    /// The client of the configured provider, see `provider::connect`
+    api: Box<dyn RepoBackend>,
     local_repo: LocalRepository,
+    /// The name of the host overlay used within feature sets
+    hostname: String,
+    /// Let the user choose the files of a pull or push
+    interactive: bool,
+    /// Encrypt the secret files of a push
+    encrypt: bool,
+    /// Push files even if the secret scan finds possible secrets
+    allow_secrets: bool,
+    /// Ask before files are deleted, renamed or overwritten
+    confirm: bool,
+    /// Overwrite remote files which changed since they were pulled or pushed
+    force: bool,
+    /// Remove local files of a full pull which were removed from the feature set
+    prune: bool,
+    /// Continue a push, pull or delete with the other files if a file fails
+    keep_going: bool,
+    /// Whether the api token may push into the repository, checked before the first change
+    push_permission: Cell<Option<bool>>,
 }
 
 impl Display for RemoteRepository {
@@ -182,60 +995,496 @@ impl RemoteRepository {
     ///   - `Error::Api` if the real client could not constructed
     ///  - ``
     pub fn new(config: RusteaConfiguration) -> Result<Self> {
-        let c = GiteaClient::new(
-            &config.repo.url,
-            Some(&config.repo.api_token),
-            None,
-            &config.repo.repository,
-            &config.repo.owner,
-        )
-        .map_err(Error::Api)?;
-        let local_repo = LocalRepository::new(&config.exclude, config.script_folder.clone())?;
+        let api = provider::connect(&config.repo, &config.http, config.cache_path().as_deref())?;
+        let local_repo = LocalRepository::new(
+            &config.exclude,
+            config.script_folder.clone(),
+            config.script_folders.clone(),
+            config.secrets.files()?,
+            config.home_feature_sets.clone(),
+            config.allowed_paths.clone(),
+        )?;
         //check_folder(&config.script_folder)?;
         Ok(RemoteRepository {
             config,
-            api: c,
+            api,
             local_repo,
+            hostname: report::hostname(),
+            interactive: false,
+            encrypt: false,
+            allow_secrets: false,
+            confirm: false,
+            force: false,
+            prune: false,
+            keep_going: false,
+            push_permission: Cell::new(None),
         })
     }
 
+    /// This function enables the interactive selection of the files which are pulled or pushed.
+    pub fn with_interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// This function enables the confirmation of deletions, renames and pulls which
+    /// overwrite local files. The affected files are summarized before.
+    pub fn with_confirm(mut self, confirm: bool) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// This function asks whether the `action` on the affected `files` continues if
+    /// confirmations are enabled, see `interactive::confirm_destruction`.
+    /// The `files` are only collected in this case.
+    fn confirm<F>(&self, action: &str, files: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<Vec<String>>,
+    {
+        if !self.confirm {
+            return Ok(());
+        }
+        match files()? {
+            files if files.is_empty() => Ok(()),
+            files => interactive::confirm_destruction(action, &files),
+        }
+    }
+
+    /// This function applies the `change` of the feature set `name`, like a push, on a new
+    /// branch instead of the configured branch and opens a pull request for it.
+    /// This allows changes of repositories whose branch is protected against direct writes.
+    ///
+    /// The branch `rustea/<name>-<timestamp>` is created at the tip of the configured or
+    /// default branch. The pull request is titled with the first line of the commit message
+    /// `cmt_msg` or of the result of the `change`. Without any commit the branch is deleted
+    /// again and no pull request is opened.
+    pub fn via_pull_request<F>(
+        &mut self,
+        name: &str,
+        cmt_msg: Option<&str>,
+        change: F,
+    ) -> Result<String>
+    where
+        F: FnOnce(&Self) -> Result<String>,
+    {
+        let base = match self.api.branch() {
+            Some(branch) => branch.to_owned(),
+            None => self.api.default_branch()?,
+        };
+        let head = pull_request_branch(name, state::now());
+        let tip = self.api.create_branch(&head, &base)?;
+        info!("Created branch {} from {}", head, base);
+        let configured = self.api.set_branch(Some(head.clone()));
+        let res = change(self);
+        self.api.set_branch(configured);
+        let changed = match res {
+            Ok(out) => match self.api.commit_sha(&head) {
+                Ok(sha) if sha == tip => Ok(None),
+                Ok(_) => Ok(Some(out)),
+                Err(e) => Err(Error::Api(e)),
+            },
+            Err(e) => Err(e),
+        };
+        let out = match changed {
+            Ok(Some(out)) => out,
+            Ok(None) => {
+                self.api.delete_branch(&head)?;
+                return Ok(format!(
+                    "Nothing changed, no pull request opened for {}",
+                    name
+                ));
+            }
+            Err(e) => {
+                if let Err(d) = self.api.delete_branch(&head) {
+                    warn!("Failed to delete the branch {}: {}", head, d);
+                }
+                return Err(e);
+            }
+        };
+        let title = cmt_msg
+            .and_then(|m| m.lines().next())
+            .or_else(|| out.lines().next())
+            .unwrap_or(name);
+        let pr = self.api.create_pull_request(&head, &base, title, &out)?;
+        Ok(format!(
+            "{}\nOpened pull request #{} {}",
+            out, pr.number, pr.html_url
+        ))
+    }
+
+    /// This function applies the `change` of the feature set `name` to the configured branch.
+    /// If the branch protection rejects it, the change is offered to be made through a
+    /// pull request with `via_pull_request`. Without confirmations the error suggests it.
+    pub fn or_via_pull_request<F>(
+        &mut self,
+        name: &str,
+        cmt_msg: Option<&str>,
+        change: F,
+    ) -> Result<String>
+    where
+        F: Fn(&Self) -> Result<String>,
+    {
+        match change(self) {
+            Err(Error::Api(ApiError::ProtectedBranch(e))) => {
+                let question = format!(
+                    "The branch is protected ({}). Open a pull request for {} instead?",
+                    e, name
+                );
+                match self.confirm && interactive::confirm(&question, true)? {
+                    true => self.via_pull_request(name, cmt_msg, |r| change(r)),
+                    false => Err(Error::Api(ApiError::ProtectedBranch(format!(
+                        "{}, use --via-pr to change {} through a pull request",
+                        e, name
+                    )))),
+                }
+            }
+            res => res,
+        }
+    }
+
+    /// Returns the commit identity of changes of the feature set `name`. Before the first
+    /// change the permissions of the repository are requested, so a read-only token fails
+    /// before anything is written instead of in the middle of a change.
+    fn commit_identity(&self, name: &str) -> Result<(&str, &str)> {
+        let push = match self.push_permission.get() {
+            Some(push) => push,
+            None => {
+                let push = self.api.can_push()?;
+                self.push_permission.set(Some(push));
+                push
+            }
+        };
+        if !push {
+            return Err(Error::Api(ApiError::PermissionDenied(format!(
+                "The api token has no push permission for {}/{}, it can only read the repository. \
+                 Use the token of a user with write access",
+                self.config.repo.owner, self.config.repo.repository
+            ))));
+        }
+        self.config.commit_identity(name)
+    }
+
+    /// This function records the `operation` on the feature set `name` in the audit log.
+    /// Changes of the remote repository are recorded with the commit author and sent
+    /// to the configured webhook. A failure is only logged, the operation already happened.
+    fn audit(
+        &self,
+        operation: Operation,
+        name: &str,
+        files: Vec<String>,
+        commits: Vec<String>,
+        cmt_msg: Option<&str>,
+    ) {
+        let mut entry = audit::Entry::new(operation, name);
+        if operation != Operation::Pull {
+            entry.author = Some(self.config.identity(name).0.to_owned());
+        }
+        entry.files = files;
+        entry.commits = commits;
+        entry.message = cmt_msg.map(str::to_owned);
+        if let Err(e) = self
+            .config
+            .audit_path()
+            .and_then(|p| audit::append(&p, &entry))
+        {
+            warn!("Failed to write the audit log: {}", e);
+        }
+        if operation != Operation::Pull {
+            self.config.notify.send(&Payload {
+                host: &self.hostname,
+                repository: format!("{}/{}", self.config.repo.owner, self.config.repo.repository),
+                change: &entry,
+            });
+        }
+    }
+
+    /// Returns the remote files below `path`, only the direct children of a folder
+    /// if not `recursive`.
+    fn remote_files(&self, path: &str, recursive: bool) -> Result<Vec<String>> {
+        Ok(self
+            .remote_entries(path, recursive)?
+            .into_iter()
+            .map(|e| e.path)
+            .collect())
+    }
+
+    /// Returns the entries of the `remote_files`.
+    fn remote_entries(&self, path: &str, recursive: bool) -> Result<Vec<ContentEntry>> {
+        let files = self.api.list_files(path)?.into_iter();
+        Ok(files
+            .filter(|e| {
+                let p = &e.path;
+                recursive || p == path || p.rsplit_once('/').map(|(d, _)| d) == Some(path)
+            })
+            .collect())
+    }
+
+    /// This function enables the encryption of pushed files which match the `[secrets]`
+    /// patterns. Without it such files are refused.
+    pub fn with_encrypt(mut self, encrypt: bool) -> Self {
+        self.encrypt = encrypt;
+        self
+    }
+
+    /// This function disables the refusal of pushes with possible secrets like
+    /// access keys or private keys.
+    pub fn with_allow_secrets(mut self, allow_secrets: bool) -> Self {
+        self.allow_secrets = allow_secrets;
+        self
+    }
+
+    /// This function lets a push overwrite remote files which changed since they were
+    /// pulled or pushed. Without it such a push is refused with the conflicting files.
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// This function lets a pull of a whole feature set remove the local files which a
+    /// former pull installed and which were removed from the feature set since then.
+    pub fn with_prune(mut self, prune: bool) -> Self {
+        self.prune = prune;
+        self
+    }
+
+    /// This function lets a push, pull or delete continue with the other files if a file
+    /// fails. The failed files are returned as `Error::Batch` once the other files are done.
+    pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// This function places the pulled files below the `prefix` instead of `/`, e.g. to
+    /// populate an image or chroot. Such a pull is not recorded in the local state and
+    /// not committed with etckeeper.
+    pub fn with_prefix(mut self, prefix: Option<PathBuf>) -> Self {
+        self.local_repo.prefix = prefix;
+        self
+    }
+
+    /// This function makes the configuration files of all feature sets relative to the
+    /// home directory of the user instead of `/`, see `home_feature_sets`.
+    pub fn with_home(mut self, home: bool) -> Self {
+        self.local_repo.home_all |= home;
+        self
+    }
+
+    /// This function pushes the content of the linked files instead of symbolic links.
+    /// Without it a symbolic link is pushed with its target, which is marked as
+    /// link in the metadata sidecar and recreated on pull.
+    pub fn with_dereference(mut self, dereference: bool) -> Self {
+        self.local_repo.dereference = dereference;
+        self
+    }
+
+    /// This function pins the pulled files to the `revision`, a tag or commit sha.
+    /// A tag is resolved to its commit.
+    pub fn with_revision(mut self, revision: Option<String>) -> Result<Self> {
+        if let Some(revision) = revision {
+            self.api.set_revision(&revision)?;
+        }
+        Ok(self)
+    }
+
+    /// This function disables the waiting for the rate limit of the remote instance,
+    /// a rejected request fails instead.
+    pub fn with_rate_limit_wait(mut self, wait: bool) -> Self {
+        self.api.set_rate_limit_wait(wait);
+        self
+    }
+
+    /// This function replaces the detected hostname used to select the host overlay.
+    pub fn with_hostname(mut self, hostname: Option<String>) -> Self {
+        if let Some(hostname) = hostname {
+            self.hostname = hostname;
+        }
+        self
+    }
+
     /// This function queries the remote repository root and
     /// returns a list of `ContentEntry` with `ContentType::Dir`.
     /// All directories in the root are considered as feature sets.
-    fn get_feature_sets(&self) -> Result<ContentsResponse> {
-        self.api
-            .get_file_or_folder("", Some(ContentType::Dir))
-            .map_err(Error::Api)
+    fn get_feature_sets(&self) -> Result<Vec<ContentEntry>> {
+        let mut feature_sets = self.api.list("")?;
+        feature_sets.retain(|e| e.content_type == ContentType::Dir && e.name != TRASH_FOLDER);
+        Ok(feature_sets)
     }
 
     /// This function returns true if a certain folder in the remote repository root is found.
     fn check_feature_set_exists(&self, name: &str) -> Result<bool> {
         self.get_feature_sets()
-            .map(|c| c.content.into_iter().any(|e| e.name == name))
+            .map(|c| c.into_iter().any(|e| e.name == name))
+    }
+
+    /// Returns true if the remote `path` is a file managed by rustea itself, which is
+    /// either the metadata sidecar, the checksum manifest or the `feature.toml` of the feature set.
+    fn is_internal_file(path: &str, feature_set: &str) -> bool {
+        metadata::is_sidecar(path, feature_set)
+            || checksum::is_manifest(path, feature_set)
+            || feature::is_manifest(path, feature_set)
+    }
+
+    /// This function returns the files of a feature set, its parsed metadata sidecar
+    /// and checksum manifest. The internal files are not part of the returned files.
+    /// The files of the host overlay replace the base files, see `apply_overlay`.
+    fn get_feature_set_content(
+        &self,
+        name: &str,
+    ) -> Result<(Metadata, Manifest, Vec<ContentEntry>)> {
+        let (internal, files): (Vec<ContentEntry>, Vec<ContentEntry>) = self
+            .api
+            .list_files(name)?
+            .into_iter()
+            .partition(|e| RemoteRepository::is_internal_file(&e.path, name));
+        let files = apply_overlay(files, name, &self.hostname);
+        let mut meta = Metadata::default();
+        let mut manifest = Manifest::default();
+        for e in internal {
+            if metadata::is_sidecar(&e.path, name) {
+                meta = Metadata::parse(&self.api.download_text(&e.path)?)?;
+            } else if checksum::is_manifest(&e.path, name) {
+                manifest = Manifest::parse(&self.api.download_text(&e.path)?)?;
+            }
+        }
+        Ok((meta, manifest, files))
+    }
+
+    /// This function returns the checksum manifest of a feature set
+    /// or an empty one if the feature set has none yet. Other errors of the
+    /// remote repository are returned, a failed request isn't a missing manifest.
+    fn get_manifest(&self, name: &str) -> Result<Manifest> {
+        let path = format!("{}/{}", name, checksum::MANIFEST_FILE);
+        match self.api.file(&path) {
+            Ok(e) if e.path == path => Manifest::parse(&self.api.download_text(&path)?),
+            Ok(_) | Err(ApiError::NotFound(_)) => Ok(Manifest::default()),
+            Err(e) => Err(Error::Api(e)),
+        }
+    }
+
+    /// This function returns the metadata sidecar of a feature set
+    /// or an empty one if the feature set has none.
+    fn get_metadata(&self, name: &str) -> Result<Metadata> {
+        let path = format!("{}/{}", name, metadata::META_FILE);
+        match self.api.file(&path) {
+            Ok(e) if e.path == path => Metadata::parse(&self.api.download_text(&path)?),
+            Ok(_) | Err(ApiError::NotFound(_)) => Ok(Metadata::default()),
+            Err(e) => Err(Error::Api(e)),
+        }
+    }
+
+    /// This function returns the `feature.toml` of a feature set
+    /// or an empty one if the feature set has none.
+    fn get_feature_manifest(&self, name: &str) -> Result<FeatureManifest> {
+        let path = format!("{}/{}", name, feature::MANIFEST_FILE);
+        match self.api.file(&path) {
+            Ok(e) if e.path == path => FeatureManifest::parse(&self.api.download_text(&path)?),
+            Ok(_) | Err(ApiError::NotFound(_)) => Ok(FeatureManifest::default()),
+            Err(e) => Err(Error::Api(e)),
+        }
+    }
+
+    /// This function selects the files of a feature set which are either script files
+    /// or configuration files and which are the file or below the folder `path` if provided,
+    /// see `in_sub_path`.
+    /// Without the `script` or `config` flag every file is selected.
+    /// Every file is returned with the information whether it is a script file.
+    fn select_files(
+        &self,
+        name: &str,
+        path: Option<&str>,
+        script: bool,
+        config: bool,
+    ) -> Result<(Metadata, Manifest, SelectedFiles)> {
+        let (meta, manifest, feature_set) = self.get_feature_set_content(name)?;
+        let files = feature_set
+            .into_iter()
+            .map(|e| {
+                let is_script = self.local_repo.check_script(&e.path, name);
+                (e, is_script)
+            })
+            .filter(|(_, is_script)| match (script, config) {
+                (true, _) => *is_script,
+                (false, true) => !*is_script,
+                (false, false) => true,
+            })
+            .filter(|(e, _)| path.is_none_or(|p| in_sub_path(&e.path, name, p, script)))
+            .collect();
+        Ok((meta, manifest, files))
     }
 
     /// This function prints informations about the remote instance and the
     /// used repository to the command line.
     pub fn info(&self) -> Result<String> {
-        Ok(format!(
-            "{}\n{}",
-            self.api.get_gitea_version()?,
-            self.api.get_repository_information()?
-        ))
+        Ok(self.api.info()?)
     }
 
     /// This function prints either the feature sets contained in the remote
     /// repository or if `name` is provided all files found in the feature set.
-    pub fn list(&self, feature_set: Option<String>) -> Result<String> {
-        let res = match feature_set {
-            Some(ref n) => self.api.get_folder(n)?,
-            None => self.get_feature_sets()?,
+    /// The description of a feature set from its `feature.toml` is printed before its files.
+    /// The entries are filtered and sorted by the `options`.
+    pub fn list(&self, feature_set: Option<String>, options: &ListOptions) -> Result<String> {
+        let (res, about) = match feature_set {
+            Some(ref n) => (
+                self.api.list_files(n)?,
+                self.get_feature_manifest(n)?.to_string(),
+            ),
+            None => (self.get_feature_sets()?, String::new()),
         };
-        Ok(format!(
-            "{} content:\n{}",
-            feature_set.unwrap_or_else(|| String::from(&self.config.repo.repository)),
-            res
-        ))
+        let content = self
+            .local_repo
+            .select_entries(feature_set.as_deref(), res, options)?;
+        let commits = match options.long && options.commits {
+            true => Some(self.last_commits(&content)?),
+            false => None,
+        };
+        let root = feature_set.unwrap_or_else(|| String::from(&self.config.repo.repository));
+        let listing = render_entries(&root, content, options, commits.as_ref())?;
+        Ok(format!("{}{} content:\n{}", about, root, listing))
+    }
+
+    /// This function fetches the last commit which touched each of the `entries` by path.
+    fn last_commits(&self, entries: &[ContentEntry]) -> Result<BTreeMap<String, Commit>> {
+        let mut commits = BTreeMap::new();
+        for e in entries {
+            if let Some(commit) = self.api.latest_commit(&e.path)? {
+                commits.insert(e.path.clone(), commit);
+            }
+        }
+        Ok(commits)
+    }
+
+    /// This function fetches the allowed versions manifest of the updater
+    /// from the repository if `update_manifest` is configured.
+    pub fn get_update_manifest(&self) -> Result<Option<AllowedVersions>> {
+        match &self.config.update_manifest {
+            Some(path) => AllowedVersions::parse(&self.api.download_text(path)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// This function updates rustea to the latest release of the configured source
+    /// or installs the release of the `version`. The `asset` overrides the name of the binary
+    /// and `pre_release` allows to select release candidates.
+    /// The binary must be allowed by the update manifest if one is configured.
+    pub fn update(
+        &self,
+        minified: bool,
+        version: Option<&str>,
+        asset: Option<&str>,
+        pre_release: bool,
+    ) -> Result<String> {
+        let allowed = self.get_update_manifest()?;
+        Updater::new(self.config.updater.clone())?
+            .with_pre_release(pre_release)
+            .update(minified, version, asset, allowed.as_ref())
+    }
+
+    /// This function compares the running version with the latest release and
+    /// returns true if rustea is outdated.
+    pub fn check_update(&self, pre_release: bool) -> Result<(String, bool)> {
+        Updater::new(self.config.updater.clone())?
+            .with_pre_release(pre_release)
+            .check()
     }
 
     /// This function creates a new feature set within the remote repositories root.
@@ -247,20 +1496,19 @@ impl RemoteRepository {
     /// useless in this case. We only check the HTTP return code.
     pub fn new_feature_set(&self, feature_set: &str, cmt_msg: Option<String>) -> Result<String> {
         if !self.check_feature_set_exists(feature_set)? {
-            self.api.create_or_update_file(
-                feature_set,
-                "/.gitkeep",
+            let (author, email) = self.commit_identity(feature_set)?;
+            self.api.upload(
+                &format!("{}/.gitkeep", feature_set),
                 "".as_bytes(),
-                &self.config.repo.author,
-                &self.config.repo.email,
+                author,
+                email,
                 cmt_msg.as_deref(),
             )?;
-            self.api.create_or_update_file(
-                feature_set,
-                "/scripts/.gitkeep",
+            self.api.upload(
+                &format!("{}/scripts/.gitkeep", feature_set),
                 "".as_bytes(),
-                &self.config.repo.author,
-                &self.config.repo.email,
+                author,
+                email,
                 cmt_msg.as_deref(),
             )?;
         }
@@ -274,12 +1522,16 @@ impl RemoteRepository {
     /// and `script` is set to true `path` shall point to a file name in the scripts folder
     /// of the feature set. Otherwise the function tries to delete a configuration file
     /// folder denoted by path.
+    ///
+    /// With `trash` or the configured `trash` the files are moved below
+    /// `.trash/<timestamp>/` instead, see `restore`.
     pub fn delete(
         &self,
         name: &str,
         path: Option<String>,
         script: bool,
         recursive: bool,
+        trash: bool,
         cmt_msg: Option<String>,
     ) -> Result<String> {
         let (p, r) = match path {
@@ -287,47 +1539,332 @@ impl RemoteRepository {
             Some(path) => (format!("{}/{}", name, path), recursive),
             None => (name.to_owned(), true),
         };
-        self.api
-            .delete_file_or_folder(
-                &p,
-                r,
-                &self.config.repo.author,
-                &self.config.repo.email,
+        let (author, email) = self.commit_identity(name)?;
+        self.confirm(&format!("Deleting {} removes", p), || {
+            self.remote_files(&p, r)
+        })?;
+        if (trash || self.config.trash) && name != TRASH_FOLDER {
+            let dst = trash_path(state::now(), &p);
+            let (moved, commit) = self.move_files(&p, &dst, r, name, cmt_msg.as_deref())?;
+            self.audit(
+                Operation::Delete,
+                name,
+                vec![p.clone()],
+                commit.into_iter().collect(),
                 cmt_msg.as_deref(),
-            )
-            .map_err(Error::Api)?;
-        Ok(format!("Deleted {} successfully.", p))
+            );
+            return Ok(format!(
+                "Moved {} files of {} to the trash {}.",
+                moved,
+                color::paint(Color::Red, p),
+                dst
+            ));
+        }
+        let (commits, files, failed) = match self.keep_going {
+            false => {
+                let commits = self
+                    .api
+                    .delete_folder(&p, r, author, email, cmt_msg.as_deref())
+                    .map_err(Error::Api)?;
+                (commits, 0, vec![])
+            }
+            true => {
+                // The files are deleted in one commit like the changes of a push,
+                // a file without a known sha can't be deleted and is left out
+                let files = self.remote_entries(&p, r)?;
+                let total = files.len();
+                let (mut changes, mut failed) = (vec![], vec![]);
+                for file in files {
+                    match file.sha {
+                        Some(sha) => changes.push(FileChange {
+                            path: file.path,
+                            sha: Some(sha),
+                            content: None,
+                        }),
+                        None => failed.push((
+                            PathBuf::from(&file.path),
+                            Error::Rustea(format!("The sha of {} is unknown", file.path)),
+                        )),
+                    }
+                }
+                let commit = self
+                    .api
+                    .change_files(&changes, author, email, cmt_msg.as_deref())?;
+                (commit.into_iter().collect(), total, failed)
+            }
+        };
+        self.audit(
+            Operation::Delete,
+            name,
+            vec![p.clone()],
+            commits,
+            cmt_msg.as_deref(),
+        );
+        failures(&format!("Deleting {}", p), files, failed)?;
+        Ok(format!(
+            "Deleted {} successfully.",
+            color::paint(Color::Red, p)
+        ))
     }
 
-    /// This function pushes files located in a `path` to the feature set in the remote repository.
+    /// This function pushes local files to the feature set in the remote repository.
+    ///
+    /// Every file is given with the information if it is a script file or configuration
+    /// file. The existence of the files should be validated beforehand.
+    /// It returns the sha of the last commit created by the remote repository.
+    ///
+    /// If the remote file changed since it was last pulled or pushed and the local
+    /// file changed too, the files are merged with the `mergetool` if `merge` is set.
+    /// The merged result is pushed and written to the local file.
     ///
-    /// It distinguishes between script files and configuration files through the `script`
-    /// argument. The existence of the `path` should be validated beforehand.
+    /// Files whose blob sha matches the remote sha are skipped. Afterwards the checksums
+    /// of the pushed files are updated in the manifest `SHA256SUMS` if anything changed.
+    /// All changed files and the manifest are pushed in one commit.
     fn push_files(
         &self,
-        path: &std::path::Path,
+        files: &[(PathBuf, bool)],
         feature_set: &str,
-        script: bool,
         cmt_msg: Option<&str>,
-    ) -> Result<()> {
-        let files = self.local_repo.read_folder(path)?;
-        for file in files {
-            let remote_path = self.local_repo.transform_to_remote_path(&file, script)?;
-            let content = LocalRepository::read_file(&file)?;
-            self.api.create_or_update_file(
-                feature_set,
-                &remote_path,
-                &content,
-                &self.config.repo.author,
-                &self.config.repo.email,
-                cmt_msg,
-            )?;
-            println!(
+        state: &mut State,
+        merge: bool,
+    ) -> Result<(Option<String>, Failures)> {
+        let (author, email) = self.commit_identity(feature_set)?;
+        let mut manifest = self.get_manifest(feature_set)?;
+        let old_manifest = manifest.to_string();
+        // The blob sha of every remote file to skip unchanged files
+        let remote_shas: BTreeMap<String, String> = self
+            .api
+            .list_files(feature_set)?
+            .into_iter()
+            .filter_map(|e| Some((e.path, e.sha?)))
+            .collect();
+        // Symbolic links are marked within the metadata sidecar
+        let meta_path = format!("{}/{}", feature_set, metadata::META_FILE);
+        let mut meta = match remote_shas.contains_key(&meta_path) {
+            true => Metadata::parse(&self.api.download_text(&meta_path)?)?,
+            false => Metadata::default(),
+        };
+        let mut meta_changed = false;
+        let mut changes = vec![];
+        let mut pushed = vec![];
+        let mut conflicts = vec![];
+        let mut seen = BTreeSet::new();
+        let mut failed = vec![];
+        for entry in files {
+            // The changes of a file are applied once it is prepared, a failed file changes nothing
+            let prepared =
+                match self.prepare_push(entry, feature_set, &remote_shas, &seen, state, merge) {
+                    Err(e) if self.keep_going => {
+                        failed.push((entry.0.clone(), e));
+                        continue;
+                    }
+                    res => res?,
+                };
+            let PreparedFile {
+                target,
+                remote_path,
+                full_path,
+                link,
+                mode,
+                change,
+            } = prepared;
+            seen.insert(target);
+            meta_changed |= meta.set_symlink(&remote_path, link);
+            if let Some(mode) = mode {
+                meta_changed |= meta.set_mode(&remote_path, Some(mode));
+            }
+            match change {
+                PushChange::Unchanged { sha, content } => {
+                    state.record_file(feature_set, &full_path, &sha);
+                    manifest.insert(remote_path.trim_start_matches('/'), &content);
+                    info!("Skipped unchanged file {}", remote_path);
+                }
+                PushChange::Conflict(conflict) => conflicts.push(conflict),
+                PushChange::Changed(content) => {
+                    manifest.insert(remote_path.trim_start_matches('/'), &content);
+                    pushed.push((
+                        full_path.clone(),
+                        remote_path,
+                        checksum::git_blob_sha(&content),
+                    ));
+                    changes.push(FileChange {
+                        sha: remote_shas.get(&full_path).cloned(),
+                        path: full_path,
+                        content: Some(content),
+                    });
+                }
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(Error::Rustea(format!(
+                "The remote files changed since they were read, push with --merge \
+                 to resolve the conflicts or with --force to overwrite them\n{}",
+                conflicts.join("\n")
+            )));
+        }
+        if manifest.to_string() != old_manifest {
+            let path = format!("{}/{}", feature_set, checksum::MANIFEST_FILE);
+            changes.push(FileChange {
+                sha: remote_shas.get(&path).cloned(),
+                path,
+                content: Some(manifest.to_string().into_bytes()),
+            });
+        }
+        if meta_changed {
+            changes.push(FileChange {
+                sha: remote_shas.get(&meta_path).cloned(),
+                path: meta_path,
+                content: Some(meta.to_toml()?.into_bytes()),
+            });
+        }
+        let commit = match self.api.change_files(&changes, author, email, cmt_msg) {
+            Err(ApiError::Conflict { message, .. }) => {
+                return Err(Error::Rustea(format!(
+                    "The feature set {} changed remotely during the push. \
+                     Push again to see the conflicting files. Cause: {}",
+                    feature_set, message
+                )))
+            }
+            res => res?,
+        };
+        for (full_path, remote_path, sha) in pushed {
+            state.record_file(feature_set, &full_path, &sha);
+            info!(
                 "Pushed file {} into feature set {}",
-                remote_path, feature_set
+                color::paint(Color::Green, &remote_path),
+                feature_set
             );
         }
-        Ok(())
+        Ok((commit, failed))
+    }
+
+    /// This function reads the local `file` of a push and compares it with the remote file.
+    /// The changes of the push are returned, see `PreparedFile`.
+    fn prepare_push(
+        &self,
+        (file, script): &(PathBuf, bool),
+        feature_set: &str,
+        remote_shas: &BTreeMap<String, String>,
+        seen: &BTreeSet<String>,
+        state: &State,
+        merge: bool,
+    ) -> Result<PreparedFile> {
+        let target = self
+            .local_repo
+            .transform_to_remote_path(file, *script, feature_set)?;
+        if seen.contains(&target) {
+            return Err(Error::Rustea(format!(
+                "{} is pushed to {} like another file, push the scripts from the script folder to keep their folders",
+                file.display(),
+                target
+            )));
+        }
+        let link = self.local_repo.is_symlink(file);
+        let secret = !link && self.local_repo.secrets.matches(file);
+        let remote_path = match secret {
+            true if !self.encrypt => {
+                return Err(Error::Rustea(format!(
+                    "{} is a secret file, push it with --encrypt",
+                    file.display()
+                )))
+            }
+            true => self.local_repo.secrets.encrypted_path(&target),
+            false => target.clone(),
+        };
+        let full_path = format!("{}{}", feature_set, remote_path);
+        let mode = match *script && !link {
+            true => Some(fs::metadata(file)?.permissions().mode()),
+            false => None,
+        };
+        let prepared = |change| PreparedFile {
+            target: target.clone(),
+            remote_path: remote_path.clone(),
+            full_path: full_path.clone(),
+            link,
+            mode,
+            change,
+        };
+        let mut content = match link {
+            true => LocalRepository::read_link(file)?,
+            false => LocalRepository::read_file(file)?,
+        };
+        let local_sha = checksum::git_blob_sha(&content);
+        let remote_sha = remote_shas
+            .get(&full_path)
+            .map(String::as_str)
+            .unwrap_or_default();
+        if secret {
+            // The encryption differs every time, compare the decrypted content
+            let remote = match remote_sha.is_empty() {
+                true => None,
+                false => Some(self.api.blob(remote_sha)?),
+            };
+            if let Some(remote) = remote {
+                if self.config.secrets.decrypt(&remote).ok().as_ref() == Some(&content) {
+                    return Ok(prepared(PushChange::Unchanged {
+                        sha: remote_sha.to_owned(),
+                        content: remote,
+                    }));
+                }
+            }
+            content = self.config.secrets.encrypt(&content)?;
+        } else if local_sha == remote_sha {
+            return Ok(prepared(PushChange::Unchanged {
+                sha: local_sha,
+                content,
+            }));
+        }
+
+        if let (false, Some(base)) = (secret || link, state.file_sha(feature_set, &full_path)) {
+            if diff::is_conflict(Some(base), &local_sha, remote_sha) {
+                if merge {
+                    let remote = self.api.blob(remote_sha)?;
+                    content = self.merge(&full_path, base, &content, &remote)?;
+                    File::create(file)?.write_all(&content)?;
+                } else if self.force {
+                    warn!("Overwriting remote changes of {}", full_path);
+                } else if self.keep_going {
+                    return Err(Error::Rustea(format!(
+                        "The remote file changed since it was read at {}, remote at {}",
+                        base, remote_sha
+                    )));
+                } else {
+                    return Ok(prepared(PushChange::Conflict(format!(
+                        "{}: read at {}, remote at {}",
+                        full_path, base, remote_sha
+                    ))));
+                }
+            }
+        }
+        Ok(prepared(PushChange::Changed(content)))
+    }
+
+    /// This function merges the `local` and `remote` content of the file at `path` with
+    /// the configured `mergetool`. The common base is fetched by its blob sha `base`.
+    fn merge(&self, path: &str, base: &str, local: &[u8], remote: &[u8]) -> Result<Vec<u8>> {
+        let tool = self
+            .config
+            .mergetool
+            .as_deref()
+            .ok_or_else(|| Error::Rustea("No mergetool configured".into()))?;
+        info!("Merging conflicting changes of {}", path);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let tmp = TempDir::new("rustea-merge")?;
+        let base = tmp.write(&format!("{}.BASE", name), &self.api.blob(base)?)?;
+        let local_file = tmp.write(&format!("{}.LOCAL", name), local)?;
+        let remote = tmp.write(&format!("{}.REMOTE", name), remote)?;
+        let merged = tmp.write(name, local)?;
+        diff::run_mergetool(
+            tool,
+            &diff::MergeFiles {
+                base: &base,
+                local: &local_file,
+                remote: &remote,
+                merged: &merged,
+            },
+        )?;
+        LocalRepository::read_file(&merged)
     }
 
     /// This function pushes files into a feature set in the remote repository.
@@ -338,22 +1875,36 @@ impl RemoteRepository {
     ///
     /// If some path is provided this function push the local file or folder.
     /// Folders are pushed recursively.
+    ///
+    /// The `pre_push` hook runs before any file is pushed and the `post_push` hook afterwards.
+    /// Conflicting changes are merged with the `mergetool` if `merge` is set.
+    /// Files matching the configured `exclude` patterns are not pushed, unless
+    /// the `exclude` patterns are provided which are used instead.
     pub fn push(
         &self,
         name: &str,
         path: Option<String>,
         script: bool,
+        merge: bool,
+        exclude: &[String],
         cmt_msg: Option<String>,
     ) -> Result<String> {
         if !self.check_feature_set_exists(name)? {
             return Err(Error::Rustea(format!("No features set named {}", name)));
         }
+        let exclude = match exclude.is_empty() {
+            true => self.local_repo.exclude.clone(),
+            false => Pattern::all(exclude)?,
+        };
 
+        let mut files = vec![];
         if let Some(path) = path {
             // Push a config or script file or folder
-            let path = PathBuf::from(path).canonicalize()?;
-            if path.exists() {
-                self.push_files(&path, name, script, cmt_msg.as_deref())?;
+            let path = PathBuf::from(path);
+            if fs::symlink_metadata(&path).is_ok() {
+                for file in self.local_repo.read_folder(&path, &exclude)? {
+                    files.push((file, script));
+                }
             } else {
                 return Err(Error::io(
                     io::ErrorKind::NotFound,
@@ -362,21 +1913,142 @@ impl RemoteRepository {
             }
         } else {
             // Push everything found in the feature set
-            let feature_set = self.api.get_folder(name)?;
+            let feature_set = self.api.list_files(name)?;
+            let feature_set = apply_overlay(feature_set, name, &self.hostname);
 
-            for entry in feature_set.content {
+            for entry in feature_set {
+                // Host overlays and templates are maintained within the remote repository
+                if RemoteRepository::is_internal_file(&entry.path, name)
+                    || is_overlay(&entry.path, name)
+                    || template::is_template(&entry.path)
+                {
+                    continue;
+                }
                 let script = self.local_repo.check_script(&entry.path, name);
                 let file_path = self
                     .local_repo
                     .transform_to_local_path(&entry.path, script)?;
-                if file_path.exists() {
-                    self.push_files(&file_path, name, script, cmt_msg.as_deref())?;
+                if fs::symlink_metadata(&file_path).is_ok()
+                    && !glob::is_excluded(&exclude, &file_path)
+                {
+                    for file in self.local_repo.read_folder(&file_path, &exclude)? {
+                        files.push((file, script));
+                    }
                 }
             }
         }
+
+        if self.interactive {
+            files = interactive::choose(files, |(f, _)| f.display().to_string())?;
+        }
+        self.push_with_hooks(name, &files, merge, cmt_msg.as_deref())?;
         Ok(format!("Files pushed to feature set {}", &name))
     }
 
+    /// This function runs the push hooks around `push_files` and records the pushed files.
+    fn push_with_hooks(
+        &self,
+        name: &str,
+        files: &[(PathBuf, bool)],
+        merge: bool,
+        cmt_msg: Option<&str>,
+    ) -> Result<()> {
+        // Fail before the hooks run and the secret scan reads the files
+        self.commit_identity(name)?;
+        if !self.allow_secrets {
+            self.scan_secrets(files)?;
+        }
+        let mut ctx = HookContext {
+            feature_set: name,
+            files: files.iter().map(|(f, _)| f.display().to_string()).collect(),
+            commit: None,
+            message: cmt_msg,
+        };
+        let feature = self.get_feature_manifest(name)?;
+        self.config.hooks.run(Hook::PrePush, &ctx)?;
+        feature.hooks.run(Hook::PrePush, &ctx)?;
+        let state_path = state::get_default_state_path()?;
+        let _lock = StateLock::acquire(&state_path)?;
+        let mut state = State::load(&state_path)?;
+        let res = self.push_files(files, name, cmt_msg, &mut state, merge);
+        state.save(&state_path)?;
+        let (commit, failed) = res?;
+        ctx.commit = commit;
+        ctx.files
+            .retain(|f| !failed.iter().any(|(p, _)| p == Path::new(f)));
+        self.audit(
+            Operation::Push,
+            name,
+            ctx.files.clone(),
+            ctx.commit.iter().cloned().collect(),
+            cmt_msg,
+        );
+        self.config.hooks.run(Hook::PostPush, &ctx)?;
+        feature.hooks.run(Hook::PostPush, &ctx)?;
+        failures(&format!("Pushing {}", name), files.len(), failed)
+    }
+
+    /// This function scans the `files` for possible secrets and returns an error which
+    /// lists them. Secret files which are encrypted on push are not scanned.
+    fn scan_secrets(&self, files: &[(PathBuf, bool)]) -> Result<()> {
+        let scanner = Scanner::new(&self.config.scan_rules)?;
+        let mut findings = vec![];
+        for (file, _) in files {
+            if self.local_repo.is_symlink(file)
+                || (self.encrypt && self.local_repo.secrets.matches(file))
+            {
+                continue;
+            }
+            let content = fs::read(file)?;
+            for finding in scanner.scan(&String::from_utf8_lossy(&content)) {
+                findings.push(format!("{}: {}", file.display(), finding));
+            }
+        }
+        match findings.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Rustea(format!(
+                "Refusing to push possible secrets, use --allow-secrets to push anyway\n{}",
+                findings.join("\n")
+            ))),
+        }
+    }
+
+    /// This function watches the local directory `dir` and pushes changed files
+    /// into the feature set `name` until rustea is interrupted.
+    ///
+    /// Changes are collected until nothing changed for the `debounce` duration.
+    /// Paths matching the configured `exclude` or one of the `exclude` patterns are ignored.
+    /// A failed push is reported and the watching continues.
+    pub fn watch_local(
+        &self,
+        name: &str,
+        dir: &str,
+        script: bool,
+        exclude: &[String],
+        debounce: Duration,
+        cmt_msg: Option<String>,
+    ) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let mut patterns = self.local_repo.exclude.clone();
+        patterns.append(&mut Pattern::all(exclude)?);
+        let dir = PathBuf::from(dir).canonicalize()?;
+        let mut watcher = Watcher::new(&dir, patterns)?;
+        info!("Watching {} for feature set {}", dir.display(), name);
+
+        loop {
+            let files: Vec<(PathBuf, bool)> = watcher
+                .wait(debounce)?
+                .into_iter()
+                .map(|f| (f, script))
+                .collect();
+            if let Err(e) = self.push_with_hooks(name, &files, false, cmt_msg.as_deref()) {
+                error!("Failed to push changes: {}", e);
+            }
+        }
+    }
+
     /// This function pulls files from the remote repository.
     ///
     /// It takes a vector of `ContentEntry` converts the path to a local one
@@ -384,144 +2056,1756 @@ impl RemoteRepository {
     /// the files are pulled from the remote repository and gets written to the
     /// local destination. It returns an error if some IO failure happens or
     /// the destination is not writable for the current user.
-    fn pull_files(&self, files: &[ContentEntry], script: bool) -> Result<()> {
-        for file in files {
-            let content = self.api.download_file(&file.path)?;
+    /// Attributes found in the `meta` sidecar are applied to the written files.
+    /// Symbolic links, either marked in the sidecar or stored as links in the remote
+    /// repository, are recreated with their target.
+    /// Files listed in the checksum `manifest` are verified before they are written.
+    /// The `checks` of the sidecar validate the staged copies before any file is installed.
+    /// Local files which already match the remote blob sha are neither downloaded nor written.
+    ///
+    /// Templates with the suffix `.tmpl` are rendered with the configured `vars` and
+    /// written without the suffix. Local changes of rendered files are always overwritten.
+    ///
+    /// Every file is downloaded and verified into a staging directory before any local
+    /// file is touched. Afterwards the files are moved into place and if this fails
+    /// the already replaced files are restored, so a pull is applied completely or not at all.
+    ///
+    /// If the local file changed since it was last pulled or pushed and the remote
+    /// file changed too, the files are merged with the `mergetool` if `merge` is set.
+    /// Otherwise the local changes are overwritten. The written local files are returned.
+    fn pull_files(
+        &self,
+        files: &[(ContentEntry, bool)],
+        feature_set: &str,
+        meta: &Metadata,
+        manifest: &Manifest,
+        state: &mut State,
+        merge: bool,
+    ) -> Result<(Vec<PathBuf>, Failures)> {
+        let mut staging = Staging::new()?;
+        let mut staged = vec![];
+        let mut failed = vec![];
+        let mut stage = |i: usize| -> Result<()> {
+            let (file, script) = &files[i];
+            let script = *script;
             let path = self
                 .local_repo
                 .transform_to_local_path(&file.path, script)?;
-            // If we have a regular config file, check if the parent folder exists and is writable
-            if !script {
-                self.local_repo.check_path(&path)?;
+            self.local_repo.check_writable(&path)?;
+            let rel_path = file
+                .path
+                .strip_prefix(&format!("{}/", feature_set))
+                .unwrap_or(&file.path);
+            if file.content_type == ContentType::Symlink || meta.is_symlink(&file.path, feature_set)
+            {
+                let target = self.api.download(&file.path)?;
+                manifest.verify(rel_path, &target)?;
+                let remote_sha = checksum::git_blob_sha(&target);
+                let target = PathBuf::from(OsStr::from_bytes(&target));
+                if fs::read_link(&path).ok().as_ref() == Some(&target) {
+                    state.record_file(feature_set, &file.path, &remote_sha);
+                    info!("Skipped unchanged link {}", path.display());
+                    return Ok(());
+                }
+                staging.add_symlink(&path, &target);
+                staged.push((file, script, path, remote_sha));
+                return Ok(());
+            }
+            if let (true, Some(sha)) = (path.is_file(), &file.sha) {
+                if checksum::git_blob_sha(&LocalRepository::read_file(&path)?) == *sha {
+                    state.record_file(feature_set, &file.path, sha);
+                    info!("Skipped unchanged file {}", path.display());
+                    return Ok(());
+                }
+            }
+
+            let mut content = self.api.download(&file.path)?;
+            manifest.verify(rel_path, &content)?;
+            let remote_sha = checksum::git_blob_sha(&content);
+            if self.is_rendered(&file.path, script) {
+                content = self.local_content(&file.path, script, &content)?;
+                if path.is_file() && LocalRepository::read_file(&path)? == content {
+                    state.record_file(feature_set, &file.path, &remote_sha);
+                    info!("Skipped unchanged file {}", path.display());
+                    return Ok(());
+                }
+            } else if path.is_file() {
+                let local = LocalRepository::read_file(&path)?;
+                let base = state.file_sha(feature_set, &file.path);
+                if diff::is_conflict(base, &checksum::git_blob_sha(&local), &remote_sha) {
+                    match (merge, base) {
+                        (true, Some(base)) => {
+                            content = self.merge(&file.path, base, &local, &content)?
+                        }
+                        _ => warn!("Overwriting local changes of {}", path.display()),
+                    }
+                }
+            }
+            staging.add(&path, &content)?;
+            staged.push((file, script, path, remote_sha));
+            Ok(())
+        };
+        for (i, (file, script)) in files.iter().enumerate() {
+            match stage(i) {
+                Err(e) if self.keep_going => {
+                    // Failed files are listed by their local path like the ones of a push
+                    let path = self
+                        .local_repo
+                        .transform_to_local_path(&file.path, *script)
+                        .unwrap_or_else(|_| PathBuf::from(&file.path));
+                    failed.push((path, e))
+                }
+                res => res?,
+            }
+        }
+        self.confirm(&format!("Pulling {} overwrites", feature_set), || {
+            Ok(staged
+                .iter()
+                .filter(|(_, _, path, _)| fs::symlink_metadata(path).is_ok())
+                .map(|(_, _, path, _)| path.display().to_string())
+                .collect())
+        })?;
+
+        let script_mode = self.config.script_mode(feature_set)?;
+        let ownership = self.config.ownership()?;
+        let prepare = |i: usize, copy: &Path| {
+            let (file, script, path, _) = &staged[i];
+            let owner = meta
+                .get(&file.path, feature_set)
+                .and_then(|m| m.owner.as_deref())
+                .or_else(|| owner_of(&ownership, path));
+            if let Some(owner) = owner {
+                metadata::chown(copy, owner)?;
+            }
+            if *script {
+                let mut perms = copy.metadata()?.permissions();
+                perms.set_mode(script_mode);
+                std::fs::set_permissions(copy, perms)?;
+            }
+            match meta.get(&file.path, feature_set) {
+                Some(m) => m.apply(copy),
+                None => Ok(()),
+            }
+        };
+        staging.commit_checked(prepare, |copies| check::run_all(&meta.checks, copies))?;
+        let mut pulled = vec![];
+        for (file, _, path, remote_sha) in staged {
+            state.record_file(feature_set, &file.path, &remote_sha);
+            info!("Pulled file {}", color::paint(Color::Green, path.display()));
+            pulled.push(path);
+        }
+        Ok((pulled, failed))
+    }
+
+    /// This function records the remote `files` and their local paths as managed by the
+    /// feature set, the install time changes only for the `pulled` files. After a `full_pull`
+    /// the managed files which were removed from the feature set are reported or, with
+    /// `with_prune`, removed if they didn't change locally. The removed files are returned.
+    fn manage_files(
+        &self,
+        feature_set: &str,
+        files: &[(&ContentEntry, &str)],
+        pulled: &[PathBuf],
+        full_pull: bool,
+        state: &mut State,
+    ) -> Result<Vec<PathBuf>> {
+        for (file, local) in files {
+            let local = PathBuf::from(local);
+            let sha = local_blob_sha(&local)?;
+            let installed = match state.managed_file(feature_set, &file.path) {
+                Some(m) if !pulled.contains(&local) => m.installed,
+                _ => state::now(),
+            };
+            let managed = ManagedFile {
+                path: local,
+                sha,
+                installed,
+            };
+            state.record_managed(feature_set, &file.path, managed);
+        }
+        if !full_pull {
+            return Ok(vec![]);
+        }
+        let remote: Vec<&str> = files.iter().map(|(e, _)| e.path.as_str()).collect();
+        let mut orphaned = vec![];
+        for (path, file) in state.orphaned(feature_set, &remote) {
+            match fs::symlink_metadata(&file.path) {
+                Err(_) => state.forget_file(feature_set, &path),
+                Ok(_) if local_blob_sha(&file.path)? != file.sha => warn!(
+                    "Keeping {} which was removed from {} but changed locally",
+                    file.path.display(),
+                    feature_set
+                ),
+                Ok(_) => orphaned.push((path, file.path)),
+            }
+        }
+        if orphaned.is_empty() {
+            return Ok(vec![]);
+        }
+        if !self.prune {
+            warn!(
+                "{} file(s) were removed from {}, remove them with --prune: {}",
+                orphaned.len(),
+                feature_set,
+                orphaned
+                    .iter()
+                    .map(|(_, p)| p.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            return Ok(vec![]);
+        }
+        self.confirm(&format!("Pruning {} removes", feature_set), || {
+            Ok(orphaned
+                .iter()
+                .map(|(_, p)| p.display().to_string())
+                .collect())
+        })?;
+        let mut pruned = vec![];
+        for (path, local) in orphaned {
+            fs::remove_file(&local)?;
+            state.forget_file(feature_set, &path);
+            info!("Pruned file {}", color::paint(Color::Red, local.display()));
+            pruned.push(local);
+        }
+        Ok(pruned)
+    }
+
+    /// Returns true if the remote `path` is a template or an encrypted secret file,
+    /// whose local content differs from the remote one.
+    fn is_rendered(&self, path: &str, script: bool) -> bool {
+        template::is_template(path) || self.local_repo.secret_path(path, script).is_some()
+    }
+
+    /// This function returns the local content of the remote file at `path`. Templates
+    /// are rendered and secret files decrypted, everything else is returned unchanged.
+    fn local_content(&self, path: &str, script: bool, content: &[u8]) -> Result<Vec<u8>> {
+        if template::is_template(path) {
+            self.render(path, content)
+        } else if self.local_repo.secret_path(path, script).is_some() {
+            self.config
+                .secrets
+                .decrypt(content)
+                .map_err(|e| Error::Rustea(format!("Failed to decrypt {}: {}", path, e)))
+        } else {
+            Ok(content.to_vec())
+        }
+    }
+
+    /// This function renders the template `content` of the remote `path` with the configured `vars`.
+    fn render(&self, path: &str, content: &[u8]) -> Result<Vec<u8>> {
+        std::str::from_utf8(content)
+            .map_err(|_| Error::Rustea(format!("Template {} is no valid UTF-8", path)))
+            .and_then(|t| template::render(t, &self.config.vars))
+            .map(String::into_bytes)
+            .map_err(|e| Error::Rustea(format!("Failed to render {}: {}", path, e)))
+    }
+
+    /// This function pulls files from the remote repository and stores them
+    /// on the local machine depending on the remote path.
+    ///
+    /// For the provided feature set either the script files or configuration files
+    /// are pulled depending on the `script` and `config` argument. If both are set
+    /// to true only script files are pulled to the local machine.
+    /// If both arguments are set to false everything if pulled from the feature set.
+    /// Files below `hosts/<hostname>/` of the feature set replace the base files with
+    /// the same path, where the hostname is detected or set with `with_hostname`.
+    /// With `with_interactive` the user chooses the pulled files from the selected ones.
+    ///
+    /// If `if_changed` is set and the whole feature set is pulled, the latest commit of the
+    /// feature set is compared with the recorded commit of the last pull first. Nothing is
+    /// listed or downloaded if the feature set didn't change in the meantime.
+    ///
+    /// The `pre_pull` hook runs before any file is written and the `post_pull` hook afterwards.
+    /// With `etckeeper` enabled pulled files below `/etc` are committed locally before the `post_pull` hook.
+    /// Conflicting changes are merged with the `mergetool` if `merge` is set.
+    ///
+    /// ## Attention
+    ///
+    /// If `path` is provided only the file `path` or the files below the folder `path` are pulled.
+    /// The `path` is relative to the feature set or with `script` to its scripts folder,
+    /// `etc` selects `feature_set/etc/fstab` but neither `feature_set/etcd` nor `feature_set/opt/etc`.
+    pub fn pull(
+        &self,
+        name: &str,
+        path: Option<String>,
+        script: bool,
+        config: bool,
+        if_changed: bool,
+        merge: bool,
+    ) -> Result<String> {
+        match self.pull_changes(name, path, script, config, if_changed, merge)? {
+            Some(_) => Ok(format!(
+                "Successfully pulled files from feature set {}",
+                &name
+            )),
+            None => Ok(format!("Feature set {} is up to date", name)),
+        }
+    }
+
+    /// This function performs a `pull` and returns the written local files
+    /// or `None` if the feature set is up to date.
+    fn pull_changes(
+        &self,
+        name: &str,
+        path: Option<String>,
+        script: bool,
+        config: bool,
+        if_changed: bool,
+        merge: bool,
+    ) -> Result<Option<Vec<PathBuf>>> {
+        // Only a pull of the whole feature set into the host is recorded in the local state
+        let into_host = self.local_repo.prefix.is_none();
+        let full_pull = path.is_none() && !script && !config && !self.interactive && into_host;
+        let latest_commit = match full_pull || self.config.etckeeper {
+            true => self.api.latest_commit(name)?,
+            false => None,
+        };
+        let state_path = state::get_default_state_path()?;
+        let _lock = StateLock::acquire(&state_path)?;
+        let mut state = State::load(&state_path)?;
+        if let (true, Some(commit)) = (if_changed, &latest_commit) {
+            // Only the commit tells if the feature set changed, the committer date may be
+            // older than the last pull, e.g. for a rollback, a rewritten date or a skewed clock
+            if state.is_applied(name, &commit.sha) {
+                return Ok(None);
+            }
+            let changed = state::parse_timestamp(&commit.commit.committer.date)?;
+            if !state.is_outdated(name, changed) {
+                info!(
+                    "The commit {} of {} is dated before the last pull, pulling it anyway",
+                    commit.sha, name
+                );
+            }
+        }
+
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let (meta, manifest, mut files) =
+            self.select_files(name, path.as_deref(), script, config)?;
+        if self.interactive {
+            files = interactive::choose(files, |(e, s)| {
+                self.local_repo
+                    .transform_to_local_path(&e.path, *s)
+                    .map_or_else(|_| e.path.clone(), |p| p.display().to_string())
+            })?;
+        }
+        let feature = self.get_feature_manifest(name)?;
+        self.check_targets(&feature, &files)?;
+
+        let mut ctx = HookContext {
+            feature_set: name,
+            files: files
+                .iter()
+                .map(|(e, s)| self.local_repo.transform_to_local_path(&e.path, *s))
+                .map(|p| p.map(|p| p.display().to_string()))
+                .collect::<Result<Vec<String>>>()?,
+            commit: latest_commit.as_ref().map(|c| c.sha.clone()),
+            message: None,
+        };
+        self.config.hooks.run(Hook::PrePull, &ctx)?;
+        feature.hooks.run(Hook::PrePull, &ctx)?;
+        let res = self
+            .pull_files(&files, name, &meta, &manifest, &mut state, merge)
+            .and_then(|(mut pulled, failed)| {
+                // The failed files are neither managed nor pruned, the next pull retries them
+                let done: Vec<(&ContentEntry, &str)> = files
+                    .iter()
+                    .map(|(e, _)| e)
+                    .zip(ctx.files.iter().map(String::as_str))
+                    .filter(|(_, l)| !failed.iter().any(|(p, _)| p == Path::new(l)))
+                    .collect();
+                let complete = full_pull && failed.is_empty();
+                if into_host {
+                    let mut pruned =
+                        self.manage_files(name, &done, &pulled, complete, &mut state)?;
+                    pulled.append(&mut pruned);
+                }
+                let locals = done.iter().map(|(_, l)| l.to_string()).collect();
+                Ok((pulled, locals, failed))
+            });
+        if matches!(&res, Ok((_, _, failed)) if failed.is_empty()) && full_pull {
+            state.applied(name, latest_commit.map(|c| c.sha));
+        }
+        if into_host {
+            state.save(&state_path)?;
+        }
+        let (pulled, locals, failed) = res?;
+        ctx.files = locals;
+        self.audit(
+            Operation::Pull,
+            name,
+            pulled.iter().map(|p| p.display().to_string()).collect(),
+            ctx.commit.iter().cloned().collect(),
+            None,
+        );
+        if self.config.etckeeper && into_host {
+            let msg = etckeeper::commit_message(name, ctx.commit.as_deref());
+            if etckeeper::commit(&ctx.files, &msg)? {
+                info!("Committed pulled files into the local repository of /etc");
+            }
+        }
+        self.config.hooks.run(Hook::PostPull, &ctx)?;
+        feature.hooks.run(Hook::PostPull, &ctx)?;
+        failures(&format!("Pulling {}", name), files.len(), failed)?;
+        Ok(Some(pulled))
+    }
+
+    /// This function returns an error if a configuration file of the `files` is
+    /// outside the `targets` of the `feature.toml`.
+    fn check_targets(&self, feature: &FeatureManifest, files: &SelectedFiles) -> Result<()> {
+        for (e, _) in files.iter().filter(|(_, script)| !script) {
+            let local = self.local_repo.transform_to_local_path(&e.path, false)?;
+            // The targets are host paths, a pull below a prefix is checked as well
+            let target = match &self.local_repo.prefix {
+                Some(prefix) => Path::new("/").join(local.strip_prefix(prefix).unwrap_or(&local)),
+                None => local,
+            };
+            if !feature.allows(&target) {
+                return Err(Error::Rustea(format!(
+                    "{} is outside the targets of the feature set",
+                    target.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the feature sets listed as `assigned` in the configuration, each after
+    /// the feature sets it `depends-on` in its `feature.toml`, see `feature::resolve`.
+    fn assigned_order(&self) -> Result<Vec<String>> {
+        if self.config.assigned.is_empty() {
+            return Err(Error::Rustea(
+                "No feature sets assigned to this host".into(),
+            ));
+        }
+        feature::resolve(&self.config.assigned, |name| {
+            Ok(self.get_feature_manifest(name)?.depends_on)
+        })
+    }
+
+    /// This function pulls every feature set listed as `assigned` in the configuration
+    /// after the feature sets they `depends-on`, like `apply`.
+    /// It stops at the first feature set which can not be pulled.
+    pub fn pull_assigned(&self, if_changed: bool, merge: bool) -> Result<String> {
+        let mut res = vec![];
+        for name in &self.assigned_order()? {
+            res.push(self.pull(name, None, false, false, if_changed, merge)?);
+        }
+        Ok(res.join("\n"))
+    }
+
+    /// Returns the metrics of the last runs if a `metrics_file` is configured.
+    fn metrics(&self) -> Metrics {
+        match &self.config.metrics_file {
+            Some(path) => Metrics::load(path),
+            None => Metrics::default(),
+        }
+    }
+
+    /// This function pulls every feature set listed as `assigned` in the configuration
+    /// after the feature sets they `depends-on` in their `feature.toml` and returns a summary table of the written files per feature set.
+    /// It stops at the first feature set which can not be pulled.
+    /// The result of every pull is written to the `metrics_file` if configured.
+    /// With `reload` the `services` of the sidecars which use a changed file are reloaded
+    /// or restarted afterwards, every unit once.
+    pub fn apply(&self, if_changed: bool, merge: bool, reload: bool) -> Result<String> {
+        let order = self.assigned_order()?;
+        let mut tw = TabWriter::new(vec![]);
+        let mut changed = 0;
+        let mut metrics = self.metrics();
+        let mut affected = BTreeMap::new();
+        writeln!(&mut tw, "Feature set\tChanged files")?;
+        for name in &order {
+            let pulled = self.pull_changes(name, None, false, false, if_changed, merge);
+            match &pulled {
+                Ok(files) => metrics.record_success(name, files.as_ref().map_or(0, Vec::len)),
+                Err(e) => metrics.record_failure(name, e),
+            }
+            metrics.save();
+            match pulled? {
+                Some(files) if !files.is_empty() => {
+                    changed += files.len();
+                    if reload {
+                        service::collect(
+                            &self.get_metadata(name)?.services,
+                            &files,
+                            &mut affected,
+                        )?;
+                    }
+                    for (i, file) in files.iter().enumerate() {
+                        let name = if i == 0 { name.as_str() } else { "" };
+                        writeln!(
+                            &mut tw,
+                            "{}\t{}",
+                            name,
+                            color::paint(Color::Green, file.display())
+                        )?;
+                    }
+                }
+                Some(_) => writeln!(&mut tw, "{}\tnone", name)?,
+                None => writeln!(&mut tw, "{}\tup to date", name)?,
+            }
+        }
+        let table = tw
+            .into_inner()
+            .map_err(|e| Error::Rustea(format!("Failed to align summary: {}", e)))?;
+        let mut summary = format!(
+            "{}\n{} files changed in {} feature sets",
+            String::from_utf8_lossy(&table).trim_end(),
+            changed,
+            order.len()
+        );
+        let mut failed = vec![];
+        for (unit, action) in affected {
+            match service::run(&unit, action) {
+                Ok(()) => summary.push_str(&format!("\nService {}: {}", unit, action)),
+                Err(e) => failed.push(e.to_string()),
+            }
+        }
+        match failed.is_empty() {
+            true => Ok(summary),
+            false => Err(Error::Rustea(format!("{}\n{}", summary, failed.join("\n")))),
+        }
+    }
+
+    /// This function runs as pull agent until rustea is interrupted.
+    ///
+    /// Every `interval` the latest commit of each `assigned` feature set is fetched
+    /// and the feature set is pulled if the commit differs from the one of the last pull.
+    /// Every action is logged in the logfmt format and failures don't stop the agent.
+    /// The pid file at `pid_path` prevents that a second agent runs at the same time.
+    pub fn watch(&self, interval: Duration, pid_path: &Path) -> Result<String> {
+        if self.config.assigned.is_empty() {
+            return Err(Error::Rustea(
+                "No feature sets assigned to this host".into(),
+            ));
+        }
+        let _pid = daemon::PidFile::acquire(pid_path)?;
+        let mut metrics = self.metrics();
+        let interval_secs = interval.as_secs().to_string();
+        daemon::log(
+            "info",
+            "Watching feature sets",
+            &[
+                ("feature_sets", &self.config.assigned.join(",")),
+                ("interval", &interval_secs),
+            ],
+        );
+        loop {
+            for name in &self.config.assigned {
+                match self.sync(name) {
+                    Ok(pulled) => metrics.record_success(name, pulled),
+                    Err(e) => {
+                        daemon::log(
+                            "error",
+                            "Failed to sync feature set",
+                            &[("feature_set", name), ("error", &e.to_string())],
+                        );
+                        metrics.record_failure(name, &e);
+                    }
+                }
+            }
+            metrics.save();
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// This function pulls the feature set `name` if its latest commit differs from the
+    /// commit of the last pull, which is a single iteration of `watch`.
+    /// Returns the number of written files.
+    fn sync(&self, name: &str) -> Result<usize> {
+        let tip = self.api.latest_commit(name)?.map(|c| c.sha);
+        let state = State::load(&state::get_default_state_path()?)?;
+        let applied = state.features.get(name).and_then(|f| f.commit.clone());
+        if tip.is_some() && tip == applied {
+            return Ok(0);
+        }
+
+        let commit = tip.unwrap_or_default();
+        daemon::log(
+            "info",
+            "New commit found",
+            &[("feature_set", name), ("commit", &commit)],
+        );
+        let files = self
+            .pull_changes(name, None, false, false, false, false)?
+            .unwrap_or_default();
+        let changed = files.len().to_string();
+        let files: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        daemon::log(
+            "info",
+            "Pulled feature set",
+            &[
+                ("feature_set", name),
+                ("commit", &commit),
+                ("changed", &changed),
+                ("files", &files.join(",")),
+            ],
+        );
+        Ok(files.len())
+    }
+
+    /// This function compares the local files with the files of a feature set.
+    ///
+    /// Without a `tool` or configured `difftool` a unified diff from the local to the
+    /// remote files is returned, which shows the changes a pull would apply and a push
+    /// would revert. Local files which doesn't exist are compared with an empty file.
+    ///
+    /// With an external `tool` the remote versions are written into a temporary directory
+    /// and every pair of differing files is passed to the tool in the order local, remote.
+    /// Local files which doesn't exist are replaced by `/dev/null`.
+    pub fn diff(&self, name: &str, path: Option<String>, tool: Option<String>) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let tool = tool.or_else(|| self.config.difftool.clone());
+        let (_, _, files) = self.select_files(name, path.as_deref(), false, false)?;
+        let tmp = TempDir::new("rustea-diff")?;
+
+        let mut out = String::new();
+        let mut differ = 0;
+        for (file, script) in &files {
+            let remote = self.api.download(&file.path)?;
+            let remote = self.local_content(&file.path, *script, &remote)?;
+            let local_path = self
+                .local_repo
+                .transform_to_local_path(&file.path, *script)?;
+            let local = match local_path.exists() {
+                true => Some(LocalRepository::read_file(&local_path)?),
+                false => None,
+            };
+            if local.as_deref() == Some(&remote[..]) {
+                continue;
             }
 
-            let mut f = File::create(&path)?;
-            f.write_all(content.as_bytes()).map_err(Error::Io)?;
-            if script {
-                let mut perms = f.metadata()?.permissions();
-                perms.set_mode(0o751);
-                std::fs::set_permissions(&path, perms)?;
+            differ += 1;
+            match &tool {
+                Some(tool) => {
+                    let remote_path = tmp.write(&file.path, &remote)?;
+                    let local_path = match local {
+                        Some(_) => local_path,
+                        None => PathBuf::from("/dev/null"),
+                    };
+                    diff::run_tool(tool, &[&local_path, &remote_path])?;
+                }
+                None => {
+                    let local_name = match local {
+                        Some(_) => local_path.display().to_string(),
+                        None => String::from("/dev/null"),
+                    };
+                    let local = local.unwrap_or_default();
+                    match (std::str::from_utf8(&local), std::str::from_utf8(&remote)) {
+                        (Ok(local), Ok(remote)) => out.push_str(&color::diff(
+                            &diff::unified_diff(&local_name, &file.path, local, remote),
+                            color::enabled(),
+                        )),
+                        _ => out.push_str(&format!(
+                            "Binary files {} and {} differ\n",
+                            local_name, file.path
+                        )),
+                    }
+                }
+            }
+        }
+        out.push_str(&format!(
+            "{} of {} files in feature set {} differ",
+            differ,
+            files.len(),
+            name
+        ));
+        Ok(out)
+    }
+
+    /// This function verifies the local files of a feature set against the remote repository
+    /// without changing anything, e.g. for compliance checks run by cron.
+    ///
+    /// The hashes of the local files are compared with the remote blob shas, rendered
+    /// templates and decrypted secret files are compared by content. The files which are
+    /// missing or differ are returned and true if there is any drift.
+    pub fn verify(&self, name: &str, path: Option<String>) -> Result<(String, bool)> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let (meta, _, files) = self.select_files(name, path.as_deref(), false, false)?;
+        let files: Vec<&(ContentEntry, bool)> =
+            files.iter().filter(|(f, _)| f.name != ".gitkeep").collect();
+        let mut out = String::new();
+        let mut drifted = 0;
+        for (file, script) in &files {
+            let local_path = self
+                .local_repo
+                .transform_to_local_path(&file.path, *script)?;
+            let link =
+                file.content_type == ContentType::Symlink || meta.is_symlink(&file.path, name);
+            let status =
+                match !link && local_path.is_file() && self.is_rendered(&file.path, *script) {
+                    true => {
+                        let remote = self.api.download(&file.path)?;
+                        let remote = self.local_content(&file.path, *script, &remote)?;
+                        match LocalRepository::read_file(&local_path)? == remote {
+                            true => None,
+                            false => Some("modified"),
+                        }
+                    }
+                    false => drift(&local_path, link, file.sha.as_deref().unwrap_or_default()),
+                };
+            if let Some(status) = status {
+                drifted += 1;
+                out.push_str(&format!(
+                    "{:<9}{}\n",
+                    color::paint(Color::Red, status),
+                    local_path.display()
+                ));
+            }
+        }
+        out.push_str(&format!(
+            "{} of {} files of feature set {} drifted",
+            drifted,
+            files.len(),
+            name
+        ));
+        Ok((out, drifted > 0))
+    }
+
+    /// This function edits the file `path` of a feature set without pulling it.
+    ///
+    /// The file is downloaded into a temporary directory and opened with `$VISUAL`,
+    /// `$EDITOR` or `vi`. The result is pushed only if the content changed. The edited
+    /// file and the updated checksum manifest are committed at once with the blob shas
+    /// read before the edit, the commit fails if someone else changed them in the meantime.
+    /// The edit is kept in the temporary directory if the commit fails.
+    pub fn edit(&self, name: &str, path: &str, cmt_msg: Option<String>) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let remote_path = format!("/{}", path.trim_start_matches('/'));
+        let full_path = format!("{}{}", name, remote_path);
+        // The blob sha of every remote file to detect changes during the edit
+        let remote_shas: BTreeMap<String, String> = self
+            .api
+            .list_files(name)?
+            .into_iter()
+            .filter_map(|e| Some((e.path, e.sha?)))
+            .collect();
+        let remote_sha = match remote_shas.get(&full_path) {
+            Some(sha) => sha.clone(),
+            None => {
+                return Err(Error::Rustea(format!(
+                    "No file {} in feature set {}",
+                    remote_path, name
+                )))
+            }
+        };
+        let manifest_path = format!("{}/{}", name, checksum::MANIFEST_FILE);
+        let mut manifest = match remote_shas.contains_key(&manifest_path) {
+            true => Manifest::parse(&self.api.download_text(&manifest_path)?)?,
+            false => Manifest::default(),
+        };
+        let content = self.api.download(&full_path)?;
+        let (author, email) = self.commit_identity(name)?;
+
+        let tmp = TempDir::new("rustea-edit")?;
+        let file_name = remote_path.rsplit('/').next().unwrap_or_default();
+        let file = tmp.write(file_name, &content)?;
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_owned());
+        diff::run_tool(&editor, &[&file])?;
+        let edited = LocalRepository::read_file(&file)?;
+        if edited == content {
+            return Ok(format!("No changes in {}", full_path));
+        }
+
+        let old_manifest = manifest.to_string();
+        manifest.insert(remote_path.trim_start_matches('/'), &edited);
+        let mut changes = vec![FileChange {
+            path: full_path.clone(),
+            content: Some(edited),
+            sha: Some(remote_sha),
+        }];
+        if manifest.to_string() != old_manifest {
+            changes.push(FileChange {
+                sha: remote_shas.get(&manifest_path).cloned(),
+                path: manifest_path,
+                content: Some(manifest.to_string().into_bytes()),
+            });
+        }
+        if let Err(e) = self
+            .api
+            .change_files(&changes, author, email, cmt_msg.as_deref())
+        {
+            tmp.keep();
+            let cause = match e {
+                ApiError::Conflict { .. } => {
+                    format!("{} was changed remotely in the meantime", full_path)
+                }
+                e => e.to_string(),
+            };
+            return Err(Error::Rustea(format!(
+                "{}, the edit is kept in {}",
+                cause,
+                file.display()
+            )));
+        }
+        Ok(format!("Pushed edited file {}", full_path))
+    }
+
+    /// This function lists up to `limit` commits which changed the feature set `name`
+    /// or the file or folder at `path` within it, the newest first.
+    pub fn log(&self, name: &str, path: Option<String>, limit: usize) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let remote_path = match path {
+            Some(p) => format!("{}/{}", name, p.trim_matches('/')),
+            None => name.to_owned(),
+        };
+        let commits = self.api.commits(&remote_path, limit)?;
+        match commits.is_empty() {
+            true => Ok(format!("No commits changed {}", remote_path)),
+            false => format_history(&commits),
+        }
+    }
+
+    /// This function searches the regex `pattern` in the files of the feature set `name`
+    /// or of every feature set in the repository. Every matching line is returned as
+    /// `feature_set/path:line:content`. Binary files and files managed by rustea are skipped.
+    pub fn grep(&self, pattern: &str, name: Option<String>) -> Result<String> {
+        let re = Regex::new(pattern)
+            .map_err(|e| Error::Rustea(format!("Invalid pattern {}: {}", pattern, e)))?;
+        let feature_sets = match name {
+            Some(name) if self.check_feature_set_exists(&name)? => vec![name],
+            Some(name) => return Err(Error::Rustea(format!("No features set named {}", name))),
+            None => self
+                .get_feature_sets()?
+                .into_iter()
+                .map(|e| e.name)
+                .collect(),
+        };
+
+        let mut matches = vec![];
+        for name in &feature_sets {
+            for e in self.api.list_files(name)? {
+                if RemoteRepository::is_internal_file(&e.path, name) {
+                    continue;
+                }
+                let content = self.api.download(&e.path)?;
+                if let Ok(content) = std::str::from_utf8(&content) {
+                    matches.append(&mut grep_lines(&re, &e.path, content));
+                }
+            }
+        }
+        match matches.is_empty() {
+            true => Ok(format!("No matches for {}", pattern)),
+            false => Ok(matches.join("\n")),
+        }
+    }
+
+    /// This function generates a report about the drift of every feature set on this machine.
+    ///
+    /// Every remote file is compared with its local copy and the blob sha recorded
+    /// at the last pull or push to determine pending pulls and pushes.
+    /// The report is written to `output` if provided and returned otherwise.
+    pub fn report(&self, format: Format, output: Option<String>) -> Result<String> {
+        let names: Vec<String> = self
+            .get_feature_sets()?
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        let report = self.drift_report(&names)?.render(format);
+        match output {
+            Some(path) => {
+                File::create(&path)?.write_all(report.as_bytes())?;
+                Ok(format!("Report written to {}", path))
+            }
+            None => Ok(report),
+        }
+    }
+
+    /// This function compares every remote file of the feature sets `names` with its local copy
+    /// and the blob sha recorded at the last pull or push.
+    fn drift_report(&self, names: &[String]) -> Result<Report> {
+        let state = State::load(&state::get_default_state_path()?)?;
+        let mut features = vec![];
+        for name in names {
+            let (_, _, entries) = self.select_files(name, None, false, false)?;
+            let mut files = vec![];
+            for (file, script) in entries {
+                if file.name == ".gitkeep" {
+                    continue;
+                }
+                let local_path = self
+                    .local_repo
+                    .transform_to_local_path(&file.path, script)?;
+                let local = match local_path.is_file() {
+                    true => Some(checksum::git_blob_sha(&LocalRepository::read_file(
+                        &local_path,
+                    )?)),
+                    false => None,
+                };
+                let status = FileStatus::new(
+                    state.file_sha(name, &file.path),
+                    local.as_deref(),
+                    file.sha.as_deref().unwrap_or_default(),
+                );
+                files.push((file.path, status));
+            }
+            let remote: Vec<&str> = files.iter().map(|(p, _)| p.as_str()).collect();
+            let orphaned: Vec<String> = state
+                .orphaned(name, &remote)
+                .into_iter()
+                .filter(|(_, f)| fs::symlink_metadata(&f.path).is_ok())
+                .map(|(p, _)| p)
+                .collect();
+            files.extend(orphaned.into_iter().map(|p| (p, FileStatus::Orphaned)));
+            let last_pull = state
+                .features
+                .get(name)
+                .map(|s| s.applied)
+                .filter(|a| *a > 0);
+            features.push(FeatureReport {
+                name: name.clone(),
+                last_pull,
+                files,
+            });
+        }
+        Ok(Report {
+            host: report::hostname(),
+            generated: state::now(),
+            features,
+        })
+    }
+
+    /// This function returns a compact summary of the drift of the `feature_set`,
+    /// of every feature set with `all` or otherwise of the `assigned` ones.
+    pub fn status(&self, feature_set: Option<&str>, all: bool) -> Result<String> {
+        let names = match (feature_set, all) {
+            (Some(_), true) => {
+                return Err(Error::Rustea(
+                    "Either provide a feature set or --all".into(),
+                ))
+            }
+            (Some(name), false) => {
+                if !self.check_feature_set_exists(name)? {
+                    return Err(Error::Rustea(format!("No features set named {}", name)));
+                }
+                vec![name.to_owned()]
+            }
+            (None, true) => self
+                .get_feature_sets()?
+                .into_iter()
+                .map(|e| e.name)
+                .collect(),
+            (None, false) if self.config.assigned.is_empty() => {
+                return Err(Error::Rustea(
+                    "No feature sets assigned to this host, provide one or --all".into(),
+                ))
+            }
+            (None, false) => self.config.assigned.clone(),
+        };
+        Ok(self.drift_report(&names)?.summary())
+    }
+
+    /// This function restores the files of a feature set `name` deleted into the trash.
+    ///
+    /// The latest deletion is restored unless the `timestamp` of another one is given.
+    /// The restored files are moved back and must not exist in the feature set.
+    pub fn restore(
+        &self,
+        name: &str,
+        timestamp: Option<u64>,
+        cmt_msg: Option<String>,
+    ) -> Result<String> {
+        let deleted: BTreeSet<u64> = self
+            .api
+            .tree()?
+            .iter()
+            .filter_map(|e| trashed_at(&e.path, name))
+            .collect();
+        let timestamp = match timestamp {
+            Some(t) if deleted.contains(&t) => t,
+            Some(t) => {
+                return Err(Error::Rustea(format!(
+                    "{} wasn't deleted at {}, found {:?}",
+                    name, t, deleted
+                )))
+            }
+            None => match deleted.iter().last() {
+                Some(t) => *t,
+                None => return Err(Error::Rustea(format!("{} is not in the trash", name))),
+            },
+        };
+        let src = trash_path(timestamp, name);
+        let (moved, commit) = self.move_files(&src, name, true, name, cmt_msg.as_deref())?;
+        self.audit(
+            Operation::Restore,
+            name,
+            vec![src],
+            commit.into_iter().collect(),
+            cmt_msg.as_deref(),
+        );
+        Ok(format!(
+            "Restored {} files of {} deleted at {}.",
+            moved,
+            color::paint(Color::Green, name),
+            timestamp
+        ))
+    }
+
+    /// This function moves the files below the remote `src` to `dst` in one commit,
+    /// including the manifest and metadata sidecar. Only the direct children of a folder
+    /// are moved if not `recursive`. The identity of the `feature_set` is used for the commit.
+    /// Returns the number of moved files and the sha of the commit.
+    fn move_files(
+        &self,
+        src: &str,
+        dst: &str,
+        recursive: bool,
+        feature_set: &str,
+        cmt_msg: Option<&str>,
+    ) -> Result<(usize, Option<String>)> {
+        let tree = self.api.tree()?;
+        let existing: BTreeSet<&str> = tree.iter().map(|e| e.path.as_str()).collect();
+        let mut changes = vec![];
+        for entry in tree.iter().filter(|e| e.entry_type == "blob") {
+            let target = match copy_target(&entry.path, src, dst) {
+                Some(t) if recursive || !t[dst.len()..].trim_start_matches('/').contains('/') => t,
+                _ => continue,
+            };
+            if existing.contains(target.as_str()) {
+                return Err(Error::Rustea(format!("{} exists already", target)));
+            }
+            changes.push(FileChange {
+                content: Some(self.api.download(&entry.path)?),
+                path: target,
+                sha: None,
+            });
+            changes.push(FileChange {
+                path: entry.path.clone(),
+                content: None,
+                sha: Some(entry.sha.clone()),
+            });
+        }
+        if changes.is_empty() {
+            return Err(Error::Rustea(format!("Nothing found at {}", src)));
+        }
+        let (author, email) = self.commit_identity(feature_set)?;
+        let commit = self.api.change_files(&changes, author, email, cmt_msg)?;
+        Ok((changes.len() / 2, commit))
+    }
+
+    /// This function renames either feature sets or folder and files within the remote repository.
+    ///
+    /// Provide the feature set `name` in which the files should be moved. If the `path` is
+    /// empty the whole feature set is renamed. Otherwise, the `path` is resolved and the
+    /// last part of the path (after `/`) is replaced with `new_name`.
+    /// Script files are renamed with the path `scripts/<name>`.
+    ///
+    /// The files are copied to the new path and deleted in the same commit, see `copy`.
+    pub fn rename(
+        &self,
+        name: &str,
+        new_name: &str,
+        path: Option<String>,
+        cmt_msg: Option<String>,
+    ) -> Result<String> {
+        if new_name.is_empty() || new_name.contains('/') {
+            return Err(Error::Rustea(format!("Invalid name {}", new_name)));
+        }
+        let (src, dst) = match path {
+            Some(path) => (
+                format!("{}/{}", name, path.trim_matches('/')),
+                format!("{}/{}", name, renamed_path(&path, new_name)),
+            ),
+            None => (name.to_owned(), new_name.to_owned()),
+        };
+        self.confirm(&format!("Renaming {} to {} moves", src, dst), || {
+            self.remote_files(&src, true)
+        })?;
+        self.copy(&src, &dst, true, cmt_msg)?;
+        Ok(format!("Successfully renamed {} to {}", src, dst))
+    }
+
+    /// This function exports the feature set `name` with all files, the checksum manifest and
+    /// the metadata into an archive which can be imported into another repository.
+    /// The archive is written to `output` or `<name>.<format>` in the current directory.
+    pub fn export(
+        &self,
+        name: &str,
+        output: Option<String>,
+        format: ArchiveFormat,
+    ) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let tmp = TempDir::new("rustea-export")?;
+        let entries = self.api.list_files(name)?;
+        for entry in &entries {
+            tmp.write(&entry.path, &self.api.download(&entry.path)?)?;
+        }
+        let info = ExportInfo {
+            feature_set: name.to_owned(),
+            url: self.config.repo.url.clone(),
+            repository: self.config.repo.repository.clone(),
+            owner: self.config.repo.owner.clone(),
+            commit: self.api.latest_commit(name)?.map(|c| c.sha),
+            exported: state::now(),
+            files: entries.len(),
+        };
+        tmp.write(archive::INFO_FILE, toml::to_string(&info)?.as_bytes())?;
+        let output = output.unwrap_or_else(|| format!("{}.{}", name, format.extension()));
+        archive::create(tmp.path(), Path::new(&output), format)?;
+        Ok(format!(
+            "Exported {} files of feature set {} to {}",
+            entries.len(),
+            name,
+            output
+        ))
+    }
+
+    /// This function imports a feature set exported with `export` from the `archive`.
+    /// The files are checked against the exported checksum manifest before any of them
+    /// is pushed. Existing files of the feature set are overwritten.
+    /// All files are pushed in one commit.
+    pub fn import(&self, archive: &str, cmt_msg: Option<String>) -> Result<String> {
+        let tmp = TempDir::new("rustea-import")?;
+        archive::extract(Path::new(archive), tmp.path())?;
+        let info = fs::read_to_string(tmp.path().join(archive::INFO_FILE))
+            .map_err(|_| Error::Rustea(format!("{} is not an export of rustea", archive)))?;
+        let info: ExportInfo = toml::from_str(&info)?;
+        let name = info.feature_set.as_str();
+        let root = tmp.path().join(name);
+        let files = match root.is_dir() {
+            true => archive::files(&root)?,
+            false => vec![],
+        };
+        if files.len() != info.files {
+            return Err(Error::Rustea(format!(
+                "The archive contains {} of {} exported files",
+                files.len(),
+                info.files
+            )));
+        }
+        let manifest_path = root.join(checksum::MANIFEST_FILE);
+        let manifest = match manifest_path.is_file() {
+            true => Manifest::parse(&fs::read_to_string(&manifest_path)?)?,
+            false => Manifest::default(),
+        };
+        let mut contents = Vec::with_capacity(files.len());
+        for file in &files {
+            let content = fs::read(root.join(file))?;
+            let path = file.to_string_lossy().to_string();
+            manifest.verify(&path, &content)?;
+            contents.push((path, content));
+        }
+
+        // The blob sha of every existing file which is overwritten
+        let exists = self.check_feature_set_exists(name)?;
+        let existing: BTreeMap<String, String> = match exists {
+            true => self
+                .api
+                .list_files(name)?
+                .into_iter()
+                .filter_map(|e| Some((e.path, e.sha?)))
+                .collect(),
+            false => BTreeMap::new(),
+        };
+        let (author, email) = self.commit_identity(name)?;
+        let mut changes: Vec<FileChange> = contents
+            .iter()
+            .map(|(path, content)| {
+                let path = format!("{}/{}", name, path);
+                FileChange {
+                    sha: existing.get(&path).cloned(),
+                    path,
+                    content: Some(content.clone()),
+                }
+            })
+            .collect();
+        if !exists {
+            changes.append(&mut keep_files(name, &changes));
+        }
+        self.api
+            .change_files(&changes, author, email, cmt_msg.as_deref())?;
+        for (path, _) in &contents {
+            info!("Imported {}/{}", name, color::paint(Color::Green, path));
+        }
+        Ok(format!(
+            "Imported {} files into feature set {} from {}",
+            contents.len(),
+            name,
+            archive
+        ))
+    }
+
+    /// This function mirrors the feature set `name` into the repository of the `profile`.
+    ///
+    /// The paths are preserved and files which are equal in both repositories are skipped.
+    /// With `keep_message` the latest commit message of the feature set is reused if no
+    /// commit message is given. With `prune` files which only exist in the mirror are deleted.
+    /// All changes of the mirror are made in one commit.
+    pub fn mirror(
+        &self,
+        name: &str,
+        profile: &str,
+        keep_message: bool,
+        prune: bool,
+        cmt_msg: Option<String>,
+    ) -> Result<String> {
+        if !self.check_feature_set_exists(name)? {
+            return Err(Error::Rustea(format!("No features set named {}", name)));
+        }
+        let mut config = self.config.clone();
+        config.use_profile(profile)?;
+        let target = RemoteRepository::new(config)?;
+        let cmt_msg = match (cmt_msg, keep_message) {
+            (None, true) => self.api.latest_commit(name)?.map(|c| c.commit.message),
+            (msg, _) => msg,
+        };
+
+        let entries = self.api.list_files(name)?;
+        let exists = target.check_feature_set_exists(name)?;
+        let mut existing: BTreeMap<String, String> = match exists {
+            true => target
+                .api
+                .list_files(name)?
+                .into_iter()
+                .filter_map(|e| Some((e.path, e.sha?)))
+                .collect(),
+            false => BTreeMap::new(),
+        };
+        let (author, email) = target.commit_identity(name)?;
+        let mut changes = vec![];
+        for entry in &entries {
+            let remote_sha = existing.remove(&entry.path);
+            if remote_sha.is_some() && remote_sha == entry.sha {
+                continue;
+            }
+            changes.push(FileChange {
+                content: Some(self.api.download(&entry.path)?),
+                path: entry.path.clone(),
+                sha: remote_sha,
+            });
+        }
+        let mirrored = changes.len();
+        if prune {
+            changes.extend(existing.into_iter().map(|(path, sha)| FileChange {
+                path,
+                content: None,
+                sha: Some(sha),
+            }));
+        }
+        if !exists {
+            changes.append(&mut keep_files(name, &changes));
+        }
+        target
+            .api
+            .change_files(&changes, author, email, cmt_msg.as_deref())?;
+        for change in &changes[..mirrored] {
+            info!("Mirrored {}", color::paint(Color::Green, &change.path));
+        }
+        for change in changes[mirrored..].iter().filter(|c| c.content.is_none()) {
+            info!("Deleted {}", color::paint(Color::Red, &change.path));
+        }
+        Ok(format!(
+            "Mirrored {} of {} files of feature set {} to profile {}",
+            mirrored,
+            entries.len(),
+            name,
+            profile
+        ))
+    }
+
+    /// This function returns the changes which remove the checksums and metadata of the
+    /// file or folder `path` from the manifest and the sidecar `meta` of the `feature_set`.
+    /// The blob shas of both are taken from the `files` of the feature set.
+    fn without_sub_path(
+        &self,
+        files: &[ContentEntry],
+        feature_set: &str,
+        path: &str,
+        mut meta: Metadata,
+    ) -> Result<Vec<FileChange>> {
+        let sha = |p: &str| {
+            files
+                .iter()
+                .find(|e| e.path == p)
+                .and_then(|e| e.sha.clone())
+        };
+        let mut changes = vec![];
+        let mut manifest = self.get_manifest(feature_set)?;
+        let old_manifest = manifest.to_string();
+        manifest.remove(path);
+        if manifest.to_string() != old_manifest {
+            let manifest_path = format!("{}/{}", feature_set, checksum::MANIFEST_FILE);
+            changes.push(FileChange {
+                sha: sha(&manifest_path),
+                path: manifest_path,
+                content: Some(manifest.to_string().into_bytes()),
+            });
+        }
+        if meta.remove(path) {
+            let meta_path = format!("{}/{}", feature_set, metadata::META_FILE);
+            changes.push(FileChange {
+                sha: sha(&meta_path),
+                path: meta_path,
+                content: Some(meta.to_toml()?.into_bytes()),
+            });
+        }
+        Ok(changes)
+    }
+
+    /// This function copies a feature set, folder or file given as `feature_set[/path]`
+    /// from `src` to `dst` within the remote repository.
+    ///
+    /// Without a destination path the source path is kept. A missing destination feature
+    /// set is created and the checksums of the copied files are added to its manifest.
+    /// A whole feature set is copied with its metadata sidecar, the metadata of a folder
+    /// or file is added to the sidecar of the destination. With `delete_source` the source
+    /// is deleted as well, which moves it, and its checksums and metadata are removed.
+    /// All changes are made in one commit.
+    pub fn copy(
+        &self,
+        src: &str,
+        dst: &str,
+        delete_source: bool,
+        cmt_msg: Option<String>,
+    ) -> Result<String> {
+        let (src_fs, src_path) = split_feature_path(src);
+        let (dst_fs, dst_path) = split_feature_path(dst);
+        if !self.check_feature_set_exists(src_fs)? {
+            return Err(Error::Rustea(format!("No features set named {}", src_fs)));
+        }
+        let src_prefix = match src_path {
+            Some(p) => format!("{}/{}", src_fs, p),
+            None => src_fs.to_owned(),
+        };
+        let dst_prefix = match dst_path.or(src_path) {
+            Some(p) => format!("{}/{}", dst_fs, p),
+            None => dst_fs.to_owned(),
+        };
+        let src_files = self.api.list_files(src_fs)?;
+        let entries: Vec<(&ContentEntry, String)> = src_files
+            .iter()
+            .filter(|e| {
+                !checksum::is_manifest(&e.path, src_fs)
+                    && (src_path.is_none()
+                        || !(metadata::is_sidecar(&e.path, src_fs)
+                            || feature::is_manifest(&e.path, src_fs)))
+            })
+            .filter_map(|e| copy_target(&e.path, &src_prefix, &dst_prefix).map(|t| (e, t)))
+            .collect();
+        if entries.is_empty() {
+            return Err(Error::Rustea(format!("Nothing found at {}", src)));
+        }
+
+        let dst_exists = self.check_feature_set_exists(dst_fs)?;
+        // The blob sha of every file of the destination to update existing files
+        let dst_shas: BTreeMap<String, String> = match dst_exists {
+            true => self
+                .api
+                .list_files(dst_fs)?
+                .into_iter()
+                .filter_map(|e| Some((e.path, e.sha?)))
+                .collect(),
+            false => BTreeMap::new(),
+        };
+        let (author, email) = self.commit_identity(dst_fs)?;
+        let same_fs = src_fs == dst_fs;
+        let mut manifest = self.get_manifest(dst_fs)?;
+        if let (Some(src_path), true) = (src_path, delete_source && same_fs) {
+            manifest.remove(src_path);
+        }
+        let mut changes = vec![];
+        for (entry, target) in &entries {
+            let content = self.api.download(&entry.path)?;
+            if !metadata::is_sidecar(target, dst_fs) && !feature::is_manifest(target, dst_fs) {
+                let remote_path = &target[dst_fs.len()..];
+                manifest.insert(remote_path.trim_start_matches('/'), &content);
+            }
+            changes.push(FileChange {
+                sha: dst_shas.get(target).cloned(),
+                path: target.clone(),
+                content: Some(content),
+            });
+        }
+        if !dst_exists {
+            changes.append(&mut keep_files(dst_fs, &changes));
+        }
+        let manifest_path = format!("{}/{}", dst_fs, checksum::MANIFEST_FILE);
+        changes.push(FileChange {
+            sha: dst_shas.get(&manifest_path).cloned(),
+            path: manifest_path,
+            content: Some(manifest.to_string().into_bytes()),
+        });
+        if let Some(src_path) = src_path {
+            let dst_path = dst_path.unwrap_or(src_path);
+            let src_meta = self.get_metadata(src_fs)?;
+            let mut meta = match same_fs {
+                true => src_meta.clone(),
+                false => self.get_metadata(dst_fs)?,
+            };
+            let mut meta_changed = false;
+            if delete_source && same_fs {
+                meta_changed |= meta.remove(src_path);
+                meta_changed |= meta.move_patterns(src_path, dst_path);
+            }
+            meta_changed |= meta.copy_from(&src_meta, src_path, dst_path);
+            if meta_changed {
+                let meta_path = format!("{}/{}", dst_fs, metadata::META_FILE);
+                changes.push(FileChange {
+                    sha: dst_shas.get(&meta_path).cloned(),
+                    path: meta_path,
+                    content: Some(meta.to_toml()?.into_bytes()),
+                });
+            }
+            if delete_source && !same_fs {
+                changes.append(&mut self.without_sub_path(&src_files, src_fs, src_path, src_meta)?);
+            }
+        }
+
+        if delete_source {
+            let targets: BTreeSet<&str> = entries.iter().map(|(_, t)| t.as_str()).collect();
+            for e in src_files.iter().filter(|e| {
+                copy_target(&e.path, &src_prefix, &src_prefix).is_some()
+                    && !targets.contains(e.path.as_str())
+            }) {
+                let sha = e
+                    .sha
+                    .clone()
+                    .ok_or_else(|| Error::Rustea(format!("The sha of {} is unknown", e.path)))?;
+                changes.push(FileChange {
+                    path: e.path.clone(),
+                    content: None,
+                    sha: Some(sha),
+                });
+            }
+        }
+        let commit = self
+            .api
+            .change_files(&changes, author, email, cmt_msg.as_deref())?;
+        for (entry, target) in &entries {
+            info!(
+                "Copied {} to {}",
+                entry.path,
+                color::paint(Color::Green, target)
+            );
+        }
+        if delete_source {
+            info!("Deleted {}", color::paint(Color::Red, &src_prefix));
+        }
+        let operation = match delete_source {
+            true => Operation::Rename,
+            false => Operation::Copy,
+        };
+        self.audit(
+            operation,
+            src_fs,
+            entries.iter().map(|(_, target)| target.clone()).collect(),
+            commit.into_iter().collect(),
+            cmt_msg.as_deref(),
+        );
+        Ok(format!(
+            "Successfully copied {} files from {} to {}",
+            entries.len(),
+            src,
+            dst
+        ))
+    }
+}
+
+/// Returns the changes which create the empty files keeping the folders of the new
+/// `feature_set`, see `new_feature_set`. Files which are part of the `changes` are left out.
+fn keep_files(feature_set: &str, changes: &[FileChange]) -> Vec<FileChange> {
+    [".gitkeep", "scripts/.gitkeep"]
+        .iter()
+        .map(|keep| format!("{}/{}", feature_set, keep))
+        .filter(|path| !changes.iter().any(|c| c.path == *path))
+        .map(|path| FileChange {
+            path,
+            content: Some(vec![]),
+            sha: None,
+        })
+        .collect()
+}
+
+/// Returns the name of the branch of a pull request which changes the `feature_set` at `timestamp`.
+fn pull_request_branch(feature_set: &str, timestamp: u64) -> String {
+    let name: String = feature_set
+        .chars()
+        .map(
+            |c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                true => c,
+                false => '-',
+            },
+        )
+        .collect();
+    format!("rustea/{}-{}", name, timestamp)
+}
+
+/// The folder of the repository which keeps deleted files below the time of their deletion.
+pub const TRASH_FOLDER: &str = ".trash";
+
+/// Returns the remote path of the remote `path` moved to the trash at `timestamp`.
+fn trash_path(timestamp: u64, path: &str) -> String {
+    format!("{}/{}/{}", TRASH_FOLDER, timestamp, path.trim_matches('/'))
+}
+
+/// Returns the time of the deletion if the remote `path` is a file of the
+/// `feature_set` within the trash.
+fn trashed_at(path: &str, feature_set: &str) -> Option<u64> {
+    let (timestamp, path) = path
+        .strip_prefix(TRASH_FOLDER)?
+        .strip_prefix('/')?
+        .split_once('/')?;
+    match path.strip_prefix(feature_set) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => timestamp.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Returns whether the local file or symbolic link at `path` is `missing` or `modified`
+/// compared with the blob sha of the remote file, `None` if it matches.
+fn drift(path: &Path, link: bool, remote_sha: &str) -> Option<&'static str> {
+    let local = match (link, fs::symlink_metadata(path)) {
+        (_, Err(_)) => return Some("missing"),
+        (true, Ok(_)) => LocalRepository::read_link(path),
+        (false, Ok(m)) if m.is_file() => LocalRepository::read_file(path),
+        (false, Ok(_)) => return Some("modified"),
+    };
+    match local {
+        Ok(local) if checksum::git_blob_sha(&local) == remote_sha => None,
+        _ => Some("modified"),
+    }
+}
+
+/// This function replaces the last part of the `path` with `new_name`.
+fn renamed_path(path: &str, new_name: &str) -> String {
+    match path.trim_matches('/').rsplit_once('/') {
+        Some((parent, _)) => format!("{}/{}", parent, new_name),
+        None => new_name.to_owned(),
+    }
+}
+
+/// This function splits `feature_set/path` into the feature set and the optional path.
+fn split_feature_path(path: &str) -> (&str, Option<&str>) {
+    let path = path.trim_matches('/');
+    match path.split_once('/') {
+        Some((feature_set, path)) => (feature_set, Some(path)),
+        None => (path, None),
+    }
+}
+
+/// This function returns the copy of the remote `path` below `dst_prefix` if the
+/// `path` is either the file `src_prefix` or located below the folder `src_prefix`.
+fn copy_target(path: &str, src_prefix: &str, dst_prefix: &str) -> Option<String> {
+    match path.strip_prefix(src_prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            Some(format!("{}{}", dst_prefix, rest))
+        }
+        _ => None,
+    }
+}
+
+/// This function formats the `commits` as table with the short sha, date, author
+/// and the first line of the message.
+fn format_history(commits: &[Commit]) -> Result<String> {
+    let mut tw = TabWriter::new(vec![]);
+    writeln!(&mut tw, "Commit\tDate\tAuthor\tMessage")?;
+    for c in commits {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{} <{}>\t{}",
+            c.sha.get(..10).unwrap_or(&c.sha),
+            c.commit.author.date,
+            c.commit.author.name,
+            c.commit.author.email,
+            c.commit.message.lines().next().unwrap_or_default()
+        )?;
+    }
+    let table = tw
+        .into_inner()
+        .map_err(|e| Error::Rustea(format!("Failed to align history: {}", e)))?;
+    Ok(String::from_utf8_lossy(&table).trim_end().to_owned())
+}
+
+/// This function returns the lines of `content` matching `re` as `path:line:content`.
+fn grep_lines(re: &Regex, path: &str, content: &str) -> Vec<String> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| re.is_match(l))
+        .map(|(i, l)| format!("{}:{}:{}", path, i + 1, l))
+        .collect()
+}
+
+/// The folder of a feature set which contains the host overlays.
+const HOSTS_FOLDER: &str = "hosts";
+
+/// Returns true if the remote `path` is part of a host overlay of the `feature_set`.
+fn is_overlay(path: &str, feature_set: &str) -> bool {
+    path.starts_with(&format!("{}/{}/", feature_set, HOSTS_FOLDER))
+}
+
+/// Returns true if the remote `path` of the `feature_set` is the file `sub_path` or located
+/// below the folder `sub_path`. The `sub_path` is relative to the feature set or to its
+/// scripts folder for a `script`. A file of the host overlay counts as the base file it replaces.
+fn in_sub_path(path: &str, feature_set: &str, sub_path: &str, script: bool) -> bool {
+    let sub_path = sub_path.trim_matches('/');
+    let folder = match script {
+        true => format!("{}/scripts/{}", feature_set, sub_path),
+        false => format!("{}/{}", feature_set, sub_path),
+    };
+    gitea::in_folder(&strip_overlay(path), folder.trim_end_matches('/'))
+}
+
+/// This function removes the host overlay from a remote path,
+/// `feature_set/hosts/<hostname>/path` becomes `feature_set/path`.
+fn strip_overlay(path: &str) -> String {
+    match path.split_once('/') {
+        Some((feature_set, rest)) if is_overlay(path, feature_set) => {
+            match rest.splitn(3, '/').nth(2) {
+                Some(rest) => format!("{}/{}", feature_set, rest),
+                None => path.to_owned(),
             }
-            println!("Pulled file {}", path.display());
         }
-        Ok(())
+        _ => path.to_owned(),
     }
+}
 
-    /// This function pulls files from the remote repository and stores them
-    /// on the local machine depending on the remote path.
-    ///
-    /// For the provided feature set either the script files or configuration files
-    /// are pulled depending on the `script` and `config` argument. If both are set
-    /// to true only script files are pulled to the local machine.
-    /// If both arguments are set to false everything if pulled from the feature set.
-    ///
-    /// ## Attention
-    ///
-    /// If `path` is provided `script` or `config` flag is set only files matching
-    /// the path are pulled. This doesn't distinguishes between remote pathes with the same suffix.
-    /// Meaning `/test` and `/example/test` are the same if only `test` is given as path.
-    pub fn pull(
-        &self,
-        name: &str,
-        path: Option<String>,
-        script: bool,
-        config: bool,
-    ) -> Result<String> {
-        if !self.check_feature_set_exists(name)? {
-            return Err(Error::Rustea(format!("No features set named {}", name)));
-        }
-        let feature_set = self.api.get_folder(name)?;
+/// Returns the owner of the first pattern of the `ownership` which matches the local `path`.
+fn owner_of<'a>(ownership: &[(Pattern, &'a str)], path: &Path) -> Option<&'a str> {
+    ownership
+        .iter()
+        .find(|(pattern, _)| pattern.matches(path))
+        .map(|(_, owner)| *owner)
+}
 
-        if script || config {
-            let files = feature_set
-                .content
-                .into_iter()
-                .filter(|e| match script {
-                    true => self.local_repo.check_script(&e.path, name),
-                    false => !self.local_repo.check_script(&e.path, name),
-                })
-                .filter(|e| match &path {
-                    Some(p) => e.path.ends_with(p.as_str()),
-                    None => true,
-                })
-                .collect::<Vec<ContentEntry>>();
-            self.pull_files(&files, script)?;
-        } else {
-            // Pull everything found in the feature set
-            for file in feature_set.content {
-                let script = self.local_repo.check_script(&file.path, name);
-                self.pull_files(&[file], script)?;
-            }
-        }
-        Ok(format!(
-            "Successfully pulled files from feature set {}",
-            &name
-        ))
+/// Returns the blob sha of the local file at `path`, of the target for a symbolic link.
+fn local_blob_sha(path: &Path) -> Result<String> {
+    match is_link(path) {
+        true => Ok(checksum::git_blob_sha(
+            fs::read_link(path)?.as_os_str().as_bytes(),
+        )),
+        false => Ok(checksum::git_blob_sha(&LocalRepository::read_file(path)?)),
     }
+}
 
-    /// This function renames either feature sets or folder and files within the remote repository.
-    ///
-    /// Provide the feature set `name` in which the files should be moved. If the `path` is
-    /// empty the whole feature set is renamed. Otherwise, the `path` is resolved and the
-    /// last part of the path (after `/`) is replaced with `new_name`.
-    ///
-    /// Script files can not be renamed.
-    pub fn rename(
-        &self,
-        name: &str,
-        new_name: &str,
-        _path: Option<String>,
-        cmt_msg: Option<String>,
-    ) -> Result<String> {
-        if !self.check_feature_set_exists(name)? {
-            return Err(Error::Rustea(format!("No features set named {}", name)));
-        }
-        let feature_set = self.api.get_folder(name)?;
-
-        self.new_feature_set(new_name, None)?;
-        for file in feature_set.content {
-            let content = self.api.download_file(&file.path)?;
-            let base_path = self.local_repo.strip_prefix(&file.path);
-            self.api.create_or_update_file(
-                new_name,
-                &base_path,
-                content.as_bytes(),
-                &self.config.repo.author,
-                &self.config.repo.email,
-                cmt_msg.as_deref(),
-            )?;
-        }
-        self.delete(name, None, false, true, cmt_msg)?;
-        Ok(format!(
-            "Successfully renamed files in feature set {}",
-            name
-        ))
+/// Returns the listing of the `entries` below `root`, see `ListOptions`.
+fn render_entries(
+    root: &str,
+    content: Vec<ContentEntry>,
+    options: &ListOptions,
+    commits: Option<&BTreeMap<String, Commit>>,
+) -> Result<String> {
+    match (options.tree, options.long) {
+        (true, true) => Err(Error::Rustea(
+            "A listing is either a tree or in the long format".into(),
+        )),
+        (true, false) => Ok(listing::render_tree(root, &content)),
+        (false, true) => ListOptions::render_long(&content, commits),
+        (false, false) => Ok(ContentsResponse { content }.to_string()),
     }
 }
 
+/// This function merges the host overlay `feature_set/hosts/<hostname>/` into the `files`
+/// of a feature set. A file of the overlay replaces the base file with the same path,
+/// the overlays of other hosts are dropped.
+fn apply_overlay(files: Vec<ContentEntry>, feature_set: &str, hostname: &str) -> Vec<ContentEntry> {
+    let own = format!("{}/{}/{}/", feature_set, HOSTS_FOLDER, hostname);
+    let (overlay, base): (Vec<ContentEntry>, Vec<ContentEntry>) = files
+        .into_iter()
+        .filter(|e| !is_overlay(&e.path, feature_set) || e.path.starts_with(&own))
+        .partition(|e| is_overlay(&e.path, feature_set));
+    let replaced: Vec<String> = overlay.iter().map(|e| strip_overlay(&e.path)).collect();
+    base.into_iter()
+        .filter(|e| !replaced.contains(&e.path))
+        .chain(overlay)
+        .collect()
+}
+
 /// The `LocalRepository` operates on local folders and takes
 /// care of transforming pathes between remote and local
 #[derive(Debug)]
 struct LocalRepository {
-    regex: Regex,
+    exclude: Vec<Pattern>,
     script_dir: PathBuf,
+    /// The script folders of single feature sets which replace the `script_dir`
+    script_dirs: BTreeMap<String, PathBuf>,
     script_prefix: String,
+    secrets: SecretFiles,
+    /// Read the linked files instead of symbolic links
+    dereference: bool,
+    /// The home directory of the user
+    home: Option<PathBuf>,
+    /// The feature sets whose configuration files are relative to the `home` directory
+    home_sets: Vec<String>,
+    /// The configuration files of all feature sets are relative to the `home` directory
+    home_all: bool,
+    /// The root folder of all local files instead of `/`, e.g. of an image
+    prefix: Option<PathBuf>,
+    /// The folders into which pulled files may be written, every folder if empty
+    allowed_paths: Vec<PathBuf>,
+}
+
+/// Local folders into which pulled files are never written, they hold pseudo file systems.
+const DENIED_PATHS: [&str; 3] = ["/proc", "/sys", "/dev"];
+
+/// Returns the `path` with the symbolic links of its existing ancestors resolved.
+fn resolve(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(real) = ancestor.canonicalize() {
+            return real.join(path.strip_prefix(ancestor).unwrap_or(path));
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Returns true if the `path` has only normal components and can't leave the
+/// folder it is joined to.
+fn is_contained(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
 }
 
 impl LocalRepository {
     /// Create a new `LocalRepository`.
     /// # Error
-    ///   - Throws an IO error if the `script_dir` can either not be created or
-    ///     it is not writable
-    fn new(regex: &str, script_dir: PathBuf) -> Result<Self> {
-        LocalRepository::create_path(&script_dir)?;
-        LocalRepository::writable_path(&script_dir)?;
-        let re = Regex::new(regex).unwrap();
+    ///   - Throws an IO error if the `script_dir` or one of the `script_dirs` can either
+    ///     not be created or it is not writable
+    ///   - Throws an error if one of the `exclude` patterns is invalid
+    fn new(
+        exclude: &[String],
+        script_dir: PathBuf,
+        script_dirs: BTreeMap<String, PathBuf>,
+        secrets: SecretFiles,
+        home_sets: Vec<String>,
+        allowed_paths: Vec<PathBuf>,
+    ) -> Result<Self> {
+        for dir in std::iter::once(&script_dir).chain(script_dirs.values()) {
+            LocalRepository::create_path(dir)?;
+            LocalRepository::writable_path(dir)?;
+        }
 
         Ok(LocalRepository {
-            regex: re,
+            exclude: Pattern::all(exclude)?,
             script_dir,
+            script_dirs,
             script_prefix: "/scripts/".into(),
+            secrets,
+            dereference: false,
+            home: env::var_os("HOME")
+                .filter(|h| !h.is_empty())
+                .map(PathBuf::from),
+            home_sets,
+            home_all: false,
+            prefix: None,
+            allowed_paths,
         })
     }
 
@@ -531,7 +3815,7 @@ impl LocalRepository {
             true => Ok(()),
             false => fs::DirBuilder::new()
                 .recursive(true)
-                .create(&path)
+                .create(path)
                 .map_err(Error::Io),
         }
     }
@@ -547,51 +3831,156 @@ impl LocalRepository {
         }
     }
 
-    // This function performs the create and writable path check
-    fn check_path(&self, path: &Path) -> Result<()> {
-        LocalRepository::create_path(path)?;
-        LocalRepository::writable_path(path)
-    }
-
     // This function checks wether a string has a certain prefix
     fn check_script(&self, path: &str, name: &str) -> bool {
         let test = format!("{}{}", name, self.script_prefix);
-        path.starts_with(&test)
+        strip_overlay(path).starts_with(&test)
+    }
+
+    /// This function selects the listed `entries` of the `feature_set` or the feature sets
+    /// with the `options`. Only the files within a feature set are scripts or configuration files.
+    fn select_entries(
+        &self,
+        feature_set: Option<&str>,
+        entries: Vec<ContentEntry>,
+        options: &ListOptions,
+    ) -> Result<Vec<ContentEntry>> {
+        match (feature_set, options.kind) {
+            (Some(name), _) => {
+                options.select(entries, |e| {
+                    match RemoteRepository::is_internal_file(&e.path, name) {
+                        true => None,
+                        false if self.check_script(&e.path, name) => Some(FileKind::Script),
+                        false => Some(FileKind::Config),
+                    }
+                })
+            }
+            (None, Some(_)) => Err(Error::Rustea(
+                "Files can only be listed by type within a feature set".into(),
+            )),
+            (None, None) => options.select(entries, |_| None),
+        }
+    }
+
+    /// Returns the script folder of the `feature_set`, the global one if it has none.
+    fn script_dir(&self, feature_set: &str) -> &Path {
+        self.script_dirs
+            .get(feature_set)
+            .unwrap_or(&self.script_dir)
     }
 
-    // This function removes the `script_dir` prefix from a path
-    fn strip_prefix(&self, path: &str) -> String {
-        path.strip_prefix(&self.script_prefix)
-            .unwrap_or(path)
-            .into()
+    /// Returns the home directory if the configuration files of `feature_set` are
+    /// relative to it, otherwise `None` and they are relative to `/`.
+    fn home_root(&self, feature_set: &str) -> Result<Option<&Path>> {
+        match self.home_all || self.home_sets.iter().any(|s| s == feature_set) {
+            true => match &self.home {
+                Some(home) => Ok(Some(home)),
+                None => Err(Error::Configuration(error::ConfigError::LocationError)),
+            },
+            false => Ok(None),
+        }
     }
 
     /// This function converts a local path to a path for the remote repository.
-    fn transform_to_remote_path(&self, path: &Path, script: bool) -> Result<String> {
-        match script {
-            true => match path.file_name() {
-                Some(name) => Ok(format!("{}{}", self.script_prefix, name.to_string_lossy())),
+    /// Scripts within the script folder of the feature set keep their folders
+    /// below `scripts/`, other scripts are pushed with their file name only.
+    /// Configuration files of a feature set relative to the home directory
+    /// have to be within it.
+    fn transform_to_remote_path(
+        &self,
+        path: &Path,
+        script: bool,
+        feature_set: &str,
+    ) -> Result<String> {
+        let script_dir = self.script_dir(feature_set);
+        let in_script_dir = [script_dir.to_path_buf(), resolve(script_dir)]
+            .iter()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .find_map(|dir| path.strip_prefix(dir).ok())
+            .filter(|rel| rel.file_name().is_some());
+        match (script, self.home_root(feature_set)?) {
+            (true, _) => match in_script_dir.or_else(|| path.file_name().map(Path::new)) {
+                Some(rel) => Ok(format!("{}{}", self.script_prefix, rel.display())),
                 None => Err(Error::io(
                     io::ErrorKind::Other,
                     format!("{} not a valid file path", path.display()),
                 )),
             },
-            false => Ok(path.display().to_string()),
+            (false, Some(home)) => match path.strip_prefix(home) {
+                Ok(rel) => Ok(format!("/{}", rel.display())),
+                Err(_) => Err(Error::io(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} is not within the home directory {}",
+                        path.display(),
+                        home.display()
+                    ),
+                )),
+            },
+            (false, None) => Ok(path.display().to_string()),
         }
     }
 
     /// This function converts a remote path to a local one.
-    /// A remote path is either `feature_set_name/path` or `feature_set_name/scripts/path`.
+    /// A remote path is either `feature_set_name/path` or `feature_set_name/scripts/path`,
+    /// optionally within a host overlay `feature_set_name/hosts/<hostname>/`.
+    /// The `path` of a configuration file is relative to `/` or to the home directory.
+    /// The suffix of templates and encrypted secret files is removed from the local path.
+    /// With a `prefix` the local path is placed below it.
     fn transform_to_local_path(&self, path: &str, script: bool) -> Result<PathBuf> {
+        let local = match self.secret_path(path, script) {
+            Some(local) => local,
+            None => {
+                let stripped = strip_overlay(path);
+                let stripped = stripped.strip_suffix(template::SUFFIX).unwrap_or(&stripped);
+                self.local_path(path, stripped, script)?
+            }
+        };
+        Ok(match &self.prefix {
+            Some(prefix) => prefix.join(local.strip_prefix("/").unwrap_or(&local)),
+            None => local,
+        })
+    }
+
+    /// Returns the local path of the remote `path` if it is an encrypted secret file.
+    fn secret_path(&self, path: &str, script: bool) -> Option<PathBuf> {
+        let stripped = strip_overlay(path);
+        let plain = self.secrets.strip_suffix(&stripped)?;
+        self.local_path(path, plain, script)
+            .ok()
+            .filter(|local| self.secrets.matches(local))
+    }
+
+    /// This function converts the remote path `stripped` of overlay and suffixes to a
+    /// local one, the original `path` is used for errors.
+    /// Paths with `..` segments are refused, they could leave the local root.
+    /// The folders of a script below `scripts/` are recreated in the script folder.
+    fn local_path(&self, path: &str, stripped: &str, script: bool) -> Result<PathBuf> {
         let split = match script {
-            true => path.rsplit_once("/"),
-            false => path.split_once("/"),
+            true => stripped
+                .split_once(self.script_prefix.as_str())
+                .filter(|(feature_set, _)| !feature_set.contains('/'))
+                .or_else(|| stripped.rsplit_once("/")),
+            false => stripped.split_once("/"),
         };
+        if let Some((_, rel)) = split.filter(|(_, rel)| !is_contained(rel)) {
+            return Err(Error::io(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Remote path {} is not a valid path, {} may leave its folder",
+                    path, rel
+                ),
+            ));
+        }
         match split {
             Some((_, name)) if script => {
-                Ok([&self.script_dir, &PathBuf::from(name)].iter().collect())
+                let feature_set = stripped.split('/').next().unwrap_or_default();
+                Ok(self.script_dir(feature_set).join(name))
             }
-            Some((_, path)) if !script => Ok(["/", path].iter().collect()),
+            Some((feature_set, path)) if !script => match self.home_root(feature_set)? {
+                Some(home) => Ok(home.join(path)),
+                None => Ok(["/", path].iter().collect()),
+            },
             None | Some(_) => Err(Error::io(
                 io::ErrorKind::InvalidInput,
                 format!("Remote path {} can not converted to local one.", path),
@@ -599,30 +3988,98 @@ impl LocalRepository {
         }
     }
 
-    fn read_folder(&self, path: &Path) -> Result<Vec<PathBuf>> {
+    /// This function checks that a pulled file may be written to the local `path`.
+    ///
+    /// The symbolic links of the existing folders of `path` are resolved first, so a link
+    /// can't redirect the file. With a `prefix` the file has to stay below it.
+    /// Afterwards the path is refused if it is within one of the `DENIED_PATHS` or
+    /// if `allowed_paths` are configured and it is neither within one of them nor the script folder.
+    fn check_writable(&self, path: &Path) -> Result<()> {
+        let refused = |reason: &str| {
+            Err(Error::io(
+                io::ErrorKind::PermissionDenied,
+                format!("Refusing to write {}, {}", path.display(), reason),
+            ))
+        };
+        let resolved = resolve(path);
+        let local = match &self.prefix {
+            Some(prefix) => match resolved.strip_prefix(resolve(prefix)) {
+                Ok(rel) => Path::new("/").join(rel),
+                Err(_) => return refused("it is not below the prefix"),
+            },
+            None => resolved,
+        };
+        if DENIED_PATHS.iter().any(|d| local.starts_with(d)) {
+            return refused("it is within a pseudo file system");
+        }
+        let allowed = self.allowed_paths.is_empty()
+            || std::iter::once(&self.script_dir)
+                .chain(self.script_dirs.values())
+                .any(|dir| local.starts_with(dir) || local.starts_with(resolve(dir)))
+            || self.allowed_paths.iter().any(|a| local.starts_with(a));
+        match allowed {
+            true => Ok(()),
+            false => refused("it is not within the allowed_paths"),
+        }
+    }
+
+    /// This function returns the files found below `path` which don't match
+    /// one of the `exclude` patterns. The patterns of a `.rusteaignore` file apply
+    /// to the folder containing it and its subfolders, the file itself is not returned.
+    /// Symbolic links are returned as files unless `dereference` is set, then linked
+    /// folders are read as well.
+    fn read_folder(&self, path: &Path, exclude: &[Pattern]) -> Result<Vec<PathBuf>> {
+        self.read_entries(&self.absolute(path)?, exclude)
+    }
+
+    /// Returns true if `path` is a symbolic link which is read as link.
+    fn is_symlink(&self, path: &Path) -> bool {
+        !self.dereference && is_link(path)
+    }
+
+    /// This function returns the absolute `path`. Unlike `canonicalize` a symbolic
+    /// link as last component is kept, so it is pushed with its own path.
+    fn absolute(&self, path: &Path) -> Result<PathBuf> {
+        match (is_link(path), path.parent(), path.file_name()) {
+            (true, Some(parent), Some(name)) => {
+                let parent = match parent.as_os_str().is_empty() {
+                    true => Path::new("."),
+                    false => parent,
+                };
+                Ok(parent.canonicalize()?.join(name))
+            }
+            _ => Ok(path.canonicalize()?),
+        }
+    }
+
+    /// See `read_folder`, the `path` is already absolute.
+    fn read_entries(&self, path: &Path, exclude: &[Pattern]) -> Result<Vec<PathBuf>> {
         let mut v: Vec<PathBuf> = vec![];
-        let path = path.canonicalize()?;
-        if path.is_dir() {
+        if path.is_dir() && !self.is_symlink(path) {
+            let mut exclude = exclude.to_vec();
+            exclude.append(&mut glob::read_ignore_file(path)?);
             // Check if the original path is a folder
-            for entry in fs::read_dir(&path)? {
+            for entry in fs::read_dir(path)? {
                 let entry = entry?;
-                // We assume that a regex only applies if a folder is pushed
+                // We assume that a pattern only applies if a folder is pushed
                 // since a file is explicitly pushed by the user.
-                if self.regex.is_match(&entry.path().display().to_string()) {
+                if entry.file_name() == glob::IGNORE_FILE
+                    || glob::is_excluded(&exclude, &entry.path())
+                {
                     continue;
                 }
 
-                if entry.path().is_dir() {
+                if entry.path().is_dir() && !self.is_symlink(&entry.path()) {
                     // Recursively push folders
-                    let mut entries = self.read_folder(&entry.path())?;
+                    let mut entries = self.read_entries(&entry.path(), &exclude)?;
                     v.append(&mut entries);
                 } else {
                     // Push a single file
-                    v.push(entry.path().canonicalize()?)
+                    v.push(entry.path())
                 }
             }
         } else {
-            v.push(path);
+            v.push(path.to_path_buf());
         }
         Ok(v)
     }
@@ -633,61 +4090,902 @@ impl LocalRepository {
         File::open(path).and_then(|mut f| f.read_to_end(&mut b))?;
         Ok(b)
     }
+
+    // This function reads the target of a symbolic link into a byte buffer.
+    fn read_link(path: &Path) -> Result<Vec<u8>> {
+        Ok(fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+    }
+}
+
+/// Returns true if `path` itself is a symbolic link.
+fn is_link(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
+    use std::{
+        cell::Cell,
+        collections::BTreeMap,
+        fs,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    };
+
+    use crate::{
+        apply_overlay,
+        checksum::{self, Manifest},
+        config_paths, copy_target, drift,
+        error::Error,
+        failures, format_history,
+        gitea::gitea_api::{Commit, CommitDetails, CommitUser, ContentEntry},
+        glob::Pattern,
+        grep_lines, in_sub_path,
+        metadata::Metadata,
+        migrate_config, owner_of,
+        provider::memory::Memory,
+        pull_request_branch, renamed_path,
+        secret::{SecretFiles, Secrets},
+        split_feature_path,
+        state::State,
+        temp::TempDir,
+        trash_path, trashed_at, Identity, LocalRepository, Profile, RemoteRepository,
+        RepositoryConfig, RusteaConfiguration,
+    };
+    use regex::Regex;
 
-    use crate::{read_file, read_folder, to_local_path, to_remote_path};
+    fn local_repo(script_dir: &str) -> LocalRepository {
+        LocalRepository {
+            exclude: vec![Pattern::new(".git").unwrap()],
+            script_dir: PathBuf::from(script_dir),
+            script_dirs: BTreeMap::new(),
+            script_prefix: "/scripts/".into(),
+            secrets: SecretFiles::default(),
+            dereference: false,
+            home: Some(PathBuf::from("/home/ops")),
+            home_sets: vec!["dotfiles".into()],
+            home_all: false,
+            prefix: None,
+            allowed_paths: vec![],
+        }
+    }
 
     #[test]
     fn test_read_folder() {
         let path = PathBuf::from("./tests");
-        let res = read_folder(&path);
+        let repo = local_repo("");
+        let res = repo.read_folder(&path, &repo.exclude);
         assert!(res.is_ok());
     }
 
     #[test]
     fn test_read_folder_single_file() {
         let path = PathBuf::from("./tests/test_config.rs");
-        let res = read_folder(&path);
+        let repo = local_repo("");
+        let res = repo.read_folder(&path, &repo.exclude);
         assert!(res.is_ok());
     }
 
     #[test]
     fn test_read_folder_recursively() {
         let path = PathBuf::from("./src");
-        let res = read_folder(&path);
+        let repo = local_repo("");
+        let res = repo.read_folder(&path, &repo.exclude);
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn test_read_folder_excludes() {
+        let tmp = crate::temp::TempDir::new("rustea-exclude").unwrap();
+        let conf = tmp.write("app.conf", b"conf").unwrap();
+        tmp.write(".app.conf.swp", b"swap").unwrap();
+        tmp.write("secrets/key", b"key").unwrap();
+        tmp.write(".git/config", b"git").unwrap();
+        tmp.write("cache/.rusteaignore", b"*\n").unwrap();
+        tmp.write("cache/data", b"data").unwrap();
+        let repo = local_repo("");
+        let mut exclude = repo.exclude.clone();
+        exclude.push(Pattern::new("*.swp").unwrap());
+        exclude.push(Pattern::new("secrets/*").unwrap());
+        let files = repo.read_folder(tmp.path(), &exclude).unwrap();
+        assert_eq!(files, vec![conf.canonicalize().unwrap()]);
+    }
+
+    #[test]
+    fn test_read_folder_symlinks() {
+        let tmp = crate::temp::TempDir::new("rustea-symlink").unwrap();
+        let conf = tmp.write("shared/app.conf", b"conf").unwrap();
+        let dir = tmp.path().canonicalize().unwrap().join("sites");
+        std::fs::create_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(&conf, dir.join("app")).unwrap();
+        std::os::unix::fs::symlink("../shared", dir.join("shared")).unwrap();
+        let mut repo = local_repo("");
+        let mut files = repo.read_folder(&dir, &repo.exclude).unwrap();
+        files.sort();
+        assert_eq!(files, vec![dir.join("app"), dir.join("shared")]);
+        assert!(repo.is_symlink(&files[0]));
+        assert_eq!(
+            LocalRepository::read_link(&files[0]).unwrap(),
+            conf.as_os_str().as_bytes()
+        );
+
+        repo.dereference = true;
+        let mut files = repo.read_folder(&dir, &repo.exclude).unwrap();
+        files.sort();
+        assert_eq!(files, vec![dir.join("app"), dir.join("shared/app.conf")]);
+        assert!(!repo.is_symlink(&files[0]));
+    }
+
     #[test]
     fn test_read_file() {
         let path = PathBuf::from(".gitignore");
-        let res = read_file(&path);
+        let res = LocalRepository::read_file(&path);
         assert!(res.is_ok())
     }
 
     #[test]
     fn test_to_remote_path() {
+        let repo = local_repo("");
         let path = PathBuf::from(".gitignore");
-        let remote_path = to_remote_path(&path, false).unwrap();
+        let remote_path = repo.transform_to_remote_path(&path, false, "t").unwrap();
         assert_eq!(remote_path, ".gitignore");
-        let remote_path = to_remote_path(&path, true).unwrap();
+        let remote_path = repo.transform_to_remote_path(&path, true, "t").unwrap();
         assert_eq!(remote_path, "/scripts/.gitignore");
-        let remote_path = to_remote_path(&PathBuf::from("/"), true);
-        assert!(remote_path.is_err())
+        let remote_path = repo.transform_to_remote_path(&PathBuf::from("/"), true, "t");
+        assert!(remote_path.is_err());
+
+        let repo = local_repo("/usr/local/bin");
+        let script = PathBuf::from("/usr/local/bin/backup/run");
+        let remote_path = repo.transform_to_remote_path(&script, true, "t").unwrap();
+        assert_eq!(remote_path, "/scripts/backup/run");
+        let script = PathBuf::from("/tmp/backup/run");
+        let remote_path = repo.transform_to_remote_path(&script, true, "t").unwrap();
+        assert_eq!(remote_path, "/scripts/run");
+        let local_path = repo
+            .transform_to_local_path("t/scripts/backup/run", true)
+            .unwrap();
+        assert_eq!(local_path, PathBuf::from("/usr/local/bin/backup/run"));
+        assert!(repo
+            .transform_to_local_path("t/scripts/../run", true)
+            .is_err());
+    }
+
+    #[test]
+    fn test_home_paths() {
+        let mut repo = local_repo("/usr/local/bin");
+        let bashrc = PathBuf::from("/home/ops/.bashrc");
+        assert_eq!(
+            repo.transform_to_remote_path(&bashrc, false, "dotfiles")
+                .unwrap(),
+            "/.bashrc"
+        );
+        assert_eq!(
+            repo.transform_to_remote_path(&bashrc, false, "shell")
+                .unwrap(),
+            "/home/ops/.bashrc"
+        );
+        let outside =
+            repo.transform_to_remote_path(&PathBuf::from("/etc/hosts"), false, "dotfiles");
+        assert!(outside.is_err());
+        assert_eq!(
+            repo.transform_to_local_path("dotfiles/.config/git/config", false)
+                .unwrap(),
+            PathBuf::from("/home/ops/.config/git/config")
+        );
+        assert_eq!(
+            repo.transform_to_local_path("dotfiles/scripts/backup", true)
+                .unwrap(),
+            PathBuf::from("/usr/local/bin/backup")
+        );
+        assert_eq!(
+            repo.transform_to_local_path("shell/.bashrc", false)
+                .unwrap(),
+            PathBuf::from("/.bashrc")
+        );
+        repo.home_all = true;
+        assert_eq!(
+            repo.transform_to_local_path("shell/.bashrc", false)
+                .unwrap(),
+            bashrc
+        );
+        repo.home = None;
+        assert!(repo
+            .transform_to_local_path("shell/.bashrc", false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_drift() {
+        let tmp = crate::temp::TempDir::new("rustea-drift").unwrap();
+        let file = tmp.write("etc/app.conf", b"listen 80\n").unwrap();
+        let sha = checksum::git_blob_sha(b"listen 80\n");
+        assert_eq!(drift(&file, false, &sha), None);
+        assert_eq!(drift(&file, false, "b2"), Some("modified"));
+        assert_eq!(
+            drift(&tmp.path().join("etc/gone"), false, &sha),
+            Some("missing")
+        );
+        assert_eq!(
+            drift(&tmp.path().join("etc"), false, &sha),
+            Some("modified")
+        );
+
+        let link = tmp.path().join("etc/app");
+        std::os::unix::fs::symlink("app.conf", &link).unwrap();
+        assert_eq!(
+            drift(&link, true, &checksum::git_blob_sha(b"app.conf")),
+            None
+        );
+        assert_eq!(drift(&link, true, &sha), Some("modified"));
+        assert_eq!(drift(&file, true, &sha), Some("modified"));
+    }
+
+    #[test]
+    fn test_failures() {
+        assert!(failures("Pushing web", 3, vec![]).is_ok());
+        let failed = vec![(
+            PathBuf::from("/etc/nginx.conf"),
+            Error::Rustea("timeout".into()),
+        )];
+        match failures("Pushing web", 3, failed) {
+            Err(Error::Batch(failed)) => {
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].0, Path::new("/etc/nginx.conf"));
+                assert!(matches!(failed[0].1, Error::Rustea(_)));
+            }
+            res => panic!("Expected a batch error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_pull_request_branch() {
+        assert_eq!(
+            pull_request_branch("web", 1700000000),
+            "rustea/web-1700000000"
+        );
+        assert_eq!(pull_request_branch("my set.d", 1), "rustea/my-set-d-1");
+    }
+
+    #[test]
+    fn test_trash_paths() {
+        assert_eq!(trash_path(1700000000, "/web/"), ".trash/1700000000/web");
+        assert_eq!(
+            trashed_at(".trash/1700000000/web/etc/a.conf", "web"),
+            Some(1700000000)
+        );
+        assert_eq!(trashed_at(".trash/1700000000/web", "web"), Some(1700000000));
+        assert_eq!(trashed_at(".trash/1700000000/webapp/a", "web"), None);
+        assert_eq!(trashed_at(".trash/latest/web/a", "web"), None);
+        assert_eq!(trashed_at("web/.trash/1/web/a", "web"), None);
+    }
+
+    #[test]
+    fn test_path_traversal() {
+        let repo = local_repo("/usr/local/bin");
+        for path in &[
+            "web/../../etc/shadow",
+            "web/etc/../../root/.ssh/authorized_keys",
+            "web/",
+            "dotfiles/../.ssh/config",
+        ] {
+            assert!(
+                repo.transform_to_local_path(path, false).is_err(),
+                "{}",
+                path
+            );
+        }
+        assert!(repo
+            .transform_to_local_path("web/scripts/..", true)
+            .is_err());
+        assert!(repo
+            .transform_to_local_path("web/etc/app..conf", false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let tmp = crate::temp::TempDir::new("rustea-writable").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("etc")).unwrap();
+        std::os::unix::fs::symlink("/proc", root.join("etc/proc")).unwrap();
+        let mut repo = local_repo("/usr/local/bin");
+        assert!(repo.check_writable(&root.join("etc/app.conf")).is_ok());
+        assert!(repo
+            .check_writable(Path::new("/proc/sys/kernel/x"))
+            .is_err());
+        assert!(repo.check_writable(&root.join("etc/proc/sys/x")).is_err());
+
+        repo.allowed_paths = vec![root.join("etc")];
+        assert!(repo
+            .check_writable(&root.join("etc/nginx/nginx.conf"))
+            .is_ok());
+        assert!(repo.check_writable(&root.join("root/.bashrc")).is_err());
+        assert!(repo
+            .check_writable(Path::new("/usr/local/bin/deploy"))
+            .is_ok());
+
+        repo.allowed_paths.clear();
+        repo.prefix = Some(root.clone());
+        assert!(repo.check_writable(&root.join("etc/app.conf")).is_ok());
+        assert!(repo.check_writable(&root.join("etc/proc/x")).is_err());
+    }
+
+    #[test]
+    fn test_prefix_paths() {
+        let mut repo = local_repo("/usr/local/bin");
+        repo.prefix = Some(PathBuf::from("/mnt/image"));
+        assert_eq!(
+            repo.transform_to_local_path("web/etc/nginx/nginx.conf", false)
+                .unwrap(),
+            PathBuf::from("/mnt/image/etc/nginx/nginx.conf")
+        );
+        assert_eq!(
+            repo.transform_to_local_path("web/scripts/deploy", true)
+                .unwrap(),
+            PathBuf::from("/mnt/image/usr/local/bin/deploy")
+        );
+        assert_eq!(
+            repo.transform_to_local_path("dotfiles/.bashrc", false)
+                .unwrap(),
+            PathBuf::from("/mnt/image/home/ops/.bashrc")
+        );
     }
 
     #[test]
     fn test_to_local_path() {
         let remote_path = "testing/etc/test";
-        let local_path = to_local_path(&remote_path, false, "").unwrap();
+        let local_path = local_repo("")
+            .transform_to_local_path(remote_path, false)
+            .unwrap();
         assert_eq!(local_path, PathBuf::from("/etc/test"));
-        let local_path = to_local_path(&remote_path, true, "/usr/local/bin").unwrap();
+        let local_path = local_repo("/usr/local/bin")
+            .transform_to_local_path(remote_path, true)
+            .unwrap();
         assert_eq!(local_path, PathBuf::from("/usr/local/bin/test"));
-        let local_path = to_local_path("test", false, "");
+        let local_path = local_repo("").transform_to_local_path("test", false);
         assert!(local_path.is_err());
+        let local_path = local_repo("")
+            .transform_to_local_path("testing/hosts/web1/etc/test", false)
+            .unwrap();
+        assert_eq!(local_path, PathBuf::from("/etc/test"));
+        let local_path = local_repo("")
+            .transform_to_local_path("testing/etc/test.conf.tmpl", false)
+            .unwrap();
+        assert_eq!(local_path, PathBuf::from("/etc/test.conf"));
+        let mut repo = local_repo("/usr/local/bin");
+        assert!(repo.check_script("testing/hosts/web1/scripts/test", "testing"));
+        repo.script_dirs
+            .insert("monitoring".into(), PathBuf::from("/opt/monitoring/bin"));
+        assert_eq!(
+            repo.transform_to_local_path("monitoring/scripts/check", true)
+                .unwrap(),
+            PathBuf::from("/opt/monitoring/bin/check")
+        );
+        assert_eq!(
+            repo.transform_to_local_path("testing/scripts/check", true)
+                .unwrap(),
+            PathBuf::from("/usr/local/bin/check")
+        );
+        repo.allowed_paths = vec![PathBuf::from("/etc")];
+        assert!(repo
+            .check_writable(Path::new("/opt/monitoring/bin/check"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_secret_to_local_path() {
+        let secrets = Secrets {
+            patterns: vec!["/etc/app/*.secret".into()],
+            ..Default::default()
+        };
+        let repo = LocalRepository {
+            secrets: secrets.files().unwrap(),
+            ..local_repo("")
+        };
+        assert_eq!(
+            repo.transform_to_local_path("app/etc/app/db.secret.age", false)
+                .unwrap(),
+            PathBuf::from("/etc/app/db.secret")
+        );
+        assert!(repo
+            .secret_path("app/etc/app/db.secret.age", false)
+            .is_some());
+        // Only files matching the patterns are secrets
+        assert_eq!(
+            repo.transform_to_local_path("app/etc/apt/trusted.age", false)
+                .unwrap(),
+            PathBuf::from("/etc/apt/trusted.age")
+        );
+        assert!(repo.secret_path("app/etc/app/db.secret", false).is_none());
+    }
+
+    #[test]
+    fn test_format_history() {
+        let commit = |sha: &str, name: &str, message: &str| Commit {
+            sha: sha.to_owned(),
+            commit: CommitDetails {
+                message: message.to_owned(),
+                author: CommitUser {
+                    name: name.to_owned(),
+                    email: format!("{}@example.org", name),
+                    date: "2021-10-01T12:00:00Z".to_owned(),
+                },
+                ..Default::default()
+            },
+        };
+        let history = format_history(&[
+            commit(
+                "7d2a1f9c3b8e4d6a",
+                "alice",
+                "Update nginx.conf\n\nRaise the timeout",
+            ),
+            commit("abc", "bob", "Add site"),
+        ])
+        .unwrap();
+        assert_eq!(
+            history,
+            "Commit      Date                  Author                     Message\n\
+             7d2a1f9c3b  2021-10-01T12:00:00Z  alice <alice@example.org>  Update nginx.conf\n\
+             abc         2021-10-01T12:00:00Z  bob <bob@example.org>      Add site"
+        );
+    }
+
+    #[test]
+    fn test_grep_lines() {
+        let re = Regex::new(r"^listen\s+\d+").unwrap();
+        let content = "server {\nlisten 80;\n# listen 8080;\nlisten  443 ssl;\n}";
+        assert_eq!(
+            grep_lines(&re, "nginx/etc/nginx.conf", content),
+            vec![
+                "nginx/etc/nginx.conf:2:listen 80;",
+                "nginx/etc/nginx.conf:4:listen  443 ssl;"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_target() {
+        assert_eq!(split_feature_path("nginx"), ("nginx", None));
+        assert_eq!(
+            split_feature_path("/nginx/etc/nginx/"),
+            ("nginx", Some("etc/nginx"))
+        );
+        assert_eq!(
+            copy_target(
+                "nginx/etc/nginx/nginx.conf",
+                "nginx/etc/nginx",
+                "web/etc/nginx"
+            ),
+            Some("web/etc/nginx/nginx.conf".to_owned())
+        );
+        assert_eq!(
+            copy_target(
+                "nginx/etc/mime.types",
+                "nginx/etc/mime.types",
+                "web/etc/mime"
+            ),
+            Some("web/etc/mime".to_owned())
+        );
+        assert_eq!(
+            copy_target("nginx/etc/nginx.conf", "nginx", "web"),
+            Some("web/etc/nginx.conf".to_owned())
+        );
+        assert_eq!(
+            copy_target("nginx/etc/nginxx/a", "nginx/etc/nginx", "web"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_path() {
+        assert_eq!(renamed_path("/etc/nginx/", "web"), "etc/web");
+        assert_eq!(
+            renamed_path("scripts/backup.sh", "restore.sh"),
+            "scripts/restore.sh"
+        );
+        assert_eq!(renamed_path("etc", "opt"), "opt");
+
+        // Renaming a folder moves everything below it
+        let (src, dst) = ("nginx/etc/nginx", "nginx/etc/web");
+        assert_eq!(
+            copy_target("nginx/etc/nginx/conf.d/site.conf", src, dst),
+            Some("nginx/etc/web/conf.d/site.conf".to_owned())
+        );
+        assert_eq!(
+            copy_target("nginx/etc/nginx/nginx.conf", src, dst),
+            Some("nginx/etc/web/nginx.conf".to_owned())
+        );
+        assert_eq!(copy_target("nginx/etc/mime.types", src, dst), None);
+    }
+
+    /// Returns a repository on the in-memory `backend` which writes its audit log into `tmp`.
+    fn memory_repo(backend: Memory, tmp: &TempDir) -> RemoteRepository {
+        let mut config = RusteaConfiguration {
+            audit_log: Some(tmp.path().join("audit.jsonl")),
+            ..RusteaConfiguration::default()
+        };
+        config.repo.author = "ops".into();
+        config.repo.email = "ops@example.com".into();
+        RemoteRepository {
+            config,
+            api: Box::new(backend),
+            local_repo: local_repo(""),
+            hostname: "web1".into(),
+            interactive: false,
+            encrypt: false,
+            allow_secrets: false,
+            confirm: false,
+            force: false,
+            prune: false,
+            keep_going: false,
+            push_permission: Cell::new(None),
+        }
+    }
+
+    #[test]
+    fn test_get_manifest() {
+        let tmp = TempDir::new("rustea-manifest").unwrap();
+        let repo = memory_repo(Memory::with_files(&[("web/.gitkeep", b"")]), &tmp);
+        assert_eq!(repo.get_manifest("web").unwrap(), Manifest::default());
+        assert!(repo.get_metadata("web").unwrap().files.is_empty());
+
+        // A failed request isn't a missing manifest
+        let mut backend = Memory::with_files(&[("web/SHA256SUMS", b"")]);
+        backend.unauthorized = true;
+        let repo = memory_repo(backend, &tmp);
+        assert!(repo.get_manifest("web").is_err());
+        assert!(repo.get_metadata("web").is_err());
+        assert!(repo.get_feature_manifest("web").is_err());
+    }
+
+    #[test]
+    fn test_assigned_order() {
+        let tmp = TempDir::new("rustea-assigned").unwrap();
+        let backend = Memory::with_files(&[
+            ("nginx/feature.toml", b"depends-on = [\"base\"]\n"),
+            ("base/.gitkeep", b""),
+            ("mail/.gitkeep", b""),
+        ]);
+        let mut repo = memory_repo(backend, &tmp);
+        assert!(repo.assigned_order().is_err());
+        repo.config.assigned = vec!["nginx".into(), "mail".into()];
+        assert_eq!(repo.assigned_order().unwrap(), ["base", "nginx", "mail"]);
+    }
+
+    #[test]
+    fn test_rename_keeps_metadata() {
+        let mut manifest = Manifest::default();
+        manifest.insert("etc/nginx/nginx.conf", b"worker_processes 2;\n");
+        manifest.insert("etc/nginx/sites/default", b"../sites-available/default");
+        manifest.insert("etc/mime.types", b"text/html html\n");
+        let manifest = manifest.to_string();
+        let meta = "checks = [{ command = \"nginx -t -c {}\", files = [\"/etc/nginx/*.conf\"] }]\n\
+                    [files.\"etc/nginx/sites/default\"]\nsymlink = true\n\
+                    [files.\"etc/nginx/nginx.conf\"]\nowner = \"nginx:nginx\"\n";
+        let backend = Memory::with_files(&[
+            ("web/.gitkeep", b""),
+            ("web/etc/mime.types", b"text/html html\n"),
+            ("web/etc/nginx/nginx.conf", b"worker_processes 2;\n"),
+            ("web/etc/nginx/sites/default", b"../sites-available/default"),
+            ("web/SHA256SUMS", manifest.as_bytes()),
+            ("web/.rustea-meta.toml", meta.as_bytes()),
+        ]);
+        let tmp = TempDir::new("rustea-rename").unwrap();
+        let repo = memory_repo(backend, &tmp);
+        repo.rename("web", "proxy", Some("etc/nginx".into()), None)
+            .unwrap();
+
+        assert!(repo.api.download("web/etc/nginx/nginx.conf").is_err());
+        assert_eq!(
+            repo.api.download("web/etc/proxy/sites/default").unwrap(),
+            b"../sites-available/default"
+        );
+        let meta =
+            Metadata::parse(&repo.api.download_text("web/.rustea-meta.toml").unwrap()).unwrap();
+        assert!(meta.is_symlink("web/etc/proxy/sites/default", "web"));
+        assert!(!meta.is_symlink("web/etc/nginx/sites/default", "web"));
+        assert_eq!(
+            meta.get("web/etc/proxy/nginx.conf", "web")
+                .unwrap()
+                .owner
+                .as_deref(),
+            Some("nginx:nginx")
+        );
+        assert_eq!(meta.files.len(), 2);
+        assert_eq!(meta.checks[0].files, ["/etc/proxy/*.conf"]);
+
+        // A listed file fails the verification with other content
+        let manifest = Manifest::parse(&repo.api.download_text("web/SHA256SUMS").unwrap()).unwrap();
+        assert!(manifest
+            .verify("etc/proxy/nginx.conf", b"worker_processes 1;\n")
+            .is_err());
+        assert!(manifest.verify("etc/mime.types", b"text/html\n").is_err());
+        assert!(!manifest.to_string().contains("etc/nginx/"));
+    }
+
+    #[test]
+    fn test_apply_overlay() {
+        let entry = |path: &str| ContentEntry {
+            path: path.into(),
+            ..ContentEntry::default()
+        };
+        let files = vec![
+            entry("nginx/etc/nginx.conf"),
+            entry("nginx/etc/mime.types"),
+            entry("nginx/hosts/web1/etc/nginx.conf"),
+            entry("nginx/hosts/web1/etc/extra.conf"),
+            entry("nginx/hosts/web2/etc/mime.types"),
+        ];
+        let paths: Vec<String> = apply_overlay(files, "nginx", "web1")
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "nginx/etc/mime.types",
+                "nginx/hosts/web1/etc/nginx.conf",
+                "nginx/hosts/web1/etc/extra.conf"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_in_sub_path() {
+        assert!(in_sub_path("nginx/etc/nginx.conf", "nginx", "etc", false));
+        assert!(in_sub_path(
+            "nginx/etc/nginx.conf",
+            "nginx",
+            "/etc/nginx.conf",
+            false
+        ));
+        assert!(in_sub_path("nginx/etc/nginx.conf", "nginx", "", false));
+        assert!(!in_sub_path(
+            "nginx/etc/nginx.conf",
+            "nginx",
+            "nginx.conf",
+            false
+        ));
+        assert!(!in_sub_path(
+            "nginx/etc/nginx.conf.bak",
+            "nginx",
+            "etc/nginx.conf",
+            false
+        ));
+        assert!(!in_sub_path("nginx/etcetera/a.conf", "nginx", "etc", false));
+        assert!(in_sub_path(
+            "nginx/hosts/web1/etc/nginx.conf",
+            "nginx",
+            "etc",
+            false
+        ));
+        assert!(in_sub_path(
+            "nginx/scripts/reload.sh",
+            "nginx",
+            "reload.sh",
+            true
+        ));
+        assert!(!in_sub_path(
+            "nginx/etc/reload.sh",
+            "nginx",
+            "reload.sh",
+            true
+        ));
+    }
+
+    #[test]
+    fn test_identity_per_feature_set() {
+        let mut conf = RusteaConfiguration::default();
+        conf.repo.author = "Default".into();
+        conf.repo.email = "default@example.com".into();
+        conf.identities.insert(
+            "network".into(),
+            Identity {
+                author: "Network Team".into(),
+                email: "network@example.com".into(),
+            },
+        );
+        assert_eq!(
+            conf.identity("network"),
+            ("Network Team", "network@example.com")
+        );
+        assert_eq!(conf.identity("mail"), ("Default", "default@example.com"));
+        assert!(conf.commit_identity("mail").is_ok());
+
+        conf.repo.email.clear();
+        assert!(conf.commit_identity("mail").is_err());
+        assert!(conf.commit_identity("network").is_ok());
+        conf.email_override = Some("ops@example.com".into());
+        assert_eq!(
+            conf.identity("network"),
+            ("Network Team", "ops@example.com")
+        );
+        assert_eq!(
+            conf.commit_identity("mail").unwrap(),
+            ("Default", "ops@example.com")
+        );
+    }
+
+    #[test]
+    fn test_script_mode() {
+        let mut conf = RusteaConfiguration::default();
+        assert_eq!(conf.script_mode("monitoring").unwrap(), 0o751);
+        conf.script_mode = Some("0700".into());
+        conf.script_modes.insert("profile".into(), "0644".into());
+        assert_eq!(conf.script_mode("monitoring").unwrap(), 0o700);
+        assert_eq!(conf.script_mode("profile").unwrap(), 0o644);
+        conf.script_mode = Some("rwx".into());
+        assert!(conf.script_mode("monitoring").is_err());
+    }
+
+    #[test]
+    fn test_ownership() {
+        let mut conf = RusteaConfiguration::default();
+        conf.ownership
+            .insert("pg_hba.conf".into(), "postgres:postgres".into());
+        conf.ownership.insert(
+            "/etc/postgresql/**/local.conf".into(),
+            "root:postgres".into(),
+        );
+        conf.ownership
+            .insert("/etc/postgresql".into(), "postgres".into());
+        let ownership = conf.ownership().unwrap();
+        let owner = |path: &str| owner_of(&ownership, Path::new(path));
+        assert_eq!(
+            owner("/etc/postgresql/14/main/pg_hba.conf"),
+            Some("postgres")
+        );
+        assert_eq!(
+            owner("/etc/postgresql/14/local.conf"),
+            Some("root:postgres")
+        );
+        assert_eq!(owner("/srv/pg_hba.conf"), Some("postgres:postgres"));
+        assert_eq!(owner("/etc/hosts"), None);
+    }
+
+    #[test]
+    fn test_configured_token() {
+        let mut repo = RepositoryConfig {
+            api_token: "stored".into(),
+            ..RepositoryConfig::default()
+        };
+        assert_eq!(repo.configured_token().unwrap(), "stored");
+        repo.token_command = Some("printf 'from-command\\n'".into());
+        assert_eq!(repo.configured_token().unwrap(), "from-command");
+        repo.token_command = Some("exit 1".into());
+        assert!(repo.configured_token().is_err());
+    }
+
+    #[test]
+    fn test_pull_binary_file() {
+        let tmp = TempDir::new("rustea-binary").unwrap();
+        let binary: &[u8] = &[0xff, 0xfe, 0x00, 0x80, 0xc3, 0x28];
+        let backend = Memory::with_files(&[("web/.gitkeep", b""), ("web/etc/cert.der", binary)]);
+        let mut repo = memory_repo(backend, &tmp);
+        repo.local_repo.prefix = Some(tmp.path().join("root"));
+
+        // The state isn't written, a pull with a prefix isn't recorded
+        let (meta, manifest, files) = repo.select_files("web", None, false, false).unwrap();
+        let (pulled, failed) = repo
+            .pull_files(
+                &files,
+                "web",
+                &meta,
+                &manifest,
+                &mut State::default(),
+                false,
+            )
+            .unwrap();
+        let path = tmp.path().join("root/etc/cert.der");
+        assert!(failed.is_empty());
+        assert!(pulled.contains(&path));
+        assert_eq!(fs::read(&path).unwrap(), binary);
+    }
+
+    #[test]
+    fn test_use_profile() {
+        let mut conf = RusteaConfiguration {
+            script_folder: PathBuf::from("/usr/local/bin"),
+            ..RusteaConfiguration::default()
+        };
+        conf.repo.url = "https://personal.example.com".into();
+        let mut team = Profile {
+            script_folder: Some(PathBuf::from("/opt/team/bin")),
+            ..Profile::default()
+        };
+        team.repo.url = "https://team.example.com".into();
+        conf.profiles.insert("team".into(), team);
+
+        assert!(conf.list_profiles().contains("team"));
+        assert!(conf.use_profile("other").is_err());
+        conf.use_profile("team").unwrap();
+        assert_eq!(conf.repo.url, "https://team.example.com");
+        assert_eq!(conf.script_folder, PathBuf::from("/opt/team/bin"));
+        assert!(conf.remove_profile("team").is_err());
+    }
+
+    #[test]
+    fn test_from_base() {
+        let base = r#"
+            script_folder = "/usr/local/bin"
+            exclude = []
+
+            [repo]
+            url = "https://gitea.example.com"
+            repository = "config"
+            owner = "ops"
+            email = "{{ short_hostname }}@example.com"
+            author = "{{ hostname }}"
+
+            [profiles.db1.repo]
+            url = "https://gitea.example.com"
+            repository = "{{ env }}-db"
+            owner = "ops"
+            email = "db@example.com"
+            author = "db"
+            token_command = "pass show gitea"
+        "#;
+        let mut vars = BTreeMap::new();
+        vars.insert("env".to_owned(), "prod".to_owned());
+        let conf =
+            RusteaConfiguration::from_base(base, "web1.example.com", &vars, Some("t0k3n")).unwrap();
+        assert_eq!(conf.repo.author, "web1.example.com");
+        assert_eq!(conf.repo.email, "web1@example.com");
+        assert_eq!(conf.repo.api_token, "t0k3n");
+        assert!(conf.profiles["db1"].repo.api_token.is_empty());
+
+        let conf = RusteaConfiguration::from_base(base, "db1.example.com", &vars, None).unwrap();
+        assert_eq!(conf.repo.repository, "prod-db");
+        assert!(conf.profiles.is_empty());
+        assert!(RusteaConfiguration::from_base(base, "db1", &BTreeMap::new(), None).is_err());
+    }
+
+    #[test]
+    fn test_read_legacy_config() {
+        // The configuration written by `init` of older versions
+        let legacy = r#"
+            script_folder = "/usr/local/bin"
+            exclude = '\.git$'
+
+            [repo]
+            url = "https://gitea.example.com"
+            api_token = "0123456789abcdef"
+            repository = "config"
+            owner = "ops"
+            email = ""
+            author = "ops"
+        "#;
+        let conf: RusteaConfiguration = toml::from_str(legacy).unwrap();
+        assert_eq!(conf.exclude, [".git"]);
+        assert_eq!(conf.repo.owner, "ops");
+        let exclude = Pattern::all(&conf.exclude).unwrap();
+        assert!(exclude[0].matches(Path::new("/srv/app/.git")));
+
+        let conf: RusteaConfiguration =
+            toml::from_str(&legacy.replace(r"'\.git$'", "'*.swp'")).unwrap();
+        assert_eq!(conf.exclude, ["*.swp"]);
+    }
+
+    #[test]
+    fn test_config_paths() {
+        let (path, legacy) = config_paths(None, Some("/home/ops".into())).unwrap();
+        assert_eq!(path, PathBuf::from("/home/ops/.config/rustea/config.toml"));
+        assert_eq!(legacy, Some(PathBuf::from("/home/ops/.rustea.toml")));
+        let (path, legacy) = config_paths(Some("/etc/xdg".into()), None).unwrap();
+        assert_eq!(path, PathBuf::from("/etc/xdg/rustea/config.toml"));
+        assert!(legacy.is_none());
+        assert!(config_paths(Some("".into()), None).is_err());
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let tmp = crate::temp::TempDir::new("rustea-config").unwrap();
+        let legacy = tmp
+            .write(".rustea.toml", b"script_folder = '/bin'")
+            .unwrap();
+        let path = tmp.path().join(".config/rustea/config.toml");
+        migrate_config(&legacy, &path).unwrap();
+        assert!(!legacy.exists());
+        assert_eq!(std::fs::read(&path).unwrap(), b"script_folder = '/bin'");
     }
 }
@@ -0,0 +1,404 @@
+//! This file implements the filters, sorting and long format of `rustea list`.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{cmp::Ordering, collections::BTreeMap, io::Write, path::Path, str::FromStr};
+
+use tabwriter::TabWriter;
+
+use crate::{
+    color::{self, Color},
+    error::{Error, Result},
+    gitea::gitea_api::{Commit, ContentEntry, ContentType},
+    glob::Pattern,
+    state,
+};
+
+/// The kind of the files within a feature set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Script,
+    Config,
+}
+
+impl FromStr for FileKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "script" => Ok(FileKind::Script),
+            "config" => Ok(FileKind::Config),
+            _ => Err(format!("Unknown file type {}, use script or config", s)),
+        }
+    }
+}
+
+/// The order of the listed entries.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    #[default]
+    Path,
+    Type,
+    /// The largest files first
+    Size,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "path" => Ok(SortKey::Path),
+            "type" => Ok(SortKey::Type),
+            "size" => Ok(SortKey::Size),
+            _ => Err(format!(
+                "Unknown sort key {}, use name, path, type or size",
+                s
+            )),
+        }
+    }
+}
+
+impl SortKey {
+    fn compare(self, a: &ContentEntry, b: &ContentEntry) -> Ordering {
+        let by_path = a.path.cmp(&b.path);
+        match self {
+            SortKey::Name => a.name.cmp(&b.name).then(by_path),
+            SortKey::Path => by_path,
+            SortKey::Type => type_order(&a.content_type)
+                .cmp(&type_order(&b.content_type))
+                .then(by_path),
+            SortKey::Size => b.size.cmp(&a.size).then(by_path),
+        }
+    }
+}
+
+/// Folders are listed before the files.
+fn type_order(content_type: &ContentType) -> u8 {
+    match content_type {
+        ContentType::Dir => 0,
+        ContentType::File => 1,
+        ContentType::Symlink => 2,
+        ContentType::Submodule => 3,
+    }
+}
+
+/// The options of `rustea list`.
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    /// A glob pattern which the path of every listed entry matches, e.g. `*.conf`
+    pub filter: Option<String>,
+    /// Only list the script or configuration files of a feature set
+    pub kind: Option<FileKind>,
+    /// Show the type, size and sha of the entries, see `render_long`
+    pub long: bool,
+    /// Fetch the last commit of every entry for the long format, one request per entry
+    pub commits: bool,
+    pub sort: SortKey,
+    pub reverse: bool,
+    /// Show the entries as indented tree, see `render_tree`
+    pub tree: bool,
+}
+
+impl ListOptions {
+    /// This function filters and sorts the `entries`, `kind_of` tells the kind of a file.
+    /// Entries without a kind, like folders, are dropped by the `kind` filter.
+    pub fn select<F>(&self, entries: Vec<ContentEntry>, kind_of: F) -> Result<Vec<ContentEntry>>
+    where
+        F: Fn(&ContentEntry) -> Option<FileKind>,
+    {
+        let filter = self.filter.as_deref().map(Pattern::new).transpose()?;
+        let mut entries: Vec<ContentEntry> = entries
+            .into_iter()
+            .filter(|e| {
+                filter
+                    .as_ref()
+                    .is_none_or(|f| f.matches(Path::new(&e.path)))
+            })
+            .filter(|e| self.kind.is_none() || self.kind == kind_of(e))
+            .collect();
+        entries.sort_by(|a, b| self.sort.compare(a, b));
+        if self.reverse {
+            entries.reverse();
+        }
+        // The scripts are grouped before the configuration files in the tree
+        if self.tree {
+            entries.sort_by_key(|e| kind_of(e) != Some(FileKind::Script));
+        }
+        Ok(entries)
+    }
+
+    /// This function returns the aligned table of the `entries` in the long format.
+    /// The column of the last commits is only shown if `commits` are provided by path.
+    pub fn render_long(
+        entries: &[ContentEntry],
+        commits: Option<&BTreeMap<String, Commit>>,
+    ) -> Result<String> {
+        let mut tw = TabWriter::new(vec![]).padding(4);
+        match commits {
+            Some(_) => writeln!(&mut tw, "Name\tType\tSize\tSha\tLast commit\tPath")?,
+            None => writeln!(&mut tw, "Name\tType\tSize\tSha\tPath")?,
+        }
+        for e in entries {
+            write!(
+                &mut tw,
+                "{}\t{}\t{}\t{}\t",
+                e.name,
+                e.content_type,
+                e.size.map_or_else(|| "-".to_owned(), |s| s.to_string()),
+                e.sha.as_deref().map_or("-", short_sha),
+            )?;
+            if let Some(commits) = commits {
+                match commits.get(&e.path) {
+                    Some(c) => write!(&mut tw, "{} {}\t", short_sha(&c.sha), commit_date(c))?,
+                    None => write!(&mut tw, "-\t")?,
+                }
+            }
+            writeln!(&mut tw, "{}", e.path)?;
+        }
+        let table = tw
+            .into_inner()
+            .map_err(|e| Error::Rustea(format!("Failed to align listing: {}", e)))?;
+        let table = String::from_utf8_lossy(&table);
+        // Color the aligned lines of folders, the first one is the header
+        let lines: Vec<String> = table
+            .lines()
+            .enumerate()
+            .map(
+                |(i, line)| match i.checked_sub(1).and_then(|i| entries.get(i)) {
+                    Some(e) if e.content_type == ContentType::Dir => {
+                        color::paint(Color::Cyan, line)
+                    }
+                    _ => line.to_owned(),
+                },
+            )
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+/// A folder of the tree, its children keep the order of the listed entries.
+#[derive(Debug, Default)]
+struct Node {
+    dir: bool,
+    children: Vec<(String, Node)>,
+}
+
+impl Node {
+    fn insert(&mut self, parts: &[&str], dir: bool) {
+        if let Some((first, rest)) = parts.split_first() {
+            let i = match self.children.iter().position(|(name, _)| name == first) {
+                Some(i) => i,
+                None => {
+                    self.children.push((first.to_string(), Node::default()));
+                    self.children.len() - 1
+                }
+            };
+            let child = &mut self.children[i].1;
+            child.dir |= dir || !rest.is_empty();
+            child.insert(rest, dir);
+        }
+    }
+
+    fn write(&self, prefix: &str, out: &mut Vec<String>) {
+        for (i, (name, node)) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            let (branch, indent) = match last {
+                true => ("└── ", "    "),
+                false => ("├── ", "│   "),
+            };
+            let name = match node.dir {
+                true => color::paint(Color::Cyan, format!("{}/", name)),
+                false => name.clone(),
+            };
+            out.push(format!("{}{}{}", prefix, branch, name));
+            node.write(&format!("{}{}", prefix, indent), out);
+        }
+    }
+}
+
+/// This function returns the `entries` below `root` as indented tree like `tree(1)`.
+pub fn render_tree(root: &str, entries: &[ContentEntry]) -> String {
+    let mut tree = Node::default();
+    let parent = format!("{}/", root);
+    for e in entries {
+        let path = e.path.strip_prefix(&parent).unwrap_or(&e.path);
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        tree.insert(&parts, e.content_type == ContentType::Dir);
+    }
+    let mut lines = vec![root.to_owned()];
+    tree.write("", &mut lines);
+    lines.join("\n")
+}
+
+/// Returns the date of the `commit` in UTC, or as sent by the server if it can't be parsed.
+fn commit_date(commit: &Commit) -> String {
+    let date = &commit.commit.committer.date;
+    state::parse_timestamp(date).map_or_else(|_| date.clone(), state::format_timestamp)
+}
+
+/// Returns the abbreviated `sha` of a git object.
+fn short_sha(sha: &str) -> &str {
+    sha.get(..10).unwrap_or(sha)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{render_tree, FileKind, ListOptions, SortKey};
+    use crate::gitea::gitea_api::{Commit, ContentEntry, ContentType};
+
+    fn entry(path: &str, content_type: ContentType) -> ContentEntry {
+        ContentEntry {
+            name: path.rsplit('/').next().unwrap_or(path).to_owned(),
+            path: path.to_owned(),
+            content_type,
+            sha: Some("0123456789abcdef".to_owned()),
+            size: Some(path.len() as u64),
+            ..Default::default()
+        }
+    }
+
+    fn paths(entries: &[ContentEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_select_entries() {
+        let entries = || {
+            vec![
+                entry("web/scripts/run", ContentType::File),
+                entry("web/etc/nginx/nginx.conf", ContentType::File),
+                entry("web/etc/app.conf", ContentType::File),
+            ]
+        };
+        let kind_of = |e: &ContentEntry| match e.path.starts_with("web/scripts/") {
+            true => Some(FileKind::Script),
+            false => Some(FileKind::Config),
+        };
+        let options = ListOptions {
+            filter: Some("*.conf".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&options.select(entries(), kind_of).unwrap()),
+            vec!["web/etc/app.conf", "web/etc/nginx/nginx.conf"]
+        );
+        let options = ListOptions {
+            kind: Some(FileKind::Script),
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&options.select(entries(), kind_of).unwrap()),
+            vec!["web/scripts/run"]
+        );
+        let options = ListOptions {
+            kind: Some(FileKind::Config),
+            sort: SortKey::Name,
+            reverse: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&options.select(entries(), kind_of).unwrap()),
+            vec!["web/etc/nginx/nginx.conf", "web/etc/app.conf"]
+        );
+        let options = ListOptions {
+            sort: SortKey::Size,
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&options.select(entries(), kind_of).unwrap()),
+            vec![
+                "web/etc/nginx/nginx.conf",
+                "web/etc/app.conf",
+                "web/scripts/run"
+            ]
+        );
+        let options = ListOptions {
+            tree: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            paths(&options.select(entries(), kind_of).unwrap()),
+            vec![
+                "web/scripts/run",
+                "web/etc/app.conf",
+                "web/etc/nginx/nginx.conf"
+            ]
+        );
+        assert_eq!("type".parse::<SortKey>(), Ok(SortKey::Type));
+        assert!("size".parse::<FileKind>().is_err());
+    }
+
+    #[test]
+    fn test_render_tree() {
+        let entries = vec![
+            entry("web/scripts/run", ContentType::File),
+            entry("web/etc/app.conf", ContentType::File),
+            entry("web/etc/nginx/nginx.conf", ContentType::File),
+            entry("web/etc/nginx/mime.types", ContentType::File),
+        ];
+        assert_eq!(
+            render_tree("web", &entries),
+            "web\n\
+             ├── scripts/\n\
+             │   └── run\n\
+             └── etc/\n\
+             \x20   ├── app.conf\n\
+             \x20   └── nginx/\n\
+             \x20       ├── nginx.conf\n\
+             \x20       └── mime.types"
+        );
+        let sets = vec![
+            entry("nginx", ContentType::Dir),
+            entry("base", ContentType::Dir),
+        ];
+        assert_eq!(render_tree("ops", &sets), "ops\n├── nginx/\n└── base/");
+    }
+
+    #[test]
+    fn test_render_entries() {
+        let entries = vec![
+            entry("web/etc/app.conf", ContentType::File),
+            ContentEntry {
+                sha: None,
+                size: None,
+                ..entry("web/etc", ContentType::Dir)
+            },
+        ];
+        assert_eq!(
+            ListOptions::render_long(&entries, None).unwrap(),
+            "Name        Type    Size    Sha           Path\n\
+             app.conf    File    16      0123456789    web/etc/app.conf\n\
+             etc         Dir     -       -             web/etc"
+        );
+
+        let mut commit = Commit {
+            sha: "7d2a1f09c4e3b5a8".to_owned(),
+            ..Default::default()
+        };
+        commit.commit.committer.date = "2021-10-01T14:30:00+02:00".to_owned();
+        let mut commits = BTreeMap::new();
+        commits.insert("web/etc/app.conf".to_owned(), commit);
+        let table = ListOptions::render_long(&entries, Some(&commits)).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].contains("Sha           Last commit"), "{}", table);
+        assert!(lines[1].contains("7d2a1f09c4 2021-10-01T12:30:00Z    web/etc/app.conf"));
+        assert!(lines[2].ends_with(&format!("-{}web/etc", " ".repeat(34))));
+    }
+}
@@ -0,0 +1,109 @@
+//! This file implements the logger which prints the messages of rustea depending on the verbosity.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{
+    color::{self, Color},
+    error::{Error, Result},
+};
+
+/// The `Logger` prints informational messages to stdout and everything else to stderr.
+/// Messages of other crates, e.g. the http client, are only printed at the trace level.
+#[derive(Debug)]
+pub struct Logger {
+    level: LevelFilter,
+}
+
+/// This function returns the level for `-q` or the number of `-v` switches.
+pub fn level(verbose: u8, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+/// This function formats a `message` of the `level` from the module `target`.
+pub fn format(level: Level, target: &str, message: &str) -> String {
+    match level {
+        Level::Info => message.to_owned(),
+        Level::Warn | Level::Error => format!("{}: {}", level.as_str().to_lowercase(), message),
+        Level::Debug | Level::Trace => {
+            format!("[{} {}] {}", level.as_str().to_lowercase(), target, message)
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let own = metadata.target().starts_with("rustea");
+        metadata.level() <= self.level && (own || self.level == LevelFilter::Trace)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format(record.level(), record.target(), &record.args().to_string());
+        match record.level() {
+            Level::Info => println!("{}", line),
+            Level::Warn => eprintln!("{}", color::paint(Color::Yellow, line)),
+            Level::Error => eprintln!("{}", color::paint(Color::Red, line)),
+            _ => eprintln!("{}", line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// This function installs the logger with the verbosity of the `-v` and `-q` switches.
+pub fn init(verbose: u8, quiet: bool) -> Result<()> {
+    let level = level(verbose, quiet);
+    log::set_boxed_logger(Box::new(Logger { level }))
+        .map_err(|e| Error::Rustea(format!("Failed to initialize the logger: {}", e)))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter};
+
+    use super::{format, level};
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0, false), LevelFilter::Info);
+        assert_eq!(level(1, false), LevelFilter::Debug);
+        assert_eq!(level(3, false), LevelFilter::Trace);
+        assert_eq!(level(2, true), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format(Level::Info, "rustea", "Pulled file a"),
+            "Pulled file a"
+        );
+        assert_eq!(format(Level::Warn, "rustea", "waiting"), "warn: waiting");
+        assert_eq!(
+            format(Level::Debug, "rustea::gitea", "GET /api/v1/version -> 200"),
+            "[debug rustea::gitea] GET /api/v1/version -> 200"
+        );
+    }
+}
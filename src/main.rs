@@ -15,17 +15,37 @@ extern crate argh;
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
 extern crate base64;
+extern crate regex;
 extern crate rpassword;
 extern crate serde;
 extern crate serde_json;
 extern crate tabwriter;
 extern crate toml;
 extern crate ureq;
-extern crate regex;
 
-use argh::FromArgs;
-use rustea::{updater::Updater, RemoteRepository, RusteaConfiguration};
-use std::process::exit;
+use argh::{FromArgs, SubCommands};
+use log::info;
+use rustea::{
+    archive::ArchiveFormat,
+    audit::{self, Operation},
+    color, daemon, doctor,
+    error::{Error, Result, EXIT_CONFIG, EXIT_DRIFT, EXIT_OUTDATED, EXIT_UNHEALTHY},
+    get_default_path,
+    gitea::RepositoryOptions,
+    listing::{FileKind, ListOptions, SortKey},
+    logger, oauth,
+    report::Format,
+    schedule::{self, Backend, Job},
+    settings, state, template, watch, InitOptions, Profile, RemoteRepository, RusteaConfiguration,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    os::unix::prelude::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{exit, Command},
+    time::Duration,
+};
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// A simple cli configuration management which uses gitea as backend.
@@ -38,6 +58,47 @@ struct Rustea {
     #[argh(option, short = 'm')]
     message: Option<String>,
 
+    /// the branch of the remote repository, overrides the configured branch
+    #[argh(option, short = 'b')]
+    branch: Option<String>,
+
+    /// use the repository of a profile from the configuration
+    #[argh(option, short = 'p')]
+    profile: Option<String>,
+
+    /// the author of commits, overrides the configured author
+    #[argh(option)]
+    author: Option<String>,
+
+    /// the email of commits, overrides the configured email
+    #[argh(option)]
+    email: Option<String>,
+
+    /// fail instead of waiting if the rate limit of the Gitea instance is reached
+    #[argh(switch)]
+    no_wait: bool,
+
+    /// pull and push the configuration files relative to the home directory instead of /
+    #[argh(switch)]
+    home: bool,
+
+    /// delete, rename and overwrite files without asking for confirmation
+    #[argh(switch, short = 'y')]
+    yes: bool,
+
+    /// print debug messages like the api requests, repeat for trace messages
+    #[argh(switch, short = 'v')]
+    verbose: u8,
+
+    /// only print warnings and errors
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+
+    /// disable colored output, also disabled by the NO_COLOR variable or if
+    /// stdout is not a terminal
+    #[argh(switch)]
+    no_color: bool,
+
     /// the action which rustea executes
     #[argh(subcommand)]
     cmd: RusteaCmd,
@@ -54,7 +115,287 @@ enum RusteaCmd {
     Pull(RusteaPull),
     Push(RusteaPush),
     Rename(RusteaRename),
+    Restore(RusteaRestore),
+    Verify(RusteaVerify),
+    History(RusteaHistory),
+    Diff(RusteaDiff),
+    WatchLocal(RusteaWatchLocal),
+    Schedule(RusteaSchedule),
+    Report(RusteaReport),
+    Status(RusteaStatus),
+    Profile(RusteaProfile),
+    Login(RusteaLogin),
     Update(RusteaUpdate),
+    Apply(RusteaApply),
+    Watch(RusteaWatch),
+    InstallService(RusteaInstallService),
+    Edit(RusteaEdit),
+    Grep(RusteaGrep),
+    Copy(RusteaCopy),
+    Log(RusteaLog),
+    Export(RusteaExport),
+    Import(RusteaImport),
+    Mirror(RusteaMirror),
+    Config(RusteaConfig),
+    Doctor(RusteaDoctor),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "doctor")]
+/// Check the configuration, the connection to Gitea, the token and the writable folders.
+struct RusteaDoctor {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "config")]
+/// Show, change or validate the configuration file.
+struct RusteaConfig {
+    #[argh(subcommand)]
+    cmd: ConfigCmd,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ConfigCmd {
+    Path(ConfigPath),
+    Get(ConfigGet),
+    Set(ConfigSet),
+    Validate(ConfigValidate),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "path")]
+/// Print the path of the used configuration file.
+struct ConfigPath {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "get")]
+/// Print a setting of the configuration file.
+struct ConfigGet {
+    /// the dotted name of the setting, e.g. repo.email
+    #[argh(positional)]
+    key: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "set")]
+/// Change a setting of the configuration file.
+struct ConfigSet {
+    /// the dotted name of the setting, e.g. repo.email
+    #[argh(positional)]
+    key: String,
+
+    /// the new value, parsed as TOML value like true or ['.git'] or used as string
+    #[argh(positional)]
+    value: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "validate")]
+/// Check the configuration file for errors.
+struct ConfigValidate {}
+
+/// This function runs the `config` subcommands on the configuration file `path`
+/// or the default one.
+fn run_config(config: &RusteaConfig, path: Option<&str>) -> Result<String> {
+    let path = match path {
+        Some(p) => p.to_owned(),
+        None => get_default_path()?,
+    };
+    let file = Path::new(&path);
+    match &config.cmd {
+        ConfigCmd::Path(_) => Ok(path),
+        ConfigCmd::Get(get) => settings::get(&settings::read(file)?, &get.key),
+        ConfigCmd::Set(set) => {
+            let mut settings = settings::read(file)?;
+            settings::set(&mut settings, &set.key, &set.value)?;
+            settings::write(file, &settings)?;
+            Ok(format!("Updated {} in {}", set.key, path))
+        }
+        ConfigCmd::Validate(_) => {
+            settings::validate(&settings::read(file)?)?;
+            Ok(format!("{} is valid", path))
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "mirror")]
+/// Mirror a feature set into the repository of a profile.
+struct RusteaMirror {
+    /// the profile whose repository receives the feature set
+    #[argh(option)]
+    to: String,
+
+    /// reuse the latest commit message of the feature set if no message is given
+    #[argh(switch)]
+    keep_message: bool,
+
+    /// delete files which only exist in the mirrored feature set
+    #[argh(switch)]
+    prune: bool,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "export")]
+/// Export a feature set into an archive for another repository.
+struct RusteaExport {
+    /// the format of the archive, either tar.gz or tar, defaults to tar.gz
+    #[argh(option, short = 'f', default = "ArchiveFormat::TarGz")]
+    format: ArchiveFormat,
+
+    /// the archive which is written, defaults to <feature_set>.<format>
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "import")]
+/// Import a feature set from an archive created by rustea export.
+struct RusteaImport {
+    /// the archive created by rustea export
+    #[argh(positional)]
+    archive: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "log")]
+/// Show the commits which changed a feature set, file or folder.
+struct RusteaLog {
+    /// the maximum number of commits shown, defaults to 10
+    #[argh(option, short = 'n', default = "10")]
+    limit: usize,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+
+    /// an optional path to a file or folder within the feature set
+    #[argh(positional)]
+    path: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "history")]
+/// Show the write operations of rustea recorded in the local audit log.
+struct RusteaHistory {
+    /// the maximum number of the latest entries shown, defaults to 20
+    #[argh(option, short = 'n', default = "20")]
+    limit: usize,
+
+    /// only show one operation: push, pull, delete, rename, restore or copy
+    #[argh(option, short = 'o')]
+    operation: Option<Operation>,
+
+    /// only show entries since a RFC 3339 date like 2021-07-21T00:00:00Z
+    #[argh(option)]
+    since: Option<String>,
+
+    /// print the entries as JSON lines with all files
+    #[argh(switch)]
+    json: bool,
+
+    /// only show the entries of a feature set
+    #[argh(positional)]
+    feature_set: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "copy")]
+/// Copy a feature set, folder or file within the remote repository.
+struct RusteaCopy {
+    /// delete the source after copying, which moves it
+    #[argh(switch)]
+    delete_source: bool,
+
+    /// the source as feature_set[/path]
+    #[argh(positional)]
+    src: String,
+
+    /// the destination as feature_set[/path], the source path is kept if omitted
+    #[argh(positional)]
+    dst: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "grep")]
+/// Search a regex in the files of the remote feature sets.
+struct RusteaGrep {
+    /// the regex which is searched line by line
+    #[argh(positional)]
+    pattern: String,
+
+    /// search only the files of this feature set
+    #[argh(positional)]
+    feature_set: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "edit")]
+/// Edit a file of a feature set with $EDITOR and push the changes.
+struct RusteaEdit {
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+
+    /// the path of the file within the feature set
+    #[argh(positional)]
+    path: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "install-service")]
+/// Install a systemd service and timer or cron entry running rustea apply.
+struct RusteaInstallService {
+    /// use cron even if systemd is available
+    #[argh(switch)]
+    cron: bool,
+
+    /// the interval between two runs like 30s, 15m, 2h or 1d, defaults to 15m
+    #[argh(option, short = 'i', default = "String::from(\"15m\")")]
+    interval: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "watch")]
+/// Periodically pull the assigned feature sets when they change.
+struct RusteaWatch {
+    /// seconds between two polls of the remote repository, defaults to 300
+    #[argh(option, short = 'i', default = "daemon::DEFAULT_INTERVAL")]
+    interval: u64,
+
+    /// the pid file which prevents a second instance,
+    /// defaults to /run/rustea.pid for root
+    #[argh(option)]
+    pid_file: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "apply")]
+/// Pull all feature sets assigned to this host in dependency order and summarize the changed files.
+struct RusteaApply {
+    /// skip feature sets which didn't change since the last pull
+    #[argh(switch)]
+    if_changed: bool,
+
+    /// resolve conflicting local changes with the configured mergetool
+    #[argh(switch)]
+    merge: bool,
+
+    /// don't reload or restart the services of the changed files
+    #[argh(switch)]
+    no_reload: bool,
+
+    /// remove local files of former pulls which were removed from the feature sets
+    #[argh(switch)]
+    prune: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -64,6 +405,22 @@ struct RusteaUpdate {
     /// fetch the minified version of rustea
     #[argh(switch, short = 'm')]
     minified: bool,
+
+    /// only print the current and the latest version, exits with 8 if outdated
+    #[argh(switch)]
+    check: bool,
+
+    /// install the release with this version, also an older one
+    #[argh(option)]
+    version: Option<String>,
+
+    /// the name of the release asset to install instead of the one for this platform
+    #[argh(option)]
+    asset: Option<String>,
+
+    /// also consider pre-releases like release candidates
+    #[argh(switch)]
+    pre_release: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -78,17 +435,48 @@ struct RusteaInit {
     #[argh(option, short = 'n')]
     token_name: Option<String>,
 
-    /// the base url for the gitea instance without trailing slash
-    #[argh(positional)]
-    url: String,
+    /// store the api token in the keyring instead of the configuration
+    #[argh(switch)]
+    keyring: bool,
 
-    /// the name of the remote repository
-    #[argh(positional)]
-    repository: String,
+    /// authenticate as this user with its password instead of an api token,
+    /// the password is asked if no --api-token is provided
+    #[argh(option)]
+    basic_auth: Option<String>,
 
-    /// the owner of the remote repository
-    #[argh(positional)]
-    owner: String,
+    /// create the remote repository as private repository if it doesn't exist
+    #[argh(switch)]
+    create_repo: bool,
+
+    /// don't initialize the created repository with a README
+    #[argh(switch)]
+    no_readme: bool,
+
+    /// only allow the owner of the token to push to the default branch of the created repository
+    #[argh(switch)]
+    protect_branch: bool,
+
+    /// the author of commits, asked if not provided
+    #[argh(option)]
+    author: Option<String>,
+
+    /// the email of commits, asked if not provided
+    #[argh(option)]
+    email: Option<String>,
+
+    /// create the configuration from a base configuration at this url instead,
+    /// e.g. to provision many hosts from the same settings
+    #[argh(option)]
+    from_url: Option<String>,
+
+    /// a variable of the base configuration as key=value, can be repeated
+    #[argh(option)]
+    var: Vec<String>,
+
+    /// the base url for the gitea instance without trailing slash, the name and the owner
+    /// of the remote repository, the missing ones are asked
+    #[argh(positional, arg_name = "url repository owner")]
+    repository: Vec<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -103,6 +491,34 @@ struct RusteaList {
     /// provide a feature set name for listing its content
     #[argh(positional)]
     feature_set: Option<String>,
+
+    /// only list entries whose path matches the glob pattern, e.g. '*.conf'
+    #[argh(option)]
+    filter: Option<String>,
+
+    /// only list the script or config files of the feature set
+    #[argh(option, long = "type")]
+    kind: Option<FileKind>,
+
+    /// show the type, size and sha of every entry
+    #[argh(switch, short = 'l')]
+    long: bool,
+
+    /// show the last commit of every entry with --long, needs one request per entry
+    #[argh(switch)]
+    commits: bool,
+
+    /// sort the entries by name, path, type or size, defaults to path
+    #[argh(option, default = "SortKey::Path")]
+    sort: SortKey,
+
+    /// reverse the order of the entries
+    #[argh(switch, short = 'r')]
+    reverse: bool,
+
+    /// show the entries as indented tree, the scripts first
+    #[argh(switch)]
+    tree: bool,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -126,6 +542,18 @@ struct RusteaDelete {
     #[argh(switch, short = 's')]
     script: bool,
 
+    /// move the files into the trash of the repository, see restore
+    #[argh(switch, short = 't')]
+    trash: bool,
+
+    /// continue with the other files if a file fails and list the failed files at the end
+    #[argh(switch)]
+    keep_going: bool,
+
+    /// delete on a new branch and open a pull request instead of a direct commit
+    #[argh(switch)]
+    via_pr: bool,
+
     /// the name of the feature set
     #[argh(positional)]
     feature_set: String,
@@ -147,13 +575,50 @@ struct RusteaPull {
     #[argh(switch, short = 'c')]
     config: bool,
 
-    /// the name of the feature set
-    #[argh(positional)]
-    feature_set: String,
+    /// skip the pull if the feature set didn't change since the last pull
+    #[argh(switch)]
+    if_changed: bool,
 
-    /// the path to a subfolder or file of the feature set
+    /// resolve conflicting local changes with the configured mergetool
+    #[argh(switch)]
+    merge: bool,
+
+    /// pull all feature sets assigned to this host in the configuration
+    #[argh(switch)]
+    assigned: bool,
+
+    /// remove local files of former pulls which were removed from the feature set
+    #[argh(switch)]
+    prune: bool,
+
+    /// continue with the other files if a file fails and list the failed files at the end
+    #[argh(switch)]
+    keep_going: bool,
+
+    /// choose the files to pull from a numbered list
+    #[argh(switch, short = 'i')]
+    interactive: bool,
+
+    /// the host overlay to use instead of the one of the detected hostname
+    #[argh(option)]
+    hostname: Option<String>,
+
+    /// pull the feature set at a tag or commit sha instead of the branch
+    #[argh(option, long = "ref")]
+    revision: Option<String>,
+
+    /// a template variable as key=value, overrides the configured vars, can be repeated
+    #[argh(option)]
+    var: Vec<String>,
+
+    /// write the files below this folder instead of /, e.g. into an image or chroot
+    #[argh(option)]
+    prefix: Option<PathBuf>,
+
+    /// the name of the feature set followed by an optional path to a subfolder or
+    /// file of the feature set, omitted with --assigned
     #[argh(positional)]
-    sub_path: Option<String>,
+    target: Vec<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -164,6 +629,43 @@ struct RusteaPush {
     #[argh(switch, short = 's')]
     script: bool,
 
+    /// resolve conflicting remote changes with the configured mergetool
+    #[argh(switch)]
+    merge: bool,
+
+    /// overwrite remote files which changed since they were pulled or pushed
+    #[argh(switch)]
+    force: bool,
+
+    /// continue with the other files if a file fails and list the failed files at the end
+    #[argh(switch)]
+    keep_going: bool,
+
+    /// choose the files to push from a numbered list
+    #[argh(switch, short = 'i')]
+    interactive: bool,
+
+    /// encrypt the files matching the configured secret patterns
+    #[argh(switch)]
+    encrypt: bool,
+
+    /// push the files even if they contain possible secrets like access keys
+    #[argh(switch)]
+    allow_secrets: bool,
+
+    /// push the content of linked files instead of symbolic links
+    #[argh(switch)]
+    dereference: bool,
+
+    /// push to a new branch and open a pull request instead of a direct commit
+    #[argh(switch)]
+    via_pr: bool,
+
+    /// a glob pattern for paths which should not be pushed instead of the
+    /// configured ones, can be repeated
+    #[argh(option, short = 'e')]
+    exclude: Vec<String>,
+
     /// the name of the feature set
     #[argh(positional)]
     feature_set: String,
@@ -181,6 +683,10 @@ struct RusteaRename {
     #[argh(option, short = 'p')]
     path: Option<String>,
 
+    /// rename on a new branch and open a pull request instead of a direct commit
+    #[argh(switch)]
+    via_pr: bool,
+
     /// the name of the feature set
     #[argh(positional)]
     feature_set: String,
@@ -190,17 +696,529 @@ struct RusteaRename {
     new_name: String,
 }
 
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "restore")]
+/// Restore a feature set deleted into the trash of the remote repository.
+struct RusteaRestore {
+    /// the time of the deletion as shown by `rustea list .trash`, the latest if omitted
+    #[argh(option)]
+    from: Option<u64>,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+}
+
+/// Options of the main command which take a value.
+const GLOBAL_OPTIONS: [&str; 10] = [
+    "-c",
+    "--config",
+    "-m",
+    "--message",
+    "-b",
+    "--branch",
+    "-p",
+    "--profile",
+    "--author",
+    "--email",
+];
+
+/// Switches of the main command besides `-v`.
+const GLOBAL_SWITCHES: [&str; 8] = [
+    "--no-wait",
+    "--home",
+    "-y",
+    "--yes",
+    "--verbose",
+    "-q",
+    "--quiet",
+    "--no-color",
+];
+
+/// Returns true for the verbosity switches `-v`, `-vv` and so on.
+fn is_verbosity(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v')
+}
+
+/// Split combined verbosity switches like `-vv` into single ones.
+fn split_verbosity(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .flat_map(|arg| match is_verbosity(&arg) {
+            true => vec!["-v".to_owned(); arg.len() - 1],
+            false => vec![arg],
+        })
+        .collect()
+}
+
+/// Search the `PATH` for an executable named `rustea-<name>`.
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    let exe = format!("rustea-{}", name);
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(&exe))
+            .find(|p| match p.metadata() {
+                Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+                Err(_) => false,
+            })
+    })
+}
+
+/// This function dispatches subcommands which are not built into rustea to an
+/// external executable named `rustea-<name>` found on the `PATH`.
+///
+/// All arguments after the subcommand are passed on. The configuration location
+/// and the global flags are passed as `RUSTEA_CONFIG`, `RUSTEA_MESSAGE`, `RUSTEA_BRANCH`
+/// and `RUSTEA_PROFILE`.
+/// Returns the exit code of the plugin or `None` if no plugin is called.
+fn run_plugin(args: &[String]) -> Option<i32> {
+    let mut config = None;
+    let mut message = None;
+    let mut branch = None;
+    let mut profile = None;
+    let mut author = None;
+    let mut email = None;
+    let mut iter = args.iter().enumerate().skip(1);
+    while let Some((i, arg)) = iter.next() {
+        if GLOBAL_SWITCHES.contains(&arg.as_str()) || is_verbosity(arg) {
+            continue;
+        }
+        if GLOBAL_OPTIONS.contains(&arg.as_str()) {
+            let value = iter.next().map(|(_, v)| v.clone());
+            match arg.as_str() {
+                "-c" | "--config" => config = value,
+                "-b" | "--branch" => branch = value,
+                "-p" | "--profile" => profile = value,
+                "--author" => author = value,
+                "--email" => email = value,
+                _ => message = value,
+            }
+            continue;
+        }
+        if arg.starts_with('-')
+            || arg == "help"
+            || RusteaCmd::COMMANDS.iter().any(|c| c.name == arg)
+        {
+            return None;
+        }
+
+        let plugin = find_plugin(arg)?;
+        let mut cmd = Command::new(&plugin);
+        cmd.args(&args[i + 1..]);
+        match config.or_else(|| get_default_path().ok()) {
+            Some(c) => cmd.env("RUSTEA_CONFIG", c),
+            None => cmd.env_remove("RUSTEA_CONFIG"),
+        };
+        if let Some(m) = message {
+            cmd.env("RUSTEA_MESSAGE", m);
+        }
+        if let Some(b) = branch {
+            cmd.env("RUSTEA_BRANCH", b);
+        }
+        if let Some(p) = profile {
+            cmd.env("RUSTEA_PROFILE", p);
+        }
+        if let Some(a) = author {
+            cmd.env("RUSTEA_AUTHOR", a);
+        }
+        if let Some(e) = email {
+            cmd.env("RUSTEA_EMAIL", e);
+        }
+        return match cmd.status() {
+            Ok(status) => Some(status.code().unwrap_or(1)),
+            Err(e) => {
+                eprintln!("Failed to run plugin {}: {}", plugin.display(), e);
+                Some(1)
+            }
+        };
+    }
+    None
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "diff")]
+/// Compare local files with the files of a feature set.
+struct RusteaDiff {
+    /// an external diff program like vimdiff, overrides the configured difftool
+    #[argh(option, short = 't')]
+    tool: Option<String>,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+
+    /// the path to a subfolder or file of the feature set
+    #[argh(positional)]
+    sub_path: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "verify")]
+/// Verify the local files against a feature set, exits with 5 if any file drifted.
+struct RusteaVerify {
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+
+    /// the path to a subfolder or file of the feature set
+    #[argh(positional)]
+    sub_path: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "watch-local")]
+/// Watch a local directory and push changed files to a feature set.
+struct RusteaWatchLocal {
+    /// push changed files as script files
+    #[argh(switch, short = 's')]
+    script: bool,
+
+    /// a glob pattern for paths which should not be pushed, can be repeated
+    #[argh(option, short = 'e')]
+    exclude: Vec<String>,
+
+    /// milliseconds without changes before they are pushed, defaults to 500
+    #[argh(option, short = 'd')]
+    debounce: Option<u64>,
+
+    /// the name of the feature set
+    #[argh(positional)]
+    feature_set: String,
+
+    /// the local directory to watch
+    #[argh(positional)]
+    dir: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "report")]
+/// Generate a report about the drift of all feature sets on this machine.
+struct RusteaReport {
+    /// the format of the report, either markdown or html, defaults to markdown
+    #[argh(option, short = 'f', default = "Format::Markdown")]
+    format: Format,
+
+    /// the file the report is written to instead of the command line
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "status")]
+/// Summarize the drift of the assigned feature sets on this machine.
+struct RusteaStatus {
+    /// the feature set which is compared instead of the assigned ones
+    #[argh(positional)]
+    feature_set: Option<String>,
+
+    /// compare every feature set of the repository
+    #[argh(switch, short = 'a')]
+    all: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "profile")]
+/// Manage the repository profiles of the configuration.
+struct RusteaProfile {
+    #[argh(subcommand)]
+    cmd: ProfileCmd,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ProfileCmd {
+    List(ProfileList),
+    Add(ProfileAdd),
+    Remove(ProfileRemove),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+/// List the default repository and all profiles.
+struct ProfileList {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "add")]
+/// Add a new profile for another repository.
+struct ProfileAdd {
+    /// provide an api token for the remote repository
+    #[argh(option, short = 't')]
+    api_token: Option<String>,
+
+    /// provide a name for the api token
+    #[argh(option, short = 'n')]
+    token_name: Option<String>,
+
+    /// a script folder used instead of the configured one
+    #[argh(option, short = 's')]
+    script_folder: Option<PathBuf>,
+
+    /// authenticate as this user with its password instead of an api token,
+    /// the password is asked if no --api-token is provided
+    #[argh(option)]
+    basic_auth: Option<String>,
+
+    /// the name of the profile
+    #[argh(positional)]
+    name: String,
+
+    /// the base url for the gitea instance without trailing slash
+    #[argh(positional)]
+    url: String,
+
+    /// the name of the remote repository
+    #[argh(positional)]
+    repository: String,
+
+    /// the owner of the remote repository
+    #[argh(positional)]
+    owner: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "remove")]
+/// Remove a profile.
+struct ProfileRemove {
+    /// the name of the profile
+    #[argh(positional)]
+    name: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "login")]
+/// Sign in with the OAuth2 device flow of the Gitea instance in the browser.
+struct RusteaLogin {
+    /// the client id of the OAuth2 application registered on the instance,
+    /// defaults to the one of the last login
+    #[argh(option, short = 'c')]
+    client_id: Option<String>,
+}
+
+/// This function signs in to the repository of the `profile` or the default one
+/// and stores the configuration at `path`. The tokens are kept in their own file.
+fn run_login(
+    config: &mut RusteaConfiguration,
+    login: &RusteaLogin,
+    profile: Option<&str>,
+    path: &str,
+) -> Result<String> {
+    let http = config.http.clone();
+    let repo = config.repo_mut(profile)?;
+    let client_id = login
+        .client_id
+        .clone()
+        .or_else(|| repo.oauth.as_ref().map(|o| o.client_id.clone()))
+        .ok_or_else(|| {
+            Error::Rustea("The client id of the OAuth2 application is missing".into())
+        })?;
+    let (oauth, tokens) = oauth::login(&repo.url, &client_id, &http)?;
+    let token_path = oauth::token_path(&repo.url, &repo.owner, &repo.repository)?;
+    tokens.save(&token_path)?;
+    repo.oauth = Some(oauth);
+    let url = repo.url.clone();
+    config.write_config_file(Path::new(path))?;
+    Ok(format!(
+        "Logged in to {}, the tokens are stored in {}",
+        url,
+        token_path.display()
+    ))
+}
+
+/// This function creates the configuration from the base configuration at `url` for `rustea init`.
+fn run_init_from_url(url: &str, init: &RusteaInit) -> Result<PathBuf> {
+    if !init.repository.is_empty() {
+        return Err(Error::Rustea(
+            "The repository is provided by the base configuration".into(),
+        ));
+    }
+    let vars = init
+        .var
+        .iter()
+        .map(|v| template::parse_var(v))
+        .collect::<Result<BTreeMap<String, String>>>()?;
+    RusteaConfiguration::create_configuration_from_url(
+        url,
+        &vars,
+        init.api_token.clone(),
+        init.keyring,
+    )
+}
+
+/// Returns the filters and sorting of the `list` subcommand.
+fn list_options(list: &RusteaList) -> ListOptions {
+    ListOptions {
+        filter: list.filter.clone(),
+        kind: list.kind,
+        long: list.long,
+        commits: list.commits,
+        sort: list.sort,
+        reverse: list.reverse,
+        tree: list.tree,
+    }
+}
+
+/// This function queries the audit log configured in `config` for `rustea history`.
+fn run_history(config: &RusteaConfiguration, history: &RusteaHistory) -> Result<String> {
+    let entries = audit::read(&config.audit_path()?)?;
+    let query = audit::Query {
+        feature_set: history.feature_set.clone(),
+        operation: history.operation,
+        since: history
+            .since
+            .as_deref()
+            .map(state::parse_timestamp)
+            .transpose()?,
+        limit: Some(history.limit),
+    };
+    let found = query.filter(&entries);
+    match (history.json, found.is_empty()) {
+        (_, true) => Ok("No operations recorded".into()),
+        (true, false) => found
+            .iter()
+            .map(|e| serde_json::to_string(e).map_err(|e| Error::Rustea(e.to_string())))
+            .collect::<Result<Vec<String>>>()
+            .map(|lines| lines.join("\n")),
+        (false, false) => audit::format(&found),
+    }
+}
+
+/// This function runs the `profile` subcommands and stores the changed
+/// configuration at `path`.
+fn run_profile(
+    config: &mut RusteaConfiguration,
+    profile: &RusteaProfile,
+    path: &str,
+) -> Result<String> {
+    match &profile.cmd {
+        ProfileCmd::List(_) => return Ok(config.list_profiles()),
+        ProfileCmd::Add(add) => {
+            let new = Profile::new(
+                &add.url,
+                add.api_token.as_deref(),
+                add.token_name.as_deref(),
+                &add.repository,
+                &add.owner,
+                add.script_folder.clone(),
+                add.basic_auth.clone(),
+            )?;
+            config.add_profile(&add.name, new)
+        }
+        ProfileCmd::Remove(remove) => config.remove_profile(&remove.name)?,
+    }
+    config.write_config_file(Path::new(path))?;
+    Ok(format!("Updated profiles in {}", path))
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "schedule")]
+/// Schedule unattended pulls of the assigned feature sets.
+struct RusteaSchedule {
+    /// use cron even if systemd is available
+    #[argh(switch)]
+    cron: bool,
+
+    #[argh(subcommand)]
+    cmd: ScheduleCmd,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ScheduleCmd {
+    Install(ScheduleInstall),
+    Status(ScheduleStatus),
+    Remove(ScheduleRemove),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "install")]
+/// Install a systemd timer or cron entry running rustea pull --assigned.
+struct ScheduleInstall {
+    /// the interval between two pulls like 30s, 15m, 2h or 1d, defaults to 15m
+    #[argh(option, short = 'i', default = "String::from(\"15m\")")]
+    interval: String,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "status")]
+/// Show the installed schedule.
+struct ScheduleStatus {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "remove")]
+/// Remove the installed schedule.
+struct ScheduleRemove {}
+
+/// This function runs the `schedule` subcommands which don't need a remote repository.
+fn run_schedule(schedule: &RusteaSchedule, config: Option<&str>) -> Result<String> {
+    let backend = Backend::detect(schedule.cron);
+    match &schedule.cmd {
+        ScheduleCmd::Install(install) => schedule::install(
+            backend,
+            Job::Pull,
+            schedule::parse_interval(&install.interval)?,
+            config,
+        ),
+        ScheduleCmd::Status(_) => schedule::status(backend),
+        ScheduleCmd::Remove(_) => schedule::remove(backend),
+    }
+}
+
 fn main() {
-    let rustea: Rustea = argh::from_env();
+    let args: Vec<String> = env::args().collect();
+    if let Some(code) = run_plugin(&args) {
+        exit(code);
+    }
+    let args = split_verbosity(args);
+    let strs: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let cmd = Path::new(strs[0])
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(strs[0]);
+    let rustea = Rustea::from_args(&[cmd], &strs[1..]).unwrap_or_else(|early_exit| {
+        println!("{}", early_exit.output);
+        exit(match early_exit.status {
+            Ok(()) => 0,
+            Err(()) => 1,
+        })
+    });
+    color::init(rustea.no_color);
+    if let Err(e) = logger::init(rustea.verbose, rustea.quiet) {
+        eprintln!("{}", e);
+        exit(e.exit_code())
+    }
 
     if let RusteaCmd::Init(ref init) = rustea.cmd {
-        match RusteaConfiguration::create_initial_configuration(
-            &init.url,
-            init.api_token.as_deref(),
-            init.token_name.as_deref(),
-            &init.repository,
-            &init.owner,
-        ) {
+        if let Some(url) = &init.from_url {
+            match run_init_from_url(url, init) {
+                Ok(p) => println!(
+                    "Successfully initialized rustea. Configuration path {}",
+                    p.display()
+                ),
+                Err(e) => {
+                    eprintln!("Failed to initialize rustea.\nCause: {}", e);
+                    exit(e.exit_code())
+                }
+            }
+            exit(0);
+        }
+        if init.repository.len() > 3 {
+            eprintln!("Only the url, repository and owner can be provided");
+            exit(1)
+        }
+        match RusteaConfiguration::create_initial_configuration(InitOptions {
+            url: init.repository.first().cloned(),
+            api_token: init.api_token.clone(),
+            token_name: init.token_name.clone(),
+            repository: init.repository.get(1).cloned(),
+            owner: init.repository.get(2).cloned(),
+            keyring: init.keyring,
+            create_repo: init.create_repo.then_some(RepositoryOptions {
+                readme: !init.no_readme,
+                protect_branch: init.protect_branch,
+            }),
+            author: init.author.clone(),
+            email: init.email.clone(),
+            username: init.basic_auth.clone(),
+        }) {
             Ok(p) => {
                 println!(
                     "Successfully initialized rustea. Configuration path {}",
@@ -209,61 +1227,370 @@ fn main() {
             }
             Err(e) => {
                 eprintln!("Failed to initialize rustea.\nCause: {}", e);
-                exit(1)
+                exit(e.exit_code())
             }
         }
     }
 
-    let config = match RusteaConfiguration::read_config_file(rustea.config.as_deref()) {
+    let scheduled = match rustea.cmd {
+        RusteaCmd::Schedule(ref schedule) => Some(run_schedule(schedule, rustea.config.as_deref())),
+        RusteaCmd::InstallService(ref install) => Some(
+            schedule::parse_interval(&install.interval).and_then(|interval| {
+                schedule::install(
+                    Backend::detect(install.cron),
+                    Job::Apply,
+                    interval,
+                    rustea.config.as_deref(),
+                )
+            }),
+        ),
+        _ => None,
+    };
+    if let Some(res) = scheduled {
+        match res {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+        }
+        exit(0);
+    }
+
+    if let RusteaCmd::Config(ref config) = rustea.cmd {
+        match run_config(config, rustea.config.as_deref()) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+        }
+        exit(0);
+    }
+
+    if let RusteaCmd::Doctor(_) = rustea.cmd {
+        let checks = doctor::run(rustea.config.as_deref(), rustea.profile.as_deref());
+        let (out, passed) = doctor::format(&checks);
+        println!("{}", out);
+        exit(if passed { 0 } else { EXIT_UNHEALTHY })
+    }
+
+    let mut config = match RusteaConfiguration::read_config_file(rustea.config.as_deref()) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Configuration file not found. Run rustea init --token rustea-devops <repository name> <owner>\nError: {}", e);
-            exit(1)
+            exit(EXIT_CONFIG)
+        }
+    };
+    let config_path = match rustea.config.clone().map_or_else(get_default_path, Ok) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code())
+        }
+    };
+    match config.store_tokens() {
+        Ok(true) => {
+            if let Err(e) = config.write_config_file(Path::new(&config_path)) {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+            info!("Moved the api tokens into the keyring");
+        }
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(e.exit_code())
+        }
+    }
+    if let RusteaCmd::History(ref history) = rustea.cmd {
+        match run_history(&config, history) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+        }
+        exit(0);
+    }
+    if let RusteaCmd::Profile(ref profile) = rustea.cmd {
+        match run_profile(&mut config, profile, &config_path) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
+        }
+        exit(0);
+    }
+
+    if let RusteaCmd::Login(ref login) = rustea.cmd {
+        match run_login(&mut config, login, rustea.profile.as_deref(), &config_path) {
+            Ok(s) => println!("{}", s),
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(e.exit_code())
+            }
         }
+        exit(0);
+    }
+
+    if let Some(ref profile) = rustea.profile {
+        if let Err(e) = config.use_profile(profile) {
+            eprintln!("{}", e);
+            exit(EXIT_CONFIG)
+        }
+    }
+    if rustea.branch.is_some() {
+        config.repo.branch = rustea.branch.clone();
+    }
+    config.author_override = rustea.author.clone();
+    config.email_override = rustea.email.clone();
+    if let RusteaCmd::Pull(ref pull) = rustea.cmd {
+        for var in &pull.var {
+            match template::parse_var(var) {
+                Ok((key, value)) => {
+                    config.vars.insert(key, value);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(e.exit_code())
+                }
+            }
+        }
+    }
+    let (hostname, interactive, revision) = match rustea.cmd {
+        RusteaCmd::Pull(ref pull) => (
+            pull.hostname.clone(),
+            pull.interactive,
+            pull.revision.clone(),
+        ),
+        RusteaCmd::Push(ref push) => (None, push.interactive, None),
+        _ => (None, false, None),
+    };
+    let prefix = match rustea.cmd {
+        RusteaCmd::Pull(ref pull) => pull.prefix.clone(),
+        _ => None,
+    };
+    let encrypt = matches!(rustea.cmd, RusteaCmd::Push(ref push) if push.encrypt);
+    let allow_secrets = matches!(rustea.cmd, RusteaCmd::Push(ref push) if push.allow_secrets);
+    let dereference = matches!(rustea.cmd, RusteaCmd::Push(ref push) if push.dereference);
+    let force = matches!(rustea.cmd, RusteaCmd::Push(ref push) if push.force);
+    let prune = match rustea.cmd {
+        RusteaCmd::Pull(ref pull) => pull.prune,
+        RusteaCmd::Apply(ref apply) => apply.prune,
+        _ => false,
+    };
+    let keep_going = match rustea.cmd {
+        RusteaCmd::Pull(ref pull) => pull.keep_going,
+        RusteaCmd::Push(ref push) => push.keep_going,
+        RusteaCmd::Delete(ref delete) => delete.keep_going,
+        _ => false,
     };
-    let remote_repository = match RemoteRepository::new(config) {
+    // Nobody can answer without a terminal, e.g. in cron jobs
+    let confirm = !rustea.yes && unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+    let mut remote_repository = match RemoteRepository::new(config).and_then(|r| {
+        r.with_hostname(hostname)
+            .with_interactive(interactive)
+            .with_encrypt(encrypt)
+            .with_allow_secrets(allow_secrets)
+            .with_dereference(dereference)
+            .with_force(force)
+            .with_prune(prune)
+            .with_keep_going(keep_going)
+            .with_home(rustea.home)
+            .with_confirm(confirm)
+            .with_prefix(prefix)
+            .with_rate_limit_wait(!rustea.no_wait)
+            .with_revision(revision)
+    }) {
         Ok(r) => r,
         Err(e) => {
             eprintln!("Could not create client for remote repository: {}", e);
-            exit(1)
+            exit(e.exit_code())
         }
     };
 
     let res = match rustea.cmd {
         RusteaCmd::Init(_) => Ok("Already initialized".to_string()),
         RusteaCmd::Info(_) => Ok(format!("{}", remote_repository)),
-        RusteaCmd::List(list) => remote_repository.list(list.feature_set),
+        RusteaCmd::List(list) => {
+            remote_repository.list(list.feature_set.clone(), &list_options(&list))
+        }
         RusteaCmd::New(new) => remote_repository.new_feature_set(&new.feature_set, rustea.message),
-        RusteaCmd::Delete(delete) => remote_repository.delete(
-            &delete.feature_set,
-            delete.sub_path,
-            delete.script,
-            delete.recursive,
+        RusteaCmd::Delete(delete) => {
+            let message = rustea.message.clone();
+            let change = |r: &RemoteRepository| {
+                r.delete(
+                    &delete.feature_set,
+                    delete.sub_path.clone(),
+                    delete.script,
+                    delete.recursive,
+                    delete.trash,
+                    message.clone(),
+                )
+            };
+            match delete.via_pr {
+                true => remote_repository.via_pull_request(
+                    &delete.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+                false => remote_repository.or_via_pull_request(
+                    &delete.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+            }
+        }
+        RusteaCmd::Pull(pull) => match (pull.assigned, pull.target.as_slice()) {
+            (true, []) => remote_repository.pull_assigned(pull.if_changed, pull.merge),
+            (false, [feature_set]) | (false, [feature_set, _]) => remote_repository.pull(
+                feature_set,
+                pull.target.get(1).cloned(),
+                pull.script,
+                pull.config,
+                pull.if_changed,
+                pull.merge,
+            ),
+            _ => Err(Error::Rustea(
+                "Provide either a feature set and an optional path or --assigned".into(),
+            )),
+        },
+        RusteaCmd::Push(push) => {
+            let message = rustea.message.clone();
+            let change = |r: &RemoteRepository| {
+                r.push(
+                    &push.feature_set,
+                    push.sub_path.clone(),
+                    push.script,
+                    push.merge,
+                    &push.exclude,
+                    message.clone(),
+                )
+            };
+            match push.via_pr {
+                true => remote_repository.via_pull_request(
+                    &push.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+                false => remote_repository.or_via_pull_request(
+                    &push.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+            }
+        }
+        RusteaCmd::Rename(rename) => {
+            let message = rustea.message.clone();
+            let change = |r: &RemoteRepository| {
+                r.rename(
+                    &rename.feature_set,
+                    &rename.new_name,
+                    rename.path.clone(),
+                    message.clone(),
+                )
+            };
+            match rename.via_pr {
+                true => remote_repository.via_pull_request(
+                    &rename.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+                false => remote_repository.or_via_pull_request(
+                    &rename.feature_set,
+                    message.as_deref(),
+                    change,
+                ),
+            }
+        }
+        RusteaCmd::Restore(restore) => {
+            remote_repository.restore(&restore.feature_set, restore.from, rustea.message)
+        }
+        RusteaCmd::Diff(diff) => {
+            remote_repository.diff(&diff.feature_set, diff.sub_path, diff.tool)
+        }
+        RusteaCmd::WatchLocal(watch) => remote_repository.watch_local(
+            &watch.feature_set,
+            &watch.dir,
+            watch.script,
+            &watch.exclude,
+            watch
+                .debounce
+                .map_or(watch::DEFAULT_DEBOUNCE, Duration::from_millis),
             rustea.message,
         ),
-        RusteaCmd::Pull(pull) => {
-            remote_repository.pull(&pull.feature_set, pull.sub_path, pull.script, pull.config)
+        RusteaCmd::Watch(watch) => remote_repository.watch(
+            Duration::from_secs(watch.interval),
+            &watch
+                .pid_file
+                .map_or_else(daemon::default_pid_path, PathBuf::from),
+        ),
+        RusteaCmd::Copy(copy) => {
+            remote_repository.copy(&copy.src, &copy.dst, copy.delete_source, rustea.message)
+        }
+        RusteaCmd::Grep(grep) => remote_repository.grep(&grep.pattern, grep.feature_set),
+        RusteaCmd::Log(log) => remote_repository.log(&log.feature_set, log.path, log.limit),
+        RusteaCmd::Edit(edit) => {
+            remote_repository.edit(&edit.feature_set, &edit.path, rustea.message)
+        }
+        RusteaCmd::Apply(apply) => {
+            remote_repository.apply(apply.if_changed, apply.merge, !apply.no_reload)
+        }
+        RusteaCmd::Report(report) => remote_repository.report(report.format, report.output),
+        RusteaCmd::Status(status) => {
+            remote_repository.status(status.feature_set.as_deref(), status.all)
+        }
+        RusteaCmd::Export(export) => {
+            remote_repository.export(&export.feature_set, export.output, export.format)
         }
-        RusteaCmd::Push(push) => remote_repository.push(
-            &push.feature_set,
-            push.sub_path,
-            push.script,
+        RusteaCmd::Import(import) => remote_repository.import(&import.archive, rustea.message),
+        RusteaCmd::Mirror(mirror) => remote_repository.mirror(
+            &mirror.feature_set,
+            &mirror.to,
+            mirror.keep_message,
+            mirror.prune,
             rustea.message,
         ),
-        RusteaCmd::Rename(rename) => remote_repository.rename(
-            &rename.feature_set,
-            &rename.new_name,
-            rename.path,
-            rustea.message,
+        RusteaCmd::Schedule(_)
+        | RusteaCmd::InstallService(_)
+        | RusteaCmd::Profile(_)
+        | RusteaCmd::Login(_)
+        | RusteaCmd::History(_)
+        | RusteaCmd::Config(_)
+        | RusteaCmd::Doctor(_) => unreachable!(),
+        RusteaCmd::Verify(verify) => {
+            match remote_repository.verify(&verify.feature_set, verify.sub_path) {
+                Ok((out, drift)) => {
+                    println!("{}", out);
+                    exit(if drift { EXIT_DRIFT } else { 0 })
+                }
+                Err(e) => Err(e),
+            }
+        }
+        RusteaCmd::Update(update) if update.check => {
+            match remote_repository.check_update(update.pre_release) {
+                Ok((versions, outdated)) => {
+                    println!("{}", versions);
+                    exit(if outdated { EXIT_OUTDATED } else { 0 })
+                }
+                Err(e) => Err(e),
+            }
+        }
+        RusteaCmd::Update(update) => remote_repository.update(
+            update.minified,
+            update.version.as_deref(),
+            update.asset.as_deref(),
+            update.pre_release,
         ),
-        RusteaCmd::Update(update) => Updater::new().and_then(|u| u.update(update.minified)),
     };
 
     match res {
         Ok(s) => println!("{}", s),
         Err(e) => {
             eprintln!("{}", e);
-            exit(1)
+            exit(e.exit_code())
         }
     }
     exit(0);
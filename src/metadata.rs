@@ -0,0 +1,570 @@
+//! This file implements the metadata sidecar of a feature set.
+//!
+//! The sidecar is stored as `<feature_set>/.rustea-meta.toml` and is never
+//! deployed itself. Files are addressed by their path within the feature set:
+//!
+//! ```toml
+//! [files."scripts/server"]
+//! capabilities = "cap_net_bind_service=+ep"
+//!
+//! [files."etc/app/app.conf".xattrs]
+//! "user.origin" = "rustea"
+//! ```
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs::{self, Permissions},
+    io,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    check::Check,
+    copy_target,
+    error::{Error, Result},
+    gitea::in_folder,
+    service::Service,
+};
+
+/// The name of the metadata sidecar within a feature set.
+pub const META_FILE: &str = ".rustea-meta.toml";
+
+/// The extended attribute which holds the file capabilities.
+const CAP_XATTR: &str = "security.capability";
+const VFS_CAP_REVISION_2: u32 = 0x0200_0000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+/// Capability names in the order of their numbers, see `capabilities(7)`.
+const CAPABILITIES: [&str; 41] = [
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+/// The mode of pulled scripts if neither the sidecar nor the configuration sets one.
+pub const DEFAULT_SCRIPT_MODE: u32 = 0o751;
+
+/// This function parses a file mode given as octal number like `0700` or `0o644`.
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if !digits.is_empty() && mode <= 0o7777 => Ok(mode),
+        _ => Err(Error::Rustea(format!(
+            "Invalid file mode {}, use an octal number like 0750",
+            mode
+        ))),
+    }
+}
+
+/// Returns the `mode` as four digit octal number like `0750`.
+pub fn format_mode(mode: u32) -> String {
+    format!("{:04o}", mode & 0o7777)
+}
+
+/// This function resolves an owner like `postgres:postgres`, `root` or `:adm` to the
+/// user and group ids. Names and numeric ids are accepted, a missing part is `None`.
+pub fn parse_owner(owner: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let invalid = |reason: &str| Error::Rustea(format!("Invalid owner {}, {}", owner, reason));
+    let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+    if user.is_empty() && group.is_empty() {
+        return Err(invalid("use user:group"));
+    }
+    let id = |name: &str, lookup: fn(&CString) -> Option<u32>| -> Result<Option<u32>> {
+        if name.is_empty() {
+            return Ok(None);
+        }
+        if let Ok(id) = name.parse() {
+            return Ok(Some(id));
+        }
+        let c_name = CString::new(name).map_err(|_| invalid("the name contains a nul byte"))?;
+        lookup(&c_name)
+            .map(Some)
+            .ok_or_else(|| invalid(&format!("{} is unknown", name)))
+    };
+    let uid = id(user, |name| {
+        let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+        (!pw.is_null()).then(|| unsafe { (*pw).pw_uid })
+    })?;
+    let gid = id(group, |name| {
+        let gr = unsafe { libc::getgrnam(name.as_ptr()) };
+        (!gr.is_null()).then(|| unsafe { (*gr).gr_gid })
+    })?;
+    Ok((uid, gid))
+}
+
+/// This function changes the owner of the local `path` to the `owner`, see `parse_owner`.
+/// Symbolic links are changed themselves. Only root may change the owner, so it is
+/// skipped with a warning if rustea runs as unprivileged user.
+pub fn chown(path: &Path, owner: &str) -> Result<()> {
+    let (uid, gid) = parse_owner(owner)?;
+    if unsafe { libc::geteuid() } != 0 {
+        println!(
+            "Skipping owner {} of {}: root required",
+            owner,
+            path.display()
+        );
+        return Ok(());
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        Error::io(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid path {}", path.display()),
+        )
+    })?;
+    // An id of -1 keeps the current user or group
+    let res = unsafe {
+        libc::lchown(
+            c_path.as_ptr(),
+            uid.unwrap_or(u32::MAX),
+            gid.unwrap_or(u32::MAX),
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(Error::Io(io::Error::last_os_error())),
+    }
+}
+
+/// Returns true if the remote path points to the metadata sidecar of the feature set.
+pub fn is_sidecar(path: &str, feature_set: &str) -> bool {
+    path == format!("{}/{}", feature_set, META_FILE)
+}
+
+/// The metadata of a single file within a feature set.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct FileMetadata {
+    /// File capabilities in the textual form of `setcap(8)`, e.g. `cap_net_bind_service=+ep`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<String>,
+    /// Extended attributes which are set on the file
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+    /// The file is a symbolic link and its content is the link target
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub symlink: bool,
+    /// The mode of the file as octal number, e.g. `0700`, recorded for scripts on push
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// The owner of the file as `user:group`, e.g. `postgres:postgres`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// The `Metadata` sidecar of a feature set.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub files: BTreeMap<String, FileMetadata>,
+    /// The commands which validate the pulled files before they are installed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<Check>,
+    /// The systemd units which are reloaded by `rustea apply` if one of their files changed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<Service>,
+}
+
+impl Metadata {
+    /// Parse the content of a metadata sidecar.
+    pub fn parse(content: &str) -> Result<Metadata> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Returns the metadata for the `remote_path` within `feature_set` if there is some.
+    pub fn get(&self, remote_path: &str, feature_set: &str) -> Option<&FileMetadata> {
+        let key = remote_path
+            .strip_prefix(feature_set)
+            .unwrap_or(remote_path)
+            .trim_start_matches('/');
+        self.files.get(key)
+    }
+
+    /// Returns true if the remote `path` within `feature_set` is a symbolic link.
+    pub fn is_symlink(&self, remote_path: &str, feature_set: &str) -> bool {
+        self.get(remote_path, feature_set)
+            .is_some_and(|m| m.symlink)
+    }
+
+    /// This function marks the file at `path` relative to the feature set as symbolic
+    /// link or regular file. Entries without any metadata left are removed.
+    /// Returns true if the metadata changed.
+    pub fn set_symlink(&mut self, path: &str, symlink: bool) -> bool {
+        let path = path.trim_start_matches('/');
+        match (self.files.get_mut(path), symlink) {
+            (Some(m), _) if m.symlink == symlink => false,
+            (None, false) => false,
+            (Some(m), _) => {
+                m.symlink = symlink;
+                if m.is_empty() {
+                    self.files.remove(path);
+                }
+                true
+            }
+            (None, true) => {
+                let m = FileMetadata {
+                    symlink,
+                    ..Default::default()
+                };
+                self.files.insert(path.to_owned(), m);
+                true
+            }
+        }
+    }
+
+    /// This function records the `mode` of the file at `path` relative to the feature set,
+    /// `None` removes it. Entries without any metadata left are removed.
+    /// Returns true if the metadata changed.
+    pub fn set_mode(&mut self, path: &str, mode: Option<u32>) -> bool {
+        let path = path.trim_start_matches('/');
+        let mode = mode.map(format_mode);
+        match self.files.get_mut(path) {
+            Some(m) if m.mode == mode => false,
+            None if mode.is_none() => false,
+            Some(m) => {
+                m.mode = mode;
+                if m.is_empty() {
+                    self.files.remove(path);
+                }
+                true
+            }
+            None => {
+                let m = FileMetadata {
+                    mode,
+                    ..Default::default()
+                };
+                self.files.insert(path.to_owned(), m);
+                true
+            }
+        }
+    }
+
+    /// This function copies the metadata of the file or folder `src` of `from` to `dst`,
+    /// both relative to the feature set. Returns true if the metadata changed.
+    pub fn copy_from(&mut self, from: &Metadata, src: &str, dst: &str) -> bool {
+        let (src, dst) = (src.trim_matches('/'), dst.trim_matches('/'));
+        let mut changed = false;
+        for (path, m) in &from.files {
+            if let Some(target) = copy_target(path, src, dst) {
+                self.files.insert(target, m.clone());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// This function removes the metadata of the file or folder `path` relative to the
+    /// feature set. Returns true if the metadata changed.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        let len = self.files.len();
+        self.files.retain(|p, _| !in_folder(p, path));
+        len != self.files.len()
+    }
+
+    /// This function moves the local files of the checks and services from the file or
+    /// folder `src` to `dst`, both relative to the feature set. Only patterns which start
+    /// with the local path of `src` are changed. Returns true if the metadata changed.
+    pub fn move_patterns(&mut self, src: &str, dst: &str) -> bool {
+        let src = format!("/{}", src.trim_matches('/'));
+        let dst = format!("/{}", dst.trim_matches('/'));
+        let mut changed = false;
+        let patterns = self
+            .checks
+            .iter_mut()
+            .flat_map(|c| c.files.iter_mut())
+            .chain(self.services.iter_mut().flat_map(|s| s.files.iter_mut()));
+        for pattern in patterns {
+            if let Some(moved) = copy_target(pattern, &src, &dst) {
+                *pattern = moved;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Returns the content of the sidecar.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+impl FileMetadata {
+    /// Returns true if no attribute is set for the file.
+    fn is_empty(&self) -> bool {
+        self.capabilities.is_none()
+            && self.xattrs.is_empty()
+            && !self.symlink
+            && self.mode.is_none()
+            && self.owner.is_none()
+    }
+
+    /// Returns the recorded mode of the file.
+    pub fn mode(&self) -> Result<Option<u32>> {
+        self.mode.as_deref().map(parse_mode).transpose()
+    }
+
+    /// Apply the mode, extended attributes and capabilities to the local `path`.
+    /// The owner is changed before by `chown`, which would reset a setuid mode.
+    ///
+    /// Attributes outside of the `user` namespace and capabilities need root privileges.
+    /// They are skipped with a warning if rustea runs as unprivileged user.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        if let Some(mode) = self.mode()? {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        let privileged = unsafe { libc::geteuid() } == 0;
+        for (name, value) in &self.xattrs {
+            if !privileged && !name.starts_with("user.") {
+                println!(
+                    "Skipping attribute {} on {}: root required",
+                    name,
+                    path.display()
+                );
+                continue;
+            }
+            set_xattr(path, name, value.as_bytes())?;
+        }
+
+        if let Some(caps) = &self.capabilities {
+            if privileged {
+                set_xattr(path, CAP_XATTR, &encode_capabilities(caps)?)?;
+            } else {
+                println!("Skipping capabilities on {}: root required", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set a single extended attribute on `path`.
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    let invalid = |_| {
+        Error::io(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid name {}", name),
+        )
+    };
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(invalid)?;
+    let c_name = CString::new(name).map_err(invalid)?;
+    let res = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(Error::Io(io::Error::last_os_error())),
+    }
+}
+
+/// This function converts capabilities in the textual form of `setcap(8)` into
+/// the binary `vfs_cap_data` (revision 2) stored in `security.capability`.
+///
+/// Each clause is a comma separated list of capability names followed
+/// by an operator and flags, e.g. `cap_net_raw,cap_net_admin+ep cap_kill=p`.
+fn encode_capabilities(text: &str) -> Result<Vec<u8>> {
+    let invalid = || Error::Rustea(format!("Invalid capabilities {}", text));
+    let mut permitted: u64 = 0;
+    let mut inheritable: u64 = 0;
+    let mut effective = false;
+
+    for clause in text.split_whitespace() {
+        let op = clause.find(['=', '+', '-']).ok_or_else(invalid)?;
+        let (names, flags) = clause.split_at(op);
+        let mut mask: u64 = 0;
+        for name in names.split(',') {
+            let name = name.to_lowercase();
+            let bit = match name.as_str() {
+                "all" => u64::MAX >> (64 - CAPABILITIES.len()),
+                n => {
+                    1 << CAPABILITIES
+                        .iter()
+                        .position(|c| *c == n)
+                        .ok_or_else(invalid)?
+                }
+            };
+            mask |= bit;
+        }
+
+        let mut add = true;
+        for c in flags.chars() {
+            match c {
+                '=' => {
+                    permitted &= !mask;
+                    inheritable &= !mask;
+                    add = true;
+                }
+                '+' => add = true,
+                '-' => add = false,
+                'p' if add => permitted |= mask,
+                'p' => permitted &= !mask,
+                'i' if add => inheritable |= mask,
+                'i' => inheritable &= !mask,
+                'e' => effective = add,
+                _ => return Err(invalid()),
+            }
+        }
+    }
+
+    let magic = VFS_CAP_REVISION_2
+        | if effective {
+            VFS_CAP_FLAGS_EFFECTIVE
+        } else {
+            0
+        };
+    let mut data = Vec::with_capacity(20);
+    data.extend_from_slice(&magic.to_le_bytes());
+    data.extend_from_slice(&(permitted as u32).to_le_bytes());
+    data.extend_from_slice(&(inheritable as u32).to_le_bytes());
+    data.extend_from_slice(&((permitted >> 32) as u32).to_le_bytes());
+    data.extend_from_slice(&((inheritable >> 32) as u32).to_le_bytes());
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_capabilities, format_mode, is_sidecar, parse_mode, parse_owner, Metadata};
+
+    #[test]
+    fn test_encode_capabilities() {
+        let data = encode_capabilities("cap_net_bind_service=+ep").unwrap();
+        assert_eq!(
+            data,
+            vec![1, 0, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let data = encode_capabilities("cap_bpf,cap_kill+p").unwrap();
+        assert_eq!(
+            data,
+            vec![0, 0, 0, 2, 32, 0, 0, 0, 0, 0, 0, 0, 128, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert!(encode_capabilities("cap_unknown=+ep").is_err());
+        assert!(encode_capabilities("cap_kill").is_err());
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let meta = Metadata::parse(
+            "[files.\"scripts/server\"]\ncapabilities = \"cap_net_bind_service=+ep\"\n\
+             [files.\"etc/app.conf\".xattrs]\n\"user.origin\" = \"rustea\"\n",
+        )
+        .unwrap();
+        let server = meta.get("web/scripts/server", "web").unwrap();
+        assert_eq!(
+            server.capabilities.as_deref(),
+            Some("cap_net_bind_service=+ep")
+        );
+        let conf = meta.get("web/etc/app.conf", "web").unwrap();
+        assert_eq!(conf.xattrs["user.origin"], "rustea");
+        assert!(meta.get("web/etc/other.conf", "web").is_none());
+        assert!(is_sidecar("web/.rustea-meta.toml", "web"));
+    }
+
+    #[test]
+    fn test_set_symlink() {
+        let mut meta =
+            Metadata::parse("[files.\"etc/app.conf\".xattrs]\n\"user.a\" = \"b\"\n").unwrap();
+        assert!(meta.set_symlink("/etc/nginx/sites-enabled/app", true));
+        assert!(!meta.set_symlink("etc/nginx/sites-enabled/app", true));
+        assert!(meta.is_symlink("web/etc/nginx/sites-enabled/app", "web"));
+        assert!(!meta.is_symlink("web/etc/app.conf", "web"));
+        assert_eq!(
+            Metadata::parse(&meta.to_toml().unwrap())
+                .unwrap()
+                .files
+                .len(),
+            2
+        );
+        assert!(meta.set_symlink("etc/nginx/sites-enabled/app", false));
+        assert!(!meta.set_symlink("etc/app.conf", false));
+        assert_eq!(meta.files.keys().collect::<Vec<_>>(), vec!["etc/app.conf"]);
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(parse_owner("root:root").unwrap(), (Some(0), Some(0)));
+        assert_eq!(parse_owner("1000:1000").unwrap(), (Some(1000), Some(1000)));
+        assert_eq!(parse_owner("root").unwrap(), (Some(0), None));
+        assert_eq!(parse_owner(":0").unwrap(), (None, Some(0)));
+        assert!(parse_owner(":").is_err());
+        assert!(parse_owner("no-such-user-rustea:root").is_err());
+    }
+
+    #[test]
+    fn test_modes() {
+        assert_eq!(parse_mode("0700").unwrap(), 0o700);
+        assert_eq!(parse_mode("0o644").unwrap(), 0o644);
+        assert!(parse_mode("0800").is_err());
+        assert!(parse_mode("17777").is_err());
+        assert!(parse_mode("").is_err());
+        assert_eq!(format_mode(0o100755), "0755");
+
+        let mut meta = Metadata::default();
+        assert!(meta.set_mode("/scripts/backup", Some(0o700)));
+        assert!(!meta.set_mode("scripts/backup", Some(0o700)));
+        let backup = meta.get("web/scripts/backup", "web").unwrap();
+        assert_eq!(backup.mode().unwrap(), Some(0o700));
+        assert!(meta.to_toml().unwrap().contains("mode = \"0700\""));
+        assert!(!meta.set_mode("scripts/other", None));
+        assert!(meta.set_mode("scripts/backup", None));
+        assert!(meta.files.is_empty());
+    }
+}
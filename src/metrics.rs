@@ -0,0 +1,220 @@
+//! This file implements the metrics file of `watch` and `apply` for the node_exporter textfile collector.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+use crate::{error::Error, state};
+
+/// The metrics of a single feature set.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeatureSetMetrics {
+    /// The number of files written by the last pull
+    pub files_pulled: usize,
+    /// True if the last pull succeeded
+    pub success: bool,
+    /// The time of the last successful pull in seconds since the unix epoch
+    pub last_success: Option<u64>,
+    /// The number of failed requests to the Gitea API, kept between runs
+    pub api_errors: u64,
+}
+
+/// The `Metrics` of the pulls of `watch` and `apply` are written in the Prometheus
+/// text format, so the textfile collector of the node_exporter exposes them.
+/// Without a path they are only collected.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    path: Option<PathBuf>,
+    pub feature_sets: BTreeMap<String, FeatureSetMetrics>,
+}
+
+/// The name, type and help text of every metric of a feature set.
+const METRICS: [(&str, &str, &str); 5] = [
+    (
+        "rustea_files_pulled",
+        "gauge",
+        "Files written by the last pull of the feature set",
+    ),
+    (
+        "rustea_drift_detected",
+        "gauge",
+        "1 if the last pull of the feature set had to change local files",
+    ),
+    (
+        "rustea_last_run_success",
+        "gauge",
+        "1 if the last pull of the feature set succeeded",
+    ),
+    (
+        "rustea_last_success_timestamp_seconds",
+        "gauge",
+        "Time of the last successful pull of the feature set",
+    ),
+    (
+        "rustea_api_errors_total",
+        "counter",
+        "Failed requests to the Gitea API while pulling the feature set",
+    ),
+];
+
+/// Returns the label value escaped for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Returns the escaped label value of the text format unescaped.
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\")
+}
+
+impl Metrics {
+    /// This function reads the metrics previously written to `path`, which keeps the time
+    /// of the last success and the error counters between runs. A missing or broken
+    /// metrics file is ignored and replaced on save.
+    pub fn load(path: &Path) -> Metrics {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let mut metrics = Metrics {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        for line in content.lines().filter(|l| !l.starts_with('#')) {
+            let sample = line.rsplit_once(' ').and_then(|(sample, value)| {
+                let (name, labels) = sample.split_once("{feature_set=\"")?;
+                let labels = labels.strip_suffix("\"}")?;
+                Some((name, unescape(labels), value.parse::<u64>().ok()?))
+            });
+            if let Some((name, feature_set, value)) = sample {
+                let entry = metrics.feature_sets.entry(feature_set).or_default();
+                match name {
+                    "rustea_last_success_timestamp_seconds" => entry.last_success = Some(value),
+                    "rustea_api_errors_total" => entry.api_errors = value,
+                    _ => {}
+                }
+            }
+        }
+        metrics
+    }
+
+    /// This function records a successful pull of the feature set `name` which wrote `files_pulled` files.
+    pub fn record_success(&mut self, name: &str, files_pulled: usize) {
+        let entry = self.feature_sets.entry(name.to_owned()).or_default();
+        entry.files_pulled = files_pulled;
+        entry.success = true;
+        entry.last_success = Some(state::now());
+    }
+
+    /// This function records a failed pull of the feature set `name`.
+    pub fn record_failure(&mut self, name: &str, error: &Error) {
+        let entry = self.feature_sets.entry(name.to_owned()).or_default();
+        entry.files_pulled = 0;
+        entry.success = false;
+        if let Error::Api(_) = error {
+            entry.api_errors += 1;
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, kind, help) in &METRICS {
+            out.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} {}\n",
+                name, help, name, kind
+            ));
+            for (feature_set, m) in &self.feature_sets {
+                let value = match *name {
+                    "rustea_files_pulled" => Some(m.files_pulled as u64),
+                    "rustea_drift_detected" => Some((m.files_pulled > 0) as u64),
+                    "rustea_last_run_success" => Some(m.success as u64),
+                    "rustea_last_success_timestamp_seconds" => m.last_success,
+                    _ => Some(m.api_errors),
+                };
+                if let Some(value) = value {
+                    out.push_str(&format!(
+                        "{}{{feature_set=\"{}\"}} {}\n",
+                        name,
+                        escape(feature_set),
+                        value
+                    ));
+                }
+            }
+        }
+        out
+    }
+
+    /// This function writes the metrics file. It is replaced at once, so the collector
+    /// never reads a partial file. A failure is only logged.
+    pub fn save(&self) {
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("prom.tmp");
+            let res = fs::write(&tmp, self.render()).and_then(|_| fs::rename(&tmp, path));
+            if let Err(e) = res {
+                warn!("Failed to write the metrics {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::{error::Error, gitea::gitea_api::ApiError, temp::TempDir};
+
+    #[test]
+    fn test_metrics() {
+        let tmp = TempDir::new("rustea-metrics").unwrap();
+        let path = tmp.path().join("rustea.prom");
+        let mut metrics = Metrics::load(&path);
+        metrics.record_success("web", 2);
+        metrics.record_failure(
+            "db\"1",
+            &Error::Api(ApiError::InvalidCredentials("no".into())),
+        );
+        metrics.record_failure("db\"1", &Error::Rustea("hook failed".into()));
+        let text = metrics.render();
+        assert!(text.contains("# TYPE rustea_api_errors_total counter\n"));
+        assert!(text.contains("rustea_files_pulled{feature_set=\"web\"} 2\n"));
+        assert!(text.contains("rustea_drift_detected{feature_set=\"web\"} 1\n"));
+        assert!(text.contains("rustea_drift_detected{feature_set=\"db\\\"1\"} 0\n"));
+        assert!(text.contains("rustea_last_run_success{feature_set=\"db\\\"1\"} 0\n"));
+        assert!(text.contains("rustea_api_errors_total{feature_set=\"db\\\"1\"} 1\n"));
+        assert!(!text.contains("rustea_last_success_timestamp_seconds{feature_set=\"db"));
+        metrics.save();
+
+        let mut metrics = Metrics::load(&path);
+        let web = metrics.feature_sets["web"].clone();
+        assert!(web.last_success.is_some());
+        assert_eq!(web.files_pulled, 0);
+        assert_eq!(metrics.feature_sets["db\"1"].api_errors, 1);
+        metrics.record_failure(
+            "web",
+            &Error::Api(ApiError::InvalidCredentials("no".into())),
+        );
+        assert_eq!(metrics.feature_sets["web"].last_success, web.last_success);
+        assert!(!tmp.path().join("rustea.prom.tmp").exists());
+    }
+}
@@ -0,0 +1,130 @@
+//! This file implements the webhook which is notified about changes of the remote repository.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::time::Duration;
+
+use log::{info, warn};
+use ring::hmac;
+use serde_derive::{Deserialize, Serialize};
+use ureq::AgentBuilder;
+
+use crate::{audit::Entry, checksum};
+
+/// The seconds to wait for the webhook, a slow receiver mustn't block rustea.
+const WEBHOOK_TIMEOUT: u64 = 10;
+
+/// The `[notify]` section of the configuration.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Notify {
+    /// The url which receives a POST request after every change of the remote repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// The key of the HMAC-SHA256 signature sent as `X-Rustea-Signature`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// The JSON body sent to the webhook.
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    /// The host rustea ran on
+    pub host: &'a str,
+    /// The changed repository as `owner/repository`
+    pub repository: String,
+    #[serde(flatten)]
+    pub change: &'a Entry,
+}
+
+/// Returns the signature of the `body` as `sha256=<hex>`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    format!(
+        "sha256={}",
+        checksum::to_hex(hmac::sign(&key, body).as_ref())
+    )
+}
+
+impl Notify {
+    /// Returns true if no webhook is configured.
+    pub fn is_empty(&self) -> bool {
+        self.webhook_url.is_none()
+    }
+
+    /// This function posts the `payload` to the webhook if one is configured.
+    /// A failure is only logged, the change of the repository already happened.
+    pub fn send(&self, payload: &Payload) {
+        let url = match &self.webhook_url {
+            Some(url) => url,
+            None => return,
+        };
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => return warn!("Failed to serialize the webhook payload: {}", e),
+        };
+        let agent = AgentBuilder::new()
+            .user_agent("rustea")
+            .timeout(Duration::from_secs(WEBHOOK_TIMEOUT))
+            .build();
+        let mut request = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .set("X-Rustea-Event", &payload.change.operation.to_string());
+        if let Some(secret) = &self.secret {
+            request = request.set("X-Rustea-Signature", &sign(secret, &body));
+        }
+        match request.send_bytes(&body) {
+            Ok(_) => info!("Notified the webhook {}", url),
+            Err(e) => warn!("Failed to notify the webhook {}: {}", url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, Payload};
+    use crate::audit::{Entry, Operation};
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let mut change = Entry::new(Operation::Push, "web");
+        change.timestamp = 100;
+        change.user = "ops".into();
+        change.author = Some("Ops Team".into());
+        change.files = vec!["/etc/nginx/nginx.conf".into()];
+        change.commits = vec!["7d2a1f".into()];
+        change.message = Some("Raise the worker limit".into());
+        let payload = Payload {
+            host: "web01",
+            repository: "ops/config".into(),
+            change: &change,
+        };
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            "{\"host\":\"web01\",\"repository\":\"ops/config\",\"timestamp\":100,\"user\":\"ops\",\
+             \"author\":\"Ops Team\",\"operation\":\"push\",\"feature_set\":\"web\",\
+             \"files\":[\"/etc/nginx/nginx.conf\"],\"commits\":[\"7d2a1f\"],\
+             \"message\":\"Raise the worker limit\"}"
+        );
+    }
+}
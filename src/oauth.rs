@@ -0,0 +1,386 @@
+//! This file implements the login with the OAuth2 device authorization flow and the refresh of its tokens.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use ureq::Agent;
+
+use crate::{
+    error::{ConfigError, Error, Result},
+    gitea::{
+        cache,
+        gitea_api::{ApiError, ApiResult},
+        GiteaClient,
+    },
+    http::HttpConfig,
+    state,
+};
+
+/// The grant type of the token request which polls the device authorization.
+const DEVICE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Seconds before the expiry at which an access token is refreshed.
+const EXPIRY_MARGIN: u64 = 60;
+
+/// The `oauth` section of a repository which signed in with `rustea login`.
+/// The tokens are kept in a separate file, see `token_path`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OAuthConfig {
+    /// The id of the OAuth2 application registered on the server
+    pub client_id: String,
+    /// The endpoint which issues and refreshes the tokens
+    pub token_url: String,
+}
+
+/// The endpoints of the server announced by its OpenID Connect discovery document.
+#[derive(Debug, Default, Deserialize)]
+pub struct Endpoints {
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub device_authorization_endpoint: Option<String>,
+}
+
+/// The answer of the server to a device authorization request.
+#[derive(Debug, Default, Deserialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    #[serde(default)]
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: u64,
+    /// The seconds to wait between the token requests
+    #[serde(default)]
+    pub interval: Option<u64>,
+}
+
+/// A successful token response of the server.
+#[derive(Debug, Default, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// The seconds the access token is valid
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+/// An error response of the token endpoint.
+#[derive(Debug, Default, Deserialize)]
+struct TokenError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// The tokens of a repository as stored in its token file.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Tokens {
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// The unix time at which the access token expires, never if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Tokens {
+    /// This function creates the tokens of a `response` received at `now`.
+    /// A refresh without a new refresh token keeps the `previous` one.
+    pub fn new(response: TokenResponse, now: u64, previous: Option<String>) -> Tokens {
+        Tokens {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.or(previous),
+            expires_at: response.expires_in.map(|s| now + s),
+        }
+    }
+
+    /// Returns true if the access token is expired or about to expire at `now`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at
+            .is_some_and(|at| at <= now.saturating_add(EXPIRY_MARGIN))
+    }
+
+    /// This function reads the tokens of the token file at `path`.
+    pub fn load(path: &Path) -> Result<Tokens> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            Error::Rustea(format!(
+                "Failed to read the OAuth2 tokens {}, run rustea login: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        serde_json::from_str(&content).map_err(|e| {
+            Error::Rustea(format!(
+                "Invalid OAuth2 tokens {}, run rustea login: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// This function writes the tokens to the file at `path`, which is only readable by its owner.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_vec(self).map_err(|e| Error::Rustea(e.to_string()))?;
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(&content)
+            .map_err(Error::Io)
+    }
+}
+
+/// Returns the token file of the repository `owner/repository` at `url`,
+/// which is kept in the `oauth` folder of the data folder, see `data_dir`.
+pub fn token_path(url: &str, owner: &str, repository: &str) -> Result<PathBuf> {
+    let dir = data_dir(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"))?;
+    Ok(dir
+        .join("oauth")
+        .join(cache::file_name(url, owner, repository)))
+}
+
+/// Returns the data folder `$XDG_DATA_HOME/rustea`, where `$XDG_DATA_HOME`
+/// defaults to `~/.local/share`.
+fn data_dir(xdg_data_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf> {
+    let data_home = match xdg_data_home.filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match home.filter(|h| !h.is_empty()) {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => return Err(Error::Configuration(ConfigError::LocationError)),
+        },
+    };
+    Ok(data_home.join("rustea"))
+}
+
+/// The `Session` holds the tokens of a repository and refreshes them if expired.
+#[derive(Debug)]
+pub struct Session {
+    config: OAuthConfig,
+    path: PathBuf,
+    tokens: Tokens,
+}
+
+impl Session {
+    /// This function reads the tokens of the session from the token file at `path`.
+    pub fn load(config: OAuthConfig, path: PathBuf) -> Result<Session> {
+        let tokens = Tokens::load(&path)?;
+        Ok(Session {
+            config,
+            path,
+            tokens,
+        })
+    }
+
+    /// Returns the access token, which is refreshed first with the `agent` if it is expired.
+    /// A failed refresh is only logged, the server rejects the expired token then.
+    pub fn access_token(&mut self, agent: &Agent) -> &str {
+        if self.tokens.is_expired(state::now()) {
+            match self.refresh(agent) {
+                Ok(()) => log::debug!("Refreshed the OAuth2 access token"),
+                Err(e) => log::warn!("Failed to refresh the OAuth2 access token: {}", e),
+            }
+        }
+        &self.tokens.access_token
+    }
+
+    /// This function requests new tokens with the refresh token and stores them.
+    fn refresh(&mut self, agent: &Agent) -> Result<()> {
+        let refresh_token = self.tokens.refresh_token.clone().ok_or_else(|| {
+            Error::Rustea("The access token expired and there is no refresh token".into())
+        })?;
+        let response = request_token(
+            agent,
+            &self.config.token_url,
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &refresh_token),
+                ("client_id", &self.config.client_id),
+            ],
+        )?;
+        self.tokens = Tokens::new(response, state::now(), Some(refresh_token));
+        self.tokens.save(&self.path)
+    }
+}
+
+/// The outcome of polling the token endpoint during the device authorization.
+#[derive(Debug, PartialEq)]
+pub enum Poll {
+    /// The user hasn't confirmed the code yet
+    Pending,
+    /// The polling is too fast and the interval is increased
+    SlowDown,
+    /// The authorization was denied or expired
+    Failed(String),
+}
+
+/// Returns the outcome of an error `body` of the token endpoint, see RFC 8628.
+pub fn poll_outcome(body: &str) -> Poll {
+    match serde_json::from_str::<TokenError>(body) {
+        Ok(e) if e.error == "authorization_pending" => Poll::Pending,
+        Ok(e) if e.error == "slow_down" => Poll::SlowDown,
+        Ok(e) => Poll::Failed(match e.error_description {
+            Some(description) => format!("{}: {}", e.error, description),
+            None => e.error,
+        }),
+        Err(_) => Poll::Failed(format!("Invalid response of the token endpoint: {}", body)),
+    }
+}
+
+/// This function posts the `form` to the token endpoint at `url`.
+fn request_token(agent: &Agent, url: &str, form: &[(&str, &str)]) -> ApiResult<TokenResponse> {
+    agent
+        .post(url)
+        .send_form(form)?
+        .into_json()
+        .map_err(ApiError::Io)
+}
+
+/// This function requests the discovery document of the server at `url`.
+pub fn discover(agent: &Agent, url: &str) -> ApiResult<Endpoints> {
+    agent
+        .get(&format!("{}/.well-known/openid-configuration", url))
+        .call()?
+        .into_json()
+        .map_err(ApiError::Io)
+}
+
+/// This function signs in with the OAuth2 device authorization flow of the server at `url`
+/// for the application `client_id`. The user confirms the printed code in the browser,
+/// meanwhile the token endpoint is polled. Returns the configuration and the tokens.
+pub fn login(url: &str, client_id: &str, http: &HttpConfig) -> Result<(OAuthConfig, Tokens)> {
+    let agent = GiteaClient::create_api_client(http)?;
+    let endpoints = discover(&agent, url)?;
+    let device_url = endpoints.device_authorization_endpoint.ok_or_else(|| {
+        Error::Rustea(format!(
+            "{} doesn't support the OAuth2 device authorization flow",
+            url
+        ))
+    })?;
+    let device: DeviceAuthorization = agent
+        .post(&device_url)
+        .send_form(&[("client_id", client_id)])
+        .map_err(ApiError::from)?
+        .into_json()?;
+    match &device.verification_uri_complete {
+        Some(uri) => println!("Open {} to confirm the code {}", uri, device.user_code),
+        None => println!(
+            "Open {} and enter the code {}",
+            device.verification_uri, device.user_code
+        ),
+    }
+
+    let mut interval = device.interval.unwrap_or(5);
+    let deadline = state::now() + device.expires_in;
+    while state::now() < deadline {
+        thread::sleep(Duration::from_secs(interval));
+        let form = [
+            ("grant_type", DEVICE_GRANT),
+            ("device_code", device.device_code.as_str()),
+            ("client_id", client_id),
+        ];
+        let body = match agent.post(&endpoints.token_endpoint).send_form(&form) {
+            Ok(response) => {
+                let response: TokenResponse = response.into_json()?;
+                let config = OAuthConfig {
+                    client_id: client_id.to_owned(),
+                    token_url: endpoints.token_endpoint,
+                };
+                return Ok((config, Tokens::new(response, state::now(), None)));
+            }
+            Err(ureq::Error::Status(_, response)) => response.into_string()?,
+            Err(e) => return Err(ApiError::from(e).into()),
+        };
+        match poll_outcome(&body) {
+            Poll::Pending => {}
+            Poll::SlowDown => interval += 5,
+            Poll::Failed(e) => return Err(Error::Rustea(format!("The login failed: {}", e))),
+        }
+    }
+    Err(Error::Rustea(
+        "The code expired before it was confirmed, run rustea login again".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{data_dir, poll_outcome, Poll, TokenResponse, Tokens};
+    use crate::temp::TempDir;
+    use std::path::Path;
+
+    #[test]
+    fn test_poll_outcome() {
+        assert_eq!(
+            poll_outcome(r#"{"error": "authorization_pending"}"#),
+            Poll::Pending
+        );
+        assert_eq!(poll_outcome(r#"{"error": "slow_down"}"#), Poll::SlowDown);
+        assert_eq!(
+            poll_outcome(r#"{"error": "access_denied", "error_description": "denied by user"}"#),
+            Poll::Failed("access_denied: denied by user".into())
+        );
+        assert!(matches!(poll_outcome("<html>"), Poll::Failed(_)));
+    }
+
+    #[test]
+    fn test_tokens() {
+        let response = TokenResponse {
+            access_token: "a1".into(),
+            refresh_token: None,
+            expires_in: Some(3600),
+        };
+        let tokens = Tokens::new(response, 1000, Some("r0".into()));
+        assert_eq!(tokens.refresh_token.as_deref(), Some("r0"));
+        assert_eq!(tokens.expires_at, Some(4600));
+        assert!(!tokens.is_expired(4000));
+        assert!(tokens.is_expired(4560));
+        assert!(!Tokens::default().is_expired(u64::MAX));
+
+        let tmp = TempDir::new("rustea-oauth").unwrap();
+        let path = tmp.path().join("oauth").join("repo.json");
+        tokens.save(&path).unwrap();
+        assert_eq!(Tokens::load(&path).unwrap(), tokens);
+        assert!(Tokens::load(&tmp.path().join("missing.json")).is_err());
+    }
+
+    #[test]
+    fn test_data_dir() {
+        assert_eq!(
+            data_dir(Some("/data".into()), Some("/home/u".into())).unwrap(),
+            Path::new("/data/rustea")
+        );
+        assert_eq!(
+            data_dir(Some("".into()), Some("/home/u".into())).unwrap(),
+            Path::new("/home/u/.local/share/rustea")
+        );
+        assert!(data_dir(None, None).is_err());
+    }
+}
@@ -0,0 +1,617 @@
+//! This file implements the `RepoBackend` of plain git remotes, e.g. over SSH, with the git command.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use super::{commit_message, files_in_tree, Provider, RepoBackend};
+use crate::gitea::{
+    gitea_api::{
+        ApiError, ApiResult, Commit, CommitDetails, CommitUser, ContentEntry, ContentType,
+        TreeEntry,
+    },
+    FileChange,
+};
+
+/// The `GitClient` works on a local clone of a plain git remote, which is fetched
+/// on the first request of a run. Every change is committed and pushed at once.
+#[derive(Debug)]
+pub struct GitClient {
+    /// The url of the remote, e.g. `git@git.example.com:ops/config.git`
+    url: String,
+    /// The branch used for the feature sets, the default branch of the remote if `None`.
+    branch: Option<String>,
+    /// The folder of the local clone
+    dir: PathBuf,
+    /// The private key used by ssh, the keys of the ssh agent if `None`
+    ssh_key: Option<PathBuf>,
+    /// The branch which is checked out after the clone is up to date.
+    checked_out: OnceLock<String>,
+}
+
+/// This function parses the output of `git ls-tree -z`, every entry is
+/// `<mode> <type> <sha>\t<path>` and terminated by a NUL. The padded size
+/// of `git ls-tree -l` after the sha is parsed as well, it is `-` for folders.
+pub fn parse_tree(out: &[u8]) -> Vec<TreeEntry> {
+    String::from_utf8_lossy(out)
+        .split('\0')
+        .filter_map(|line| {
+            let (info, path) = line.split_once('\t')?;
+            let mut info = info.split_whitespace();
+            Some(TreeEntry {
+                mode: info.next()?.to_owned(),
+                entry_type: info.next()?.to_owned(),
+                sha: info.next()?.to_owned(),
+                size: info.next().and_then(|s| s.parse().ok()),
+                path: path.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the ssh command which only offers the `key`. If it is a public key,
+/// the matching private key of the ssh agent is used.
+fn ssh_command(key: &Path) -> String {
+    let key = key.display().to_string().replace('\'', "'\\''");
+    format!("ssh -i '{}' -o IdentitiesOnly=yes", key)
+}
+
+/// This function runs git with the `args`, within the repository at `dir` if provided,
+/// and returns its stdout. Git never asks for credentials on the terminal.
+/// With an `ssh_key` the remote is accessed with this key only.
+fn run_git(dir: Option<&Path>, ssh_key: Option<&Path>, args: &[&str]) -> ApiResult<Vec<u8>> {
+    let mut cmd = Command::new("git");
+    if let Some(dir) = dir {
+        cmd.arg("-C").arg(dir);
+    }
+    if let Some(key) = ssh_key {
+        cmd.env("GIT_SSH_COMMAND", ssh_command(key));
+    }
+    let out = cmd
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|e| ApiError::Git(format!("git couldn't be started: {}", e)))?;
+    match out.status.success() {
+        true => Ok(out.stdout),
+        false => Err(ApiError::Git(format!(
+            "git {} failed: {}",
+            args.iter()
+                .find(|a| !a.starts_with('-') && !a.contains('='))
+                .unwrap_or(&""),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+impl GitClient {
+    /// This creates a new client for the remote `url` whose clone is kept in `dir`.
+    pub fn new(url: &str, branch: Option<String>, dir: PathBuf) -> GitClient {
+        GitClient {
+            url: url.to_owned(),
+            branch,
+            dir,
+            ssh_key: None,
+            checked_out: OnceLock::new(),
+        }
+    }
+
+    /// Use the private `ssh_key` to access the remote instead of the keys of the ssh agent.
+    pub fn with_ssh_key(mut self, ssh_key: Option<PathBuf>) -> Self {
+        self.ssh_key = ssh_key;
+        self
+    }
+
+    fn git(&self, args: &[&str]) -> ApiResult<Vec<u8>> {
+        run_git(Some(&self.dir), self.ssh_key.as_deref(), args)
+    }
+
+    /// This function clones the remote or fetches it if the clone exists and checks out
+    /// the branch at the state of the remote. Local changes of the clone are discarded.
+    /// Returns the checked out branch.
+    fn prepare(&self) -> ApiResult<String> {
+        if let Some(branch) = self.checked_out.get() {
+            return Ok(branch.clone());
+        }
+        if self.dir.join(".git").is_dir() {
+            self.git(&["remote", "set-url", "origin", &self.url])?;
+            self.git(&["fetch", "--quiet", "--prune", "origin"])?;
+        } else {
+            fs::create_dir_all(&self.dir)?;
+            let dir = self.dir.to_string_lossy();
+            run_git(
+                None,
+                self.ssh_key.as_deref(),
+                &["clone", "--quiet", "--no-checkout", &self.url, &dir],
+            )?;
+        }
+        let branch = match &self.branch {
+            Some(branch) => branch.clone(),
+            None => {
+                let head = self.git(&["symbolic-ref", "--short", "refs/remotes/origin/HEAD"])?;
+                let head = String::from_utf8_lossy(&head);
+                let head = head.trim();
+                head.strip_prefix("origin/").unwrap_or(head).to_owned()
+            }
+        };
+        let remote = format!("origin/{}", branch);
+        self.git(&["checkout", "--quiet", "--force", "-B", &branch, &remote])?;
+        Ok(self.checked_out.get_or_init(|| branch).clone())
+    }
+
+    /// Returns the entries of the folder `path`, including the folder itself, and
+    /// of all its subfolders if `recursive` is set.
+    fn get_tree(&self, path: &str, recursive: bool) -> ApiResult<Vec<TreeEntry>> {
+        self.prepare()?;
+        let mut args = vec!["ls-tree", "-z", "-l"];
+        if recursive {
+            args.extend(["-r", "-t"]);
+        }
+        args.push("HEAD");
+        if !path.is_empty() {
+            args.extend(["--", path]);
+        }
+        Ok(parse_tree(&self.git(&args)?))
+    }
+
+    /// This function commits the staged changes of the `paths` and pushes them. A rejected
+    /// push is returned as `ApiError::Conflict` and the commit is dropped again.
+    /// Returns the sha of the commit or `None` if nothing changed.
+    fn commit(
+        &self,
+        paths: &[&str],
+        author: &str,
+        mail: &str,
+        message: &str,
+    ) -> ApiResult<Option<String>> {
+        let branch = self.prepare()?;
+        // Nothing is committed if the content didn't change
+        let mut diff = vec!["diff", "--cached", "--quiet", "--"];
+        diff.extend(paths);
+        if self.git(&diff).is_ok() {
+            return Ok(None);
+        }
+        self.git(&[
+            "-c",
+            &format!("user.name={}", author),
+            "-c",
+            &format!("user.email={}", mail),
+            "commit",
+            "--quiet",
+            "--author",
+            &format!("{} <{}>", author, mail),
+            "-m",
+            message,
+        ])?;
+        match self.git(&[
+            "push",
+            "--quiet",
+            "origin",
+            &format!("HEAD:refs/heads/{}", branch),
+        ]) {
+            Err(ApiError::Git(e)) => {
+                // The next change starts from the last pushed state again
+                self.git(&["reset", "--quiet", "--hard", &format!("origin/{}", branch)])?;
+                match e.contains("[rejected]") || e.contains("fetch first") {
+                    true => Err(ApiError::Conflict {
+                        message: e,
+                        expected_sha: None,
+                    }),
+                    false => Err(ApiError::Git(e)),
+                }
+            }
+            Err(e) => Err(e),
+            Ok(_) => {
+                let commit = self.git(&["rev-parse", "HEAD"])?;
+                Ok(Some(String::from_utf8_lossy(&commit).trim().to_owned()))
+            }
+        }
+    }
+
+    /// This function writes the `content` of the file `path` into the clone and stages it.
+    fn stage(&self, path: &str, content: &[u8]) -> ApiResult<()> {
+        let file = self.dir.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, content)?;
+        self.git(&["add", "--", path]).map(|_| ())
+    }
+}
+
+/// This function parses the output of `git log` with the format of `LOG_FORMAT`.
+pub fn parse_log(out: &[u8]) -> Vec<Commit> {
+    String::from_utf8_lossy(out)
+        .split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').split('\0');
+            let mut next = || fields.next().map(str::to_owned);
+            Some(Commit {
+                sha: next().filter(|s| !s.is_empty())?,
+                commit: CommitDetails {
+                    author: CommitUser {
+                        name: next()?,
+                        email: next()?,
+                        date: next()?,
+                    },
+                    committer: CommitUser {
+                        name: next()?,
+                        email: next()?,
+                        date: next()?,
+                    },
+                    message: next()?,
+                },
+            })
+        })
+        .collect()
+}
+
+/// The format of `git log` parsed by `parse_log`, the fields are separated by a NUL
+/// and every commit is terminated by a record separator.
+const LOG_FORMAT: &str = "--format=%H%x00%an%x00%ae%x00%aI%x00%cn%x00%ce%x00%cI%x00%B%x1e";
+
+impl RepoBackend for GitClient {
+    fn provider(&self) -> Provider {
+        Provider::Git
+    }
+
+    fn info(&self) -> ApiResult<String> {
+        let branch = self.prepare()?;
+        let commit = self.git(&["rev-parse", "HEAD"])?;
+        Ok(format!(
+            "Git repository {}\n\tBranch = {}\n\tCommit = {}\n\tClone = {}",
+            self.url,
+            branch,
+            String::from_utf8_lossy(&commit).trim(),
+            self.dir.display()
+        ))
+    }
+
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let path = path.trim_matches('/');
+        let mut entries = self.get_tree(path, false)?;
+        if !path.is_empty() {
+            match entries.as_slice() {
+                [] => {
+                    return Err(ApiError::NotFound(format!(
+                        "{} doesn't exist in the remote repository",
+                        path
+                    )))
+                }
+                [e] if e.entry_type == "tree" => {
+                    entries = self.get_tree(&format!("{}/", path), false)?
+                }
+                _ => {}
+            }
+        }
+        Ok(entries.into_iter().map(ContentEntry::from).collect())
+    }
+
+    fn list_files(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let path = path.trim_matches('/');
+        files_in_tree(self.get_tree(path, true)?, path)
+    }
+
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        self.get_tree("", true)
+    }
+
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>> {
+        self.prepare()?;
+        let object = format!("HEAD:{}", path.trim_matches('/'));
+        self.git(&["cat-file", "blob", &object])
+            .map_err(|e| match e {
+                ApiError::Git(e) => ApiError::NotFound(e),
+                e => e,
+            })
+    }
+
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        self.prepare()?;
+        self.git(&["cat-file", "blob", sha])
+    }
+
+    fn commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>> {
+        self.prepare()?;
+        let limit = format!("--max-count={}", limit);
+        let mut args = vec!["log", &limit, LOG_FORMAT, "HEAD"];
+        let path = path.trim_matches('/');
+        if !path.is_empty() {
+            args.extend(["--", path]);
+        }
+        Ok(parse_log(&self.git(&args)?))
+    }
+
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        self.prepare()?;
+        let path = path.trim_matches('/');
+        let action = if self.dir.join(path).exists() {
+            "Update"
+        } else {
+            "Create"
+        };
+        self.stage(path, content)?;
+        self.commit(
+            &[path],
+            author,
+            mail,
+            &commit_message(cmt_msg, action, path),
+        )
+    }
+
+    fn delete(
+        &self,
+        path: &str,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        self.prepare()?;
+        let path = path.trim_matches('/');
+        self.git(&["rm", "--quiet", "-r", "--", path])?;
+        self.commit(
+            &[path],
+            author,
+            mail,
+            &commit_message(cmt_msg, "Delete", path),
+        )
+    }
+
+    /// All changes are committed and pushed at once. If a change can't be staged,
+    /// the clone is reset to the state of the remote and nothing is committed.
+    fn change_files(
+        &self,
+        changes: &[FileChange],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let branch = self.prepare()?;
+        let mut paths = vec![];
+        let mut action = "Update";
+        for change in changes {
+            let path = change.path.trim_matches('/');
+            let res = match (&change.content, &change.sha) {
+                (Some(content), _) => self.stage(path, content),
+                (None, Some(_)) => {
+                    action = "Delete";
+                    self.git(&["rm", "--quiet", "--", path]).map(|_| ())
+                }
+                (None, None) => continue,
+            };
+            if let Err(e) = res {
+                self.git(&["reset", "--quiet", "--hard", &format!("origin/{}", branch)])?;
+                return Err(e);
+            }
+            paths.push(path);
+        }
+        let message = match (cmt_msg, paths.as_slice()) {
+            (_, []) => return Ok(None),
+            (Some(msg), _) => msg.to_owned(),
+            (None, [path]) => commit_message(None, action, path),
+            (None, _) => format!("Update {} files with rustea", paths.len()),
+        };
+        self.commit(&paths, author, mail, &message)
+    }
+
+    /// A folder is deleted with all its files in one commit.
+    fn delete_folder(
+        &self,
+        path: &str,
+        recursive: bool,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Vec<String>> {
+        if recursive {
+            return Ok(self
+                .delete(path, author, mail, cmt_msg)?
+                .into_iter()
+                .collect());
+        }
+        let changes: Vec<FileChange> = self
+            .list(path)?
+            .into_iter()
+            .filter(|e| e.content_type != ContentType::Dir)
+            .map(|e| FileChange {
+                path: e.path,
+                content: None,
+                sha: e.sha,
+            })
+            .collect();
+        Ok(self
+            .change_files(&changes, author, mail, cmt_msg)?
+            .into_iter()
+            .collect())
+    }
+
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// The branch is checked out again before the next request.
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String> {
+        self.checked_out = OnceLock::new();
+        std::mem::replace(&mut self.branch, branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_log, parse_tree, run_git, ssh_command, GitClient};
+    use crate::{gitea::FileChange, provider::RepoBackend, temp::TempDir};
+
+    #[test]
+    fn test_parse_tree() {
+        let out = b"040000 tree a1\tweb\x00100644 blob b2\tweb/a b.conf\0";
+        let entries = parse_tree(out);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].entry_type, "tree");
+        assert_eq!(entries[1].path, "web/a b.conf");
+        assert_eq!(entries[1].sha, "b2");
+        assert_eq!(entries[1].size, None);
+        let out = b"040000 tree a1       -\tweb\x00100644 blob b2     120\tweb/a.conf\0";
+        let entries = parse_tree(out);
+        assert_eq!((entries[0].size, entries[1].size), (None, Some(120)));
+        assert_eq!(entries[1].path, "web/a.conf");
+        assert!(parse_tree(b"").is_empty());
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let out = b"c3\0ops\0ops@x\x002024-05-01T10:00:00+02:00\0ci\0ci@x\0\
+                    2024-05-01T10:05:00+02:00\0Update nginx\n\n\x1e\n\
+                    b2\0ops\0ops@x\x002024-04-01T10:00:00Z\0ops\0ops@x\0\
+                    2024-04-01T10:00:00Z\0init\n\x1e\n";
+        let commits = parse_log(out);
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].sha, "c3");
+        assert_eq!(commits[0].commit.committer.name, "ci");
+        assert_eq!(
+            commits[0].commit.committer.date,
+            "2024-05-01T10:05:00+02:00"
+        );
+        assert_eq!(commits[0].commit.message.trim(), "Update nginx");
+        assert_eq!(commits[1].commit.author.date, "2024-04-01T10:00:00Z");
+        assert!(parse_log(b"").is_empty());
+    }
+
+    #[test]
+    fn test_ssh_command() {
+        assert_eq!(
+            ssh_command(Path::new("/root/.ssh/id_ed25519")),
+            "ssh -i '/root/.ssh/id_ed25519' -o IdentitiesOnly=yes"
+        );
+        assert_eq!(
+            ssh_command(Path::new("/keys/it's")),
+            "ssh -i '/keys/it'\\''s' -o IdentitiesOnly=yes"
+        );
+    }
+
+    #[test]
+    fn test_git_client() {
+        let tmp = TempDir::new("rustea-git").unwrap();
+        let seed = tmp.write("seed/web/etc/nginx.conf", b"worker 1\n").unwrap();
+        let seed = seed.ancestors().nth(3).unwrap();
+        let git = |args: &[&str]| run_git(Some(seed), None, args).unwrap();
+        git(&["init", "--quiet", "-b", "main"]);
+        git(&["add", "--all"]);
+        git(&[
+            "-c",
+            "user.name=ops",
+            "-c",
+            "user.email=ops@x",
+            "commit",
+            "-qm",
+            "init",
+        ]);
+        let remote = tmp.path().join("remote.git");
+        let seed = seed.to_string_lossy();
+        let remote_url = remote.to_string_lossy();
+        run_git(
+            None,
+            None,
+            &["clone", "--quiet", "--bare", &seed, &remote_url],
+        )
+        .unwrap();
+
+        let client = GitClient::new(&remote_url, None, tmp.path().join("clone"));
+        let root = client.list("").unwrap();
+        assert_eq!(root.len(), 1);
+        assert_eq!(root[0].name, "web");
+        assert_eq!(client.list("web/etc/nginx.conf").unwrap().len(), 1);
+        assert!(client.list("db").is_err());
+        assert_eq!(client.list_files("web").unwrap().len(), 1);
+        assert_eq!(
+            client.download("web/etc/nginx.conf").unwrap(),
+            b"worker 1\n"
+        );
+        client
+            .upload("web/etc/app.conf", b"port 80\n", "ops", "ops@x", None)
+            .unwrap();
+        client
+            .delete("web/etc/nginx.conf", "ops", "ops@x", Some("Remove nginx"))
+            .unwrap();
+
+        // A second clone sees the pushed changes
+        let other = GitClient::new(&remote_url, Some("main".into()), tmp.path().join("other"));
+        let files = other.list_files("web").unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "web/etc/app.conf");
+        assert!(other.info().unwrap().contains("Branch = main"));
+
+        // Several changes are pushed in one commit
+        let sha = files[0].sha.clone();
+        let changes = [
+            FileChange {
+                path: "web/etc/app.conf".into(),
+                content: None,
+                sha,
+            },
+            FileChange {
+                path: "db/my.cnf".into(),
+                content: Some(b"port 3306\n".to_vec()),
+                sha: None,
+            },
+        ];
+        let commit = other
+            .change_files(&changes, "ops", "ops@x", Some("Move to db"))
+            .unwrap();
+        let commits = other.commits("", 10).unwrap();
+        assert_eq!(commits.len(), 4);
+        assert_eq!(Some(&commits[0].sha), commit.as_ref());
+        assert_eq!(commits[0].commit.message.trim(), "Move to db");
+        assert_eq!(
+            other.latest_commit("web").unwrap().unwrap().sha,
+            commits[0].sha
+        );
+        assert_eq!(
+            other
+                .latest_commit("web/etc/nginx.conf")
+                .unwrap()
+                .unwrap()
+                .commit
+                .message
+                .trim(),
+            "Remove nginx"
+        );
+        let db = other.file("db/my.cnf").unwrap();
+        assert_eq!(
+            other.blob(db.sha.as_deref().unwrap()).unwrap(),
+            b"port 3306\n"
+        );
+        assert!(other.tree().unwrap().iter().any(|e| e.path == "db"));
+        assert!(other.create_branch("rustea/db", "main").is_err());
+
+        other
+            .delete_folder("db", true, "ops", "ops@x", None)
+            .unwrap();
+        assert!(other.list("db").is_err());
+    }
+}
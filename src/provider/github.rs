@@ -0,0 +1,362 @@
+//! This file implements the `RepoBackend` of repositories hosted by GitHub.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::io::Read;
+
+use base64::encode;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use ureq::Agent;
+
+use super::{
+    commit_message, encode_component, encode_path, files_in_tree, send, Provider, RepoBackend,
+};
+use crate::{
+    gitea::{
+        gitea_api::{
+            decode_base64, ApiError, ApiResult, Blob, Commit, ContentEntry, ContentsResponse,
+            Permission, Tree, TreeEntry,
+        },
+        GiteaClient,
+    },
+    http::HttpConfig,
+};
+
+/// The api of github.com, GitHub Enterprise serves it below `/api/v3` of the instance.
+const GITHUB_API: &str = "https://api.github.com";
+
+/// The repository as returned by the repos API of GitHub.
+#[derive(Deserialize, Debug, Default)]
+pub struct GitHubRepository {
+    pub full_name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub default_branch: String,
+    #[serde(default)]
+    pub private: bool,
+    /// Only provided if the request is authenticated
+    #[serde(default)]
+    pub permissions: Option<Permission>,
+}
+
+/// The `GitHubClient` accesses a repository with the contents API of GitHub.
+#[derive(Debug)]
+pub struct GitHubClient {
+    api_url: String,
+    api_token: String,
+    owner: String,
+    repository: String,
+    /// The branch used for all content calls, the default branch if `None`.
+    branch: Option<String>,
+    client: Agent,
+}
+
+/// Returns the api url of the GitHub instance at `url`.
+pub fn api_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    match url.split("://").last() {
+        Some("github.com") | Some("www.github.com") | Some("api.github.com") => {
+            GITHUB_API.to_owned()
+        }
+        _ => format!("{}/api/v3", url),
+    }
+}
+
+impl GitHubClient {
+    /// This creates a new client for the `repository` of the `owner` on the GitHub
+    /// instance at `url` with the timeouts, proxy and TLS settings of `http`.
+    pub fn new(
+        url: &str,
+        api_token: &str,
+        owner: &str,
+        repository: &str,
+        branch: Option<String>,
+        http: &HttpConfig,
+    ) -> ApiResult<GitHubClient> {
+        Ok(GitHubClient {
+            api_url: api_url(url),
+            api_token: api_token.to_owned(),
+            owner: owner.to_owned(),
+            repository: repository.to_owned(),
+            branch,
+            client: GiteaClient::create_api_client(http)?,
+        })
+    }
+
+    /// Returns an authenticated request of the repository `path`, e.g. `/contents/web`.
+    /// Reading requests get the `ref` of the branch if one is set.
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        let request = self
+            .client
+            .request(
+                method,
+                &format!(
+                    "{}/repos/{}/{}{}",
+                    self.api_url, self.owner, self.repository, path
+                ),
+            )
+            .set("Authorization", &format!("Bearer {}", self.api_token))
+            .set("Accept", "application/vnd.github+json");
+        match (&self.branch, method) {
+            (Some(branch), "GET") => request.query("ref", branch),
+            _ => request,
+        }
+    }
+
+    fn contents(&self, method: &str, path: &str) -> ureq::Request {
+        self.request(method, &format!("/contents/{}", encode_path(path)))
+    }
+
+    pub fn get_repository_information(&self) -> ApiResult<GitHubRepository> {
+        send(self.request("GET", ""), None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)
+    }
+
+    /// Returns the blob sha of the file `path` or `None` if it doesn't exist.
+    fn file_sha(&self, path: &str) -> ApiResult<Option<String>> {
+        match self.list(path) {
+            Ok(entries) => Ok(entries
+                .into_iter()
+                .find(|e| e.path == path.trim_matches('/'))
+                .and_then(|e| e.sha)),
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the recursive git tree of the branch in use, which GitHub truncates
+    /// for very large repositories.
+    fn get_tree(&self) -> ApiResult<Tree> {
+        let tree_ref = match &self.branch {
+            Some(branch) => branch.clone(),
+            None => self.get_repository_information()?.default_branch,
+        };
+        let request = self.request(
+            "GET",
+            &format!("/git/trees/{}", encode_component(&tree_ref)),
+        );
+        send(request.query("recursive", "1"), None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)
+    }
+
+    /// Returns the json body of a change with the commit `message` by the committer.
+    fn change_body(&self, message: String, author: &str, mail: &str) -> Value {
+        let mut body = ureq::json!({
+            "message": message,
+            "committer": { "name": author, "email": mail },
+        });
+        if let Some(branch) = &self.branch {
+            body["branch"] = branch.as_str().into();
+        }
+        body
+    }
+}
+
+/// Returns the sha of the commit of the response of a change.
+fn commit_sha(res: ureq::Response) -> ApiResult<Option<String>> {
+    let res: Value = res.into_json().map_err(ApiError::Io)?;
+    Ok(res["commit"]["sha"].as_str().map(str::to_owned))
+}
+
+impl RepoBackend for GitHubClient {
+    fn provider(&self) -> Provider {
+        Provider::GitHub
+    }
+
+    fn info(&self) -> ApiResult<String> {
+        let repo = self.get_repository_information()?;
+        let permissions = match &repo.permissions {
+            Some(p) => p.to_string(),
+            None => "unknown".to_owned(),
+        };
+        Ok(format!(
+            "GitHub repository {}\n\tDescription = {}\n\tDefault branch = {}\n\tPrivate = {}\n\tPermissions = {}",
+            repo.full_name,
+            repo.description.unwrap_or_default(),
+            repo.default_branch,
+            repo.private,
+            permissions
+        ))
+    }
+
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let res: Value = send(self.contents("GET", path), None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)?;
+        Ok(ContentsResponse::new(res, None)?.content)
+    }
+
+    /// The whole tree is requested at once, a truncated tree is listed folder by folder.
+    fn list_files(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let tree = self.get_tree()?;
+        match tree.truncated {
+            true => super::list_recursively(self, path),
+            false => files_in_tree(tree.tree, path),
+        }
+    }
+
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        let tree = self.get_tree()?;
+        match tree.truncated {
+            true => Err(ApiError::InvalidContentResponse(format!(
+                "The tree of {}/{} is too large for GitHub",
+                self.owner, self.repository
+            ))),
+            false => Ok(tree.tree),
+        }
+    }
+
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>> {
+        let request = self
+            .contents("GET", path)
+            .set("Accept", "application/vnd.github.raw+json");
+        let mut content = vec![];
+        send(request, None, &self.owner)?
+            .into_reader()
+            .read_to_end(&mut content)
+            .map_err(ApiError::Io)?;
+        Ok(content)
+    }
+
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        let blob: Blob = send(
+            self.request("GET", &format!("/git/blobs/{}", sha)),
+            None,
+            &self.owner,
+        )?
+        .into_json()
+        .map_err(ApiError::Io)?;
+        decode_base64(&blob.content)
+            .map_err(|e| ApiError::InvalidContentResponse(format!("Invalid blob {}: {}", sha, e)))
+    }
+
+    fn commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>> {
+        let mut request = self
+            .request("GET", "/commits")
+            .query("per_page", &limit.to_string());
+        if let Some(branch) = &self.branch {
+            request = request.query("sha", branch);
+        }
+        if !path.trim_matches('/').is_empty() {
+            request = request.query("path", path.trim_matches('/'));
+        }
+        send(request, None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)
+    }
+
+    fn can_push(&self) -> ApiResult<bool> {
+        Ok(self
+            .get_repository_information()?
+            .permissions
+            .is_none_or(|p| p.push))
+    }
+
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let sha = self.file_sha(path)?;
+        let action = if sha.is_some() { "Update" } else { "Create" };
+        let mut body = self.change_body(commit_message(cmt_msg, action, path), author, mail);
+        body["content"] = encode(content).into();
+        if let Some(sha) = sha {
+            body["sha"] = sha.into();
+        }
+        commit_sha(send(self.contents("PUT", path), Some(body), &self.owner)?)
+    }
+
+    fn delete(
+        &self,
+        path: &str,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let sha = self.file_sha(path)?.ok_or_else(|| {
+            ApiError::NotFound(format!("{} doesn't exist in the remote repository", path))
+        })?;
+        let mut body = self.change_body(commit_message(cmt_msg, "Delete", path), author, mail);
+        body["sha"] = sha.into();
+        commit_sha(send(
+            self.contents("DELETE", path),
+            Some(body),
+            &self.owner,
+        )?)
+    }
+
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.branch, branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{api_url, GitHubRepository};
+    use crate::gitea::gitea_api::{Commit, ContentType, ContentsResponse};
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!(api_url("https://github.com/"), "https://api.github.com");
+        assert_eq!(
+            api_url("https://git.example.com"),
+            "https://git.example.com/api/v3"
+        );
+    }
+
+    #[test]
+    fn test_github_responses() {
+        let res: Value = serde_json::from_str(
+            r#"[{"type": "dir", "name": "web", "path": "web", "sha": "a1", "size": 0},
+                {"type": "symlink", "name": "cur", "path": "cur", "sha": "b2", "size": 3}]"#,
+        )
+        .unwrap();
+        let entries = ContentsResponse::new(res, None).unwrap().content;
+        assert_eq!(entries[0].content_type, ContentType::Dir);
+        assert_eq!(entries[1].content_type, ContentType::Symlink);
+
+        let repo: GitHubRepository = serde_json::from_str(
+            r#"{"full_name": "ops/config", "description": null, "default_branch": "main",
+                "private": true, "permissions": {"admin": false, "maintain": false,
+                "push": true, "triage": true, "pull": true}}"#,
+        )
+        .unwrap();
+        assert_eq!(repo.default_branch, "main");
+        assert!(repo.permissions.unwrap().push);
+
+        let commits: Vec<Commit> = serde_json::from_str(
+            r#"[{"sha": "c3", "node_id": "C_1", "commit": {"message": "Update nginx",
+                "author": {"name": "ops", "email": "ops@example.com", "date": "2024-05-01T08:00:00Z"},
+                "committer": {"name": "GitHub", "email": "noreply@github.com",
+                "date": "2024-05-01T08:05:00Z"}}}]"#,
+        )
+        .unwrap();
+        assert_eq!(commits[0].sha, "c3");
+        assert_eq!(commits[0].commit.committer.date, "2024-05-01T08:05:00Z");
+    }
+}
@@ -0,0 +1,403 @@
+//! This file implements the `RepoBackend` of repositories hosted by GitLab.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{io::Read, sync::OnceLock};
+
+use base64::encode;
+use serde_derive::Deserialize;
+use ureq::Agent;
+
+use super::{commit_message, encode_component, files_in_tree, send, Provider, RepoBackend};
+use crate::{
+    gitea::{
+        gitea_api::{
+            ApiError, ApiResult, Commit, CommitDetails, CommitUser, ContentEntry, TreeEntry,
+        },
+        GiteaClient,
+    },
+    http::HttpConfig,
+};
+
+/// The number of tree entries requested per page, the maximum of GitLab.
+const PAGE_SIZE: usize = 100;
+
+/// The project as returned by the projects API of GitLab.
+#[derive(Deserialize, Debug, Default)]
+pub struct GitLabProject {
+    pub path_with_namespace: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Empty projects have no default branch
+    #[serde(default)]
+    pub default_branch: Option<String>,
+    #[serde(default)]
+    pub visibility: String,
+}
+
+/// A single entry of a repository tree as returned by GitLab.
+#[derive(Deserialize, Debug, Default)]
+pub struct TreeItem {
+    pub id: String,
+    pub path: String,
+    /// Either `blob`, `tree` or `commit` for submodules
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub mode: String,
+}
+
+impl From<TreeItem> for TreeEntry {
+    fn from(item: TreeItem) -> TreeEntry {
+        TreeEntry {
+            path: item.path,
+            mode: item.mode,
+            entry_type: item.item_type,
+            sha: item.id,
+            size: None,
+        }
+    }
+}
+
+/// A single commit as returned by the commits API of GitLab.
+#[derive(Deserialize, Debug, Default)]
+pub struct GitLabCommit {
+    pub id: String,
+    pub message: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub authored_date: String,
+    pub committer_name: String,
+    pub committer_email: String,
+    pub committed_date: String,
+}
+
+impl From<GitLabCommit> for Commit {
+    fn from(commit: GitLabCommit) -> Commit {
+        Commit {
+            sha: commit.id,
+            commit: CommitDetails {
+                message: commit.message,
+                author: CommitUser {
+                    name: commit.author_name,
+                    email: commit.author_email,
+                    date: commit.authored_date,
+                },
+                committer: CommitUser {
+                    name: commit.committer_name,
+                    email: commit.committer_email,
+                    date: commit.committed_date,
+                },
+            },
+        }
+    }
+}
+
+/// The `GitLabClient` accesses a repository with the repository files API of GitLab.
+#[derive(Debug)]
+pub struct GitLabClient {
+    api_url: String,
+    api_token: String,
+    owner: String,
+    /// The url encoded path of the project, e.g. `ops%2Fconfig`
+    project: String,
+    /// The branch used for all content calls, the default branch if `None`.
+    branch: Option<String>,
+    default_branch: OnceLock<String>,
+    client: Agent,
+}
+
+impl GitLabClient {
+    /// This creates a new client for the project `repository` of the `owner` on the
+    /// GitLab instance at `url` with the timeouts, proxy and TLS settings of `http`.
+    pub fn new(
+        url: &str,
+        api_token: &str,
+        owner: &str,
+        repository: &str,
+        branch: Option<String>,
+        http: &HttpConfig,
+    ) -> ApiResult<GitLabClient> {
+        Ok(GitLabClient {
+            api_url: format!("{}/api/v4", url.trim_end_matches('/')),
+            api_token: api_token.to_owned(),
+            owner: owner.to_owned(),
+            project: encode_component(&format!("{}/{}", owner, repository)),
+            branch,
+            default_branch: OnceLock::new(),
+            client: GiteaClient::create_api_client(http)?,
+        })
+    }
+
+    /// Returns an authenticated request of the project `path`, e.g. `/repository/tree`.
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.client
+            .request(
+                method,
+                &format!("{}/projects/{}{}", self.api_url, self.project, path),
+            )
+            .set("PRIVATE-TOKEN", &self.api_token)
+    }
+
+    fn file(&self, method: &str, path: &str) -> ureq::Request {
+        let path = encode_component(path.trim_matches('/'));
+        self.request(method, &format!("/repository/files/{}", path))
+    }
+
+    pub fn get_project(&self) -> ApiResult<GitLabProject> {
+        send(self.request("GET", ""), None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)
+    }
+
+    /// Returns the branch in use, the default branch of the project is requested once.
+    fn branch(&self) -> ApiResult<String> {
+        if let Some(branch) = self.branch.as_ref().or_else(|| self.default_branch.get()) {
+            return Ok(branch.clone());
+        }
+        let branch = self.get_project()?.default_branch.ok_or_else(|| {
+            ApiError::NotFound("The project is empty and has no default branch".into())
+        })?;
+        Ok(self.default_branch.get_or_init(|| branch).clone())
+    }
+
+    /// Returns the entries of the tree of the folder `path`, of all subfolders if `recursive`.
+    /// The pages are requested until GitLab sends no `X-Next-Page` anymore.
+    fn get_tree(&self, path: &str, recursive: bool) -> ApiResult<Vec<TreeEntry>> {
+        let branch = self.branch()?;
+        let mut entries = vec![];
+        let mut page = "1".to_owned();
+        loop {
+            let mut request = self
+                .request("GET", "/repository/tree")
+                .query("ref", &branch)
+                .query("recursive", &recursive.to_string())
+                .query("per_page", &PAGE_SIZE.to_string())
+                .query("page", &page);
+            if !path.trim_matches('/').is_empty() {
+                request = request.query("path", path.trim_matches('/'));
+            }
+            let res = send(request, None, &self.owner)?;
+            let next = res.header("X-Next-Page").unwrap_or_default().to_owned();
+            let items: Vec<TreeItem> = res.into_json().map_err(ApiError::Io)?;
+            entries.extend(items.into_iter().map(TreeEntry::from));
+            match next.is_empty() {
+                true => return Ok(entries),
+                false => page = next,
+            }
+        }
+    }
+
+    /// Returns the file `path` or `None` if it doesn't exist.
+    fn get_file(&self, path: &str) -> ApiResult<Option<ContentEntry>> {
+        let request = self.file("HEAD", path).query("ref", &self.branch()?);
+        match send(request, None, &self.owner) {
+            Ok(res) => {
+                let entry = TreeEntry {
+                    path: path.trim_matches('/').to_owned(),
+                    mode: "100644".into(),
+                    entry_type: "blob".into(),
+                    sha: res
+                        .header("X-Gitlab-Blob-Id")
+                        .unwrap_or_default()
+                        .to_owned(),
+                    size: res.header("X-Gitlab-Size").and_then(|s| s.parse().ok()),
+                };
+                Ok(Some(ContentEntry::from(entry)))
+            }
+            Err(ApiError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the json body of a change with the commit `message` by the `author`.
+    fn change_body(
+        &self,
+        message: String,
+        author: &str,
+        mail: &str,
+    ) -> ApiResult<serde_json::Value> {
+        Ok(ureq::json!({
+            "branch": self.branch()?,
+            "commit_message": message,
+            "author_name": author,
+            "author_email": mail,
+        }))
+    }
+}
+
+impl RepoBackend for GitLabClient {
+    fn provider(&self) -> Provider {
+        Provider::GitLab
+    }
+
+    fn info(&self) -> ApiResult<String> {
+        let project = self.get_project()?;
+        Ok(format!(
+            "GitLab project {}\n\tDescription = {}\n\tDefault branch = {}\n\tVisibility = {}",
+            project.path_with_namespace,
+            project.description.unwrap_or_default(),
+            project.default_branch.unwrap_or_default(),
+            project.visibility
+        ))
+    }
+
+    /// GitLab lists an empty tree for a file, which is looked up instead.
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let entries = self.get_tree(path, false)?;
+        if entries.is_empty() && !path.trim_matches('/').is_empty() {
+            return Ok(self.get_file(path)?.into_iter().collect());
+        }
+        Ok(entries.into_iter().map(ContentEntry::from).collect())
+    }
+
+    fn list_files(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let entries = self.get_tree(path, true)?;
+        if entries.is_empty() {
+            if let Some(file) = self.get_file(path)? {
+                return Ok(vec![file]);
+            }
+        }
+        files_in_tree(entries, path)
+    }
+
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        self.get_tree("", true)
+    }
+
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>> {
+        let request = self
+            .request(
+                "GET",
+                &format!(
+                    "/repository/files/{}/raw",
+                    encode_component(path.trim_matches('/'))
+                ),
+            )
+            .query("ref", &self.branch()?);
+        let mut content = vec![];
+        send(request, None, &self.owner)?
+            .into_reader()
+            .read_to_end(&mut content)
+            .map_err(ApiError::Io)?;
+        Ok(content)
+    }
+
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        let request = self.request("GET", &format!("/repository/blobs/{}/raw", sha));
+        let mut content = vec![];
+        send(request, None, &self.owner)?
+            .into_reader()
+            .read_to_end(&mut content)
+            .map_err(ApiError::Io)?;
+        Ok(content)
+    }
+
+    fn commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>> {
+        let mut request = self
+            .request("GET", "/repository/commits")
+            .query("ref_name", &self.branch()?)
+            .query("per_page", &limit.to_string());
+        if !path.trim_matches('/').is_empty() {
+            request = request.query("path", path.trim_matches('/'));
+        }
+        let commits: Vec<GitLabCommit> = send(request, None, &self.owner)?
+            .into_json()
+            .map_err(ApiError::Io)?;
+        Ok(commits.into_iter().map(Commit::from).collect())
+    }
+
+    /// GitLab doesn't report the sha of the commit of a change.
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let (method, action) = match self.get_file(path)? {
+            Some(_) => ("PUT", "Update"),
+            None => ("POST", "Create"),
+        };
+        let mut body = self.change_body(commit_message(cmt_msg, action, path), author, mail)?;
+        body["content"] = encode(content).into();
+        body["encoding"] = "base64".into();
+        send(self.file(method, path), Some(body), &self.owner).map(|_| None)
+    }
+
+    fn delete(
+        &self,
+        path: &str,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let body = self.change_body(commit_message(cmt_msg, "Delete", path), author, mail)?;
+        send(self.file("DELETE", path), Some(body), &self.owner).map(|_| None)
+    }
+
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.branch, branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GitLabCommit, GitLabProject, TreeItem};
+    use crate::gitea::gitea_api::{Commit, ContentEntry, ContentType, TreeEntry};
+
+    #[test]
+    fn test_gitlab_responses() {
+        let items: Vec<TreeItem> = serde_json::from_str(
+            r#"[{"id": "a1", "name": "etc", "type": "tree", "path": "web/etc", "mode": "040000"},
+                {"id": "b2", "name": "cur", "type": "blob", "path": "web/cur", "mode": "120000"}]"#,
+        )
+        .unwrap();
+        let entries: Vec<ContentEntry> = items
+            .into_iter()
+            .map(|i| ContentEntry::from(TreeEntry::from(i)))
+            .collect();
+        assert_eq!(entries[0].content_type, ContentType::Dir);
+        assert_eq!(entries[0].name, "etc");
+        assert_eq!(entries[1].content_type, ContentType::Symlink);
+        assert_eq!(entries[1].sha.as_deref(), Some("b2"));
+
+        let project: GitLabProject = serde_json::from_str(
+            r#"{"id": 3, "path_with_namespace": "ops/config", "description": null,
+                "default_branch": "main", "visibility": "private"}"#,
+        )
+        .unwrap();
+        assert_eq!(project.default_branch.as_deref(), Some("main"));
+
+        let commits: Vec<GitLabCommit> = serde_json::from_str(
+            r#"[{"id": "c3", "short_id": "c3", "title": "Update nginx", "message": "Update nginx\n",
+                "author_name": "ops", "author_email": "ops@example.com",
+                "authored_date": "2024-05-01T10:00:00.000+02:00", "committer_name": "ci",
+                "committer_email": "ci@example.com", "committed_date": "2024-05-01T10:05:00.000+02:00"}]"#,
+        )
+        .unwrap();
+        let commit = Commit::from(commits.into_iter().next().unwrap());
+        assert_eq!(commit.sha, "c3");
+        assert_eq!(commit.commit.committer.name, "ci");
+        assert_eq!(
+            commit.commit.committer.date,
+            "2024-05-01T10:05:00.000+02:00"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! This file implements an in-memory backend for the tests of the remote repository.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet},
+};
+
+use super::{Provider, RepoBackend};
+use crate::{
+    checksum,
+    gitea::gitea_api::{ApiError, ApiResult, Commit, ContentEntry, TreeEntry},
+};
+
+/// A backend which keeps the files in memory and only implements the required methods.
+#[derive(Default)]
+pub(crate) struct Memory {
+    files: RefCell<BTreeMap<String, Vec<u8>>>,
+    commits: Cell<usize>,
+    branch: Option<String>,
+    /// Reject every listing like the server does for a revoked api token
+    pub(crate) unauthorized: bool,
+}
+
+impl Memory {
+    /// Returns a backend which contains the `files` as path and content.
+    pub(crate) fn with_files(files: &[(&str, &[u8])]) -> Memory {
+        let memory = Memory::default();
+        for (path, content) in files {
+            memory
+                .files
+                .borrow_mut()
+                .insert((*path).to_owned(), content.to_vec());
+        }
+        memory
+    }
+
+    fn commit(&self) -> ApiResult<Option<String>> {
+        self.commits.set(self.commits.get() + 1);
+        Ok(Some(format!("c{}", self.commits.get())))
+    }
+}
+
+impl RepoBackend for Memory {
+    fn provider(&self) -> Provider {
+        Provider::GitHub
+    }
+
+    fn info(&self) -> ApiResult<String> {
+        Ok("memory".into())
+    }
+
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        let path = path.trim_matches('/');
+        let entries: Vec<ContentEntry> = self
+            .tree()?
+            .into_iter()
+            .filter(|e| match e.path.rsplit_once('/') {
+                Some((parent, _)) => parent == path,
+                None => path.is_empty(),
+            })
+            .map(ContentEntry::from)
+            .collect();
+        match (entries.is_empty(), self.files.borrow().contains_key(path)) {
+            (false, _) => Ok(entries),
+            (true, true) => Ok(vec![ContentEntry::from(entry(path, "blob"))]),
+            (true, false) => Err(ApiError::NotFound(path.to_owned())),
+        }
+    }
+
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        if self.unauthorized {
+            return Err(ApiError::Unauthorized("The token was revoked".into()));
+        }
+        let files = self.files.borrow();
+        let folders: BTreeSet<&str> = files
+            .keys()
+            .flat_map(|p| p.match_indices('/').map(move |(i, _)| &p[..i]))
+            .collect();
+        Ok(folders
+            .into_iter()
+            .map(|f| entry(f, "tree"))
+            .chain(files.iter().map(|(p, content)| TreeEntry {
+                sha: checksum::git_blob_sha(content),
+                ..entry(p, "blob")
+            }))
+            .collect())
+    }
+
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>> {
+        self.files
+            .borrow()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| ApiError::NotFound(path.to_owned()))
+    }
+
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        Err(ApiError::NotFound(sha.to_owned()))
+    }
+
+    fn commits(&self, _path: &str, _limit: usize) -> ApiResult<Vec<Commit>> {
+        Ok(vec![])
+    }
+
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        _author: &str,
+        _mail: &str,
+        _cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        self.files.borrow_mut().insert(path.into(), content.into());
+        self.commit()
+    }
+
+    fn delete(
+        &self,
+        path: &str,
+        _author: &str,
+        _mail: &str,
+        _cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        match self.files.borrow_mut().remove(path) {
+            Some(_) => self.commit(),
+            None => Err(ApiError::NotFound(path.to_owned())),
+        }
+    }
+
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.branch, branch)
+    }
+}
+
+/// Returns a tree entry of the `entry_type` at `path`.
+pub(crate) fn entry(path: &str, entry_type: &str) -> TreeEntry {
+    TreeEntry {
+        path: path.into(),
+        mode: "100644".into(),
+        entry_type: entry_type.into(),
+        sha: "b2".into(),
+        size: None,
+    }
+}
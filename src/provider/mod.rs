@@ -0,0 +1,663 @@
+//! This module abstracts the hosting service of the remote repository behind the `RepoBackend` trait.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+pub mod git;
+pub mod github;
+pub mod gitlab;
+#[cfg(test)]
+pub(crate) mod memory;
+
+use core::fmt;
+use std::{fmt::Display, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    gitea::{
+        self, cache,
+        gitea_api::{
+            ApiError, ApiResult, Commit, ContentEntry, ContentType, PullRequest, TreeEntry,
+        },
+        FileChange, GiteaClient,
+    },
+    http::HttpConfig,
+    RepositoryConfig,
+};
+
+/// The service which hosts the remote repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Gitea,
+    GitHub,
+    GitLab,
+    /// A plain git remote, e.g. over SSH, without an api
+    Git,
+}
+
+impl Provider {
+    /// Returns true for the default provider Gitea.
+    pub fn is_gitea(&self) -> bool {
+        *self == Provider::Gitea
+    }
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provider::Gitea => write!(f, "Gitea"),
+            Provider::GitHub => write!(f, "GitHub"),
+            Provider::GitLab => write!(f, "GitLab"),
+            Provider::Git => write!(f, "git"),
+        }
+    }
+}
+
+/// The `RepoBackend` is the access to the files of a remote repository which
+/// every hosting service provides. Paths are relative to the repository root.
+///
+/// The methods with a default implementation are either built on the other methods
+/// or need Gitea, like pull requests, and fail with `ApiError::Unsupported` otherwise.
+pub trait RepoBackend {
+    /// Returns the service which hosts the repository.
+    fn provider(&self) -> Provider;
+
+    /// Returns a description of the server and the repository.
+    fn info(&self) -> ApiResult<String>;
+
+    /// Returns the files and folders directly within the folder `path`, the
+    /// repository root if it is empty, or the file `path` itself.
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>>;
+
+    /// Returns every file below the folder `path`, or the file `path` itself.
+    fn list_files(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        list_recursively(self, path)
+    }
+
+    /// Returns the file `path` or an `ApiError::NotFound` if it doesn't exist.
+    fn file(&self, path: &str) -> ApiResult<ContentEntry> {
+        let path = path.trim_matches('/');
+        self.list(path)?
+            .into_iter()
+            .find(|e| e.path == path)
+            .ok_or_else(|| {
+                ApiError::NotFound(format!("{} doesn't exist in the remote repository", path))
+            })
+    }
+
+    /// Returns every entry of the git tree of the branch in use, including the folders.
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>>;
+
+    /// Returns the content of the file `path`.
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>>;
+
+    /// Returns the content of the file `path` which must contain valid UTF-8.
+    fn download_text(&self, path: &str) -> ApiResult<String> {
+        String::from_utf8(self.download(path)?).map_err(|e| {
+            ApiError::InvalidContentResponse(format!("{} is no valid UTF-8: {}", path, e))
+        })
+    }
+
+    /// Returns the content of the git blob with the `sha`.
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>>;
+
+    /// Returns up to `limit` commits which touched `path`, the newest first.
+    fn commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>>;
+
+    /// Returns the latest commit which touched `path` or `None` if there is no such commit.
+    fn latest_commit(&self, path: &str) -> ApiResult<Option<Commit>> {
+        Ok(self.commits(path, 1)?.into_iter().next())
+    }
+
+    /// Returns false if the credentials can only read the repository.
+    /// Providers which don't report the permissions return true.
+    fn can_push(&self) -> ApiResult<bool> {
+        Ok(true)
+    }
+
+    /// This function creates the file `path` or replaces its content.
+    /// Returns the sha of the commit if the provider reports it.
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>>;
+
+    /// This function deletes the file `path`.
+    /// Returns the sha of the commit if the provider reports it.
+    fn delete(
+        &self,
+        path: &str,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>>;
+
+    /// This function deletes the file `path` or the files within the folder `path`,
+    /// including the files of its subfolders if `recursive`. The empty files which keep
+    /// the folders are deleted as well. Returns the shas of the reported commits.
+    fn delete_folder(
+        &self,
+        path: &str,
+        recursive: bool,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Vec<String>> {
+        let mut commits = vec![];
+        for entry in self.list(path)? {
+            match entry.content_type {
+                ContentType::Dir if recursive => commits.append(&mut self.delete_folder(
+                    &entry.path,
+                    true,
+                    author,
+                    mail,
+                    cmt_msg,
+                )?),
+                ContentType::Dir => {}
+                _ => commits.extend(self.delete(&entry.path, author, mail, cmt_msg)?),
+            }
+        }
+        Ok(commits)
+    }
+
+    /// This function applies all `changes` and returns the sha of the last commit,
+    /// or `None` if there are no changes or the provider doesn't report it.
+    /// Without a batch api every change is committed on its own.
+    fn change_files(
+        &self,
+        changes: &[FileChange],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let mut commit = None;
+        for change in changes {
+            let res = match (&change.content, &change.sha) {
+                (Some(content), _) => self.upload(&change.path, content, author, mail, cmt_msg)?,
+                (None, Some(_)) => self.delete(&change.path, author, mail, cmt_msg)?,
+                (None, None) => continue,
+            };
+            commit = res.or(commit);
+        }
+        Ok(commit)
+    }
+
+    /// Returns the branch used for all content calls, the default branch if `None`.
+    fn branch(&self) -> Option<&str>;
+
+    /// This function replaces the branch used for all content calls and returns the former one.
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String>;
+
+    /// This function pins all reads to the tag or commit `revision`.
+    fn set_revision(&mut self, _revision: &str) -> ApiResult<()> {
+        Err(unsupported(self.provider(), "Pulling a revision"))
+    }
+
+    /// This function enables or disables the waiting for the rate limit of the server.
+    fn set_rate_limit_wait(&mut self, _wait: bool) {}
+
+    /// Returns the default branch of the repository.
+    fn default_branch(&self) -> ApiResult<String> {
+        Err(unsupported(self.provider(), "A pull request"))
+    }
+
+    /// This function creates the branch `name` at the tip of the branch `from`
+    /// and returns the sha of its commit.
+    fn create_branch(&self, _name: &str, _from: &str) -> ApiResult<String> {
+        Err(unsupported(self.provider(), "A pull request"))
+    }
+
+    /// This function deletes the branch `name`.
+    fn delete_branch(&self, _name: &str) -> ApiResult<()> {
+        Err(unsupported(self.provider(), "A pull request"))
+    }
+
+    /// Returns the sha of the commit the branch, tag or commit `revision` points to.
+    fn commit_sha(&self, _revision: &str) -> ApiResult<String> {
+        Err(unsupported(self.provider(), "A pull request"))
+    }
+
+    /// This function opens a pull request which merges the branch `head` into `base`.
+    fn create_pull_request(
+        &self,
+        _head: &str,
+        _base: &str,
+        _title: &str,
+        _body: &str,
+    ) -> ApiResult<PullRequest> {
+        Err(unsupported(self.provider(), "A pull request"))
+    }
+}
+
+/// Returns the error of an `action` which the `provider` doesn't support.
+fn unsupported(provider: Provider, action: &str) -> ApiError {
+    ApiError::Unsupported(format!(
+        "{} needs a Gitea repository, the repository is hosted by {}",
+        action, provider
+    ))
+}
+
+impl RepoBackend for GiteaClient {
+    fn provider(&self) -> Provider {
+        Provider::Gitea
+    }
+
+    fn info(&self) -> ApiResult<String> {
+        let mut info = format!(
+            "{}\n{}",
+            self.get_gitea_version()?,
+            self.get_repository_information()?
+        );
+        if let Some(org) = self.get_organization()? {
+            info.push_str(&format!("\n{}", org));
+        }
+        Ok(info)
+    }
+
+    fn list(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        Ok(self.get_file_or_folder(path, None)?.content)
+    }
+
+    fn list_files(&self, path: &str) -> ApiResult<Vec<ContentEntry>> {
+        Ok(self.get_folder(path)?.content)
+    }
+
+    fn file(&self, path: &str) -> ApiResult<ContentEntry> {
+        self.get_file(path)
+    }
+
+    fn tree(&self) -> ApiResult<Vec<TreeEntry>> {
+        self.get_tree()
+    }
+
+    fn download(&self, path: &str) -> ApiResult<Vec<u8>> {
+        self.download_file(path)
+    }
+
+    fn blob(&self, sha: &str) -> ApiResult<Vec<u8>> {
+        self.get_blob(sha)
+    }
+
+    fn commits(&self, path: &str, limit: usize) -> ApiResult<Vec<Commit>> {
+        self.get_commits(path, limit)
+    }
+
+    fn can_push(&self) -> ApiResult<bool> {
+        Ok(self.get_repository_information()?.permissions.push)
+    }
+
+    fn upload(
+        &self,
+        path: &str,
+        content: &[u8],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        self.create_or_update_file("", path, content, author, mail, cmt_msg)
+            .map(|res| Some(res.commit.sha))
+    }
+
+    fn delete(
+        &self,
+        path: &str,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        let sha = self.get_file(path)?.sha.unwrap_or_default();
+        self.delete_file(path, &sha, author, mail, cmt_msg)
+            .map(|res| Some(res.commit.sha))
+    }
+
+    fn delete_folder(
+        &self,
+        path: &str,
+        recursive: bool,
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Vec<String>> {
+        self.delete_file_or_folder(path, recursive, author, mail, cmt_msg)
+    }
+
+    fn change_files(
+        &self,
+        changes: &[FileChange],
+        author: &str,
+        mail: &str,
+        cmt_msg: Option<&str>,
+    ) -> ApiResult<Option<String>> {
+        GiteaClient::change_files(self, changes, author, mail, cmt_msg)
+    }
+
+    fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    fn set_branch(&mut self, branch: Option<String>) -> Option<String> {
+        std::mem::replace(&mut self.branch, branch)
+    }
+
+    fn set_revision(&mut self, revision: &str) -> ApiResult<()> {
+        self.revision = Some(self.resolve_revision(revision)?);
+        Ok(())
+    }
+
+    fn set_rate_limit_wait(&mut self, wait: bool) {
+        self.wait_on_rate_limit = wait;
+    }
+
+    fn default_branch(&self) -> ApiResult<String> {
+        self.get_default_branch()
+    }
+
+    fn create_branch(&self, name: &str, from: &str) -> ApiResult<String> {
+        Ok(GiteaClient::create_branch(self, name, from)?.commit.id)
+    }
+
+    fn delete_branch(&self, name: &str) -> ApiResult<()> {
+        GiteaClient::delete_branch(self, name)
+    }
+
+    fn commit_sha(&self, revision: &str) -> ApiResult<String> {
+        self.get_commit_sha(revision)
+    }
+
+    fn create_pull_request(
+        &self,
+        head: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+    ) -> ApiResult<PullRequest> {
+        GiteaClient::create_pull_request(self, head, base, title, body)
+    }
+}
+
+/// This function lists every file below the folder `path` with one `list` per folder.
+pub fn list_recursively<B: RepoBackend + ?Sized>(
+    backend: &B,
+    path: &str,
+) -> ApiResult<Vec<ContentEntry>> {
+    let mut files = vec![];
+    for entry in backend.list(path)? {
+        match entry.content_type {
+            ContentType::Dir => files.append(&mut list_recursively(backend, &entry.path)?),
+            _ => files.push(entry),
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the files of the recursive git tree `entries` which are below the folder `path`,
+/// or the file `path` itself. The empty files which keep folders in git are skipped.
+fn files_in_tree(entries: Vec<TreeEntry>, path: &str) -> ApiResult<Vec<ContentEntry>> {
+    let path = path.trim_matches('/');
+    let mut found = path.is_empty();
+    let mut files = vec![];
+    for entry in entries
+        .into_iter()
+        .filter(|e| gitea::in_folder(&e.path, path))
+    {
+        found = true;
+        if entry.entry_type != "tree" && !entry.path.ends_with("/.gitkeep") {
+            files.push(ContentEntry::from(entry));
+        }
+    }
+    match found {
+        true => Ok(files),
+        false => Err(ApiError::NotFound(format!(
+            "{} doesn't exist in the remote repository",
+            path
+        ))),
+    }
+}
+
+/// This function creates the backend of the configured `provider` of the repository.
+/// The Gitea client keeps its responses in the `cache` file if provided.
+pub fn connect(
+    repo: &RepositoryConfig,
+    http: &HttpConfig,
+    cache: Option<&Path>,
+) -> Result<Box<dyn RepoBackend>> {
+    let branch = repo.branch.clone();
+    let backend: Box<dyn RepoBackend> = match repo.provider {
+        Provider::Gitea => Box::new(
+            GiteaClient::new(
+                &repo.url,
+                Some(&repo.token()?),
+                None,
+                &repo.repository,
+                &repo.owner,
+            )
+            .and_then(|c| c.with_http(http))?
+            .with_username(repo.username.clone())
+            .with_oauth(repo.oauth_session()?)
+            .with_branch(branch)
+            .with_cache(cache),
+        ),
+        Provider::GitHub => Box::new(github::GitHubClient::new(
+            &repo.url,
+            &repo.token()?,
+            &repo.owner,
+            &repo.repository,
+            branch,
+            http,
+        )?),
+        Provider::GitLab => Box::new(gitlab::GitLabClient::new(
+            &repo.url,
+            &repo.token()?,
+            &repo.owner,
+            &repo.repository,
+            branch,
+            http,
+        )?),
+        Provider::Git => {
+            let dir = cache::default_dir().ok_or_else(|| {
+                Error::Rustea("Neither XDG_CACHE_HOME nor HOME is set for the clone".into())
+            })?;
+            let name = cache::file_name(&repo.url, &repo.owner, &repo.repository);
+            let name = name.trim_end_matches(".json");
+            Box::new(
+                git::GitClient::new(&repo.url, branch, dir.join("git").join(name))
+                    .with_ssh_key(repo.ssh_key.clone()),
+            )
+        }
+    };
+    Ok(backend)
+}
+
+/// The default commit message of a change of the file `path`.
+fn commit_message(cmt_msg: Option<&str>, action: &str, path: &str) -> String {
+    match cmt_msg {
+        Some(msg) => msg.to_owned(),
+        None => format!("{} {} with rustea", action, path),
+    }
+}
+
+/// This function sends the `request` with the json `body` if provided and turns
+/// a rejection into the matching `ApiError`, see `gitea::status_error`.
+fn send(
+    request: ureq::Request,
+    body: Option<serde_json::Value>,
+    owner: &str,
+) -> ApiResult<ureq::Response> {
+    let res = match body {
+        Some(b) => request.clone().send_json(b),
+        None => request.clone().call(),
+    };
+    match res {
+        Err(ureq::Error::Status(status, r)) => {
+            let retry_after = r
+                .header("Retry-After")
+                .and_then(|s| s.parse().ok())
+                .map(std::time::Duration::from_secs);
+            Err(gitea::status_error(
+                status,
+                r,
+                request.method(),
+                request.url(),
+                owner,
+                retry_after,
+            ))
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Returns the `path` with every character except unreserved ones percent encoded,
+/// e.g. a file path as single component of an url.
+pub fn encode_component(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Returns the `path` with each of its components percent encoded, the slashes are kept.
+pub fn encode_path(path: &str) -> String {
+    path.trim_matches('/')
+        .split('/')
+        .map(encode_component)
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        commit_message, encode_component, encode_path, files_in_tree,
+        memory::{entry, Memory},
+        Provider, RepoBackend,
+    };
+    use crate::{gitea::FileChange, RepositoryConfig};
+
+    #[test]
+    fn test_default_backend() {
+        let backend = Memory::default();
+        for path in ["web/.gitkeep", "web/etc/nginx.conf", "web/etc/app.conf"] {
+            backend.upload(path, b"a\n", "ops", "ops@x", None).unwrap();
+        }
+        assert_eq!(
+            backend.file("/web/etc/nginx.conf").unwrap().name,
+            "nginx.conf"
+        );
+        assert!(backend.file("web/etc").is_err());
+        assert_eq!(backend.list_files("web").unwrap().len(), 3);
+        assert_eq!(backend.download_text("web/etc/app.conf").unwrap(), "a\n");
+        assert!(backend.latest_commit("web").unwrap().is_none());
+        let err = backend.default_branch().unwrap_err().to_string();
+        assert!(err.contains("hosted by GitHub"), "{}", err);
+
+        let changes = [
+            FileChange {
+                path: "web/etc/app.conf".into(),
+                content: None,
+                sha: Some("b2".into()),
+            },
+            FileChange {
+                path: "db/my.cnf".into(),
+                content: Some(b"port 3306\n".to_vec()),
+                sha: None,
+            },
+            FileChange {
+                path: "db/unknown.cnf".into(),
+                content: None,
+                sha: None,
+            },
+        ];
+        let commit = backend
+            .change_files(&changes, "ops", "ops@x", None)
+            .unwrap();
+        assert_eq!(commit.as_deref(), Some("c5"));
+        assert!(backend.download("web/etc/app.conf").is_err());
+
+        // Only the direct children are deleted without `recursive`
+        let commits = backend
+            .delete_folder("web", false, "ops", "ops@x", None)
+            .unwrap();
+        assert_eq!(commits, ["c6"]);
+        assert!(backend.file("web/etc/nginx.conf").is_ok());
+        let commits = backend
+            .delete_folder("web", true, "ops", "ops@x", None)
+            .unwrap();
+        assert_eq!(commits, ["c7"]);
+        assert!(backend.list("web").is_err());
+        assert_eq!(
+            backend
+                .tree()
+                .unwrap()
+                .iter()
+                .map(|e| e.path.as_str())
+                .collect::<Vec<_>>(),
+            ["db", "db/my.cnf"]
+        );
+    }
+
+    #[test]
+    fn test_files_in_tree() {
+        let tree = || {
+            vec![
+                entry("web", "tree"),
+                entry("web/.gitkeep", "blob"),
+                entry("web/etc/nginx.conf", "blob"),
+                entry("website/index.html", "blob"),
+            ]
+        };
+        let files = files_in_tree(tree(), "web").unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "web/etc/nginx.conf");
+        assert_eq!(files[0].name, "nginx.conf");
+        assert_eq!(files_in_tree(tree(), "").unwrap().len(), 2);
+        assert!(files_in_tree(tree(), "db").is_err());
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_component("ops/config"), "ops%2Fconfig");
+        assert_eq!(encode_component("a b+c.conf"), "a%20b%2Bc.conf");
+        assert_eq!(encode_path("/web/etc/a b.conf"), "web/etc/a%20b.conf");
+        assert_eq!(encode_path(""), "");
+    }
+
+    #[test]
+    fn test_provider_config() {
+        let repo: RepositoryConfig = toml::from_str(
+            "url = 'https://gitlab.com'\nrepository = 'config'\nowner = 'ops'\n\
+             email = 'ops@example.com'\nauthor = 'ops'\nprovider = 'gitlab'",
+        )
+        .unwrap();
+        assert_eq!(repo.provider, Provider::GitLab);
+        let repo = RepositoryConfig::default();
+        assert!(repo.provider.is_gitea());
+        assert!(!toml::to_string(&repo).unwrap().contains("provider"));
+        assert_eq!(
+            commit_message(None, "Update", "web/etc/nginx.conf"),
+            "Update web/etc/nginx.conf with rustea"
+        );
+    }
+}
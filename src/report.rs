@@ -0,0 +1,348 @@
+//! This file implements the drift report of the local machine.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{fmt::Display, fmt::Write, str::FromStr};
+
+use crate::state;
+
+/// The output formats of a report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("Unknown report format {}, use markdown or html", s)),
+        }
+    }
+}
+
+/// The state of a single file compared to the remote repository.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileStatus {
+    /// The local file matches the remote file.
+    InSync,
+    /// The local file doesn't exist.
+    Missing,
+    /// The local file changed since the last pull or push.
+    LocalChanges,
+    /// The remote file changed since the last pull or push.
+    RemoteChanges,
+    /// Both files changed since the last pull or push.
+    Conflict,
+    /// The files differ and were never pulled or pushed from this machine.
+    Drifted,
+    /// The local file was pulled but removed from the feature set afterwards.
+    Orphaned,
+}
+
+impl FileStatus {
+    /// This function determines the status from the blob shas of the recorded `base`,
+    /// the `local` file if it exists and the `remote` file.
+    pub fn new(base: Option<&str>, local: Option<&str>, remote: &str) -> FileStatus {
+        match (base, local) {
+            (_, None) => FileStatus::Missing,
+            (_, Some(l)) if l == remote => FileStatus::InSync,
+            (Some(b), Some(l)) if l == b => FileStatus::RemoteChanges,
+            (Some(b), Some(_)) if remote == b => FileStatus::LocalChanges,
+            (Some(_), Some(_)) => FileStatus::Conflict,
+            (None, Some(_)) => FileStatus::Drifted,
+        }
+    }
+}
+
+impl Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileStatus::InSync => write!(f, "in sync"),
+            FileStatus::Missing => write!(f, "missing"),
+            FileStatus::LocalChanges => write!(f, "pending push"),
+            FileStatus::RemoteChanges => write!(f, "pending pull"),
+            FileStatus::Conflict => write!(f, "conflict"),
+            FileStatus::Drifted => write!(f, "drifted"),
+            FileStatus::Orphaned => write!(f, "orphaned"),
+        }
+    }
+}
+
+/// The report of a single feature set.
+#[derive(Debug)]
+pub struct FeatureReport {
+    pub name: String,
+    /// The time of the last full pull, if any.
+    pub last_pull: Option<u64>,
+    /// The remote path and status of every file.
+    pub files: Vec<(String, FileStatus)>,
+}
+
+impl FeatureReport {
+    /// Returns the number of files which are not in sync.
+    fn drifted(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, s)| *s != FileStatus::InSync)
+            .count()
+    }
+
+    fn last_pull(&self) -> String {
+        self.last_pull
+            .map(state::format_timestamp)
+            .unwrap_or_else(|| "never".to_owned())
+    }
+}
+
+/// A `Report` aggregates the drift of all feature sets on one host.
+#[derive(Debug)]
+pub struct Report {
+    pub host: String,
+    /// The time the report was generated.
+    pub generated: u64,
+    pub features: Vec<FeatureReport>,
+}
+
+impl Report {
+    /// This function renders the report in the requested `format`.
+    pub fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        // Writing into a string never fails
+        let _ = match format {
+            Format::Markdown => self.markdown(&mut out),
+            Format::Html => self.html(&mut out),
+        };
+        out
+    }
+
+    /// This function returns a compact plain text summary of the report, one line per
+    /// feature set followed by the files which are not in sync, e.g. for a cron mail.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let width = self
+            .features
+            .iter()
+            .map(|fs| fs.name.len())
+            .max()
+            .unwrap_or(0);
+        for fs in &self.features {
+            let drift = match fs.drifted() {
+                0 => "in sync".to_owned(),
+                n => format!("{} not in sync", n),
+            };
+            let _ = writeln!(
+                out,
+                "{:width$}  {} files, {}, last pull {}",
+                fs.name,
+                fs.files.len(),
+                drift,
+                fs.last_pull(),
+                width = width
+            );
+            for (path, status) in fs.files.iter().filter(|(_, s)| *s != FileStatus::InSync) {
+                let _ = writeln!(out, "    {:12}  {}", status.to_string(), path);
+            }
+        }
+        let drifted = self.features.iter().filter(|fs| fs.drifted() > 0).count();
+        let _ = write!(
+            out,
+            "{} of {} feature sets drifted on {}",
+            drifted,
+            self.features.len(),
+            self.host
+        );
+        out
+    }
+
+    fn markdown(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "# rustea report for {}\n", self.host)?;
+        writeln!(
+            out,
+            "Generated at {}\n",
+            state::format_timestamp(self.generated)
+        )?;
+        writeln!(out, "| Feature set | Last pull | Files | Not in sync |")?;
+        writeln!(out, "|---|---|---|---|")?;
+        for fs in &self.features {
+            writeln!(
+                out,
+                "| {} | {} | {} | {} |",
+                fs.name,
+                fs.last_pull(),
+                fs.files.len(),
+                fs.drifted()
+            )?;
+        }
+        for fs in self.features.iter().filter(|fs| fs.drifted() > 0) {
+            writeln!(out, "\n## {}\n", fs.name)?;
+            writeln!(out, "| File | Status |")?;
+            writeln!(out, "|---|---|")?;
+            for (path, status) in fs.files.iter().filter(|(_, s)| *s != FileStatus::InSync) {
+                writeln!(out, "| `{}` | {} |", path, status)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn html(&self, out: &mut String) -> fmt::Result {
+        let host = escape(&self.host);
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+        )?;
+        writeln!(
+            out,
+            "<title>rustea report for {}</title>\n</head>\n<body>",
+            host
+        )?;
+        writeln!(out, "<h1>rustea report for {}</h1>", host)?;
+        writeln!(
+            out,
+            "<p>Generated at {}</p>",
+            state::format_timestamp(self.generated)
+        )?;
+        writeln!(out, "<table>\n<tr><th>Feature set</th><th>Last pull</th><th>Files</th><th>Not in sync</th></tr>")?;
+        for fs in &self.features {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&fs.name),
+                fs.last_pull(),
+                fs.files.len(),
+                fs.drifted()
+            )?;
+        }
+        writeln!(out, "</table>")?;
+        for fs in self.features.iter().filter(|fs| fs.drifted() > 0) {
+            writeln!(out, "<h2>{}</h2>", escape(&fs.name))?;
+            writeln!(out, "<table>\n<tr><th>File</th><th>Status</th></tr>")?;
+            for (path, status) in fs.files.iter().filter(|(_, s)| *s != FileStatus::InSync) {
+                writeln!(
+                    out,
+                    "<tr><td><code>{}</code></td><td>{}</td></tr>",
+                    escape(path),
+                    status
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+        writeln!(out, "</body>\n</html>")
+    }
+}
+
+/// Escape the characters with a special meaning in html.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// This function returns the name of the local machine.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    match res {
+        0 => String::from_utf8_lossy(&buf[..len]).into_owned(),
+        _ => "localhost".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeatureReport, FileStatus, Format, Report};
+
+    #[test]
+    fn test_file_status() {
+        assert_eq!(FileStatus::new(None, None, "a"), FileStatus::Missing);
+        assert_eq!(FileStatus::new(None, Some("a"), "a"), FileStatus::InSync);
+        assert_eq!(FileStatus::new(None, Some("b"), "a"), FileStatus::Drifted);
+        assert_eq!(
+            FileStatus::new(Some("b"), Some("b"), "a"),
+            FileStatus::RemoteChanges
+        );
+        assert_eq!(
+            FileStatus::new(Some("a"), Some("b"), "a"),
+            FileStatus::LocalChanges
+        );
+        assert_eq!(
+            FileStatus::new(Some("c"), Some("b"), "a"),
+            FileStatus::Conflict
+        );
+    }
+
+    #[test]
+    fn test_render_report() {
+        let report = Report {
+            host: "web<1>".into(),
+            generated: 0,
+            features: vec![FeatureReport {
+                name: "nginx".into(),
+                last_pull: None,
+                files: vec![
+                    ("nginx/etc/nginx.conf".into(), FileStatus::Drifted),
+                    ("nginx/etc/mime.types".into(), FileStatus::InSync),
+                ],
+            }],
+        };
+        let md = report.render(Format::Markdown);
+        assert!(md.contains("| nginx | never | 2 | 1 |"));
+        assert!(md.contains("| `nginx/etc/nginx.conf` | drifted |"));
+        assert!(!md.contains("mime.types"));
+
+        let html = report.render(Format::Html);
+        assert!(html.contains("<h1>rustea report for web&lt;1&gt;</h1>"));
+        assert!(html.contains("<td><code>nginx/etc/nginx.conf</code></td><td>drifted</td>"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let report = Report {
+            host: "web1".into(),
+            generated: 0,
+            features: vec![
+                FeatureReport {
+                    name: "nginx".into(),
+                    last_pull: None,
+                    files: vec![
+                        ("nginx/etc/nginx.conf".into(), FileStatus::RemoteChanges),
+                        ("nginx/etc/mime.types".into(), FileStatus::InSync),
+                        ("nginx/etc/old.conf".into(), FileStatus::Orphaned),
+                    ],
+                },
+                FeatureReport {
+                    name: "base".into(),
+                    last_pull: Some(0),
+                    files: vec![("base/etc/motd".into(), FileStatus::InSync)],
+                },
+            ],
+        };
+        assert_eq!(
+            report.summary(),
+            "nginx  3 files, 2 not in sync, last pull never\n\
+             \x20   pending pull  nginx/etc/nginx.conf\n\
+             \x20   orphaned      nginx/etc/old.conf\n\
+             base   1 files, in sync, last pull 1970-01-01T00:00:00Z\n\
+             1 of 2 feature sets drifted on web1"
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! This file implements the scheduling of unattended pulls and applies with systemd or cron.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use crate::error::{Error, Result};
+
+/// The rustea command which is run periodically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Job {
+    /// `rustea pull --assigned`, scheduled by `rustea schedule install`
+    Pull,
+    /// `rustea apply`, scheduled by `rustea install-service`
+    Apply,
+}
+
+impl Job {
+    /// Every job, which `status` and `remove` look for.
+    const ALL: [Job; 2] = [Job::Pull, Job::Apply];
+
+    /// Returns the arguments of the rustea command.
+    fn args(&self) -> &'static str {
+        match self {
+            Job::Pull => "pull --assigned",
+            Job::Apply => "apply",
+        }
+    }
+
+    /// Returns the name of the generated systemd units.
+    fn unit_name(&self) -> &'static str {
+        match self {
+            Job::Pull => "rustea-pull",
+            Job::Apply => "rustea-apply",
+        }
+    }
+
+    /// Returns the marker which identifies the crontab entry of the job.
+    fn cron_marker(&self) -> &'static str {
+        match self {
+            Job::Pull => "# rustea-schedule",
+            Job::Apply => "# rustea-apply",
+        }
+    }
+
+    /// Returns the verb of the job used in the descriptions of the units.
+    fn verb(&self) -> &'static str {
+        match self {
+            Job::Pull => "Pull",
+            Job::Apply => "Apply",
+        }
+    }
+}
+
+/// The scheduler used to run rustea periodically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Systemd,
+    Cron,
+}
+
+impl Backend {
+    /// This function prefers systemd if the system is booted with it, otherwise cron is used.
+    pub fn detect(cron: bool) -> Backend {
+        match !cron && Path::new("/run/systemd/system").is_dir() {
+            true => Backend::Systemd,
+            false => Backend::Cron,
+        }
+    }
+}
+
+/// This function parses an interval like `30s`, `15m`, `2h` or `1d`.
+/// A number without unit is interpreted as minutes.
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let interval = interval.trim();
+    let (num, unit) = match interval.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => interval.split_at(i),
+        None => (interval, "m"),
+    };
+    let factor = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match num.parse::<u64>() {
+        Ok(n) if n > 0 && factor > 0 => Ok(Duration::from_secs(n * factor)),
+        _ => Err(Error::Rustea(format!("Invalid interval {}", interval))),
+    }
+}
+
+/// This function builds the command line of the `job` which is executed by the scheduler.
+/// The configuration file `config` or the default one is passed explicitly.
+fn scheduled_command(job: Job, config: Option<&str>) -> Result<String> {
+    let exe = env::current_exe()?;
+    let config = match config {
+        Some(c) => PathBuf::from(c),
+        None => PathBuf::from(crate::get_default_path()?),
+    };
+    Ok(format!(
+        "{} -c {} {}",
+        exe.display(),
+        config.canonicalize()?.display(),
+        job.args()
+    ))
+}
+
+/// Returns true if the current user is root.
+fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// The folder of the systemd units, system wide for root and the user folder otherwise.
+fn unit_folder() -> Result<PathBuf> {
+    match is_root() {
+        true => Ok(PathBuf::from("/etc/systemd/system")),
+        false => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config/systemd/user"))
+            .ok_or_else(|| Error::Rustea("No home directory found".into())),
+    }
+}
+
+/// Run `systemctl` for the system or user instance.
+fn systemctl(args: &[&str]) -> Result<String> {
+    let mut cmd = Command::new("systemctl");
+    if !is_root() {
+        cmd.arg("--user");
+    }
+    let out = cmd.args(args).output()?;
+    let stdout = String::from_utf8_lossy(&out.stdout).into_owned();
+    match out.status.success() {
+        true => Ok(stdout),
+        false => Err(Error::Rustea(format!(
+            "systemctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+/// This function generates the systemd service of the `job` which runs `command`.
+fn service_unit(job: Job, command: &str) -> String {
+    format!(
+        "[Unit]\nDescription={} the feature sets assigned to this host with rustea\n\
+         Wants=network-online.target\nAfter=network-online.target\n\n\
+         [Service]\nType=oneshot\nExecStart={}\n",
+        job.verb(),
+        command
+    )
+}
+
+/// This function generates the systemd timer which starts the service of the `job`
+/// every `interval`.
+fn timer_unit(job: Job, interval: Duration) -> String {
+    format!(
+        "[Unit]\nDescription=Periodic rustea {}\n\n\
+         [Timer]\nOnBootSec=1min\nOnUnitActiveSec={}s\nPersistent=true\n\n\
+         [Install]\nWantedBy=timers.target\n",
+        job.verb().to_lowercase(),
+        interval.as_secs()
+    )
+}
+
+/// This function converts an `interval` into a cron schedule.
+/// Cron only supports intervals which evenly divide an hour or a day.
+fn cron_schedule(interval: Duration) -> Result<String> {
+    let min = interval.as_secs() / 60;
+    match min {
+        m if interval.as_secs().is_multiple_of(60) && m > 0 && m < 60 && 60 % m == 0 => {
+            Ok(format!("*/{} * * * *", m))
+        }
+        m if m > 0 && m.is_multiple_of(60) && m / 60 < 24 && 24 % (m / 60) == 0 => {
+            Ok(format!("0 */{} * * *", m / 60))
+        }
+        m if m == 24 * 60 => Ok("0 0 * * *".to_owned()),
+        _ => Err(Error::Rustea(format!(
+            "The interval of {}s can not be expressed with cron",
+            interval.as_secs()
+        ))),
+    }
+}
+
+/// Read the crontab of the current user without the entries of the `jobs`.
+fn crontab_without_entry(jobs: &[Job]) -> Result<Vec<String>> {
+    let out = Command::new("crontab").arg("-l").output()?;
+    // An error means that the user has no crontab yet
+    let tab = String::from_utf8_lossy(&out.stdout).into_owned();
+    Ok(tab
+        .lines()
+        .filter(|l| !jobs.iter().any(|j| l.ends_with(j.cron_marker())))
+        .map(String::from)
+        .collect())
+}
+
+/// Replace the crontab of the current user with `lines`.
+fn write_crontab(lines: &[String]) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        for line in lines {
+            writeln!(stdin, "{}", line)?;
+        }
+    }
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea("Failed to install the crontab".into())),
+    }
+}
+
+/// This function installs the periodic `job` with the `backend`.
+/// The configuration file `config` is passed to the scheduled rustea if provided.
+pub fn install(
+    backend: Backend,
+    job: Job,
+    interval: Duration,
+    config: Option<&str>,
+) -> Result<String> {
+    let command = scheduled_command(job, config)?;
+    let unit = job.unit_name();
+    match backend {
+        Backend::Systemd => {
+            let folder = unit_folder()?;
+            fs::create_dir_all(&folder)?;
+            fs::write(
+                folder.join(format!("{}.service", unit)),
+                service_unit(job, &command),
+            )?;
+            fs::write(
+                folder.join(format!("{}.timer", unit)),
+                timer_unit(job, interval),
+            )?;
+            systemctl(&["daemon-reload"])?;
+            systemctl(&["enable", "--now", &format!("{}.timer", unit)])?;
+            Ok(format!(
+                "Installed systemd timer {}.timer in {}",
+                unit,
+                folder.display()
+            ))
+        }
+        Backend::Cron => {
+            let mut lines = crontab_without_entry(&[job])?;
+            lines.push(format!(
+                "{} {} {}",
+                cron_schedule(interval)?,
+                command,
+                job.cron_marker()
+            ));
+            write_crontab(&lines)?;
+            Ok("Installed crontab entry".to_owned())
+        }
+    }
+}
+
+/// This function reports if and how the periodic pull and apply are scheduled.
+pub fn status(backend: Backend) -> Result<String> {
+    match backend {
+        Backend::Systemd => {
+            let folder = unit_folder()?;
+            let timers: Vec<String> = Job::ALL
+                .iter()
+                .map(|j| format!("{}.timer", j.unit_name()))
+                .filter(|t| folder.join(t).exists())
+                .collect();
+            match timers.is_empty() {
+                false => {
+                    let mut args = vec!["list-timers", "--all"];
+                    args.extend(timers.iter().map(String::as_str));
+                    systemctl(&args)
+                }
+                true => Ok("No systemd timer installed".to_owned()),
+            }
+        }
+        Backend::Cron => {
+            let out = Command::new("crontab").arg("-l").output()?;
+            let entries: Vec<String> = String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .filter(|l| Job::ALL.iter().any(|j| l.ends_with(j.cron_marker())))
+                .map(String::from)
+                .collect();
+            match entries.is_empty() {
+                false => Ok(entries.join("\n")),
+                true => Ok("No crontab entry installed".to_owned()),
+            }
+        }
+    }
+}
+
+/// This function removes the periodic pull and apply installed by `install`.
+pub fn remove(backend: Backend) -> Result<String> {
+    match backend {
+        Backend::Systemd => {
+            let folder = unit_folder()?;
+            let mut removed = vec![];
+            for unit in Job::ALL.iter().map(Job::unit_name) {
+                let timer = folder.join(format!("{}.timer", unit));
+                if !timer.exists() {
+                    continue;
+                }
+                systemctl(&["disable", "--now", &format!("{}.timer", unit)])?;
+                fs::remove_file(timer)?;
+                fs::remove_file(folder.join(format!("{}.service", unit)))?;
+                removed.push(format!("{}.timer", unit));
+            }
+            if removed.is_empty() {
+                return Ok("No systemd timer installed".to_owned());
+            }
+            systemctl(&["daemon-reload"])?;
+            Ok(format!("Removed systemd timer {}", removed.join(", ")))
+        }
+        Backend::Cron => {
+            write_crontab(&crontab_without_entry(&Job::ALL)?)?;
+            Ok("Removed crontab entry".to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{cron_schedule, parse_interval, scheduled_command, service_unit, timer_unit, Job};
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_interval("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_interval("30").unwrap(), Duration::from_secs(1800));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("5w").is_err());
+        assert!(parse_interval("m").is_err());
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        let min = |m: u64| Duration::from_secs(m * 60);
+        assert_eq!(cron_schedule(min(15)).unwrap(), "*/15 * * * *");
+        assert_eq!(cron_schedule(min(120)).unwrap(), "0 */2 * * *");
+        assert_eq!(cron_schedule(min(24 * 60)).unwrap(), "0 0 * * *");
+        assert!(cron_schedule(min(7)).is_err());
+        assert!(cron_schedule(Duration::from_secs(30)).is_err());
+    }
+
+    #[test]
+    fn test_scheduled_command() {
+        let config = std::path::Path::new("Cargo.toml").canonicalize().unwrap();
+        let command = scheduled_command(Job::Pull, Some("Cargo.toml")).unwrap();
+        assert!(command.ends_with(&format!(" -c {} pull --assigned", config.display())));
+        let command = scheduled_command(Job::Apply, Some("Cargo.toml")).unwrap();
+        assert!(command.ends_with(&format!(" -c {} apply", config.display())));
+
+        assert!(service_unit(Job::Pull, &command).contains("Description=Pull the feature sets"));
+        let timer = timer_unit(Job::Apply, Duration::from_secs(900));
+        assert!(timer.contains("Description=Periodic rustea apply\n"));
+        assert!(timer.contains("OnUnitActiveSec=900s\n"));
+    }
+}
@@ -0,0 +1,298 @@
+//! This file implements the encryption of secret files within feature sets with age or GnuPG.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    glob::Pattern,
+    temp::TempDir,
+};
+
+/// The tool which encrypts and decrypts the secret files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretBackend {
+    /// `age` with the public keys of the `recipients` and the `identity` for decryption
+    #[default]
+    Age,
+    /// `gpg` with the keys of the `recipients` or a symmetric passphrase
+    Gpg,
+}
+
+/// The `[secrets]` section of the configuration.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Secrets {
+    /// Glob patterns of the files which are encrypted, e.g. `*.secret`
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub backend: SecretBackend,
+    /// The age recipients or gpg key ids the files are encrypted for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+    /// The age identity file used to decrypt the files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<PathBuf>,
+    /// A shell command which prints the symmetric passphrase, only used by gpg
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_command: Option<String>,
+}
+
+impl Secrets {
+    /// Returns true if no file is encrypted.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns the suffix of the encrypted files in the remote repository.
+    pub fn suffix(&self) -> &'static str {
+        match self.backend {
+            SecretBackend::Age => ".age",
+            SecretBackend::Gpg => ".gpg",
+        }
+    }
+
+    /// This function compiles the patterns which select the secret files.
+    pub fn files(&self) -> Result<SecretFiles> {
+        Ok(SecretFiles {
+            patterns: Pattern::all(&self.patterns)?,
+            suffix: self.suffix(),
+        })
+    }
+
+    /// Returns the program and arguments which encrypt stdin to stdout.
+    fn encrypt_args(&self, passphrase_file: Option<&Path>) -> Result<(&'static str, Vec<String>)> {
+        let recipients = |flag: &str| {
+            self.recipients
+                .iter()
+                .flat_map(|r| vec![flag.to_owned(), r.clone()])
+                .collect::<Vec<String>>()
+        };
+        match (self.backend, self.recipients.is_empty(), passphrase_file) {
+            (SecretBackend::Age, false, _) => Ok(("age", recipients("-r"))),
+            (SecretBackend::Age, true, _) => Err(Error::Rustea(
+                "age needs recipients to encrypt secret files".into(),
+            )),
+            (SecretBackend::Gpg, false, _) => {
+                let mut args = vec!["--batch".into(), "--yes".into(), "--encrypt".into()];
+                args.append(&mut recipients("-r"));
+                Ok(("gpg", args))
+            }
+            (SecretBackend::Gpg, true, Some(file)) => {
+                let mut args = gpg_passphrase_args(file);
+                args.push("--symmetric".into());
+                Ok(("gpg", args))
+            }
+            (SecretBackend::Gpg, true, None) => Err(Error::Rustea(
+                "gpg needs recipients or a passphrase_command to encrypt secret files".into(),
+            )),
+        }
+    }
+
+    /// Returns the program and arguments which decrypt stdin to stdout.
+    fn decrypt_args(&self, passphrase_file: Option<&Path>) -> Result<(&'static str, Vec<String>)> {
+        match (self.backend, &self.identity, passphrase_file) {
+            (SecretBackend::Age, Some(identity), _) => Ok((
+                "age",
+                vec!["-d".into(), "-i".into(), identity.display().to_string()],
+            )),
+            (SecretBackend::Age, None, _) => Err(Error::Rustea(
+                "age needs an identity to decrypt secret files".into(),
+            )),
+            (SecretBackend::Gpg, _, file) => {
+                let mut args = match file {
+                    Some(file) => gpg_passphrase_args(file),
+                    None => vec!["--batch".into()],
+                };
+                args.push("--quiet".into());
+                args.push("--decrypt".into());
+                Ok(("gpg", args))
+            }
+        }
+    }
+
+    /// This function writes the passphrase of the `passphrase_command` into a private
+    /// temporary file which is passed to gpg.
+    fn passphrase(&self) -> Result<Option<(TempDir, PathBuf)>> {
+        match (self.backend, &self.passphrase_command) {
+            (SecretBackend::Gpg, Some(cmd)) => {
+                let passphrase = run("sh", &["-c".to_owned(), cmd.clone()], &[])?;
+                let tmp = TempDir::new("rustea-secret")?;
+                let file = tmp.write("passphrase", &passphrase)?;
+                Ok(Some((tmp, file)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// This function encrypts the `content` of a secret file.
+    pub fn encrypt(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let passphrase = self.passphrase()?;
+        let (program, args) = self.encrypt_args(passphrase.as_ref().map(|(_, f)| f.as_path()))?;
+        run(program, &args, content)
+    }
+
+    /// This function decrypts the `content` of a secret file.
+    pub fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let passphrase = self.passphrase()?;
+        let (program, args) = self.decrypt_args(passphrase.as_ref().map(|(_, f)| f.as_path()))?;
+        run(program, &args, content)
+    }
+}
+
+fn gpg_passphrase_args(file: &Path) -> Vec<String> {
+    vec![
+        "--batch".into(),
+        "--pinentry-mode".into(),
+        "loopback".into(),
+        "--passphrase-file".into(),
+        file.display().to_string(),
+    ]
+}
+
+/// This function runs the `program` with the `input` on stdin and returns its stdout.
+fn run(program: &str, args: &[String], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Rustea(format!("Failed to run {}: {}", program, e)))?;
+    // Write in another thread, a large input would block while stdout is not read
+    let mut stdin = child.stdin.take();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || match stdin.as_mut() {
+        Some(stdin) => stdin.write_all(&input),
+        None => Ok(()),
+    });
+    let out = child.wait_with_output()?;
+    let written = writer.join();
+    match (out.status.success(), written) {
+        (true, Ok(Ok(()))) => Ok(out.stdout),
+        _ => Err(Error::Rustea(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+/// The `SecretFiles` select the files which are encrypted in the remote repository.
+#[derive(Debug, Default, Clone)]
+pub struct SecretFiles {
+    patterns: Vec<Pattern>,
+    suffix: &'static str,
+}
+
+impl SecretFiles {
+    /// Returns true if the local `path` is a secret file which is encrypted on push.
+    pub fn matches(&self, path: &Path) -> bool {
+        crate::glob::is_excluded(&self.patterns, path)
+    }
+
+    /// Returns the remote `path` with the suffix of encrypted files.
+    pub fn encrypted_path(&self, path: &str) -> String {
+        format!("{}{}", path, self.suffix)
+    }
+
+    /// Returns the remote `path` without the suffix of encrypted files if it has one.
+    /// Whether it is a secret file depends on its local path, see `matches`.
+    pub fn strip_suffix<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match self.patterns.is_empty() {
+            true => None,
+            false => path.strip_suffix(self.suffix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{SecretBackend, Secrets};
+
+    #[test]
+    fn test_secret_files() {
+        let secrets = Secrets {
+            patterns: vec!["*.secret".into()],
+            ..Default::default()
+        };
+        let files = secrets.files().unwrap();
+        assert!(files.matches(Path::new("/etc/app/db.secret")));
+        assert!(!files.matches(Path::new("/etc/app/db.conf")));
+        assert_eq!(
+            files.encrypted_path("/etc/app/db.secret"),
+            "/etc/app/db.secret.age"
+        );
+        assert_eq!(
+            files.strip_suffix("app/etc/app/db.secret.age"),
+            Some("app/etc/app/db.secret")
+        );
+        assert_eq!(files.strip_suffix("app/etc/app/db.secret"), None);
+        assert_eq!(
+            Secrets::default().files().unwrap().strip_suffix("a.age"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_secret_commands() {
+        let mut secrets = Secrets {
+            patterns: vec!["*.secret".into()],
+            recipients: vec!["age1abc".into(), "age1def".into()],
+            ..Default::default()
+        };
+        let (program, args) = secrets.encrypt_args(None).unwrap();
+        assert_eq!(program, "age");
+        assert_eq!(args, vec!["-r", "age1abc", "-r", "age1def"]);
+        assert!(secrets.decrypt_args(None).is_err());
+        secrets.identity = Some("/root/.age/key.txt".into());
+        assert_eq!(
+            secrets.decrypt_args(None).unwrap().1,
+            vec!["-d", "-i", "/root/.age/key.txt"]
+        );
+
+        secrets.backend = SecretBackend::Gpg;
+        secrets.recipients.clear();
+        assert!(secrets.encrypt_args(None).is_err());
+        let (program, args) = secrets.encrypt_args(Some(Path::new("/tmp/pw"))).unwrap();
+        assert_eq!(program, "gpg");
+        assert_eq!(
+            args,
+            vec![
+                "--batch",
+                "--pinentry-mode",
+                "loopback",
+                "--passphrase-file",
+                "/tmp/pw",
+                "--symmetric"
+            ]
+        );
+        assert_eq!(
+            secrets.decrypt_args(None).unwrap().1,
+            vec!["--batch", "--quiet", "--decrypt"]
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! This file implements the reload of the systemd units whose files were changed by a pull.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use core::fmt;
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf, process::Command};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    glob::Pattern,
+};
+
+/// The action which applies the changed files to a unit, a restart includes a reload.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Reload,
+    Restart,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Reload => write!(f, "reload"),
+            Action::Restart => write!(f, "restart"),
+        }
+    }
+}
+
+/// A service of the metadata sidecar, e.g. `nginx.service` for the files below `/etc/nginx`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Service {
+    /// The systemd unit, e.g. `nginx.service`
+    pub unit: String,
+    #[serde(default)]
+    pub action: Action,
+    /// Glob patterns of the local files used by the unit, see `glob::Pattern`
+    pub files: Vec<String>,
+}
+
+/// This function adds the units of the `services` which use one of the changed `files`
+/// to `affected`. A unit which needs a restart for one file is restarted instead of reloaded.
+pub fn collect(
+    services: &[Service],
+    files: &[PathBuf],
+    affected: &mut BTreeMap<String, Action>,
+) -> Result<()> {
+    for service in services {
+        let patterns = Pattern::all(&service.files)?;
+        if files
+            .iter()
+            .any(|file| patterns.iter().any(|p| p.matches(file)))
+        {
+            let action = affected.entry(service.unit.clone()).or_default();
+            *action = (*action).max(service.action);
+        }
+    }
+    Ok(())
+}
+
+/// This function runs `systemctl` with the `action` for the `unit`.
+pub fn run(unit: &str, action: Action) -> Result<()> {
+    let out = Command::new("systemctl")
+        .arg(action.to_string())
+        .arg(unit)
+        .output()
+        .map_err(|e| Error::Rustea(format!("Failed to run systemctl: {}", e)))?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "Failed to {} {}: {}",
+            action,
+            unit,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use super::{collect, Action, Service};
+
+    #[test]
+    fn test_collect_services() {
+        let services = vec![
+            Service {
+                unit: "nginx.service".into(),
+                action: Action::Reload,
+                files: vec!["/etc/nginx/**".into()],
+            },
+            Service {
+                unit: "nginx.service".into(),
+                action: Action::Restart,
+                files: vec!["/etc/default/nginx".into()],
+            },
+            Service {
+                unit: "sshd.service".into(),
+                action: Action::Reload,
+                files: vec!["sshd_config".into()],
+            },
+        ];
+        let mut affected = BTreeMap::new();
+        collect(
+            &services,
+            &[PathBuf::from("/etc/nginx/nginx.conf")],
+            &mut affected,
+        )
+        .unwrap();
+        assert_eq!(
+            affected,
+            BTreeMap::from([("nginx.service".into(), Action::Reload)])
+        );
+        let files = [
+            PathBuf::from("/etc/default/nginx"),
+            PathBuf::from("/etc/hosts"),
+        ];
+        collect(&services, &files, &mut affected).unwrap();
+        assert_eq!(affected["nginx.service"], Action::Restart);
+        assert!(!affected.contains_key("sshd.service"));
+    }
+}
@@ -0,0 +1,145 @@
+//! This file implements reading and changing single settings of the configuration file.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{fs, path::Path};
+
+use toml::{value::Table, Value};
+
+use crate::{
+    error::{Error, Result},
+    glob::Pattern,
+    scan::Scanner,
+    RusteaConfiguration,
+};
+
+/// This function reads the configuration file at `path` as plain TOML.
+pub fn read(path: &Path) -> Result<Value> {
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// This function validates the `config` and writes it to `path`.
+/// Settings unknown to this version of rustea are kept.
+pub fn write(path: &Path, config: &Value) -> Result<()> {
+    validate(config)?;
+    fs::write(path, toml::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+/// Returns the setting with the dotted `key`, e.g. `repo.email`.
+/// Strings are returned without quotes, everything else in the TOML notation.
+pub fn get(config: &Value, key: &str) -> Result<String> {
+    let value = key
+        .split('.')
+        .try_fold(config, |v, k| v.get(k))
+        .ok_or_else(|| Error::Rustea(format!("No setting named {}", key)))?;
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string().trim_end().to_owned(),
+    })
+}
+
+/// This function changes the setting with the dotted `key` to `value`, missing
+/// tables are created. The `value` is parsed as TOML, e.g. `true` or `['.git']`,
+/// and is kept as string if it is no valid TOML or replaces a string.
+pub fn set(config: &mut Value, key: &str, value: &str) -> Result<()> {
+    let invalid = || Error::Rustea(format!("Invalid setting {}", key));
+    let (tables, name) = match key.rsplit_once('.') {
+        Some((tables, name)) => (tables.split('.').collect(), name),
+        None => (vec![], key),
+    };
+    if name.is_empty() || tables.iter().any(|t| t.is_empty()) {
+        return Err(invalid());
+    }
+    let mut table = config.as_table_mut().ok_or_else(invalid)?;
+    for t in tables {
+        table = table
+            .entry(t)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(invalid)?;
+    }
+    let value = match table.get(name) {
+        Some(Value::String(_)) => Value::String(value.to_owned()),
+        _ => parse_value(value),
+    };
+    table.insert(name.to_owned(), value);
+    Ok(())
+}
+
+/// Parses a single TOML `value` or returns it as string.
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+/// This function checks that the `config` is a valid configuration, including
+/// its exclude patterns, secret patterns and secret scan rules.
+pub fn validate(config: &Value) -> Result<RusteaConfiguration> {
+    let conf: RusteaConfiguration = config.clone().try_into()?;
+    Pattern::all(&conf.exclude)?;
+    conf.secrets.files()?;
+    Scanner::new(&conf.scan_rules)?;
+    Ok(conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get, set, validate};
+
+    const CONFIG: &str = "script_folder = '/usr/local/bin'\nexclude = ['.git']\n\
+        [repo]\nurl = 'https://gitea'\nrepository = 'conf'\nowner = 'ops'\n\
+        email = ''\nauthor = ''\n";
+
+    #[test]
+    fn test_get_and_set() {
+        let mut config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(get(&config, "repo.owner").unwrap(), "ops");
+        assert_eq!(get(&config, "exclude").unwrap(), "[\".git\"]");
+        assert!(get(&config, "repo.branch").is_err());
+
+        set(&mut config, "repo.email", "ops@example.com").unwrap();
+        set(&mut config, "repo.author", "42").unwrap();
+        set(&mut config, "etckeeper", "true").unwrap();
+        set(&mut config, "vars.port", "8080").unwrap();
+        set(&mut config, "difftool", "vimdiff -d").unwrap();
+        assert!(set(&mut config, "repo.url.host", "gitea").is_err());
+        assert!(set(&mut config, "repo..url", "gitea").is_err());
+
+        assert_eq!(get(&config, "repo.email").unwrap(), "ops@example.com");
+        assert_eq!(get(&config, "difftool").unwrap(), "vimdiff -d");
+        let conf = validate(&config);
+        // vars only holds strings
+        assert!(conf.is_err());
+        set(&mut config, "vars.port", "'8080'").unwrap();
+        let conf = validate(&config).unwrap();
+        assert_eq!(conf.repo.author, "42");
+        assert!(conf.etckeeper);
+        assert_eq!(conf.vars["port"], "8080");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = toml::from_str(CONFIG).unwrap();
+        assert!(validate(&config).is_ok());
+        set(&mut config, "scan_rules.broken", "'('").unwrap();
+        assert!(validate(&config).is_err());
+        let mut config = toml::from_str(CONFIG).unwrap();
+        set(&mut config, "etckeeper", "yes").unwrap();
+        assert!(validate(&config).is_err());
+    }
+}
@@ -0,0 +1,326 @@
+//! This file implements the staging of pulled files which are moved into place at once.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    fs,
+    os::unix::fs::{chown, symlink, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{error::Result, temp::TempDir};
+
+/// The `Staging` area holds the content of pulled files until all of them are
+/// downloaded and verified. Afterwards they are moved to their targets in one step.
+#[derive(Debug)]
+pub struct Staging {
+    dir: TempDir,
+    /// The staged file and its target
+    files: Vec<(Staged, PathBuf)>,
+}
+
+/// The content of a target within the staging area.
+#[derive(Debug)]
+enum Staged {
+    /// A file with the staged content
+    File(PathBuf),
+    /// A symbolic link to the path
+    Symlink(PathBuf),
+}
+
+impl Staging {
+    /// Create a new and empty staging area.
+    pub fn new() -> Result<Staging> {
+        Ok(Staging {
+            dir: TempDir::new("rustea-staging")?,
+            files: vec![],
+        })
+    }
+
+    /// This function stages the `content` which is later written to `target`.
+    /// A symbolic link as `target` is resolved, so the linked file is written.
+    pub fn add(&mut self, target: &Path, content: &[u8]) -> Result<()> {
+        let staged = self.dir.write(&self.files.len().to_string(), content)?;
+        let target = fs::canonicalize(target).unwrap_or_else(|_| target.to_path_buf());
+        self.files.push((Staged::File(staged), target));
+        Ok(())
+    }
+
+    /// This function stages a symbolic link to `link` which later replaces `target`.
+    /// Unlike with `add`, a symbolic link as `target` is replaced itself.
+    pub fn add_symlink(&mut self, target: &Path, link: &Path) {
+        self.files
+            .push((Staged::Symlink(link.to_path_buf()), target.to_path_buf()));
+    }
+
+    /// This function moves the staged files to their targets.
+    ///
+    /// First every file is copied next to its target, missing parent folders are created
+    /// and `prepare` is called with the index and path of the copy to set permissions
+    /// or attributes. Existing targets keep their permissions and owner. Symbolic links
+    /// are created next to their target and `prepare` is not called for them. Afterwards
+    /// the copies are renamed to their targets. If any step fails the targets written
+    /// so far are restored and the created copies and folders are removed.
+    pub fn commit<F>(&self, prepare: F) -> Result<()>
+    where
+        F: FnMut(usize, &Path) -> Result<()>,
+    {
+        self.commit_checked(prepare, |_| Ok(()))
+    }
+
+    /// This function moves the staged files to their targets like `commit`. Before the
+    /// first target is replaced, `check` is called with the targets and prepared copies
+    /// of the files and if it fails nothing is changed.
+    pub fn commit_checked<F, C>(&self, mut prepare: F, check: C) -> Result<()>
+    where
+        F: FnMut(usize, &Path) -> Result<()>,
+        C: FnOnce(&[(&Path, &Path)]) -> Result<()>,
+    {
+        let mut rollback = Rollback::default();
+        let mut copies = vec![];
+        for (i, (staged, target)) in self.files.iter().enumerate() {
+            let copy = match staged {
+                Staged::File(staged) => rollback.copy(staged, target).and_then(|copy| {
+                    prepare(i, &copy)?;
+                    Ok(copy)
+                }),
+                Staged::Symlink(link) => rollback.link(link, target),
+            };
+            match copy {
+                Ok(copy) => copies.push(copy),
+                Err(e) => {
+                    rollback.undo();
+                    return Err(e);
+                }
+            }
+        }
+
+        let checked: Vec<(&Path, &Path)> = self
+            .files
+            .iter()
+            .zip(copies.iter())
+            .filter(|((staged, _), _)| matches!(staged, Staged::File(_)))
+            .map(|((_, target), copy)| (target.as_path(), copy.as_path()))
+            .collect();
+        if let Err(e) = check(&checked) {
+            rollback.undo();
+            return Err(e);
+        }
+
+        for (copy, target) in copies.iter().zip(self.files.iter().map(|f| &f.1)) {
+            if let Err(e) = rollback.replace(copy, target) {
+                rollback.undo();
+                return Err(e);
+            }
+        }
+        rollback.finish();
+        Ok(())
+    }
+}
+
+/// The changes of a commit which are reverted if it fails.
+#[derive(Debug, Default)]
+struct Rollback {
+    /// Created folders
+    dirs: Vec<PathBuf>,
+    /// Copies which are not yet moved to their target
+    copies: Vec<PathBuf>,
+    /// Replaced targets and the backup of their former content
+    replaced: Vec<(PathBuf, Option<PathBuf>)>,
+}
+
+/// Returns a path next to `target` with the `suffix`, within the same file system.
+fn sibling(target: &Path, suffix: &str) -> PathBuf {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    target.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+impl Rollback {
+    /// Create the missing parent folders of the `target`.
+    fn create_parents(&mut self, target: &Path) -> Result<()> {
+        if let Some(parent) = target.parent() {
+            let mut missing: Vec<&Path> = parent.ancestors().take_while(|p| !p.exists()).collect();
+            while let Some(dir) = missing.pop() {
+                fs::create_dir(dir)?;
+                self.dirs.push(dir.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the `staged` file next to the `target` and create its missing parent folders.
+    fn copy(&mut self, staged: &Path, target: &Path) -> Result<PathBuf> {
+        self.create_parents(target)?;
+        let copy = sibling(target, "rustea-new");
+        fs::copy(staged, &copy)?;
+        self.copies.push(copy.clone());
+        if let Ok(meta) = fs::metadata(target) {
+            fs::set_permissions(&copy, meta.permissions())?;
+            // Only root may change the owner, other users keep their own files
+            let _ = chown(&copy, Some(meta.uid()), Some(meta.gid()));
+        }
+        Ok(copy)
+    }
+
+    /// Create a symbolic link to `link` next to the `target` and its missing parent folders.
+    fn link(&mut self, link: &Path, target: &Path) -> Result<PathBuf> {
+        self.create_parents(target)?;
+        let copy = sibling(target, "rustea-new");
+        symlink(link, &copy)?;
+        self.copies.push(copy.clone());
+        Ok(copy)
+    }
+
+    /// Move the `copy` to the `target` and keep a backup of the former target.
+    fn replace(&mut self, copy: &Path, target: &Path) -> Result<()> {
+        // A dangling symbolic link doesn't exist but is replaced as well
+        let backup = match fs::symlink_metadata(target).is_ok() {
+            true => {
+                let backup = sibling(target, "rustea-old");
+                fs::hard_link(target, &backup)?;
+                Some(backup)
+            }
+            false => None,
+        };
+        if let Err(e) = fs::rename(copy, target) {
+            if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+            return Err(e.into());
+        }
+        self.copies.retain(|c| c != copy);
+        self.replaced.push((target.to_path_buf(), backup));
+        Ok(())
+    }
+
+    /// Restore the replaced targets and remove everything created so far.
+    fn undo(self) {
+        for (target, backup) in self.replaced.into_iter().rev() {
+            let _ = match backup {
+                Some(backup) => fs::rename(backup, &target),
+                None => fs::remove_file(&target),
+            };
+        }
+        for copy in self.copies {
+            let _ = fs::remove_file(copy);
+        }
+        for dir in self.dirs.into_iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+
+    /// Remove the backups after every target was replaced.
+    fn finish(self) {
+        for backup in self.replaced.into_iter().filter_map(|(_, b)| b) {
+            let _ = fs::remove_file(backup);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::Staging;
+    use crate::{error::Error, temp::TempDir};
+
+    #[test]
+    fn test_commit_staging() {
+        let tmp = TempDir::new("rustea-staging-test").unwrap();
+        let existing = tmp.write("existing", b"old").unwrap();
+        let nested = tmp.path().join("new/nested/file");
+
+        let mut staging = Staging::new().unwrap();
+        staging.add(&existing, b"new").unwrap();
+        staging.add(&nested, b"content").unwrap();
+        staging.commit(|_, _| Ok(())).unwrap();
+        assert_eq!(fs::read(&existing).unwrap(), b"new");
+        assert_eq!(fs::read(&nested).unwrap(), b"content");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_commit_symlink() {
+        let tmp = TempDir::new("rustea-staging-test").unwrap();
+        let existing = tmp.write("existing", b"old").unwrap();
+        let link = tmp.path().join("link");
+        std::os::unix::fs::symlink("missing", &link).unwrap();
+
+        let mut staging = Staging::new().unwrap();
+        staging.add_symlink(&link, &existing);
+        staging.add_symlink(&tmp.path().join("new/link"), Path::new("../existing"));
+        let mut prepared = vec![];
+        staging
+            .commit(|i, _| {
+                prepared.push(i);
+                Ok(())
+            })
+            .unwrap();
+        assert!(prepared.is_empty());
+        assert_eq!(fs::read_link(&link).unwrap(), existing);
+        assert_eq!(fs::read(tmp.path().join("new/link")).unwrap(), b"old");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_rollback_staging() {
+        let tmp = TempDir::new("rustea-staging-test").unwrap();
+        let existing = tmp.write("existing", b"old").unwrap();
+        let nested = tmp.path().join("new/nested/file");
+
+        let mut staging = Staging::new().unwrap();
+        staging.add(&existing, b"new").unwrap();
+        staging.add(&nested, b"content").unwrap();
+        let res = staging.commit(|i, _| match i {
+            1 => Err(Error::Rustea("failed".into())),
+            _ => Ok(()),
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+        assert!(!tmp.path().join("new").exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+
+        // A folder can not be replaced by a file
+        let dir = tmp.write("dir/file", b"").unwrap();
+        let mut staging = Staging::new().unwrap();
+        staging.add(&existing, b"new").unwrap();
+        staging.add(dir.parent().unwrap(), b"content").unwrap();
+        assert!(staging.commit(|_, _| Ok(())).is_err());
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_commit_checked() {
+        let tmp = TempDir::new("rustea-staging-test").unwrap();
+        let existing = tmp.write("existing", b"old").unwrap();
+        let mut staging = Staging::new().unwrap();
+        staging.add(&existing, b"new").unwrap();
+        staging.add_symlink(&tmp.path().join("link"), Path::new("existing"));
+        let res = staging.commit_checked(
+            |_, _| Ok(()),
+            |copies| {
+                assert_eq!(copies.len(), 1);
+                assert_eq!(fs::read(copies[0].1).unwrap(), b"new");
+                Err(Error::Rustea("nginx: invalid config".into()))
+            },
+        );
+        assert!(res.is_err());
+        assert_eq!(fs::read(&existing).unwrap(), b"old");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+        staging.commit_checked(|_, _| Ok(()), |_| Ok(())).unwrap();
+        assert_eq!(fs::read(&existing).unwrap(), b"new");
+    }
+}
@@ -0,0 +1,421 @@
+//! This file implements the local state rustea keeps between runs.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{ConfigError, Error, Result};
+
+/// The default state file name used by rustea.
+const DEFAULT_STATE_NAME: &str = ".rustea.state.json";
+
+/// The state file of the host, which is used if rustea runs as root.
+const HOST_STATE_PATH: &str = "/var/lib/rustea/state.json";
+
+/// The state of the host lives in `/var/lib/rustea` if rustea runs as root, otherwise the
+/// state file lives next to the configuration in the users home directory.
+/// The former state of root in its home directory is moved into `/var/lib/rustea`.
+pub fn get_default_state_path() -> Result<PathBuf> {
+    let home = env::var_os("HOME").map(|h| PathBuf::from(h).join(DEFAULT_STATE_NAME));
+    if unsafe { libc::geteuid() } != 0 {
+        return home.ok_or(Error::Configuration(ConfigError::LocationError));
+    }
+    let path = PathBuf::from(HOST_STATE_PATH);
+    if let Some(legacy) = home.filter(|h| h.is_file() && !path.exists()) {
+        crate::migrate_config(&legacy, &path)?;
+        info!(
+            "Moved the state file {} to {}",
+            legacy.display(),
+            path.display()
+        );
+    }
+    Ok(path)
+}
+
+/// The current time in seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// This function converts a RFC 3339 date like `2021-07-21T12:34:56+02:00`,
+/// as returned by the Gitea API, into seconds since the unix epoch.
+pub fn parse_timestamp(date: &str) -> Result<u64> {
+    let invalid = || Error::Rustea(format!("Invalid timestamp {}", date));
+    let num = |s: &str| s.parse::<i64>().map_err(|_| invalid());
+    if date.len() < 19 || !date.is_char_boundary(19) {
+        return Err(invalid());
+    }
+    let (datetime, zone) = date.split_at(19);
+    let (day, time) = datetime.split_once('T').ok_or_else(invalid)?;
+
+    let d: Vec<&str> = day.split('-').collect();
+    let t: Vec<&str> = time.split(':').collect();
+    if d.len() != 3 || t.len() != 3 {
+        return Err(invalid());
+    }
+    let (year, month, day) = (num(d[0])?, num(d[1])?, num(d[2])?);
+
+    // Skip fractional seconds and apply the zone offset
+    let zone = zone.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "Z" | "" => 0,
+        z if z.len() == 6 => {
+            let sign = if z.starts_with('-') { -1 } else { 1 };
+            sign * (num(&z[1..3])? * 3600 + num(&z[4..6])? * 60)
+        }
+        _ => return Err(invalid()),
+    };
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + num(t[0])? * 3600 + num(t[1])? * 60 + num(t[2])? - offset;
+    Ok(secs.max(0) as u64)
+}
+
+/// This function converts seconds since the unix epoch into a RFC 3339 date in UTC.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// The recorded state of a single feature set on this machine.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FeatureState {
+    /// The time of the last full pull in seconds since the unix epoch.
+    pub applied: u64,
+    /// The latest commit of the feature set at the time of the pull.
+    pub commit: Option<String>,
+    /// The remote blob sha of every file at the time it was last pulled or pushed.
+    /// It is the common base if local and remote file changed afterwards.
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+    /// The local files installed by a pull by their remote path, see `State::orphaned`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub managed: BTreeMap<String, ManagedFile>,
+}
+
+/// A local file which was installed by a pull of a feature set.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ManagedFile {
+    pub path: PathBuf,
+    /// The blob sha of the local content after the pull, which differs from
+    /// the remote one for templates and secret files
+    pub sha: String,
+    /// The time the file was last written by a pull in seconds since the unix epoch
+    #[serde(default)]
+    pub installed: u64,
+}
+
+/// A `StateLock` serializes the changes of the state by concurrent runs of rustea,
+/// e.g. a pull while the watch mode applies a feature set. The state is loaded and
+/// saved while the lock is held, it is released if the value is dropped.
+#[derive(Debug)]
+pub struct StateLock {
+    _file: File,
+}
+
+impl StateLock {
+    /// This function locks the state file at `path` and waits for another process
+    /// which holds the lock.
+    pub fn acquire(path: &Path) -> Result<StateLock> {
+        create_parent(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path.with_extension("lock"))?;
+        let fd = file.as_raw_fd();
+        if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            info!("Waiting for another rustea to release {}", path.display());
+            if unsafe { libc::flock(fd, libc::LOCK_EX) } != 0 {
+                return Err(Error::Io(io::Error::last_os_error()));
+            }
+        }
+        Ok(StateLock { _file: file })
+    }
+}
+
+/// This function creates the missing parent folders of `path`.
+fn create_parent(path: &Path) -> Result<()> {
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => fs::create_dir_all(parent).map_err(Error::Io),
+        None => Ok(()),
+    }
+}
+
+/// The `State` records which feature sets were applied to the local machine.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    #[serde(default)]
+    pub features: BTreeMap<String, FeatureState>,
+}
+
+impl State {
+    /// Read the state from `path`. A missing file results in an empty state.
+    pub fn load(path: &Path) -> Result<State> {
+        let mut content = String::new();
+        match File::open(path) {
+            Ok(mut f) => f.read_to_string(&mut content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        serde_json::from_str(&content)
+            .map_err(|e| Error::Rustea(format!("Invalid state file {}: {}", path.display(), e)))
+    }
+
+    /// Write the state to `path`. The file is replaced at once, so an interrupted
+    /// run never leaves a partial state behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Rustea(format!("Failed to serialize state: {}", e)))?;
+        create_parent(path)?;
+        let tmp = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path).map_err(Error::Io)
+    }
+
+    /// Returns true if the remote change at `changed` (seconds since the unix epoch)
+    /// happened after the last recorded pull of the feature set.
+    pub fn is_outdated(&self, feature_set: &str, changed: u64) -> bool {
+        match self.features.get(feature_set) {
+            Some(s) => s.applied < changed,
+            None => true,
+        }
+    }
+
+    /// Returns true if the last recorded pull of the feature set was at the `commit`.
+    pub fn is_applied(&self, feature_set: &str, commit: &str) -> bool {
+        self.features
+            .get(feature_set)
+            .and_then(|s| s.commit.as_deref())
+            == Some(commit)
+    }
+
+    /// Record a full pull of the feature set at the current time.
+    pub fn applied(&mut self, feature_set: &str, commit: Option<String>) {
+        let state = self.features.entry(feature_set.to_owned()).or_default();
+        state.applied = now();
+        state.commit = commit;
+    }
+
+    /// Returns the recorded blob sha of the remote `path` within the feature set.
+    pub fn file_sha(&self, feature_set: &str, path: &str) -> Option<&str> {
+        self.features
+            .get(feature_set)
+            .and_then(|s| s.files.get(path))
+            .map(String::as_str)
+    }
+
+    /// Record the local `file` installed from the remote `path` by a pull.
+    pub fn record_managed(&mut self, feature_set: &str, path: &str, file: ManagedFile) {
+        self.features
+            .entry(feature_set.to_owned())
+            .or_default()
+            .managed
+            .insert(path.to_owned(), file);
+    }
+
+    /// Returns the record of the managed file with the remote `path`.
+    pub fn managed_file(&self, feature_set: &str, path: &str) -> Option<&ManagedFile> {
+        self.features.get(feature_set)?.managed.get(path)
+    }
+
+    /// Returns the managed files of the feature set whose remote path is not in `remote`
+    /// anymore, since they were removed from the feature set after the pull.
+    pub fn orphaned(&self, feature_set: &str, remote: &[&str]) -> Vec<(String, ManagedFile)> {
+        self.features
+            .get(feature_set)
+            .map(|s| {
+                s.managed
+                    .iter()
+                    .filter(|(path, _)| !remote.contains(&path.as_str()))
+                    .map(|(path, file)| (path.clone(), file.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drop every record of the remote `path` within the feature set.
+    pub fn forget_file(&mut self, feature_set: &str, path: &str) {
+        if let Some(state) = self.features.get_mut(feature_set) {
+            state.files.remove(path);
+            state.managed.remove(path);
+        }
+    }
+
+    /// Record the blob sha of the remote `path` after it was pulled or pushed.
+    pub fn record_file(&mut self, feature_set: &str, path: &str, sha: &str) {
+        self.features
+            .entry(feature_set.to_owned())
+            .or_default()
+            .files
+            .insert(path.to_owned(), sha.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_timestamp, parse_timestamp, ManagedFile, State, StateLock};
+    use crate::temp::TempDir;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(1626870896), "2021-07-21T12:34:56Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        let date = "2024-12-31T23:59:59Z";
+        assert_eq!(format_timestamp(parse_timestamp(date).unwrap()), date);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(parse_timestamp("2021-07-21T12:34:56Z").unwrap(), 1626870896);
+        assert_eq!(
+            parse_timestamp("2021-07-21T14:34:56+02:00").unwrap(),
+            1626870896
+        );
+        assert_eq!(
+            parse_timestamp("2021-07-21T12:34:56.123Z").unwrap(),
+            1626870896
+        );
+        assert!(parse_timestamp("2021-07-21").is_err());
+        assert!(parse_timestamp("yesterday at noon").is_err());
+    }
+
+    #[test]
+    fn test_is_outdated() {
+        let mut state = State::default();
+        assert!(state.is_outdated("nginx", 0));
+        state.applied("nginx", None);
+        assert!(!state.is_outdated("nginx", 1626870896));
+        assert!(state.is_outdated("postgres", 1626870896));
+    }
+
+    #[test]
+    fn test_is_applied() {
+        let mut state = State::default();
+        assert!(!state.is_applied("nginx", "7d2a1f"));
+        state.applied("nginx", None);
+        assert!(!state.is_applied("nginx", "7d2a1f"));
+        state.applied("nginx", Some("7d2a1f".into()));
+        assert!(state.is_applied("nginx", "7d2a1f"));
+        assert!(!state.is_applied("nginx", "8e3b20"));
+    }
+
+    #[test]
+    fn test_record_file() {
+        let mut state = State::default();
+        state.record_file("nginx", "nginx/etc/nginx.conf", "abc");
+        state.applied("nginx", Some("123".into()));
+        assert_eq!(state.file_sha("nginx", "nginx/etc/nginx.conf"), Some("abc"));
+        assert_eq!(state.file_sha("nginx", "nginx/etc/other.conf"), None);
+        assert_eq!(state.features["nginx"].commit.as_deref(), Some("123"));
+    }
+
+    #[test]
+    fn test_orphaned_files() {
+        let mut state = State::default();
+        let file = |path: &str| ManagedFile {
+            path: path.into(),
+            sha: "abc".into(),
+            installed: 1626870896,
+        };
+        state.record_file("nginx", "nginx/etc/old.conf", "abc");
+        state.record_managed("nginx", "nginx/etc/old.conf", file("/etc/old.conf"));
+        state.record_managed("nginx", "nginx/etc/nginx.conf", file("/etc/nginx.conf"));
+        let orphaned = state.orphaned("nginx", &["nginx/etc/nginx.conf"]);
+        assert_eq!(
+            orphaned,
+            vec![("nginx/etc/old.conf".to_owned(), file("/etc/old.conf"))]
+        );
+        assert!(state.orphaned("postgres", &[]).is_empty());
+        state.forget_file("nginx", "nginx/etc/old.conf");
+        assert!(state
+            .orphaned("nginx", &["nginx/etc/nginx.conf"])
+            .is_empty());
+        assert_eq!(state.file_sha("nginx", "nginx/etc/old.conf"), None);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let tmp = TempDir::new("rustea-state").unwrap();
+        let path = tmp.path().join("lib").join("state.json");
+        let lock = StateLock::acquire(&path).unwrap();
+        let mut state = State::load(&path).unwrap();
+        assert!(state.features.is_empty());
+        let file = ManagedFile {
+            path: "/etc/nginx.conf".into(),
+            sha: "abc".into(),
+            installed: 1626870896,
+        };
+        state.record_managed("nginx", "nginx/etc/nginx.conf", file.clone());
+        state.save(&path).unwrap();
+        drop(lock);
+
+        let _lock = StateLock::acquire(&path).unwrap();
+        let state = State::load(&path).unwrap();
+        assert_eq!(
+            state.features["nginx"].managed["nginx/etc/nginx.conf"],
+            file
+        );
+        assert!(!path.with_extension("json.tmp").exists());
+        tmp.write("lib/state.json", b"{").unwrap();
+        assert!(State::load(&path).is_err());
+    }
+}
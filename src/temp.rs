@@ -0,0 +1,95 @@
+//! This file implements a small temporary directory which is removed on drop.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    env,
+    fs::{self, DirBuilder, File},
+    io::Write,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::error::Result;
+
+/// Counter to distinguish several temporary directories of one process.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A `TempDir` is a private directory below the systems temporary directory.
+/// The directory and its content are removed if the value is dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create a new temporary directory with the `prefix` in its name.
+    pub fn new(prefix: &str) -> Result<TempDir> {
+        let path = env::temp_dir().join(format!(
+            "{}-{}-{}",
+            prefix,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        DirBuilder::new().mode(0o700).create(&path)?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the directory and its content after the value is dropped.
+    pub fn keep(self) -> PathBuf {
+        let path = self.path.clone();
+        std::mem::forget(self);
+        path
+    }
+
+    /// Write `content` into the file `name` within the temporary directory.
+    /// Missing parent folders are created.
+    pub fn write(&self, name: &str, content: &[u8]) -> Result<PathBuf> {
+        let path = self.path.join(name.trim_start_matches('/'));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(&path)?.write_all(content)?;
+        Ok(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TempDir;
+
+    #[test]
+    fn test_temp_dir() {
+        let tmp = TempDir::new("rustea-test").unwrap();
+        let dir = tmp.path().to_path_buf();
+        let file = tmp.write("/etc/nested/file", b"content").unwrap();
+        assert_eq!(file, dir.join("etc/nested/file"));
+        assert_eq!(std::fs::read(&file).unwrap(), b"content");
+        drop(tmp);
+        assert!(!dir.exists());
+    }
+}
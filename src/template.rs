@@ -0,0 +1,97 @@
+//! This file implements the rendering of template files with variable substitution.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+
+/// The suffix of remote files which are rendered on pull.
+pub const SUFFIX: &str = ".tmpl";
+
+/// Returns true if the remote `path` is a template.
+pub fn is_template(path: &str) -> bool {
+    path.ends_with(SUFFIX)
+}
+
+/// This function parses a variable definition of the form `key=value`.
+pub fn parse_var(var: &str) -> Result<(String, String)> {
+    match var.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_owned(), value.to_owned()))
+        }
+        _ => Err(Error::Rustea(format!(
+            "Invalid variable {}, use key=value",
+            var
+        ))),
+    }
+}
+
+/// This function replaces every `{{ var }}` in the `template` with the value from `vars`.
+/// The whitespace around the variable name is optional. An unknown variable or an
+/// unclosed `{{` is an error.
+pub fn render(template: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| Error::Rustea("Unclosed {{ in template".into()))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = vars
+            .get(name)
+            .ok_or_else(|| Error::Rustea(format!("Unknown template variable {}", name)))?;
+        out.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{parse_var, render};
+
+    #[test]
+    fn test_render() {
+        let mut vars = BTreeMap::new();
+        vars.insert("port".to_owned(), "8080".to_owned());
+        vars.insert("host".to_owned(), "web1".to_owned());
+        assert_eq!(
+            render("listen {{ port }};\nserver_name {{host}};\n", &vars).unwrap(),
+            "listen 8080;\nserver_name web1;\n"
+        );
+        assert_eq!(render("no variables", &vars).unwrap(), "no variables");
+        assert!(render("{{ missing }}", &vars).is_err());
+        assert!(render("{{ port", &vars).is_err());
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("port=8080").unwrap(),
+            ("port".to_owned(), "8080".to_owned())
+        );
+        assert_eq!(
+            parse_var("url=a=b").unwrap(),
+            ("url".to_owned(), "a=b".to_owned())
+        );
+        assert!(parse_var("port").is_err());
+        assert!(parse_var("=8080").is_err());
+    }
+}
@@ -14,25 +14,91 @@
 ///
 /// You should have received a copy of the GNU General Public License
 /// along with this program. If not, see <https://www.gnu.org/licenses/>.
-
-/// Static repository configuration for the self-updater
+///
+/// Default repository configuration for the self-updater
 const OWNER: &str = "santifa";
 const REPO: &str = "rustea";
 const URL: &str = "https://api.github.com";
 const MIME_TYPE: &str = "application/vnd.github.v3+json";
 const CUR_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The release asset with the SHA-256 checksums of the binaries
+const CHECKSUMS: &str = "SHA256SUMS";
+/// The release assets with a detached GnuPG signature of the checksums
+const SIGNATURES: [&str; 2] = ["SHA256SUMS.asc", "SHA256SUMS.sig"];
 
 use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
     env,
     io::{Read, Write},
     os::unix::prelude::PermissionsExt,
     path::PathBuf,
+    process::Command,
 };
 
+use crate::checksum;
 use crate::error::{Error, Result};
-use serde_derive::Deserialize;
-use ureq::AgentBuilder;
+use crate::temp::TempDir;
+use log::{debug, info};
+use serde_derive::{Deserialize, Serialize};
+use ureq::{Agent, AgentBuilder, Request};
+
+/// The `[updater]` section of the configuration selects the source of the releases.
+/// Besides github any Gitea or Forgejo instance with the same releases API can be used.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct UpdaterConfig {
+    /// The base url of the releases API, e.g. `https://git.example.com/api/v1`
+    #[serde(default = "default_url")]
+    pub url: String,
+    #[serde(default = "default_owner")]
+    pub owner: String,
+    #[serde(default = "default_repository")]
+    pub repository: String,
+    /// The token used to access private releases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+fn default_url() -> String {
+    URL.to_owned()
+}
+
+fn default_owner() -> String {
+    OWNER.to_owned()
+}
+
+fn default_repository() -> String {
+    REPO.to_owned()
+}
+
+impl Default for UpdaterConfig {
+    fn default() -> Self {
+        UpdaterConfig {
+            url: default_url(),
+            owner: default_owner(),
+            repository: default_repository(),
+            token: None,
+        }
+    }
+}
+
+impl UpdaterConfig {
+    /// Returns true if the github releases of rustea are used.
+    pub fn is_default(&self) -> bool {
+        *self == UpdaterConfig::default()
+    }
+}
 
+/// This function returns the target triple of the running binary, e.g. `x86_64-unknown-linux-gnu`.
+pub fn target() -> String {
+    let env = match cfg!(target_env = "musl") {
+        true => "musl",
+        false => "gnu",
+    };
+    format!("{}-unknown-{}-{}", env::consts::ARCH, env::consts::OS, env)
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 struct Release {
     name: String,
@@ -43,57 +109,241 @@ struct Release {
 }
 
 impl Release {
-    // This function returns either the minified or normal binary
-    // download url. At the moment the files are hard-coded.
-    fn get_download_url(&self, minified: bool) -> String {
-        match minified {
-            true => self.assets[1].browser_download_url.to_owned(),
-            false => self.assets[0].browser_download_url.to_owned(),
+    // This function returns either the minified or normal binary for the `target` triple.
+    // Releases without assets named by target provide the binaries `rustea` and `rustea-min`.
+    fn get_binary(&self, target: &str, minified: bool) -> Result<&Asset> {
+        let binaries: Vec<&Asset> = self
+            .assets
+            .iter()
+            .filter(|a| !a.name.starts_with(CHECKSUMS))
+            .collect();
+        let targeted: Vec<&Asset> = binaries
+            .iter()
+            .copied()
+            .filter(|a| matches_target(&a.name, target))
+            .collect();
+        let candidates = match targeted.is_empty() {
+            true => binaries
+                .into_iter()
+                .filter(|a| a.name == "rustea" || a.name == "rustea-min")
+                .collect(),
+            false => targeted,
+        };
+        candidates
+            .into_iter()
+            .find(|a| a.name.contains("min") == minified)
+            .ok_or_else(|| {
+                Error::Rustea(format!(
+                    "Release {} has no binary for {}",
+                    self.tag_name, target
+                ))
+            })
+    }
+
+    // This function returns the binary named `asset` or the binary for the `target`, see `get_binary`.
+    fn select_binary(&self, asset: Option<&str>, target: &str, minified: bool) -> Result<&Asset> {
+        match asset {
+            Some(name) => self.get_asset(name).ok_or_else(|| {
+                Error::Rustea(format!("Release {} has no asset {}", self.tag_name, name))
+            }),
+            None => self.get_binary(target, minified),
         }
     }
+
+    // This function returns the asset with the `name`.
+    fn get_asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
 }
 
+// Returns true if the asset `name` contains the `target` triple, or its architecture
+// and operating system. musl binaries are only used for a musl target.
+fn matches_target(name: &str, target: &str) -> bool {
+    let parts: Vec<&str> = target.split('-').collect();
+    match (parts.first(), parts.len()) {
+        _ if name.contains(target) => true,
+        (Some(arch), len) if len >= 3 => {
+            let (os, env) = (parts[len - 2], parts[len - 1]);
+            name.contains(arch) && name.contains(os) && name.contains("musl") == (env == "musl")
+        }
+        _ => false,
+    }
+}
+
+// This function returns an error if the `binary` doesn't match the checksum of the
+// asset `name` within the `sums`, which are formatted like the output of `sha256sum`.
+fn verify_checksum(sums: &str, name: &str, binary: &[u8]) -> Result<()> {
+    let expected = sums
+        .lines()
+        .filter_map(|l| l.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim_start().trim_start_matches('*') == name)
+        .map(|(sum, _)| sum)
+        .ok_or_else(|| Error::Rustea(format!("No checksum for {} in {}", name, CHECKSUMS)))?;
+    let sum = checksum::sha256(binary);
+    match expected.eq_ignore_ascii_case(&sum) {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "Checksum {} of {} doesn't match {}",
+            sum, name, expected
+        ))),
+    }
+}
+
+// This function verifies the detached `signature` of the `sums` with gpg.
+// The key of the signer must be within the keyring of the user.
+fn verify_signature(sums: &[u8], signature: &[u8]) -> Result<()> {
+    let tmp = TempDir::new("rustea-update")?;
+    let sums = tmp.write(CHECKSUMS, sums)?;
+    let signature = tmp.write("SHA256SUMS.sig", signature)?;
+    let out = Command::new("gpg")
+        .arg("--batch")
+        .arg("--verify")
+        .arg(&signature)
+        .arg(&sums)
+        .output()
+        .map_err(|e| Error::Rustea(format!("Failed to run gpg: {}", e)))?;
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(Error::Rustea(format!(
+            "Invalid signature of {}: {}",
+            CHECKSUMS,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ))),
+    }
+}
+
+// This function downloads an asset with the `request`.
+fn download(request: Request) -> Result<Vec<u8>> {
+    info!("Downloading {}", request.url());
+    let mut buffer = Vec::new();
+    request.call()?.into_reader().read_to_end(&mut buffer)?;
+    debug!("Downloaded {} bytes", buffer.len());
+    Ok(buffer)
+}
+
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 struct Asset {
     name: String,
     browser_download_url: String,
 }
 
-#[derive(Debug, PartialEq, PartialOrd)]
+/// A part of the pre-release of a version, numeric parts are lower than alphanumeric ones.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+/// A semantic version like `v1.2.3-rc.1`. Missing minor or patch numbers are zero
+/// and the build metadata after a `+` is ignored.
+#[derive(Debug, PartialEq, Eq)]
 struct Version {
-    major: u8,
-    minor: u8,
-    patch: u8,
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Vec<Identifier>,
 }
 
 impl Version {
     fn new(s: &str) -> Result<Self> {
-        let version = if s.starts_with('v') {
-            s.strip_prefix('v').unwrap_or("0.0.0")
-        } else {
-            s
+        let invalid = || Error::Rustea(format!("Invalid version {}", s));
+        let version = s.strip_prefix('v').unwrap_or(s);
+        let version = version.split('+').next().unwrap_or(version);
+        let (core, pre) = match version.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (version, None),
         };
-        let parts: Result<Vec<u8>> = version
+        let parts = core
             .split('.')
-            .map(|e| e.parse::<u8>().map_err(Error::Version))
-            .collect();
-        let parts = parts?;
+            .map(|e| e.parse::<u64>().map_err(Error::Version))
+            .collect::<Result<Vec<u64>>>()?;
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        let pre = match pre {
+            Some(pre) => pre
+                .split('.')
+                .map(|p| match p.parse::<u64>() {
+                    Ok(n) => Ok(Identifier::Numeric(n)),
+                    Err(_) if p.is_empty() => Err(invalid()),
+                    Err(_) => Ok(Identifier::Alphanumeric(p.to_owned())),
+                })
+                .collect::<Result<Vec<Identifier>>>()?,
+            None => vec![],
+        };
         Ok(Version {
             major: parts[0],
-            minor: parts[1],
-            patch: parts[2],
+            minor: parts.get(1).copied().unwrap_or(0),
+            patch: parts.get(2).copied().unwrap_or(0),
+            pre,
         })
     }
+
+    fn is_pre_release(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // A pre-release is lower than the release
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The allowed versions manifest lists the SHA-256 of every binary which
+/// may be installed by the updater, e.g.
+/// `[versions]` followed by `"0.1.5" = ["<sha256 normal>", "<sha256 minified>"]`.
+#[derive(Deserialize, Debug, Default)]
+pub struct AllowedVersions {
+    #[serde(default)]
+    versions: BTreeMap<String, Vec<String>>,
+}
+
+impl AllowedVersions {
+    pub fn parse(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    // This function returns an error if the `binary` is not listed for the `version`.
+    fn verify(&self, version: &str, binary: &[u8]) -> Result<()> {
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let sum = checksum::sha256(binary);
+        match self.versions.get(version) {
+            Some(sums) if sums.iter().any(|s| s.eq_ignore_ascii_case(&sum)) => Ok(()),
+            Some(_) => Err(Error::Rustea(format!(
+                "Checksum {} of version {} is not allowed",
+                sum, version
+            ))),
+            None => Err(Error::Rustea(format!("Version {} is not allowed", version))),
+        }
+    }
 }
 
 pub struct Updater {
     binary_path: PathBuf,
+    source: UpdaterConfig,
+    pre_release: bool,
 }
 
 impl Updater {
-    // Create a new updater which figures out its
-    // own binary path and checks the permissions.
-    pub fn new() -> Result<Self> {
+    // Create a new updater for the releases of the `source` which figures
+    // out its own binary path and checks the permissions.
+    pub fn new(source: UpdaterConfig) -> Result<Self> {
         let binary_path = std::env::current_exe()?;
         if binary_path.metadata()?.permissions().readonly() {
             return Err(Error::Rustea(format!(
@@ -101,7 +351,17 @@ impl Updater {
                 binary_path.display()
             )));
         }
-        Ok(Updater { binary_path })
+        Ok(Updater {
+            binary_path,
+            source,
+            pre_release: false,
+        })
+    }
+
+    // This function lets the updater select release candidates and other pre-releases.
+    pub fn with_pre_release(mut self, pre_release: bool) -> Self {
+        self.pre_release = pre_release;
+        self
     }
 
     // Set the binary as executable. This should be done after the update.
@@ -132,30 +392,312 @@ impl Updater {
         std::fs::remove_file(tmp_bin).map_err(Error::Io)
     }
 
-    pub fn update(&self, minified: bool) -> Result<String> {
-        let agent = AgentBuilder::new().build();
-        // get all releases but we only care for the last one
-        let release = agent
-            .get(&format!("{}/repos/{}/{}/releases", URL, OWNER, REPO))
-            .set("Accept", MIME_TYPE)
+    // This function creates a request to the release source with the token if one is configured.
+    fn request(&self, agent: &Agent, url: &str) -> Request {
+        let request = agent.get(url).set("Accept", MIME_TYPE);
+        match &self.source.token {
+            Some(token) => request.set("Authorization", &format!("token {}", token)),
+            None => request,
+        }
+    }
+
+    // This function fetches the releases of the source, the newest first.
+    fn releases(&self, agent: &Agent) -> Result<Vec<Release>> {
+        let releases_url = format!(
+            "{}/repos/{}/{}/releases",
+            self.source.url.trim_end_matches('/'),
+            self.source.owner,
+            self.source.repository
+        );
+        debug!("GET {}", releases_url);
+        Ok(self
+            .request(agent, &releases_url)
             .call()?
-            .into_json::<Vec<Release>>()?;
-
-        if let Some(release) = release.first() {
-            if Version::new(CUR_VERSION)? < Version::new(&release.tag_name)? {
-                let url = release.get_download_url(minified);
-                let mut reader = agent.get(&url).call()?.into_reader();
-                let mut buffer = Vec::new();
-                reader.read_to_end(&mut buffer)?;
-                self.replace_binary(&buffer)?;
+            .into_json::<Vec<Release>>()?)
+    }
+
+    // This function returns the current and the latest version and
+    // whether a newer release is available.
+    pub fn check(&self) -> Result<(String, bool)> {
+        let agent = AgentBuilder::new().build();
+        let release = select_release(self.releases(&agent)?, None, self.pre_release)?;
+        let outdated = Version::new(CUR_VERSION)? < Version::new(&release.tag_name)?;
+        Ok((
+            format!(
+                "Current version {}, latest version {}",
+                CUR_VERSION, release.tag_name
+            ),
+            outdated,
+        ))
+    }
+
+    // This function updates rustea to the latest release or installs the release
+    // of the `version`, which may also be older than the running one.
+    // The `asset` overrides the name of the installed binary.
+    pub fn update(
+        &self,
+        minified: bool,
+        version: Option<&str>,
+        asset: Option<&str>,
+        allowed: Option<&AllowedVersions>,
+    ) -> Result<String> {
+        let agent = AgentBuilder::new().build();
+        let release = select_release(self.releases(&agent)?, version, self.pre_release)?;
+        debug!(
+            "Selected release {}, running {}",
+            release.tag_name, CUR_VERSION
+        );
+        let current = Version::new(CUR_VERSION)?;
+        let selected = Version::new(&release.tag_name)?;
+        match version {
+            None if current >= selected => Err(Error::Rustea("Nothing to update".to_string())),
+            Some(_) if current == selected => Err(Error::Rustea(format!(
+                "Version {} is already installed",
+                CUR_VERSION
+            ))),
+            _ => {
+                self.install(&agent, &release, minified, asset, allowed)?;
                 Ok(format!("Updated to version {}", release.tag_name))
-            } else {
-                Err(Error::Rustea("Nothing to update".to_string()))
             }
-        } else {
-            Err(Error::Rustea(
-                "Failed to fetch the latest release from github.".to_string(),
+        }
+    }
+
+    // This function installs the binary of the `release`. The downloaded binary is only
+    // installed if its checksum matches the `SHA256SUMS` of the release, which are
+    // verified with gpg if the release contains a signature. If `allowed` is provided
+    // the checksum must also be listed for the release.
+    fn install(
+        &self,
+        agent: &Agent,
+        release: &Release,
+        minified: bool,
+        asset: Option<&str>,
+        allowed: Option<&AllowedVersions>,
+    ) -> Result<()> {
+        let binary = release.select_binary(asset, &target(), minified)?;
+        let sums = release.get_asset(CHECKSUMS).ok_or_else(|| {
+            Error::Rustea(format!(
+                "Release {} has no {}, refusing to update",
+                release.tag_name, CHECKSUMS
             ))
+        })?;
+        let sums = download(self.request(agent, &sums.browser_download_url))?;
+        if let Some(sig) = SIGNATURES.iter().find_map(|s| release.get_asset(s)) {
+            let sig = download(self.request(agent, &sig.browser_download_url))?;
+            verify_signature(&sums, &sig)?;
+        }
+        let buffer = download(self.request(agent, &binary.browser_download_url))?;
+        verify_checksum(&String::from_utf8_lossy(&sums), &binary.name, &buffer)?;
+        if let Some(allowed) = allowed {
+            allowed.verify(&release.tag_name, &buffer)?;
         }
+        debug!("Replacing the binary {}", self.binary_path.display());
+        self.replace_binary(&buffer)
+    }
+}
+
+// This function returns the highest version of the `releases` or the one with the `version`.
+// Drafts and releases with unparsable tags are skipped, pre-releases only with `pre_release`.
+fn select_release(
+    releases: Vec<Release>,
+    version: Option<&str>,
+    pre_release: bool,
+) -> Result<Release> {
+    let mut releases = releases
+        .into_iter()
+        .filter(|r| !r.draft)
+        .filter_map(|r| Version::new(&r.tag_name).ok().map(|v| (v, r)));
+    match version {
+        Some(version) => {
+            let wanted = Version::new(version)?;
+            releases
+                .find(|(v, _)| *v == wanted)
+                .map(|(_, r)| r)
+                .ok_or_else(|| Error::Rustea(format!("No release with version {}", version)))
+        }
+        None => releases
+            .filter(|(v, r)| pre_release || !(r.prerelease || v.is_pre_release()))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, r)| r)
+            .ok_or_else(|| Error::Rustea("Failed to fetch the latest release.".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        select_release, verify_checksum, AllowedVersions, Asset, Release, UpdaterConfig, Version,
+    };
+
+    #[test]
+    fn test_verify_allowed_versions() {
+        let allowed = AllowedVersions::parse(
+            r#"
+            [versions]
+            "0.1.5" = ["5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"]
+            "#,
+        )
+        .unwrap();
+        assert!(allowed.verify("v0.1.5", b"hello\n").is_ok());
+        assert!(allowed.verify("0.1.5", b"hello").is_err());
+        assert!(allowed.verify("0.1.6", b"hello\n").is_err());
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let sums = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03  rustea\n\
+                    0000000000000000000000000000000000000000000000000000000000000000 *rustea-min\n";
+        assert!(verify_checksum(sums, "rustea", b"hello\n").is_ok());
+        assert!(verify_checksum(sums, "rustea", b"hello").is_err());
+        assert!(verify_checksum(sums, "rustea-min", b"hello\n").is_err());
+        assert!(verify_checksum(sums, "rustea-other", b"hello\n").is_err());
+    }
+
+    #[test]
+    fn test_get_binary() {
+        let asset = |name: &str| Asset {
+            name: name.into(),
+            browser_download_url: format!("https://example.com/{}", name),
+        };
+        let release = Release {
+            name: "v0.1.5".into(),
+            tag_name: "v0.1.5".into(),
+            draft: false,
+            prerelease: false,
+            assets: vec![asset("SHA256SUMS"), asset("rustea"), asset("rustea-min")],
+        };
+        let target = "x86_64-unknown-linux-gnu";
+        assert_eq!(release.get_binary(target, false).unwrap().name, "rustea");
+        assert_eq!(release.get_binary(target, true).unwrap().name, "rustea-min");
+        assert!(release.get_asset("SHA256SUMS.asc").is_none());
+
+        let release = Release {
+            assets: vec![
+                asset("rustea-aarch64-unknown-linux-gnu"),
+                asset("rustea-min-x86_64-unknown-linux-gnu"),
+                asset("rustea-x86_64-unknown-linux-gnu"),
+                asset("SHA256SUMS"),
+            ],
+            ..release
+        };
+        assert_eq!(
+            release.get_binary(target, false).unwrap().name,
+            "rustea-x86_64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            release.get_binary(target, true).unwrap().name,
+            "rustea-min-x86_64-unknown-linux-gnu"
+        );
+        assert!(release
+            .get_binary("armv7-unknown-linux-gnueabihf", false)
+            .is_err());
+        assert_eq!(
+            release
+                .select_binary(Some("rustea-aarch64-unknown-linux-gnu"), target, true)
+                .unwrap()
+                .name,
+            "rustea-aarch64-unknown-linux-gnu"
+        );
+        assert!(release
+            .select_binary(Some("rustea"), target, false)
+            .is_err());
+
+        let release = Release {
+            assets: vec![
+                asset("rustea-x86_64-linux"),
+                asset("rustea-x86_64-linux-musl"),
+                asset("rustea-aarch64-linux-musl"),
+            ],
+            ..release
+        };
+        assert_eq!(
+            release.get_binary(target, false).unwrap().name,
+            "rustea-x86_64-linux"
+        );
+        assert_eq!(
+            release
+                .get_binary("aarch64-unknown-linux-musl", false)
+                .unwrap()
+                .name,
+            "rustea-aarch64-linux-musl"
+        );
+        assert!(release
+            .get_binary("aarch64-unknown-linux-gnu", false)
+            .is_err());
+    }
+
+    #[test]
+    fn test_select_release() {
+        let release = |tag: &str| Release {
+            name: tag.into(),
+            tag_name: tag.into(),
+            draft: false,
+            prerelease: false,
+            assets: vec![],
+        };
+        let releases = || {
+            vec![
+                release("v0.2.0-rc1"),
+                release("v0.1.6"),
+                release("nightly"),
+                release("v0.1.5"),
+                release("0.1.4"),
+            ]
+        };
+        assert_eq!(
+            select_release(releases(), None, false).unwrap().tag_name,
+            "v0.1.6"
+        );
+        assert_eq!(
+            select_release(releases(), None, true).unwrap().tag_name,
+            "v0.2.0-rc1"
+        );
+        assert_eq!(
+            select_release(releases(), Some("0.1.5"), false)
+                .unwrap()
+                .tag_name,
+            "v0.1.5"
+        );
+        assert_eq!(
+            select_release(releases(), Some("v0.1.4"), false)
+                .unwrap()
+                .tag_name,
+            "0.1.4"
+        );
+        assert!(select_release(releases(), Some("0.3.0"), false).is_err());
+        assert!(select_release(vec![], None, false).is_err());
+    }
+
+    #[test]
+    fn test_version() {
+        let v = |s: &str| Version::new(s).unwrap();
+        assert_eq!(v("v1.2"), v("1.2.0"));
+        assert_eq!(v("1"), v("1.0.0+build.5"));
+        assert!(v("1.3.0-rc1") < v("1.3.0"));
+        assert!(v("1.3.0-rc1") > v("1.2.9"));
+        assert!(v("1.3.0-alpha") < v("1.3.0-alpha.1"));
+        assert!(v("1.3.0-alpha.1") < v("1.3.0-beta"));
+        assert!(v("1.3.0-rc.2") < v("1.3.0-rc.10"));
+        assert!(v("0.1.300") > v("0.1.255"));
+        assert!(v("1.3.0-rc1").is_pre_release());
+        assert!(Version::new("1.2.3.4").is_err());
+        assert!(Version::new("1.x").is_err());
+        assert!(Version::new("1.2.3-").is_err());
+        assert!(Version::new("").is_err());
+    }
+
+    #[test]
+    fn test_updater_config() {
+        let config: UpdaterConfig = toml::from_str(
+            r#"
+            url = "https://git.example.com/api/v1"
+            token = "secret"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.owner, "santifa");
+        assert_eq!(config.repository, "rustea");
+        assert!(!config.is_default());
+        assert!(UpdaterConfig::default().is_default());
     }
 }
@@ -0,0 +1,254 @@
+//! This file implements the watching of local directories for changes.
+/// rustea is a small cli tool to interact with git repositories hosted
+/// by Gitea Instances. Copyright (C) 2021  Henrik Jürges (juerges.henrik@gmail.com)
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program. If not, see <https://www.gnu.org/licenses/>.
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    error::Result,
+    glob::{self, Pattern},
+};
+
+/// The default time to wait for further changes before they are pushed.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// A `Watcher` reports files which are written, created or moved below a directory.
+/// Paths matching one of the `exclude` patterns are ignored.
+#[derive(Debug)]
+pub struct Watcher {
+    exclude: Vec<Pattern>,
+    #[cfg(target_os = "linux")]
+    inotify: inotify::Inotify,
+}
+
+impl Watcher {
+    fn is_excluded(&self, path: &Path) -> bool {
+        glob::is_excluded(&self.exclude, path)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    /// Create a new watcher for `dir` and all its subdirectories.
+    pub fn new(dir: &Path, exclude: Vec<Pattern>) -> Result<Watcher> {
+        let mut watcher = Watcher {
+            exclude,
+            inotify: inotify::Inotify::new()?,
+        };
+        watcher.watch_dir(dir, &mut BTreeSet::new())?;
+        Ok(watcher)
+    }
+
+    /// Add a watch for `dir` and its subdirectories. Files already found
+    /// in the directories are added to `files`.
+    fn watch_dir(&mut self, dir: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+        self.inotify.add_watch(dir)?;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if self.is_excluded(&path) {
+                continue;
+            }
+            match path.is_dir() {
+                true => self.watch_dir(&path, files)?,
+                false => {
+                    files.insert(path);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// This function blocks until some files changed and returns them once
+    /// no further change happened within the `debounce` duration.
+    pub fn wait(&mut self, debounce: Duration) -> Result<BTreeSet<PathBuf>> {
+        let mut files = BTreeSet::new();
+        let mut timeout = None;
+        loop {
+            if !self.inotify.poll(timeout)? {
+                if files.is_empty() {
+                    timeout = None;
+                    continue;
+                }
+                return Ok(files);
+            }
+            for (path, is_dir) in self.inotify.read_events()? {
+                if self.is_excluded(&path) {
+                    continue;
+                }
+                match is_dir {
+                    true => self.watch_dir(&path, &mut files)?,
+                    false => {
+                        files.insert(path);
+                    }
+                }
+            }
+            // Files which vanished in the meantime can not be pushed
+            files.retain(|f| f.is_file());
+            timeout = Some(debounce);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    /// Watching directories is only supported on linux right now.
+    pub fn new(_dir: &Path, exclude: Vec<Pattern>) -> Result<Watcher> {
+        let _ = Watcher { exclude };
+        Err(crate::error::Error::Rustea(
+            "Watching directories is not supported on this platform".into(),
+        ))
+    }
+
+    pub fn wait(&mut self, _debounce: Duration) -> Result<BTreeSet<PathBuf>> {
+        Ok(BTreeSet::new())
+    }
+}
+
+/// A thin wrapper around the inotify api of linux.
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::{
+        collections::HashMap,
+        ffi::CString,
+        io, mem,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+        ptr,
+        time::Duration,
+    };
+
+    /// The events which signal a changed file or a new directory.
+    const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+
+    #[derive(Debug)]
+    pub struct Inotify {
+        fd: libc::c_int,
+        watches: HashMap<libc::c_int, PathBuf>,
+    }
+
+    impl Inotify {
+        pub fn new() -> io::Result<Inotify> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                fd,
+                watches: HashMap::new(),
+            })
+        }
+
+        pub fn add_watch(&mut self, dir: &Path) -> io::Result<()> {
+            let c_path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.watches.insert(wd, dir.to_path_buf());
+            Ok(())
+        }
+
+        /// Wait until events are available or the `timeout` elapsed.
+        /// Returns false if no event is available.
+        pub fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+            let mut fds = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout.map_or(-1, |t| t.as_millis() as libc::c_int);
+            match unsafe { libc::poll(&mut fds, 1, timeout) } {
+                n if n < 0 => {
+                    let e = io::Error::last_os_error();
+                    match e.kind() {
+                        io::ErrorKind::Interrupted => Ok(false),
+                        _ => Err(e),
+                    }
+                }
+                n => Ok(n > 0),
+            }
+        }
+
+        /// Read the pending events and return the affected paths and if they are directories.
+        pub fn read_events(&self) -> io::Result<Vec<(PathBuf, bool)>> {
+            let mut buf = [0u8; 4096];
+            let len =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut events = vec![];
+            let mut offset = 0;
+            let header = mem::size_of::<libc::inotify_event>();
+            while offset + header <= len as usize {
+                let event: libc::inotify_event =
+                    unsafe { ptr::read_unaligned(buf.as_ptr().add(offset) as *const _) };
+                let name = &buf[offset + header..offset + header + event.len as usize];
+                offset += header + event.len as usize;
+
+                let dir = match self.watches.get(&event.wd) {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                if name.is_empty() {
+                    continue;
+                }
+                let path = dir.join(std::ffi::OsStr::from_bytes(name));
+                let is_dir = event.mask & libc::IN_ISDIR != 0;
+                // New files are reported on close, only new directories are of interest here
+                if event.mask & libc::IN_CREATE != 0 && !is_dir {
+                    continue;
+                }
+                events.push((path, is_dir));
+            }
+            Ok(events)
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::Watcher;
+    use crate::{glob::Pattern, temp::TempDir};
+
+    #[test]
+    fn test_watch_dir() {
+        let tmp = TempDir::new("rustea-watch").unwrap();
+        let mut watcher = Watcher::new(tmp.path(), vec![Pattern::new("*.swp").unwrap()]).unwrap();
+        let file = tmp.write("config", b"content").unwrap();
+        tmp.write("config.swp", b"swap").unwrap();
+        let nested = tmp.write("nested/file", b"content").unwrap();
+
+        let files = watcher.wait(Duration::from_millis(50)).unwrap();
+        assert!(files.contains(&file));
+        assert!(files.contains(&nested));
+        assert_eq!(files.len(), 2);
+    }
+}
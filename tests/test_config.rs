@@ -14,7 +14,7 @@ fn test_parse_config() {
     let conf_result = RusteaConfiguration::read_config_file(DEV_FILE);
     assert!(conf_result.is_ok());
     let conf = conf_result.unwrap();
-    assert_eq!(conf.script_folder, "test_bin");
+    assert_eq!(conf.script_folder, PathBuf::from("test_bin"));
     assert_eq!(conf.repo.owner, "Juerges");
     assert_eq!(conf.repo.email, "test@test.de");
     assert_eq!(conf.repo.url, "https://git.cobios.de");
@@ -34,7 +34,7 @@ fn test_write_config_file() {
     let conf_result = RusteaConfiguration::read_config_file(DEV_FILE).unwrap();
     let path = PathBuf::from("test_bin/rustea.toml");
     assert!(!path.exists());
-    conf_result.write_config_file(&path);
+    assert!(conf_result.write_config_file(&path).is_ok());
     assert!(path.exists());
-    fs::remove_file(&path);
+    assert!(fs::remove_file(&path).is_ok());
 }